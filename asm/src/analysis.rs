@@ -0,0 +1,53 @@
+//! Optional analyses of a validated program that report likely mistakes or help size the stack
+//!
+//! These are not needed to assemble a program, so each one is only run if the lint it reports
+//! is enabled or its report is requested.
+
+mod stack_usage;
+
+pub use stack_usage::*;
+
+use crate::asm;
+use crate::diagnostics::{Diagnostics, Lint, LintLevel};
+
+/// Reports instructions in the `.code` section that can never be executed
+///
+/// An instruction is unreachable if it comes after an unconditional `jmp` or a `ret` and no
+/// label is written between them. Data in the `.code` section is never reported, but it does not
+/// make the instructions after it reachable. Only the first instruction of each unreachable run
+/// of instructions is reported.
+pub fn check_unreachable_code(prog: &asm::Program, diag: &Diagnostics) {
+    if diag.lint_level(Lint::UnreachableCode) == LintLevel::Allow {
+        return;
+    }
+
+    let stmts = match &prog.code_section {
+        Some(section) => &section.stmts,
+        None => return,
+    };
+
+    // The span of the `jmp` or `ret` that the current statement comes after, if any
+    let mut prev_exit = None;
+    for stmt in stmts {
+        // Labels generated for `$here` can't be jumped to from anywhere else
+        if stmt.labels.iter().any(|label| !label.generated) {
+            prev_exit = None;
+        }
+
+        let instr = match &stmt.kind {
+            asm::StmtKind::Instr(instr) => instr,
+            asm::StmtKind::StaticData(_) => continue,
+        };
+
+        // Taking the span ensures that only the first instruction of each run is reported
+        if let Some(exit_span) = prev_exit.take() {
+            diag.span_lint(Lint::UnreachableCode, instr.span(), "unreachable instruction")
+                .span_note(exit_span, "any code following this instruction is unreachable unless it has a label")
+                .emit();
+        }
+
+        if let asm::Instr::Jmp(_) | asm::Instr::Ret(_) = instr {
+            prev_exit = Some(instr.span());
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::collections::HashMap;
+
+use crate::asm::{self, Instr, Location, Source, Destination, RegisterKind};
+use crate::interner::InternedStr;
+
+/// The maximum number of bytes a function may push onto the stack
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StackUsage {
+    /// The function (including every function it calls) uses at most this many bytes
+    Bytes(u64),
+    /// The function is part of (or calls into) a cycle of recursive calls
+    Recursive,
+    /// The stack usage cannot be determined statically for the given reason
+    Unknown(&'static str),
+}
+
+impl StackUsage {
+    /// Returns the usage that covers both of the given usages
+    ///
+    /// Unknown usage takes precedence over recursion, since recursion does not make a function
+    /// any more analyzable.
+    fn max(self, other: Self) -> Self {
+        use StackUsage::*;
+        match (self, other) {
+            (Unknown(reason), _) | (_, Unknown(reason)) => Unknown(reason),
+            (Recursive, _) | (_, Recursive) => Recursive,
+            (Bytes(a), Bytes(b)) => Bytes(a.max(b)),
+        }
+    }
+}
+
+impl fmt::Display for StackUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use StackUsage::*;
+        match self {
+            Bytes(bytes) => write!(f, "{} bytes", bytes),
+            Recursive => write!(f, "unbounded (recursive)"),
+            Unknown(reason) => write!(f, "unknown ({})", reason),
+        }
+    }
+}
+
+/// The stack usage of every function in a program and the recursive calls between them
+///
+/// A function is the first labeled statement of the `.code` section (the entry point) or any
+/// label used as the target of a `call`. The stack usage of a function is the largest number of
+/// bytes that may be pushed onto the stack (with `push`, `call`, or by adding to or subtracting
+/// from `$sp`) at any point between the start of the function and its `ret`, including by the
+/// functions that it calls.
+///
+/// Every path through each function is followed using only the instructions of the program, so
+/// jumps and calls through registers cannot be analyzed. Instructions other than `push`, `pop`,
+/// `add`, `sub`, and `mov` are assumed not to modify `$sp`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackReport {
+    /// The name and stack usage of each function, in the order they appear in the program
+    functions: Vec<(InternedStr, StackUsage)>,
+    /// Each cycle of functions that call each other, starting with the first function in the
+    /// cycle (e.g. `a -> b -> a` is stored as `[a, b]`)
+    cycles: Vec<Vec<InternedStr>>,
+}
+
+impl StackReport {
+    /// Computes the stack usage of every function in the program
+    pub fn new(prog: &asm::Program) -> Self {
+        let stmts = match &prog.code_section {
+            Some(section) => &section.stmts[..],
+            None => &[],
+        };
+
+        let mut label_indexes = HashMap::new();
+        for (index, stmt) in stmts.iter().enumerate() {
+            for label in &stmt.labels {
+                label_indexes.insert(label.value, index);
+            }
+        }
+
+        // The name used for each function, by the index of its first statement
+        let mut function_names = HashMap::new();
+        if let Some(label) = stmts.first().and_then(|stmt| stmt.labels.first()) {
+            function_names.insert(0, label.value);
+        }
+        for stmt in stmts {
+            if let asm::StmtKind::Instr(Instr::Call(call)) = &stmt.kind {
+                if let Location::Label(label) = &call.loc {
+                    if let Some(&index) = label_indexes.get(&label.value) {
+                        function_names.entry(index).or_insert(label.value);
+                    }
+                }
+            }
+        }
+
+        let mut analyzer = Analyzer {
+            stmts,
+            label_indexes,
+            usage: HashMap::new(),
+            in_progress: Vec::new(),
+            cycles: Vec::new(),
+        };
+
+        let mut function_indexes: Vec<_> = function_names.keys().copied().collect();
+        function_indexes.sort_unstable();
+        let functions = function_indexes.iter()
+            .map(|&index| (function_names[&index], analyzer.function_usage(index)))
+            .collect();
+
+        let cycles = analyzer.cycles.into_iter()
+            .map(|cycle| cycle.into_iter().map(|index| {
+                function_names.get(&index).copied()
+                    .unwrap_or_else(|| stmts[index].labels[0].value)
+            }).collect())
+            .collect();
+
+        Self {functions, cycles}
+    }
+
+    /// Writes a report listing the stack usage of every function, followed by every cycle of
+    /// recursive calls
+    pub fn write<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let width = self.functions.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        for (name, usage) in &self.functions {
+            writeln!(out, "{:<width$}  {}", name.as_str(), usage, width = width)?;
+        }
+
+        for cycle in &self.cycles {
+            let names: Vec<_> = cycle.iter().chain(cycle.first()).map(|name| name.as_str()).collect();
+            writeln!(out, "recursion: {}", names.join(" -> "))?;
+        }
+
+        Ok(())
+    }
+}
+
+struct Analyzer<'a> {
+    /// The statements of the `.code` section
+    stmts: &'a [asm::Stmt],
+    /// The index of the statement that each label in the `.code` section refers to
+    label_indexes: HashMap<InternedStr, usize>,
+    /// The stack usage of each function that has been analyzed, by the index of its first statement
+    usage: HashMap<usize, StackUsage>,
+    /// The functions currently being analyzed, outermost first
+    in_progress: Vec<usize>,
+    /// Each cycle of recursive calls that has been found
+    cycles: Vec<Vec<usize>>,
+}
+
+impl<'a> Analyzer<'a> {
+    fn function_usage(&mut self, start: usize) -> StackUsage {
+        if let Some(usage) = self.usage.get(&start) {
+            return usage.clone();
+        }
+
+        if let Some(pos) = self.in_progress.iter().position(|&index| index == start) {
+            let mut cycle = self.in_progress[pos..].to_vec();
+            // Rotate so that the same cycle is always stored the same way, no matter which
+            // function it was found from
+            let min_pos = cycle.iter().enumerate().min_by_key(|&(_, &index)| index).map(|(pos, _)| pos).unwrap_or(0);
+            cycle.rotate_left(min_pos);
+            if !self.cycles.contains(&cycle) {
+                self.cycles.push(cycle);
+            }
+            return StackUsage::Recursive;
+        }
+
+        self.in_progress.push(start);
+        let usage = self.follow_paths(start);
+        self.in_progress.pop();
+
+        self.usage.insert(start, usage.clone());
+        usage
+    }
+
+    /// Follows every path from the given statement until it reaches a `ret`, computing the
+    /// largest stack depth reached along the way
+    fn follow_paths(&mut self, start: usize) -> StackUsage {
+        let mut usage = StackUsage::Bytes(0);
+        // The stack depth when each statement is reached
+        let mut depths = HashMap::new();
+        // Each path is the index of its next statement, the stack depth at that statement, and
+        // the stack depth saved in `$fp` (if known)
+        let mut paths = vec![(start, 0i64, None)];
+
+        while let Some((index, depth, fp_depth)) = paths.pop() {
+            match depths.get(&index) {
+                Some(&prev_depth) if prev_depth == depth => continue,
+                Some(_) => {
+                    usage = usage.max(StackUsage::Unknown("the stack pointer changes in a loop"));
+                    continue;
+                },
+                None => {
+                    depths.insert(index, depth);
+                },
+            }
+
+            // Data and the end of the section are not executed
+            let instr = match self.stmts.get(index).map(|stmt| &stmt.kind) {
+                Some(asm::StmtKind::Instr(instr)) => instr,
+                _ => continue,
+            };
+
+            let next = index + 1;
+            match instr {
+                Instr::Push(_) => {
+                    usage = usage.max(bytes(depth + 8));
+                    paths.push((next, depth + 8, fp_depth));
+                },
+
+                Instr::Pop(pop) => match pop.dest {
+                    Destination::Register(ref reg) if reg.kind == RegisterKind::StackPointer => {
+                        usage = usage.max(StackUsage::Unknown("`$sp` is popped from the stack"));
+                    },
+                    Destination::Register(ref reg) if reg.kind == RegisterKind::FramePointer => {
+                        paths.push((next, depth - 8, None));
+                    },
+                    Destination::Register(_) => paths.push((next, depth - 8, fp_depth)),
+                },
+
+                Instr::Add(asm::Add {dest: Destination::Register(reg), source, ..}) |
+                Instr::Sub(asm::Sub {dest: Destination::Register(reg), source, ..})
+                    if reg.kind == RegisterKind::StackPointer => match source {
+                    Source::Immediate(imm) => {
+                        // Subtracting from `$sp` grows the stack
+                        let delta = imm.value as i64;
+                        let new_depth = if let Instr::Sub(_) = instr { depth + delta } else { depth - delta };
+                        usage = usage.max(bytes(new_depth));
+                        paths.push((next, new_depth, fp_depth));
+                    },
+                    _ => usage = usage.max(StackUsage::Unknown("`$sp` is changed by a value that is not constant")),
+                },
+
+                Instr::Mov(asm::Mov {dest: Destination::Register(reg), source, ..}) => {
+                    let source_kind = match source {
+                        Source::Register(source) => Some(source.kind),
+                        _ => None,
+                    };
+                    match (reg.kind, source_kind) {
+                        (RegisterKind::StackPointer, Some(RegisterKind::FramePointer)) if fp_depth.is_some() => {
+                            let fp_depth = fp_depth.expect("bug: checked that depth is known");
+                            paths.push((next, fp_depth, Some(fp_depth)));
+                        },
+                        (RegisterKind::StackPointer, _) => {
+                            usage = usage.max(StackUsage::Unknown("`$sp` is set to a value that is not known"));
+                        },
+                        (RegisterKind::FramePointer, Some(RegisterKind::StackPointer)) => {
+                            paths.push((next, depth, Some(depth)));
+                        },
+                        (RegisterKind::FramePointer, _) => paths.push((next, depth, None)),
+                        _ => paths.push((next, depth, fp_depth)),
+                    }
+                },
+
+                Instr::Call(call) => match self.label_target(&call.loc) {
+                    Some(target) => {
+                        // The return address is pushed before the function is called
+                        let callee_usage = match self.function_usage(target) {
+                            StackUsage::Bytes(bytes) => StackUsage::Bytes(depth.max(0) as u64 + 8 + bytes),
+                            callee_usage => callee_usage,
+                        };
+                        usage = usage.max(callee_usage);
+                        paths.push((next, depth, fp_depth));
+                    },
+                    None => usage = usage.max(StackUsage::Unknown("indirect call")),
+                },
+
+                Instr::Ret(_) => {},
+
+                Instr::Jmp(jmp) => match self.label_target(&jmp.loc) {
+                    Some(target) => paths.push((target, depth, fp_depth)),
+                    None => usage = usage.max(StackUsage::Unknown("indirect jump")),
+                },
+
+                instr => match instr.jump_target() {
+                    // Conditional jumps may either jump or continue to the next instruction
+                    Some(loc) => match self.label_target(loc) {
+                        Some(target) => {
+                            paths.push((target, depth, fp_depth));
+                            paths.push((next, depth, fp_depth));
+                        },
+                        None => usage = usage.max(StackUsage::Unknown("indirect jump")),
+                    },
+                    None => paths.push((next, depth, fp_depth)),
+                },
+            }
+        }
+
+        usage
+    }
+
+    /// Returns the index of the statement that a jump or call goes to, if it is a label in the
+    /// `.code` section
+    fn label_target(&self, loc: &Location) -> Option<usize> {
+        match loc {
+            Location::Label(label) => self.label_indexes.get(&label.value).copied(),
+            _ => None,
+        }
+    }
+}
+
+/// The usage for a stack depth, ignoring values popped from the caller's stack
+fn bytes(depth: i64) -> StackUsage {
+    StackUsage::Bytes(depth.max(0) as u64)
+}
@@ -10,26 +10,37 @@ use std::fmt;
 
 use crate::ast;
 use crate::parser::Span;
-use crate::diagnostics::Diagnostics;
+use crate::diagnostics::{Diagnostics, ErrorCode};
 
 /// The number of registers supported by the machine
 pub const REGISTERS: u8 = 64;
 
+/// The sections of the program, in the order that they are laid out in the executable
 #[derive(Debug, Clone, PartialEq)]
 pub struct Program {
     /// The statements in the `.code` section
     pub code_section: Option<Section>,
+    /// The statements in the `.rodata` section (read-only data)
+    pub rodata_section: Option<Section>,
     /// The statements in the `.static` section
     pub static_section: Option<Section>,
+    /// The statements in the `.bss` section (only `.zero` and `.uninit`)
+    pub bss_section: Option<Section>,
+    /// The labels exported with `.export`, which can be referenced by other object files
+    pub exports: Vec<Ident>,
+    /// The labels declared with `.extern`, which are defined by other object files
+    pub externs: Vec<Ident>,
 }
 
 impl Program {
     /// Iterates through all the statements in the program, in order
     pub fn iter_all_stmts(&self) -> impl Iterator<Item = &Stmt> {
-        let Program {code_section, static_section} = self;
+        let Program {code_section, rodata_section, static_section, bss_section, exports: _, externs: _} = self;
         code_section.as_ref().map(|section| section.stmts.iter())
             .into_iter()
+            .chain(rodata_section.as_ref().map(|section| section.stmts.iter()))
             .chain(static_section.as_ref().map(|section| section.stmts.iter()))
+            .chain(bss_section.as_ref().map(|section| section.stmts.iter()))
             .flatten()
     }
 }
@@ -204,12 +215,20 @@ impl StaticByteStr {
     }
 }
 
+/// An immediate value that is derived from the address of one or more labels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LabelRef<'a> {
+    Label(&'a Ident),
+    Distance(&'a Distance),
+}
+
 /// Represents an argument for an instruction that may be used as a source operand
 #[derive(Debug, Clone, PartialEq)]
 pub enum Source {
     Register(Register),
     Immediate(Immediate),
     Label(Ident),
+    Distance(Distance),
 }
 
 impl Source {
@@ -230,6 +249,24 @@ impl Source {
             ast::InstrArg::Immediate(imm) => Source::Immediate(imm),
             // After const expansion, the only names left are labels
             ast::InstrArg::Name(label) => Source::Label(label),
+            ast::InstrArg::Distance(dist) => Source::Distance(Distance::validate(dist)),
+            ast::InstrArg::Here(_) => unreachable!("bug: `$here` should have been replaced with a label by now"),
+            ast::InstrArg::Expr(_) => unreachable!("bug: expressions should have been evaluated during const expansion"),
+        }
+    }
+
+    /// Returns true if this argument will be encoded as an immediate value
+    pub fn is_immediate(&self) -> bool {
+        !matches!(self, Source::Register(_))
+    }
+
+    /// Returns the labels that the value of this argument is derived from, if any
+    pub fn label_ref(&self) -> Option<LabelRef<'_>> {
+        match self {
+            Source::Register(_) |
+            Source::Immediate(_) => None,
+            Source::Label(label) => Some(LabelRef::Label(label)),
+            Source::Distance(dist) => Some(LabelRef::Distance(dist)),
         }
     }
 
@@ -273,6 +310,16 @@ impl Destination {
         }
     }
 
+    /// Returns true if this argument will be encoded as an immediate value
+    pub fn is_immediate(&self) -> bool {
+        false
+    }
+
+    /// Returns the labels that the value of this argument is derived from, if any
+    pub fn label_ref(&self) -> Option<LabelRef<'_>> {
+        None
+    }
+
     /// Returns a default value for this type in case of an error (for error recovery)
     pub fn error_default(span: Span) -> Self {
         Destination::Register(Register {
@@ -288,6 +335,7 @@ pub enum Location {
     Register(Register, Option<Offset>),
     Immediate(Immediate),
     Label(Ident),
+    Distance(Distance),
 }
 
 impl Location {
@@ -305,6 +353,24 @@ impl Location {
             ast::InstrArg::Immediate(imm) => Location::Immediate(imm),
             // After const expansion, the only names left are labels
             ast::InstrArg::Name(label) => Location::Label(label),
+            ast::InstrArg::Distance(dist) => Location::Distance(Distance::validate(dist)),
+            ast::InstrArg::Here(_) => unreachable!("bug: `$here` should have been replaced with a label by now"),
+            ast::InstrArg::Expr(_) => unreachable!("bug: expressions should have been evaluated during const expansion"),
+        }
+    }
+
+    /// Returns true if this argument will be encoded as an immediate value
+    pub fn is_immediate(&self) -> bool {
+        !matches!(self, Location::Register(_, _))
+    }
+
+    /// Returns the labels that the value of this argument is derived from, if any
+    pub fn label_ref(&self) -> Option<LabelRef<'_>> {
+        match self {
+            Location::Register(_, _) |
+            Location::Immediate(_) => None,
+            Location::Label(label) => Some(LabelRef::Label(label)),
+            Location::Distance(dist) => Some(LabelRef::Distance(dist)),
         }
     }
 
@@ -317,6 +383,32 @@ impl Location {
     }
 }
 
+/// The distance in bytes between two labels, `end - start`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distance {
+    pub end: Ident,
+    pub start: Ident,
+    pub span: Span,
+}
+
+impl fmt::Display for Distance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} - {}", self.end, self.start)
+    }
+}
+
+impl Distance {
+    pub fn validate(dist: ast::Distance) -> Self {
+        let ast::Distance {end, start, span} = dist;
+        let label = |sym| match sym {
+            ast::Symbol::Label(label) => label,
+            ast::Symbol::Here(_) => unreachable!("bug: `$here` should have been replaced with a label by now"),
+        };
+
+        Distance {end: label(end), start: label(start), span}
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Size {
     pub value: u64,
@@ -354,13 +446,17 @@ impl Register {
                 RegisterKind::FramePointer
             },
 
+            ast::RegisterKind::Named(name) if abi_register(&name).is_some() => {
+                RegisterKind::Numbered(abi_register(&name).unwrap())
+            },
+
             ast::RegisterKind::Numbered(num) if num <= 63 => {
                 RegisterKind::Numbered(num)
             },
 
             _ => {
-                diag.span_error(span, format!("invalid register `${}`", kind))
-                    .span_note(span, "registers must be `$0` to `$63`, `$sp`, or `$fp`").emit();
+                diag.span_error_code(ErrorCode::InvalidRegister, span, format!("invalid register `${}`", kind))
+                    .span_note(span, "registers must be `$0` to `$63`, `$sp`, `$fp`, an ABI name like `$a0`, or an alias declared with `.reg`").emit();
 
                 // Error recovery: return a default register so we can keep producing errors
                 RegisterKind::Numbered(0)
@@ -373,6 +469,39 @@ impl Register {
     }
 }
 
+/// Returns the register number for a conventional ABI register name (without the `$`)
+///
+/// * `v0` - `$0` (return value)
+/// * `a0` to `a7` - `$1` to `$8` (arguments)
+/// * `t0` to `t15` - `$9` to `$24` (temporaries, saved by the caller)
+/// * `s0` to `s15` - `$25` to `$40` (saved by the callee)
+/// * `ra` - `$61` (return address when linking without the stack)
+pub fn abi_register(name: &str) -> Option<u8> {
+    if name == "v0" {
+        return Some(0);
+    } else if name == "ra" {
+        return Some(61);
+    }
+
+    let (first_reg, count) = match name.as_bytes().first() {
+        Some(b'a') => (1, 8),
+        Some(b't') => (9, 16),
+        Some(b's') => (25, 16),
+        _ => return None,
+    };
+
+    let index = &name[1..];
+    // Do not allow leading zeros, e.g. `$a01`
+    if index.len() > 1 && index.starts_with('0') {
+        return None;
+    }
+
+    match index.parse::<u8>() {
+        Ok(index) if index < count => Some(first_reg + index),
+        _ => None,
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RegisterKind {
     /// The `$sp` register
@@ -415,7 +544,7 @@ impl Offset {
         let value = if value >= i16::min_value() as i128 && value <= i16::max_value() as i128 {
             value as i16
         } else {
-            diag.span_error(span, format!("offset value `{}` must be in the range of a 16-bit signed integer, `{}` to `{}`", value, i16::min_value(), i16::max_value())).emit();
+            diag.span_error_code(ErrorCode::OffsetOutOfRange, span, format!("offset value `{}` must be in the range of a 16-bit signed integer, `{}` to `{}`", value, i16::min_value(), i16::max_value())).emit();
 
             // Error recovery: return a default value so we can keep producing errors
             0
@@ -1,12 +1,13 @@
 use crate::ast;
 use crate::parser::Span;
-use crate::diagnostics::Diagnostics;
+use crate::diagnostics::{Diagnostics, ErrorCode, closest_match};
 use crate::label_offsets::LabelOffsets;
 
 use super::{
     Source,
     Destination,
     Location,
+    LabelRef,
     layout::{InstrLayout, LayoutArguments, Opcode},
 };
 
@@ -24,7 +25,7 @@ macro_rules! instr {
         $(#[$m:meta])*
         $v:vis enum $instr_enum:ident / $instr_kind_enum:ident {
             $(
-                #[opcode = $opcode:literal, name = $instr_name:literal $(, cond = $cond:expr)?]
+                #[opcode = $opcode:literal, name = $instr_name:literal $(, aliases = [$($alias:literal),* $(,)?])? $(, cond = $cond:expr)?]
                 $instr_variant:ident(struct $instr_struct:ident {
                     $( $instr_field:ident : $instr_value_ty:ident ),* $(,)?
                 }),
@@ -37,17 +38,24 @@ macro_rules! instr {
         }
 
         impl $instr_enum {
+            /// The name and aliases of every instruction
+            pub const NAMES: &'static [&'static str] = &[$($instr_name, $($($alias,)*)?)*];
+
             pub fn validate(instr: ast::Instr, diag: &Diagnostics) -> Self {
                 #![deny(unreachable_patterns)]
                 match &*instr.name.value {
                     $(
-                        $instr_name $(if $cond(&instr))? => $instr_enum::$instr_variant(
+                        $instr_name $($(| $alias)*)? $(if $cond(&instr))? => $instr_enum::$instr_variant(
                             $instr_struct::validate(instr, diag)
                         ),
                     )*
 
                     _ => {
-                        diag.span_error(instr.name.span, format!("unknown instruction `{}`", instr.name.value)).emit();
+                        let mut writer = diag.span_error_code(ErrorCode::UnknownInstruction, instr.name.span, format!("unknown instruction `{}`", instr.name.value));
+                        if let Some(name) = closest_match(&instr.name.value, Self::NAMES.iter().copied()) {
+                            writer = writer.span_help(instr.name.span, format!("did you mean `{}`?", name));
+                        }
+                        writer.emit();
 
                         // Error Recovery: Default to a `nop` instruction
                         $instr_enum::Nop(Nop {span: instr.name.span})
@@ -68,6 +76,15 @@ macro_rules! instr {
                 8
             }
 
+            /// Returns each argument whose value is derived from labels, along with the index of
+            /// the immediate it will be encoded in (see `layout::Layout::set_immediate`)
+            pub fn label_refs(&self) -> Vec<(usize, LabelRef<'_>)> {
+                use $instr_enum::*;
+                match self {
+                    $($instr_variant(instr) => instr.label_refs()),*
+                }
+            }
+
             pub fn layout(self, diag: &Diagnostics, labels: &LabelOffsets) -> InstrLayout {
                 use $instr_enum::*;
                 match self {
@@ -94,6 +111,23 @@ macro_rules! instr {
                 (kind, opcode - instr_opcode)
             }
 
+            /// Returns the canonical name of this instruction
+            ///
+            /// Tools that print instructions should use this name, even if the instruction was
+            /// written using one of its aliases.
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $($instr_kind_enum::$instr_variant => $instr_name),*
+                }
+            }
+
+            /// Returns the other names that the assembler accepts for this instruction
+            pub fn aliases(&self) -> &'static [&'static str] {
+                match self {
+                    $($instr_kind_enum::$instr_variant => &[$($($alias),*)?]),*
+                }
+            }
+
             /// Returns the size in bytes that this will have in the generated executable
             pub fn size_bytes(&self) -> usize {
                 // All instructions are currently 8 bytes
@@ -129,7 +163,7 @@ macro_rules! instr {
                         let $instr_field = match args.pop() {
                             Some(arg) => $instr_value_ty::validate(arg, diag),
                             None => {
-                                diag.span_error(name.span, format!("expected a {} argument for `{}` instruction (takes {} arguments)", $instr_value_ty::arg_type_name(), name, expected_args)).emit();
+                                diag.span_error_code(ErrorCode::WrongArgumentCount, name.span, format!("expected a {} argument for `{}` instruction (takes {} arguments)", $instr_value_ty::arg_type_name(), name, expected_args)).emit();
 
                                 // Error Recovery: use a default value so we can return *something*
                                 // and keep checking for more errors
@@ -139,7 +173,12 @@ macro_rules! instr {
                     )*
 
                     if provided_args > expected_args {
-                        diag.span_error(name.span, format!("expected {} arguments for `{}` instruction, found {} arguments", expected_args, name, provided_args)).emit();
+                        // The remaining arguments are in reverse order
+                        let extra_span = args[args.len()-1].span().to(args[0].span());
+                        let extra_label = if args.len() == 1 { "extra argument" } else { "extra arguments" };
+                        diag.span_error_code(ErrorCode::WrongArgumentCount, name.span, format!("expected {} arguments for `{}` instruction, found {} arguments", expected_args, name, provided_args))
+                            .span_label(extra_span, extra_label)
+                            .emit();
                     }
 
                     Self {
@@ -148,6 +187,25 @@ macro_rules! instr {
                     }
                 }
 
+                /// Returns each argument whose value is derived from labels, along with the index
+                /// of the immediate it will be encoded in
+                ///
+                /// Immediates are laid out in the same order as the arguments they come from.
+                #[allow(unused_mut, unused_assignments, unused_variables)]
+                pub fn label_refs(&self) -> Vec<(usize, LabelRef<'_>)> {
+                    let mut refs = Vec::new();
+                    let mut imm_index = 0;
+                    $(
+                        if self.$instr_field.is_immediate() {
+                            if let Some(label_ref) = self.$instr_field.label_ref() {
+                                refs.push((imm_index, label_ref));
+                            }
+                            imm_index += 1;
+                        }
+                    )*
+                    refs
+                }
+
                 pub fn layout(self, diag: &Diagnostics, labels: &LabelOffsets) -> InstrLayout {
                     let Self {$($instr_field,)* span: _} = self;
 
@@ -209,7 +267,7 @@ instr! {
         #[opcode = 216, name = "cmp"]
         Cmp(struct Cmp {source1: Source, source2: Source}),
 
-        #[opcode = 228, name = "mov"]
+        #[opcode = 228, name = "mov", aliases = ["cpy"]]
         Mov(struct Mov {dest: Destination, source: Source}),
 
         #[opcode = 240, name = "load1"]
@@ -245,34 +303,30 @@ instr! {
 
         #[opcode = 408, name = "jmp"]
         Jmp(struct Jmp {loc: Location}),
-        #[opcode = 420, name = "je"]
+        #[opcode = 420, name = "je", aliases = ["jz"]]
         Je(struct Je {loc: Location}),
-        #[opcode = 432, name = "jne"]
+        #[opcode = 432, name = "jne", aliases = ["jnz"]]
         Jne(struct Jne {loc: Location}),
-        #[opcode = 444, name = "jg"]
+        #[opcode = 444, name = "jg", aliases = ["jnle"]]
         Jg(struct Jg {loc: Location}),
-        #[opcode = 456, name = "jge"]
+        #[opcode = 456, name = "jge", aliases = ["jnl"]]
         Jge(struct Jge {loc: Location}),
-        #[opcode = 468, name = "ja"]
+        #[opcode = 468, name = "ja", aliases = ["jnbe"]]
         Ja(struct Ja {loc: Location}),
-        #[opcode = 480, name = "jae"]
+        #[opcode = 480, name = "jae", aliases = ["jnb"]]
         Jae(struct Jae {loc: Location}),
-        #[opcode = 492, name = "jl"]
+        #[opcode = 492, name = "jl", aliases = ["jnge"]]
         Jl(struct Jl {loc: Location}),
-        #[opcode = 504, name = "jle"]
+        #[opcode = 504, name = "jle", aliases = ["jng"]]
         Jle(struct Jle {loc: Location}),
-        #[opcode = 516, name = "jb"]
+        #[opcode = 516, name = "jb", aliases = ["jnae"]]
         Jb(struct Jb {loc: Location}),
-        #[opcode = 528, name = "jbe"]
+        #[opcode = 528, name = "jbe", aliases = ["jna"]]
         Jbe(struct Jbe {loc: Location}),
         #[opcode = 540, name = "jo"]
         Jo(struct Jo {loc: Location}),
         #[opcode = 552, name = "jno"]
         Jno(struct Jno {loc: Location}),
-        #[opcode = 564, name = "jz"]
-        Jz(struct Jz {loc: Location}),
-        #[opcode = 576, name = "jnz"]
-        Jnz(struct Jnz {loc: Location}),
         #[opcode = 588, name = "js"]
         Js(struct Js {loc: Location}),
         #[opcode = 600, name = "jns"]
@@ -282,5 +336,37 @@ instr! {
         Call(struct Call {loc: Location}),
         #[opcode = 624, name = "ret"]
         Ret(struct Ret {}),
+
+        // Opcodes starting at 0xf00 are reserved for host calls. Regular instructions are
+        // allocated upwards from zero so they will never conflict with this range.
+        #[opcode = 3840, name = "hcall"]
+        Hcall(struct Hcall {source: Source}),
+    }
+}
+
+impl Instr {
+    /// Returns the location that this instruction transfers control to, if it is a jump or a call
+    pub fn jump_target(&self) -> Option<&Location> {
+        use Instr::*;
+        match self {
+            Jmp(instr) => Some(&instr.loc),
+            Je(instr) => Some(&instr.loc),
+            Jne(instr) => Some(&instr.loc),
+            Jg(instr) => Some(&instr.loc),
+            Jge(instr) => Some(&instr.loc),
+            Ja(instr) => Some(&instr.loc),
+            Jae(instr) => Some(&instr.loc),
+            Jl(instr) => Some(&instr.loc),
+            Jle(instr) => Some(&instr.loc),
+            Jb(instr) => Some(&instr.loc),
+            Jbe(instr) => Some(&instr.loc),
+            Jo(instr) => Some(&instr.loc),
+            Jno(instr) => Some(&instr.loc),
+            Js(instr) => Some(&instr.loc),
+            Jns(instr) => Some(&instr.loc),
+            Call(instr) => Some(&instr.loc),
+
+            _ => None,
+        }
     }
 }
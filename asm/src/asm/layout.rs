@@ -2,7 +2,7 @@ use std::marker::PhantomData;
 
 use serde::{Serialize, Deserialize};
 
-use crate::diagnostics::Diagnostics;
+use crate::diagnostics::{Diagnostics, ErrorCode};
 use crate::label_offsets::LabelOffsets;
 use crate::asm;
 
@@ -53,6 +53,27 @@ macro_rules! layout {
                 }
             }
 
+            /// Sets the value of the immediate at the given index (counting only the immediate
+            /// fields of this layout, in order)
+            ///
+            /// Returns false if there is no immediate at that index or if the value does not fit
+            /// in it.
+            pub fn set_immediate(&mut self, index: usize, value: i128) -> bool {
+                use $layout_enum::*;
+                match self {
+                    $($layout_variant(layout) => layout.set_immediate(index, value),)*
+                }
+            }
+
+            /// Returns the value of the immediate at the given index (counting only the immediate
+            /// fields of this layout, in order), or `None` if there is no immediate at that index
+            pub fn immediate(&self, index: usize) -> Option<i128> {
+                use $layout_enum::*;
+                match self {
+                    $($layout_variant(layout) => layout.immediate(index),)*
+                }
+            }
+
             /// Decodes the given binary representation of an instruction using
             /// the provided opcode offset to determine which layout to use
             ///
@@ -101,6 +122,48 @@ macro_rules! layout {
                     out
                 }
 
+                /// Sets the value of the immediate at the given index (counting only the immediate
+                /// fields of this layout, in order)
+                ///
+                /// Returns false if there is no immediate at that index or if the value does not
+                /// fit in it.
+                #[allow(unused_assignments, unused_mut, unused_variables)]
+                pub fn set_immediate(&mut self, index: usize, value: i128) -> bool {
+                    let $layout_struct($($field_var),*) = self;
+                    let mut imm_index = 0;
+
+                    $(
+                        if <$layout_field_ty $(<$field_ty_param>)? as LayoutField>::is_immediate() {
+                            if imm_index == index {
+                                return $field_var.set_immediate(value);
+                            }
+                            imm_index += 1;
+                        }
+                    )*
+
+                    false
+                }
+
+                /// Returns the value of the immediate at the given index (counting only the
+                /// immediate fields of this layout, in order), or `None` if there is no immediate
+                /// at that index
+                #[allow(unused_assignments, unused_mut, unused_variables)]
+                pub fn immediate(&self, index: usize) -> Option<i128> {
+                    let $layout_struct($($field_var),*) = self;
+                    let mut imm_index = 0;
+
+                    $(
+                        if <$layout_field_ty $(<$field_ty_param>)? as LayoutField>::is_immediate() {
+                            if imm_index == index {
+                                return $field_var.immediate();
+                            }
+                            imm_index += 1;
+                        }
+                    )*
+
+                    None
+                }
+
                 /// Decodes the 64-bit binary representation of this instruction
                 pub fn from_binary(instr: u64) -> Self {
                     // Start right after opcode
@@ -344,6 +407,7 @@ impl Src {
             asm::Source::Register(reg) => Src::Register(reg),
             asm::Source::Immediate(imm) => Src::Immediate(imm),
             asm::Source::Label(label) => Src::Immediate(labels.lookup(&label, diag)),
+            asm::Source::Distance(dist) => Src::Immediate(labels.distance(&dist, diag)),
         }
     }
 }
@@ -375,6 +439,7 @@ impl Loc {
             asm::Location::Register(reg, offset) => Loc::Register(reg, offset),
             asm::Location::Immediate(imm) => Loc::Immediate(imm),
             asm::Location::Label(label) => Loc::Immediate(labels.lookup(&label, diag)),
+            asm::Location::Distance(dist) => Loc::Immediate(labels.distance(&dist, diag)),
         }
     }
 }
@@ -400,6 +465,26 @@ pub trait BitPattern {
     fn read(value: u64, msb_offset: u8) -> Self;
 }
 
+/// A field in the `arguments` section of an instruction layout
+pub trait LayoutField {
+    /// Returns true if this field is an immediate value
+    fn is_immediate() -> bool {
+        false
+    }
+
+    /// Sets the value of this field if it is an immediate
+    ///
+    /// Returns false if this field is not an immediate or if the value does not fit in it.
+    fn set_immediate(&mut self, _value: i128) -> bool {
+        false
+    }
+
+    /// Returns the value of this field if it is an immediate
+    fn immediate(&self) -> Option<i128> {
+        None
+    }
+}
+
 /// The opcode of an instruction, encoded in 12-bits
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Opcode(u16);
@@ -484,6 +569,8 @@ impl BitPattern for Reg {
     }
 }
 
+impl LayoutField for Reg {}
+
 impl From<asm::RegisterKind> for Reg {
     fn from(kind: asm::RegisterKind) -> Self {
         match kind {
@@ -525,12 +612,15 @@ impl<S: ImmSize> BitPattern for Imm<S> {
         let bits = Self::size_bits();
         let value = self.0;
 
+        debug_assert!(value >= -2i128.pow(bits as u32 - 1) && value < 2i128.pow(bits as u32),
+            "bug: immediate value does not fit in {}-bits", bits);
+
         // Get the bits of the value, preserving signedness
         let value_bits = u128::from_le_bytes(value.to_le_bytes());
 
-        // Truncate to max 64 bits (safe because no bits past that should be set)
-        debug_assert!(value_bits < 2u128.pow(bits as u32), "bug: immediate value does not fit in {}-bits", bits);
-        let value = value_bits as u64;
+        // Truncate to the size of the immediate (safe because only sign bits are past that)
+        let mask = !0u64 >> (asm::REGISTERS - bits);
+        let value = value_bits as u64 & mask;
 
         // Shift the value to the position specified by msb_offset
         let value = value << (asm::REGISTERS - msb_offset - bits);
@@ -551,7 +641,7 @@ impl<S: ImmSize> BitPattern for Imm<S> {
 
         // Sign-extend the number: http://graphics.stanford.edu/~seander/bithacks.html#VariableSignExtend
         let mask = 1u64 << (bits - 1);
-        let value = (value ^ mask) - mask;
+        let value = (value ^ mask).wrapping_sub(mask);
         // Reinterpret the value as signed
         let value = i64::from_le_bytes(value.to_le_bytes());
 
@@ -559,10 +649,31 @@ impl<S: ImmSize> BitPattern for Imm<S> {
     }
 }
 
+impl<S: ImmSize> LayoutField for Imm<S> {
+    fn is_immediate() -> bool {
+        true
+    }
+
+    fn set_immediate(&mut self, value: i128) -> bool {
+        let (smin, umax) = S::range();
+        if value >= smin && value <= umax {
+            self.0 = value;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn immediate(&self) -> Option<i128> {
+        Some(self.0)
+    }
+}
+
 pub trait ImmSize {
     fn size_bits() -> u8;
 
-    fn validate_immediate(imm: asm::Immediate, diag: &Diagnostics) -> i128 {
+    /// Returns the minimum and maximum values (inclusive) of immediates with this size
+    fn range() -> (i128, i128) {
         let bits = Self::size_bits() as u32;
 
         // minimum value if immediate is interpreted as signed
@@ -571,11 +682,18 @@ pub trait ImmSize {
         // Note: we always need a sign bit to determine signedness in decoding
         let umax = 2i128.pow(bits-1)-1;
 
+        (smin, umax)
+    }
+
+    fn validate_immediate(imm: asm::Immediate, diag: &Diagnostics) -> i128 {
+        let bits = Self::size_bits() as u32;
+        let (smin, umax) = Self::range();
+
         let asm::Immediate {value, span} = imm;
         if value >= smin && value <= umax {
             value
         } else {
-            diag.span_error(span, format!("immediate value `{}` (`0x{:x}`) for this instruction must fit in a {}-bit signed number", value, value, bits))
+            diag.span_error_code(ErrorCode::ImmediateOutOfRange, span, format!("immediate value `{}` (`0x{:x}`) for this instruction must fit in a {}-bit signed number", value, value, bits))
                 .span_note(span, format!("that means the value must be between `{}` and `{}` (`0x{:x}`)", smin, umax, umax))
                 .emit();
 
@@ -642,6 +760,8 @@ impl BitPattern for Offset {
     }
 }
 
+impl LayoutField for Offset {}
+
 impl Offset {
     pub fn new(offset: asm::Offset, _diag: &Diagnostics) -> Self {
         let asm::Offset {value, span: _} = offset;
@@ -720,4 +840,53 @@ mod tests {
         let expected = 0b_00000010_0100__1111_01__111100_10110000_00__111111_00111111_01111010_11001001_u64;
         assert_eq!(layout.to_binary(base_opcode), expected);
     }
+
+    #[test]
+    fn set_immediate() {
+        let mut layout = Layout::L5(L5(Reg(61), Offset(-3392), Imm(0, PhantomData)));
+        assert!(layout.set_immediate(0, 0x1f3f7ac9));
+        assert_eq!(layout, Layout::L5(L5(Reg(61), Offset(-3392), Imm(0x1f3f7ac9, PhantomData))));
+        // There is only one immediate
+        assert!(!layout.set_immediate(1, 3));
+
+        let mut layout = Layout::L6(L6(Imm(1, PhantomData), Imm(2, PhantomData)));
+        assert!(layout.set_immediate(1, -5));
+        assert_eq!(layout, Layout::L6(L6(Imm(1, PhantomData), Imm(-5, PhantomData))));
+        // Values must fit in the 26-bit immediate
+        assert!(!layout.set_immediate(0, 2i128.pow(25)));
+        assert!(layout.set_immediate(0, 2i128.pow(25) - 1));
+
+        let mut layout = Layout::L1(L1(Reg(1), Reg(2)));
+        assert!(!layout.set_immediate(0, 0));
+    }
+
+    #[test]
+    fn immediate() {
+        let layout = Layout::L6(L6(Imm(1, PhantomData), Imm(-2, PhantomData)));
+        assert_eq!(layout.immediate(0), Some(1));
+        assert_eq!(layout.immediate(1), Some(-2));
+        assert_eq!(layout.immediate(2), None);
+
+        let layout = Layout::L5(L5(Reg(61), Offset(-3392), Imm(7, PhantomData)));
+        assert_eq!(layout.immediate(0), Some(7));
+
+        let layout = Layout::L1(L1(Reg(1), Reg(2)));
+        assert_eq!(layout.immediate(0), None);
+    }
+
+    #[test]
+    fn negative_immediate() {
+        let imm: Imm<S46> = Imm(-5, PhantomData);
+        let mut out = 0;
+        imm.write(12, &mut out);
+        // Only the bits of the immediate should be set
+        assert_eq!(out, 0xffffffffffec0_u64);
+        assert_eq!(Imm::<S46>::read(out, 12), imm);
+
+        let imm: Imm<S46> = Imm(-2i128.pow(45), PhantomData);
+        let mut out = 0;
+        imm.write(12, &mut out);
+        assert_eq!(out, 0x8000000000000_u64);
+        assert_eq!(Imm::<S46>::read(out, 12), imm);
+    }
 }
@@ -0,0 +1,476 @@
+//! Assembles programs into executables without going through the `wolf-asm` binary
+//!
+//! `Assembler` runs each step of the assembler (lexing, parsing, conditional assembly, include
+//! expansion, validation, and layout) one at a time. `wolf-asm` is a command line interface over
+//! these same steps, so the executable produced is exactly the one `wolf-asm` would write for the
+//! same options. `assemble_files` and `assemble_str` run every step at once.
+
+use std::sync::Arc;
+use std::path::{Path, PathBuf};
+
+use parking_lot::RwLock;
+use termcolor::ColorChoice;
+
+use crate::ast;
+use crate::asm;
+use crate::diagnostics::{Diagnostics, Lint, LintLevel, MessageFormat};
+use crate::parser::{self, SourceFiles, FileHandle, Lexer};
+use crate::conditional_assembly::{Defines, expand_conditionals};
+use crate::include_expansion::expand_includes_all;
+use crate::program_merge::merge_programs;
+use crate::validate::validate_program;
+use crate::analysis::check_unreachable_code;
+use crate::optimize::optimize_program;
+use crate::label_offsets::LabelOffsets;
+use crate::executable::{Executable, DEFAULT_BASE_ADDR};
+use crate::object::Object;
+use crate::debug_info::DebugInfo;
+
+/// The maximum number of times we are allowed to recurse when expanding `.include` directives
+const MAX_INCLUDE_DEPTH: usize = 50;
+
+/// The name used in diagnostics for the constants in `AssembleOptions::define_consts`
+pub const DEFINES_PATH: &str = "<command line>";
+
+/// Options that control how a program is assembled (the equivalent of the `wolf-asm` flags with
+/// the same names)
+#[derive(Debug, Clone)]
+pub struct AssembleOptions {
+    /// The directories searched for files included with `.include` (`-I`)
+    pub include_dirs: Vec<PathBuf>,
+    /// The names defined for `.ifdef` and `.ifndef` directives (`-D`)
+    pub defines: Defines,
+    /// The constants declared as if by `.const NAME VALUE` after the rest of the program, so they
+    /// override any declaration in the program (`-D NAME=VALUE`)
+    pub define_consts: Vec<(String, String)>,
+    /// Treat labels and constant names that only differ in case as different names
+    /// (`--case-sensitive`)
+    pub case_sensitive: bool,
+    /// The address that the code section will be loaded at (`--base-addr`)
+    pub base_addr: u64,
+    /// Record the immediates that hold the addresses of labels (`--relocatable`)
+    pub relocatable: bool,
+    /// Embed debug info in the executable (`-g`)
+    pub debug_info: bool,
+    /// Apply the peephole optimizations in `optimize_program` (`-O`)
+    pub optimize: bool,
+    /// The level of each lint that should not be reported at its default level, applied in order
+    /// (`-W`, `-A`, and `--deny`)
+    pub lint_levels: Vec<(Lint, LintLevel)>,
+    /// Report every warning as an error (`--deny warnings`)
+    pub deny_warnings: bool,
+    /// Configures coloring of the diagnostics written to stderr
+    pub color: ColorChoice,
+    /// The format of the diagnostics written to stderr (`--message-format`)
+    pub message_format: MessageFormat,
+    /// Only report errors, not warnings (`--quiet`)
+    pub quiet: bool,
+    /// The number of errors written before any further errors are left out, or `None` to write
+    /// every error (`--error-limit`)
+    pub error_limit: Option<usize>,
+    /// Store diagnostics instead of writing them to stderr, so they can be retrieved with
+    /// `Diagnostics::take_collected`
+    pub collect_diagnostics: bool,
+}
+
+impl Default for AssembleOptions {
+    fn default() -> Self {
+        Self {
+            include_dirs: Vec::new(),
+            defines: Defines::default(),
+            define_consts: Vec::new(),
+            case_sensitive: false,
+            base_addr: DEFAULT_BASE_ADDR,
+            relocatable: false,
+            debug_info: false,
+            optimize: false,
+            lint_levels: Vec::new(),
+            deny_warnings: false,
+            color: ColorChoice::Auto,
+            message_format: MessageFormat::Human,
+            quiet: false,
+            error_limit: None,
+            collect_diagnostics: false,
+        }
+    }
+}
+
+/// Returned by a step of `Assembler` when it (or an earlier step) reported an error
+///
+/// The errors themselves have already been reported through `Assembler::diag`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorsReported;
+
+/// A source file added to an `Assembler`
+#[derive(Debug, Clone)]
+pub struct Input {
+    /// The path used in diagnostics and to resolve relative `.include` paths
+    pub path: PathBuf,
+    pub file: FileHandle,
+}
+
+/// Runs the steps of the assembler one at a time, stopping at the first step that reports an
+/// error
+pub struct Assembler<'a> {
+    options: &'a AssembleOptions,
+    source_files: Arc<RwLock<SourceFiles>>,
+    diag: Diagnostics,
+}
+
+impl<'a> Assembler<'a> {
+    pub fn new(options: &'a AssembleOptions) -> Self {
+        let source_files = Arc::new(RwLock::new(SourceFiles::default()));
+        source_files.write().set_case_sensitive(options.case_sensitive);
+        let mut diag = Diagnostics::stderr(source_files.clone(), options.color);
+        for &(lint, level) in &options.lint_levels {
+            diag.set_lint_level(lint, level);
+        }
+        diag.set_deny_warnings(options.deny_warnings);
+        diag.set_quiet(options.quiet);
+        diag.set_message_format(options.message_format);
+        diag.set_error_limit(options.error_limit);
+        diag.set_collect(options.collect_diagnostics);
+
+        Self {options, source_files, diag}
+    }
+
+    /// The diagnostics reported by every step so far
+    pub fn diag(&self) -> &Diagnostics {
+        &self.diag
+    }
+
+    /// Returns the diagnostics, boxed because the output stream they hold is large
+    pub fn into_diag(self) -> Box<Diagnostics> {
+        Box::new(self.diag)
+    }
+
+    /// Every file read so far, including the files read by `.include` directives
+    pub fn source_files(&self) -> &Arc<RwLock<SourceFiles>> {
+        &self.source_files
+    }
+
+    fn check_errors(&self) -> Result<(), ErrorsReported> {
+        match self.diag.emitted_errors() {
+            0 => Ok(()),
+            _ => Err(ErrorsReported),
+        }
+    }
+
+    /// Reads the source file at the given path
+    pub fn add_file<P: AsRef<Path>>(&self, path: P) -> Result<Input, ErrorsReported> {
+        let path = path.as_ref();
+        // Need this separate statement so we don't hold the write() lock in the error case and end
+        // up with a deadlock
+        let file = self.source_files.write().add_file(path);
+        match file {
+            Ok(file) => Ok(Input {path: path.to_path_buf(), file}),
+            Err(err) => {
+                self.diag.error(format!("Could not read source file `{}`: {}", path.display(), err)).emit();
+                Err(ErrorsReported)
+            },
+        }
+    }
+
+    /// Adds the given source code as if it was read from the given path
+    ///
+    /// The path is used in diagnostics and relative `.include` paths are resolved against its
+    /// directory, but nothing is read from it.
+    pub fn add_source<P: AsRef<Path>>(&self, path: P, source: &[u8]) -> Input {
+        let path = path.as_ref();
+        let file = self.source_files.write().add_source(path, source);
+        Input {path: path.to_path_buf(), file}
+    }
+
+    /// Parses a single input, without expanding any directives
+    pub fn parse(&self, input: &Input) -> Result<ast::Program, ErrorsReported> {
+        let program = {
+            // New scope because we want to drop this lock guard as soon as possible
+            let files = self.source_files.read();
+            parser::parse_tokens(Lexer::new(files.source(input.file), &self.diag), &self.diag)
+        };
+        self.check_errors()?;
+
+        Ok(program)
+    }
+
+    /// Expands the conditional and `.include` directives of each parsed input
+    pub fn expand(&self, programs: Vec<(&Input, ast::Program)>) -> Result<Vec<ast::Program>, ErrorsReported> {
+        let programs: Vec<_> = programs.into_iter()
+            .map(|(input, program)| (input.path.as_path(), expand_conditionals(program, &self.options.defines, &self.diag)))
+            .collect();
+        self.check_errors()?;
+
+        let programs = expand_includes_all(programs, &self.source_files, &self.diag,
+            &self.options.include_dirs, &self.options.defines, MAX_INCLUDE_DEPTH);
+        self.check_errors()?;
+
+        Ok(programs)
+    }
+
+    /// Combines the sections of each expanded program (in order), declares the constants in
+    /// `define_consts`, and validates the result
+    ///
+    /// The validated program is optimized if `optimize` is set.
+    pub fn validate(&self, programs: Vec<ast::Program>) -> Result<asm::Program, ErrorsReported> {
+        // Error recovery: validation can still find errors if the sections could not be merged
+        let mut program = merge_programs(programs, &self.diag);
+
+        if !self.options.define_consts.is_empty() {
+            // The constants are parsed like any other source so errors in the values can be
+            // reported
+            let define_consts: String = self.options.define_consts.iter()
+                .map(|(name, value)| format!(".const {} {}\n", name, value))
+                .collect();
+            let defines_file = self.add_source(DEFINES_PATH, define_consts.as_bytes());
+            let define_consts = self.parse(&defines_file)?;
+
+            // Declared after the rest of the program so that these values are the ones used
+            program.stmts.extend(define_consts.stmts);
+        }
+
+        let program = validate_program(program, &self.diag);
+        self.check_errors()?;
+        check_unreachable_code(&program, &self.diag);
+        // Unreachable code is only an error when the lint is denied
+        self.check_errors()?;
+
+        if self.options.optimize {
+            Ok(optimize_program(program))
+        } else {
+            Ok(program)
+        }
+    }
+
+    /// Computes the address of every label in the validated program, starting at `base_addr`
+    pub fn label_offsets(&self, program: &asm::Program) -> LabelOffsets {
+        LabelOffsets::new(program, self.options.base_addr)
+    }
+
+    /// Lays out the validated program into an executable, with debug info if `debug_info` is set
+    pub fn layout(&self, program: asm::Program, label_offsets: &LabelOffsets) -> Result<Executable, ErrorsReported> {
+        // Source locations must be recorded before the program is consumed by layout
+        let debug_info = if self.options.debug_info {
+            Some(DebugInfo::new(&program, label_offsets, &self.source_files.read()))
+        } else {
+            None
+        };
+        let mut exec = Executable::layout_executable(program, &self.diag, label_offsets, self.options.relocatable);
+        self.check_errors()?;
+        exec.debug_info = debug_info;
+
+        Ok(exec)
+    }
+
+    /// Generates an object file to be linked with `wolf-ld` from the validated program
+    pub fn object(&self, name: String, program: asm::Program) -> Result<Object, ErrorsReported> {
+        let object = Object::new(name, program, &self.diag);
+        self.check_errors()?;
+
+        Ok(object)
+    }
+}
+
+/// An assembled executable and the diagnostics (e.g. warnings) reported while assembling it
+pub struct Assembled {
+    pub exec: Executable,
+    pub diag: Box<Diagnostics>,
+}
+
+/// Assembles the program in the file at the given path
+///
+/// Diagnostics are written to stderr as they are found (unless `collect_diagnostics` is set). If
+/// any errors occur, only the diagnostics are returned. They are boxed because the output stream
+/// they hold is large.
+pub fn assemble_file<P: AsRef<Path>>(path: P, options: &AssembleOptions) -> Result<Assembled, Box<Diagnostics>> {
+    assemble_files(&[path], options)
+}
+
+/// Assembles a single program from the files at the given paths, combining the sections of each
+/// file in order
+///
+/// Diagnostics are reported the same way as in `assemble_file`.
+pub fn assemble_files<P: AsRef<Path>>(paths: &[P], options: &AssembleOptions) -> Result<Assembled, Box<Diagnostics>> {
+    let assembler = Assembler::new(options);
+    let inputs: Result<Vec<_>, _> = paths.iter().map(|path| assembler.add_file(path)).collect();
+    match inputs {
+        Ok(inputs) => assemble(assembler, &inputs),
+        Err(ErrorsReported) => Err(assembler.into_diag()),
+    }
+}
+
+/// Assembles the given source code
+///
+/// The path is used in diagnostics and relative `.include` paths are resolved against its
+/// directory, but nothing is read from it. Diagnostics are reported the same way as in
+/// `assemble_file`.
+pub fn assemble_str<P: AsRef<Path>>(path: P, source: &str, options: &AssembleOptions) -> Result<Assembled, Box<Diagnostics>> {
+    let assembler = Assembler::new(options);
+    let input = assembler.add_source(path, source.as_bytes());
+    assemble(assembler, &[input])
+}
+
+fn assemble(assembler: Assembler, inputs: &[Input]) -> Result<Assembled, Box<Diagnostics>> {
+    let result = (|| {
+        let programs = inputs.iter()
+            .map(|input| Ok((input, assembler.parse(input)?)))
+            .collect::<Result<_, _>>()?;
+        let programs = assembler.expand(programs)?;
+        let program = assembler.validate(programs)?;
+        let label_offsets = assembler.label_offsets(&program);
+        assembler.layout(program, &label_offsets)
+    })();
+
+    match result {
+        Ok(exec) => Ok(Assembled {exec, diag: assembler.into_diag()}),
+        Err(ErrorsReported) => Err(assembler.into_diag()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::diagnostics::{Level, DiagnosticCode, ErrorCode};
+
+    fn expect_errors(result: Result<Assembled, Box<Diagnostics>>) -> Box<Diagnostics> {
+        match result {
+            Ok(_) => panic!("expected an error"),
+            Err(diag) => diag,
+        }
+    }
+
+    #[test]
+    fn assemble_str_matches_file() {
+        let source = "section .code\nmain:\n  mov $1, 5\n  add $1, 1\n  ret\n";
+        let exec = assemble_str("test.wa", source, &AssembleOptions::default())
+            .unwrap_or_else(|diag| panic!("{} errors", diag.emitted_errors())).exec;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("test.wa");
+        fs::write(&path, source).unwrap();
+        let file_exec = assemble_file(&path, &AssembleOptions::default())
+            .unwrap_or_else(|diag| panic!("{} errors", diag.emitted_errors())).exec;
+
+        assert_eq!(exec.to_bytes().unwrap(), file_exec.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn no_trailing_newline() {
+        let with_newline = assemble_str("test.wa", "section .code\nmain:\n  mov $1, 5\n", &AssembleOptions::default())
+            .unwrap_or_else(|diag| panic!("{} errors", diag.emitted_errors())).exec;
+        let without_newline = assemble_str("test.wa", "section .code\nmain:\n  mov $1, 5", &AssembleOptions::default())
+            .unwrap_or_else(|diag| panic!("{} errors", diag.emitted_errors())).exec;
+        assert_eq!(with_newline.to_flat_binary(), without_newline.to_flat_binary());
+    }
+
+    #[test]
+    fn expressions() {
+        let source = ".const SIZE 16\n.const TOTAL (SIZE + 1) * 2\n.const LAST SIZE-1\n.const DIFF TOTAL - SIZE\nsection .code\n  mov $1, TOTAL\n  mov $2, LAST * 2 - -1\n  mov $3, DIFF % 5\n  mov $4, (7 - 10) / 2\n";
+        let exec = assemble_str("test.wa", source, &AssembleOptions::default())
+            .unwrap_or_else(|diag| panic!("{} errors", diag.emitted_errors())).exec;
+        let expected = assemble_str("test.wa", "section .code\n  mov $1, 34\n  mov $2, 31\n  mov $3, 3\n  mov $4, -1\n", &AssembleOptions::default())
+            .unwrap_or_else(|diag| panic!("{} errors", diag.emitted_errors())).exec;
+        assert_eq!(exec.to_flat_binary(), expected.to_flat_binary());
+    }
+
+    #[test]
+    fn includes_and_defines() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::write(temp_dir.path().join("consts.wa"), ".const VALUE 5\n").unwrap();
+        let path = temp_dir.path().join("main.wa");
+        let source = ".include \"consts.wa\"\nsection .code\n.ifdef FAST\n  mov $1, VALUE\n.endif\n  ret\n";
+
+        let mut options = AssembleOptions::default();
+        let plain = assemble_str(&path, source, &options)
+            .unwrap_or_else(|diag| panic!("{} errors", diag.emitted_errors())).exec;
+        options.defines.define("FAST");
+        let fast = assemble_str(&path, source, &options)
+            .unwrap_or_else(|diag| panic!("{} errors", diag.emitted_errors())).exec;
+
+        assert_eq!(plain.to_flat_binary().len() + 8, fast.to_flat_binary().len());
+    }
+
+    #[test]
+    fn define_consts_and_multiple_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let first = temp_dir.path().join("first.wa");
+        let second = temp_dir.path().join("second.wa");
+        fs::write(&first, ".const VALUE 1\nsection .code\n  mov $1, VALUE\n").unwrap();
+        fs::write(&second, "section .code\n  mov $2, VALUE\n").unwrap();
+
+        let options = AssembleOptions {
+            define_consts: vec![("VALUE".to_string(), "7".to_string())],
+            ..AssembleOptions::default()
+        };
+        let exec = assemble_files(&[&first, &second], &options)
+            .unwrap_or_else(|diag| panic!("{} errors", diag.emitted_errors())).exec;
+        let expected = assemble_str("test.wa", "section .code\n  mov $1, 7\n  mov $2, 7\n", &AssembleOptions::default())
+            .unwrap_or_else(|diag| panic!("{} errors", diag.emitted_errors())).exec;
+        assert_eq!(exec.to_flat_binary(), expected.to_flat_binary());
+    }
+
+    #[test]
+    fn errors() {
+        let options = AssembleOptions {
+            collect_diagnostics: true,
+            ..AssembleOptions::default()
+        };
+        let diag = expect_errors(assemble_str("test.wa", "section .code\n  mov $1\n", &options));
+        assert_eq!(diag.emitted_errors(), 1);
+
+        let diag = expect_errors(assemble_file("does-not-exist.wa", &options));
+        assert_eq!(diag.emitted_errors(), 1);
+    }
+
+    #[test]
+    fn collected_diagnostics() {
+        let mut options = AssembleOptions {
+            collect_diagnostics: true,
+            ..AssembleOptions::default()
+        };
+        let source = "section .code\nmain:\n  ret\n  mov $1\n";
+        let diag = expect_errors(assemble_str("test.wa", source, &options));
+
+        let diagnostics = diag.take_collected();
+        assert_eq!(diagnostics.len(), 1);
+        let error = &diagnostics[0];
+        assert_eq!(error.title.level, Level::Error);
+        assert_eq!(error.code, Some(DiagnosticCode::Error(ErrorCode::WrongArgumentCount)));
+        let files = diag.source_files().read();
+        let pos = files.pos(error.fragments[0].span);
+        assert_eq!((pos.start_line, pos.start_offset), (4, 3));
+        drop(files);
+
+        // Lints are collected with the lint that produced them, even if there are no errors
+        options.lint_levels.push((Lint::UnusedLabel, LintLevel::Warn));
+        let Assembled {diag, ..} = assemble_str("test.wa", "section .code\nmain:\n  ret\n", &options)
+            .unwrap_or_else(|diag| panic!("{} errors", diag.emitted_errors()));
+        let diagnostics = diag.take_collected();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].title.level, Level::Warning);
+        assert_eq!(diagnostics[0].code, Some(DiagnosticCode::Lint(Lint::UnusedLabel)));
+        assert!(diag.take_collected().is_empty());
+    }
+
+    #[test]
+    fn extra_arguments_label() {
+        let options = AssembleOptions {
+            collect_diagnostics: true,
+            ..AssembleOptions::default()
+        };
+        let diag = expect_errors(assemble_str("test.wa", "section .code
+  add $1, $2, $3, 4
+", &options));
+
+        let diagnostics = diag.take_collected();
+        assert_eq!(diagnostics.len(), 1);
+        let labels = &diagnostics[0].labels;
+        assert_eq!(labels.len(), 1);
+        assert_eq!(labels[0].label, "extra arguments");
+        let files = diag.source_files().read();
+        let pos = files.pos(labels[0].span);
+        assert_eq!((pos.start_column, pos.end_column), (15, 19));
+    }
+}
@@ -4,10 +4,10 @@
 
 use std::fmt;
 use std::sync::Arc;
-use std::borrow::Borrow;
 use std::hash::{Hash, Hasher};
 
 use crate::parser::Span;
+use crate::interner::InternedStr;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Program {
@@ -21,7 +21,12 @@ pub enum Stmt {
     Section(Section),
 
     Include(Include),
+    Conditional(Conditional),
+    Export(Export),
+    Extern(Extern),
+    Lint(LintDirective),
     Const(Const),
+    Reg(Reg),
 
     StaticData(StaticData),
 
@@ -50,6 +55,10 @@ pub enum SectionKind {
     Static(Span),
     /// The `.code` section
     Code(Span),
+    /// The `.rodata` section
+    RoData(Span),
+    /// The `.bss` section
+    Bss(Span),
 }
 
 impl fmt::Display for SectionKind {
@@ -58,6 +67,8 @@ impl fmt::Display for SectionKind {
         match self {
             Static(_) => write!(f, ".static"),
             Code(_) => write!(f, ".code"),
+            RoData(_) => write!(f, ".rodata"),
+            Bss(_) => write!(f, ".bss"),
         }
     }
 }
@@ -67,15 +78,119 @@ impl SectionKind {
         use SectionKind::*;
         match self {
             Static(span) |
-            Code(span) => span,
+            Code(span) |
+            RoData(span) |
+            Bss(span) => span,
+        }
+    }
+
+    /// The position of this section in the executable
+    ///
+    /// Sections must be declared in this order: `.code`, `.rodata`, `.static`, `.bss`
+    pub fn order(self) -> usize {
+        use SectionKind::*;
+        match self {
+            Code(_) => 0,
+            RoData(_) => 1,
+            Static(_) => 2,
+            Bss(_) => 3,
         }
     }
 }
 
-/// An `.include` directive
+/// An `.include` or `.include_once` directive
 #[derive(Debug, Clone, PartialEq)]
 pub struct Include {
     pub path: Bytes,
+    /// True for `.include_once`, which skips files that have already been included
+    pub once: bool,
+    /// The span of the entire directive
+    pub span: Span,
+}
+
+/// A conditional assembly directive
+#[derive(Debug, Clone, PartialEq)]
+pub enum Conditional {
+    /// An `.ifdef NAME` or `.ifndef NAME` directive
+    IfDef(IfDef),
+    /// An `.else` directive
+    Else(Span),
+    /// An `.endif` directive
+    EndIf(Span),
+}
+
+impl Conditional {
+    pub fn span(&self) -> Span {
+        use Conditional::*;
+        match self {
+            IfDef(ifdef) => ifdef.span,
+            Else(span) |
+            EndIf(span) => *span,
+        }
+    }
+}
+
+/// An `.ifdef` or `.ifndef` directive
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfDef {
+    pub name: Ident,
+    /// True for `.ifndef`, which assembles its block if `name` is *not* defined
+    pub negated: bool,
+    /// The span of the entire directive
+    pub span: Span,
+}
+
+/// An `.export` directive (or its alias `.global`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Export {
+    /// The name of the label to export
+    pub name: Ident,
+    /// The span of the entire directive
+    pub span: Span,
+}
+
+/// An `.extern` directive
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extern {
+    /// The name of the label that is defined in another object file
+    pub name: Ident,
+    /// The span of the entire directive
+    pub span: Span,
+}
+
+/// A directive that configures the warnings reported for a region of a file
+#[derive(Debug, Clone, PartialEq)]
+pub enum LintDirective {
+    /// An `.allow lint-name` directive
+    Allow(Allow),
+    /// An `.endallow` directive
+    EndAllow(Span),
+}
+
+impl LintDirective {
+    pub fn span(&self) -> Span {
+        use LintDirective::*;
+        match self {
+            Allow(allow) => allow.span,
+            EndAllow(span) => *span,
+        }
+    }
+}
+
+/// An `.allow` directive
+#[derive(Debug, Clone, PartialEq)]
+pub struct Allow {
+    /// The name of the lint to allow (e.g. `constant-redefined`)
+    pub lint: Ident,
+    /// The span of the entire directive
+    pub span: Span,
+}
+
+/// A `.reg` directive
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reg {
+    pub name: Ident,
+    pub reg: Register,
     /// The span of the entire directive
     pub span: Span,
 }
@@ -84,17 +199,48 @@ pub struct Include {
 #[derive(Debug, Clone, PartialEq)]
 pub struct Const {
     pub name: Ident,
-    pub value: Immediate,
+    pub value: ConstValue,
     /// The span of the entire directive
     pub span: Span,
 }
 
+/// The value of a `.const` directive
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstValue {
+    Immediate(Immediate),
+    Distance(Distance),
+    Expr(Expr),
+}
+
+impl fmt::Display for ConstValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ConstValue::*;
+        match self {
+            Immediate(imm) => write!(f, "{}", imm),
+            Distance(dist) => write!(f, "{}", dist),
+            Expr(expr) => write!(f, "{}", expr),
+        }
+    }
+}
+
+impl ConstValue {
+    pub fn span(&self) -> Span {
+        use ConstValue::*;
+        match self {
+            Immediate(imm) => imm.span,
+            Distance(dist) => dist.span,
+            Expr(expr) => expr.span(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum StaticData {
     StaticBytes(StaticBytes),
     StaticZero(StaticZero),
     StaticUninit(StaticUninit),
     StaticByteStr(StaticByteStr),
+    StaticFloat(StaticFloat),
 }
 
 /// The `.b1`, `.b2`, `.b4`, or `.b8` static data directive
@@ -107,6 +253,16 @@ pub struct StaticBytes {
     pub span: Span,
 }
 
+/// The `.f4` or `.f8` static data directive
+#[derive(Debug, Clone, PartialEq)]
+pub struct StaticFloat {
+    /// Either 4 or 8
+    pub size: u8,
+    pub value: Float,
+    /// The span of the entire directive
+    pub span: Span,
+}
+
 /// The `.zero` directive
 #[derive(Debug, Clone, PartialEq)]
 pub struct StaticZero {
@@ -155,6 +311,10 @@ pub enum InstrArg {
     Register(Register),
     Immediate(Immediate),
     Name(Ident),
+    /// The `$here` symbol
+    Here(Span),
+    Distance(Distance),
+    Expr(Expr),
 }
 
 impl fmt::Display for InstrArg {
@@ -164,6 +324,9 @@ impl fmt::Display for InstrArg {
             Register(reg) => write!(f, "{}", reg),
             Immediate(imm) => write!(f, "{}", imm),
             Name(name) => write!(f, "{}", name),
+            Here(_) => write!(f, "$here"),
+            Distance(dist) => write!(f, "{}", dist),
+            Expr(expr) => write!(f, "{}", expr),
         }
     }
 }
@@ -175,6 +338,9 @@ impl InstrArg {
             Register(reg) => reg.span,
             Immediate(imm) => imm.span,
             Name(name) => name.span,
+            &Here(span) => span,
+            Distance(dist) => dist.span,
+            Expr(expr) => expr.span(),
         }
     }
 
@@ -184,6 +350,127 @@ impl InstrArg {
             _ => false,
         }
     }
+
+    pub fn is_named_register(&self) -> bool {
+        matches!(self, InstrArg::Register(Register {kind: RegisterKind::Named(_), ..}))
+    }
+}
+
+/// A symbol that evaluates to an address once the program has been laid out
+#[derive(Debug, Clone, PartialEq)]
+pub enum Symbol {
+    Label(Ident),
+    /// The `$here` symbol, which evaluates to the address of the statement it is used in
+    Here(Span),
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Symbol::*;
+        match self {
+            Label(label) => write!(f, "{}", label),
+            Here(_) => write!(f, "$here"),
+        }
+    }
+}
+
+impl Symbol {
+    pub fn span(&self) -> Span {
+        use Symbol::*;
+        match self {
+            Label(label) => label.span,
+            &Here(span) => span,
+        }
+    }
+}
+
+/// The distance in bytes between two addresses, written `end - start`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distance {
+    pub end: Symbol,
+    pub start: Symbol,
+    pub span: Span,
+}
+
+impl fmt::Display for Distance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} - {}", self.end, self.start)
+    }
+}
+
+/// An arithmetic expression on integers and constant names, e.g. `(SIZE + 1) * 8`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Integer(Integer),
+    /// The name of a constant
+    Name(Ident),
+    Binary(Box<BinaryExpr>),
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Expr::*;
+        match self {
+            Integer(value) => write!(f, "{}", value),
+            Name(name) => write!(f, "{}", name),
+            Binary(expr) => write!(f, "{}", expr),
+        }
+    }
+}
+
+impl Expr {
+    pub fn span(&self) -> Span {
+        use Expr::*;
+        match self {
+            Integer(value) => value.span,
+            Name(name) => name.span,
+            Binary(expr) => expr.span,
+        }
+    }
+}
+
+/// An expression with an operator between two operands, e.g. `a + b`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinaryExpr {
+    pub op: BinOp,
+    pub lhs: Expr,
+    pub rhs: Expr,
+    pub span: Span,
+}
+
+impl fmt::Display for BinaryExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Nested operations are always parenthesized so the order of operations is clear
+        let operand = |f: &mut fmt::Formatter, expr: &Expr| match expr {
+            Expr::Binary(_) => write!(f, "({})", expr),
+            _ => write!(f, "{}", expr),
+        };
+        operand(f, &self.lhs)?;
+        write!(f, " {} ", self.op)?;
+        operand(f, &self.rhs)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use BinOp::*;
+        match self {
+            Add => write!(f, "+"),
+            Sub => write!(f, "-"),
+            Mul => write!(f, "*"),
+            Div => write!(f, "/"),
+            Rem => write!(f, "%"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -201,8 +488,8 @@ impl fmt::Display for Register {
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum RegisterKind {
-    /// A named register like `$sp` or `$fp`
-    Named(Arc<str>),
+    /// A named register like `$sp`, `$fp`, `$a0`, or an alias declared with `.reg`
+    Named(InternedStr),
     /// A numbered register like `$0`, `$1`, `$63`
     ///
     /// At this stage, this number may be any valid `u8`
@@ -235,6 +522,20 @@ impl fmt::Display for Integer {
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct Float {
+    pub value: f64,
+    /// The integer literal this value was converted from, if any
+    pub integer: Option<i128>,
+    pub span: Span,
+}
+
+impl fmt::Display for Float {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.value)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Bytes {
     pub value: Arc<[u8]>,
@@ -250,13 +551,27 @@ impl fmt::Display for Bytes {
 
 #[derive(Debug, Clone, Eq)]
 pub struct Ident {
-    pub value: Arc<str>,
+    pub value: InternedStr,
     pub span: Span,
+    /// True if this is a label generated for a use of `$here` rather than a name written in the
+    /// program
+    pub generated: bool,
+}
+
+impl Ident {
+    /// Creates an identifier for a name written in the program
+    pub fn new(value: InternedStr, span: Span) -> Self {
+        Self {value, span, generated: false}
+    }
 }
 
 impl fmt::Display for Ident {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.value)
+        if self.generated {
+            write!(f, "$here")
+        } else {
+            write!(f, "{}", self.value)
+        }
     }
 }
 
@@ -271,9 +586,3 @@ impl Hash for Ident {
         self.value.hash(state);
     }
 }
-
-impl Borrow<str> for Ident {
-    fn borrow(&self) -> &str {
-        &self.value
-    }
-}
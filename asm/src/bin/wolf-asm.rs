@@ -6,68 +6,167 @@
 
 use std::env;
 use std::process;
-use std::sync::Arc;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::fs::File;
+use std::fs::{self, File};
+use std::ffi::OsStr;
+use std::io::{self, Read, Write, BufWriter};
 
-use parking_lot::RwLock;
-use termcolor::ColorChoice;
 use structopt::StructOpt;
 
 use wolf_asm::{
-    diagnostics::Diagnostics,
+    cli::{ColorArg, parse_addr},
+    assemble::{Assembler, AssembleOptions, DEFINES_PATH},
+    diagnostics::{Diagnostics, ErrorCode, Lint, LintLevel, MessageFormat},
     parser::{self, SourceFiles},
-    include_expansion::expand_includes,
-    validate::validate_program,
-    label_offsets::LabelOffsets,
-    executable::Executable,
+    conditional_assembly::Defines,
+    analysis::StackReport,
+    executable::{Executable, DEFAULT_BASE_ADDR},
+    listing::Listing,
+    hex_formats,
+    elf,
 };
 
-/// The maximum number of times we are allowed to recurse when expanding `.include` directives
-const MAX_INCLUDE_DEPTH: usize = 50;
+/// The name used in diagnostics for source read from stdin (when the input path is `-`)
+const STDIN_PATH: &str = "<stdin>";
 
-/// A command line argument that configures the coloring of the output
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub struct ColorArg(pub ColorChoice);
+/// A command line argument that defines a name for conditional assembly: `NAME` or `NAME=VALUE`
+///
+/// If a value is provided, a constant is also declared as if by `.const NAME VALUE`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefineArg {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl FromStr for DefineArg {
+    type Err = String;
+
+    fn from_str(src: &str) -> Result<DefineArg, String> {
+        let (name, value) = match src.split_once('=') {
+            Some((name, value)) => (name, Some(value.trim())),
+            None => (src, None),
+        };
+
+        let mut chars = name.chars();
+        let is_ident = chars.next().map(|ch| ch.is_ascii_alphabetic() || ch == '_').unwrap_or(false)
+            && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_');
+        if !is_ident {
+            return Err(format!("`{}` is not a valid name", name));
+        }
+
+        // The value is parsed as part of a `.const` directive, so it must fit on a single line
+        if let Some(value) = value {
+            if value.is_empty() || value.contains(&['\n', '\r', '#'][..]) {
+                return Err(format!("`{}` is not a valid value for `{}`", value, name));
+            }
+        }
+
+        Ok(DefineArg {name: name.to_string(), value: value.map(str::to_string)})
+    }
+}
+
+/// A command line argument that names a lint, or every lint with `warnings`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintArg {
+    Warnings,
+    Lint(Lint),
+}
+
+impl FromStr for LintArg {
+    type Err = String;
 
-impl Default for ColorArg {
-    fn default() -> Self {
-        ColorArg(ColorChoice::Auto)
+    fn from_str(src: &str) -> Result<LintArg, String> {
+        match src {
+            "warnings" => Ok(LintArg::Warnings),
+            _ => src.parse().map(LintArg::Lint),
+        }
     }
 }
 
-impl ColorArg {
+/// The format of the file written by the assembler
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// An executable that can be run by `wolf-vm`
+    Exec,
+    /// The raw bytes of the program as they are loaded into memory
+    Bin,
+    /// The bytes of `Bin` in the Intel HEX format
+    Ihex,
+    /// The bytes of `Bin` in the Motorola S-record format
+    Srec,
+    /// An ELF file with a section for each section of the program and a symbol for each label
+    Elf,
+}
+
+impl OutputFormat {
     /// Allowed values the argument
-    pub const VARIANTS: &'static [&'static str] = &["auto", "always", "ansi", "never"];
+    pub const VARIANTS: &'static [&'static str] = &["exec", "bin", "ihex", "srec", "elf"];
+
+    /// The extension used for the default output path, or `None` for no extension
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            OutputFormat::Exec => None,
+            OutputFormat::Bin => Some("bin"),
+            OutputFormat::Ihex => Some("hex"),
+            OutputFormat::Srec => Some("srec"),
+            OutputFormat::Elf => Some("elf"),
+        }
+    }
 }
 
-impl FromStr for ColorArg {
+impl FromStr for OutputFormat {
     type Err = &'static str;
 
-    fn from_str(src: &str) -> Result<ColorArg, &'static str> {
+    fn from_str(src: &str) -> Result<OutputFormat, &'static str> {
         match src {
-            _ if src.eq_ignore_ascii_case("auto") => Ok(ColorArg(ColorChoice::Auto)),
-            _ if src.eq_ignore_ascii_case("always") => Ok(ColorArg(ColorChoice::Always)),
-            _ if src.eq_ignore_ascii_case("ansi") => Ok(ColorArg(ColorChoice::AlwaysAnsi)),
-            _ if src.eq_ignore_ascii_case("never") => Ok(ColorArg(ColorChoice::Never)),
-            _ => Err("valid values: auto, always, ansi, never"),
+            _ if src.eq_ignore_ascii_case("exec") => Ok(OutputFormat::Exec),
+            _ if src.eq_ignore_ascii_case("bin") => Ok(OutputFormat::Bin),
+            _ if src.eq_ignore_ascii_case("ihex") => Ok(OutputFormat::Ihex),
+            _ if src.eq_ignore_ascii_case("srec") => Ok(OutputFormat::Srec),
+            _ if src.eq_ignore_ascii_case("elf") => Ok(OutputFormat::Elf),
+            _ => Err("valid values: exec, bin, ihex, srec, elf"),
         }
     }
 }
 
-impl Into<ColorChoice> for ColorArg {
-    fn into(self) -> ColorChoice {
-        self.0
+/// An intermediate representation of the program that can be printed instead of assembling it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmitKind {
+    /// The tokens produced by the lexer, one per line
+    Tokens,
+    /// The syntax tree produced by the parser, before includes and conditionals are expanded
+    Ast,
+    /// The validated program, after includes and conditionals are expanded
+    Asm,
+}
+
+impl EmitKind {
+    /// Allowed values the argument
+    pub const VARIANTS: &'static [&'static str] = &["tokens", "ast", "asm"];
+}
+
+impl FromStr for EmitKind {
+    type Err = &'static str;
+
+    fn from_str(src: &str) -> Result<EmitKind, &'static str> {
+        match src {
+            _ if src.eq_ignore_ascii_case("tokens") => Ok(EmitKind::Tokens),
+            _ if src.eq_ignore_ascii_case("ast") => Ok(EmitKind::Ast),
+            _ if src.eq_ignore_ascii_case("asm") => Ok(EmitKind::Asm),
+            _ => Err("valid values: tokens, ast, asm"),
+        }
     }
 }
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "wolf-asm", about)]
 struct AssemblerOptions {
-    /// The assembly language file (`.wa`) to generate an executable for
-    #[structopt(name = "input", parse(from_os_str))]
-    program_path: PathBuf,
+    /// The assembly language files (`.wa`) to generate an executable for, or
+    /// `-` to read from stdin (requires `-o` unless nothing is written). The
+    /// sections of each file are combined in the order the files are given
+    #[structopt(name = "input", parse(from_os_str), required_unless = "code", min_values = 1)]
+    program_paths: Vec<PathBuf>,
     /// Write output to <file>
     #[structopt(short = "o", name = "file")]
     output_path: Option<PathBuf>,
@@ -75,6 +174,110 @@ struct AssemblerOptions {
     #[structopt(long = "color", parse(try_from_str), default_value = "auto",
         possible_values = ColorArg::VARIANTS, case_insensitive = true)]
     pub color: ColorArg,
+    /// Do not report warnings or the number of warnings emitted, only errors
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+    /// The format of errors and warnings written to stderr. `json` writes
+    /// one JSON object per line for each diagnostic, for editors and CI tools
+    #[structopt(long = "message-format", name = "message-format", value_name = "format", parse(try_from_str), default_value = "human",
+        possible_values = MessageFormat::VARIANTS, case_insensitive = true)]
+    message_format: MessageFormat,
+    /// Stop reporting errors after the first <limit> errors, or `0` to
+    /// report every error
+    #[structopt(long = "error-limit", name = "limit", default_value = "20")]
+    error_limit: usize,
+    /// Generate an object file to be linked with `wolf-ld` instead of an
+    /// executable (the default output path uses the extension `o`)
+    #[structopt(short = "c")]
+    object: bool,
+    /// The address that the code section will be loaded at [default: 0x1000]
+    #[structopt(long = "base-addr", name = "addr", parse(try_from_str = parse_addr), conflicts_with = "object")]
+    base_addr: Option<u64>,
+    /// Record the immediates that hold the addresses of labels so the
+    /// executable can be loaded at a different address (see `wolf-vm --load-addr`)
+    #[structopt(long = "relocatable", conflicts_with = "object")]
+    relocatable: bool,
+    /// Add <dir> to the directories searched for files included with
+    /// `.include` (searched in order, before the directory of the including file)
+    #[structopt(short = "I", name = "dir", parse(from_os_str), number_of_values = 1)]
+    include_dirs: Vec<PathBuf>,
+    /// Define <name> for `.ifdef` and `.ifndef` directives (written as
+    /// `NAME` or `NAME=VALUE`). `NAME=VALUE` also declares a constant as if by
+    /// `.const NAME VALUE`, overriding any declaration in the program
+    #[structopt(short = "D", name = "name", parse(try_from_str), number_of_values = 1)]
+    defines: Vec<DefineArg>,
+    /// Treat labels and constant names that only differ in case (e.g. `Loop`
+    /// and `loop`) as different names. Instruction names, registers, and
+    /// directives are always case-insensitive
+    #[structopt(long = "case-sensitive")]
+    case_sensitive: bool,
+    /// Instead of writing an executable, check that assembling the input
+    /// produces exactly the same bytes as the existing executable <exec>
+    #[structopt(long = "verify", name = "exec", parse(from_os_str), conflicts_with_all = &["file", "object"])]
+    verify_path: Option<PathBuf>,
+    /// Write a listing to <listing> showing the address and encoding of
+    /// every statement next to its source line
+    #[structopt(long = "listing", name = "listing", parse(from_os_str), conflicts_with = "object")]
+    listing_path: Option<PathBuf>,
+    /// Write a map file to <map> listing the address and section of every
+    /// label, sorted by address
+    #[structopt(long = "map", name = "map", parse(from_os_str), conflicts_with = "object")]
+    map_path: Option<PathBuf>,
+    /// Write a report to <report> with the maximum number of bytes that each
+    /// function (the entry point and every label that is called) may push
+    /// onto the stack, and every cycle of recursive calls
+    #[structopt(long = "stack-usage", name = "report", parse(from_os_str))]
+    stack_usage_path: Option<PathBuf>,
+    /// Write a Makefile rule to <deps> that lists the input files and every
+    /// file included with `.include` as dependencies of the output file, so
+    /// `make` or `ninja` can rebuild the output when any of them change
+    #[structopt(short = "M", long = "deps", name = "deps", parse(from_os_str))]
+    deps_path: Option<PathBuf>,
+    /// Embed debug info (the address of every label and the source line of
+    /// every statement) in the executable, which the VM uses to report errors
+    #[structopt(short = "g", long = "debug-info", conflicts_with = "object")]
+    debug_info: bool,
+    /// The format of the output file [default: exec]. `bin` writes the bytes
+    /// of every section exactly as they are loaded into memory (starting at
+    /// the base address), with `.uninit` bytes set to zero. `ihex` and `srec`
+    /// write the same bytes as Intel HEX or Motorola S-records. `elf` writes an
+    /// ELF file with symbols that can be inspected with tools like `readelf`
+    #[structopt(long = "format", parse(try_from_str),
+        possible_values = OutputFormat::VARIANTS, case_insensitive = true,
+        conflicts_with_all = &["object", "exec"])]
+    format: Option<OutputFormat>,
+    /// Print the tokens, the parsed syntax tree, or the validated program to
+    /// stdout and exit without writing any output files
+    #[structopt(long = "emit", name = "stage", parse(try_from_str),
+        possible_values = EmitKind::VARIANTS, case_insensitive = true,
+        conflicts_with_all = &["file", "object", "exec", "format", "listing", "map", "report", "deps"])]
+    emit: Option<EmitKind>,
+    /// Report the given warning (e.g. `unused-label`), or every warning with
+    /// `-W warnings`. Warnings: constant-redefined, register-redefined,
+    /// unused-label (not reported by default), truncation, unreachable-code
+    /// (not reported by default), case-collision (not reported by default)
+    #[structopt(short = "W", name = "warn", value_name = "warning", parse(try_from_str), number_of_values = 1)]
+    warn_lints: Vec<LintArg>,
+    /// Do not report the given warning, or any warnings with `-A warnings`.
+    /// Naming a specific warning takes precedence over `warnings`
+    #[structopt(short = "A", name = "allow", value_name = "warning", parse(try_from_str), number_of_values = 1)]
+    allow_lints: Vec<LintArg>,
+    /// Report the given warning as an error, or every warning that would be
+    /// reported with `--deny warnings`
+    #[structopt(long = "deny", name = "deny", value_name = "warning", parse(try_from_str), number_of_values = 1)]
+    deny_lints: Vec<LintArg>,
+    /// Assemble the program and report any errors without writing any
+    /// output files
+    #[structopt(long = "check", conflicts_with_all = &["file", "exec", "format", "listing", "map", "report", "stage"])]
+    check: bool,
+    /// Optimize the program with simple rewrites that do not change its
+    /// behaviour (e.g. removing `mov $1, $1` or data that is never used)
+    #[structopt(short = "O")]
+    optimize: bool,
+    /// Print an explanation of the error with the given code (e.g. `E0001`,
+    /// shown in brackets after the error message) and exit
+    #[structopt(long = "explain", name = "code", parse(try_from_str))]
+    explain: Option<ErrorCode>,
 }
 
 macro_rules! quit {
@@ -88,66 +291,366 @@ macro_rules! quit {
 
 macro_rules! check_errors {
     ($diag:expr) => {
-        let diag = $diag;
-        match diag.emitted_errors() {
-            0 => {},
-            1 => quit!(diag, "aborting due to 1 previous error"),
-            errors => quit!(diag, "aborting due to {} previous errors", errors),
+        if $diag.emitted_errors() > 0 {
+            abort($diag);
         }
     };
 }
 
+/// Reports the number of errors (and warnings) that caused the assembler to stop and exits
+fn abort(diag: &Diagnostics) -> ! {
+    match diag.emitted_errors() {
+        1 => quit!(diag, "aborting due to 1 previous error{}", warnings_summary(diag, "; ")),
+        errors => quit!(diag, "aborting due to {} previous errors{}", errors, warnings_summary(diag, "; ")),
+    }
+}
+
+/// Returns the number of warnings emitted (e.g. "2 warnings emitted") after the given separator,
+/// or an empty string if there were no warnings
+fn warnings_summary(diag: &Diagnostics, separator: &str) -> String {
+    match diag.emitted_warnings() {
+        0 => String::new(),
+        1 => format!("{}1 warning emitted", separator),
+        warnings => format!("{}{} warnings emitted", separator, warnings),
+    }
+}
+
+/// Reports the number of warnings emitted by a successful run, if there were any
+fn report_warnings(diag: &Diagnostics) {
+    let summary = warnings_summary(diag, "");
+    if !summary.is_empty() {
+        diag.warning(summary).emit();
+    }
+}
+
 fn main() {
-    let AssemblerOptions {program_path, output_path, color} = AssemblerOptions::from_args();
+    let AssemblerOptions {program_paths, output_path, color, quiet, message_format, error_limit, object, base_addr, relocatable, include_dirs, defines, case_sensitive, verify_path, listing_path, map_path, stack_usage_path, deps_path, debug_info, format, emit, check, optimize, explain, warn_lints, allow_lints, deny_lints} = AssemblerOptions::from_args();
+
+    if let Some(code) = explain {
+        print!("{}", code.explanation());
+        return;
+    }
 
-    let source_files = Arc::new(RwLock::new(SourceFiles::default()));
-    let diag = Diagnostics::new(source_files.clone(), color.into());
+    // `warnings` is applied first so that naming a specific lint takes precedence
+    let mut lint_levels = Vec::new();
+    for &(lints, level) in &[(&allow_lints, LintLevel::Allow), (&warn_lints, LintLevel::Warn)] {
+        if lints.contains(&LintArg::Warnings) {
+            lint_levels.extend(Lint::ALL.iter().map(|&lint| (lint, level)));
+        }
+    }
+    for &(lints, level) in &[(&allow_lints, LintLevel::Allow), (&warn_lints, LintLevel::Warn), (&deny_lints, LintLevel::Deny)] {
+        for lint in lints.iter() {
+            if let &LintArg::Lint(lint) = lint {
+                lint_levels.push((lint, level));
+            }
+        }
+    }
 
-    // Check that the path and stem are valid
-    let program_stem = match (program_path.file_stem(), program_path.extension()) {
-        (Some(stem), Some(ext)) if !stem.is_empty() && ext == "wa" => stem,
-        _ => quit!(&diag, "Invalid input path. Must use extension `wa`"),
+    let options = AssembleOptions {
+        include_dirs,
+        defines: {
+            let mut table = Defines::default();
+            for define in &defines {
+                table.define(&define.name);
+            }
+            table
+        },
+        define_consts: defines.into_iter()
+            .filter_map(|DefineArg {name, value}| value.map(|value| (name, value)))
+            .collect(),
+        case_sensitive,
+        base_addr: base_addr.unwrap_or(DEFAULT_BASE_ADDR),
+        relocatable,
+        debug_info,
+        optimize,
+        lint_levels,
+        deny_warnings: deny_lints.contains(&LintArg::Warnings),
+        color: color.into(),
+        message_format,
+        quiet,
+        error_limit: if error_limit == 0 { None } else { Some(error_limit) },
+        collect_diagnostics: false,
     };
+    let assembler = Assembler::new(&options);
+    let diag = assembler.diag();
+    let source_files = assembler.source_files();
+
+    let format = format.unwrap_or(OutputFormat::Exec);
+    // Other formats have no way to store relocations
+    if relocatable && format != OutputFormat::Exec {
+        quit!(diag, "`--relocatable` can only be used with `--format exec`");
+    }
 
-    // Default output path is the input path without its extension
-    let output_path = output_path.as_ref().map(|p| p.as_path())
-        .unwrap_or_else(|| Path::new(program_stem));
+    // Check that the paths and stems are valid
+    let program_stems: Vec<_> = program_paths.iter().map(|program_path| {
+        match (program_path.file_stem(), program_path.extension()) {
+            // There is no path to base the default output path on
+            _ if program_path == Path::new("-") => None,
+            (Some(stem), Some(ext)) if !stem.is_empty() && ext == "wa" => Some(stem),
+            _ => quit!(diag, "Invalid input path. Must use extension `wa`"),
+        }
+    }).collect();
+    // The output is named after the first input file
+    let program_stem = match program_stems[0] {
+        Some(stem) => stem,
+        None => {
+            if output_path.is_none() && emit.is_none() && verify_path.is_none() && !check {
+                quit!(diag, "An output path must be provided with `-o` when reading from stdin");
+            }
+            OsStr::new("stdin")
+        },
+    };
+
+    // Default output path is the input path without its extension (or with `.o` for objects)
+    let output_path = match output_path {
+        Some(output_path) => output_path,
+        None if object => Path::new(program_stem).with_extension("o"),
+        None => match format.extension() {
+            Some(ext) => Path::new(program_stem).with_extension(ext),
+            None => PathBuf::from(program_stem),
+        },
+    };
+    // The dependency rule names the output path as it was given so that it matches the target
+    // in the Makefile
+    let deps_target = output_path.clone();
     // Append the current directory to the output path if necessary
     let output_path = if output_path.is_absolute() {
         output_path.to_path_buf()
     } else {
         let current_dir = env::current_dir()
-            .unwrap_or_else(|err| quit!(&diag, "Could not access current directory: {}", err));
+            .unwrap_or_else(|err| quit!(diag, "Could not access current directory: {}", err));
         current_dir.join(output_path)
     };
 
-    // Need this separate statement so we don't hold the write() lock in the
-    // error case and end up with a deadlock
-    let root_file = source_files.write().add_file(&program_path);
-    let root_file = root_file.unwrap_or_else(|err| {
-        quit!(&diag, "Could not read source file `{}`: {}", program_path.display(), err)
-    });
-    let program = {
-        // New scope because we want to drop this lock guard as soon as possible
-        let files = source_files.read();
-        let tokens = parser::collect_tokens(files.source(root_file), &diag);
-        check_errors!(&diag);
-        parser::parse_program(&tokens, &diag)
-    };
-    check_errors!(&diag);
+    let mut inputs = Vec::new();
+    for program_path in &program_paths {
+        // Includes in source from stdin are resolved relative to the current directory
+        let input = if program_path == Path::new("-") {
+            let mut source = Vec::new();
+            io::stdin().read_to_end(&mut source)
+                .unwrap_or_else(|err| quit!(diag, "Could not read source from stdin: {}", err));
+            assembler.add_source(STDIN_PATH, &source)
+        } else {
+            assembler.add_file(program_path).unwrap_or_else(|_| process::exit(1))
+        };
+        inputs.push(input);
+    }
 
-    let expanded_program = expand_includes(&program_path, program, &source_files, &diag, MAX_INCLUDE_DEPTH);
-    check_errors!(&diag);
+    let mut programs = Vec::new();
+    for input in &inputs {
+        // Each file is printed separately when there is more than one
+        if emit.is_some() && inputs.len() > 1 && emit != Some(EmitKind::Asm) {
+            println!("# {}", input.path.display());
+        }
+
+        if emit == Some(EmitKind::Tokens) {
+            let files = source_files.read();
+            let tokens = parser::collect_tokens(files.source(input.file), diag);
+            check_errors!(diag);
+
+            for token in &tokens {
+                let pos = files.pos(token.span);
+                match &token.value {
+                    Some(value) => println!("{}:{}: {:?} {:?}", pos.start_line, pos.start_column, token.kind, value),
+                    None => println!("{}:{}: {:?}", pos.start_line, pos.start_column, token.kind),
+                }
+            }
+            continue;
+        }
+
+        let program = assembler.parse(input).unwrap_or_else(|_| abort(diag));
+        if emit == Some(EmitKind::Ast) {
+            println!("{:#?}", program);
+            continue;
+        }
+        programs.push((input, program));
+    }
+    if emit == Some(EmitKind::Tokens) || emit == Some(EmitKind::Ast) {
+        report_warnings(diag);
+        return;
+    }
+
+    let input_paths: Vec<_> = inputs.iter().map(|input| input.path.clone()).collect();
+    // Diagnostics and object files refer to the program using the path of the first file
+    let program_path = &input_paths[0];
+    let expanded_programs = assembler.expand(programs).unwrap_or_else(|_| abort(diag));
+
+    if let Some(deps_path) = &deps_path {
+        let deps_file = File::create(deps_path)
+            .unwrap_or_else(|err| quit!(diag, "Could not open dependency file path `{}`: {}", deps_path.display(), err));
+        write_deps(BufWriter::new(deps_file), &deps_target, &input_paths, &source_files.read())
+            .unwrap_or_else(|err| quit!(diag, "Unable to write dependency file `{}`: {}", deps_path.display(), err));
+    }
+
+    let validated_program = assembler.validate(expanded_programs).unwrap_or_else(|_| abort(diag));
+
+    if let Some(stack_usage_path) = &stack_usage_path {
+        let report_file = File::create(stack_usage_path)
+            .unwrap_or_else(|err| quit!(diag, "Could not open stack usage report path `{}`: {}", stack_usage_path.display(), err));
+        StackReport::new(&validated_program).write(BufWriter::new(report_file))
+            .unwrap_or_else(|err| quit!(diag, "Unable to write stack usage report `{}`: {}", stack_usage_path.display(), err));
+    }
+
+    if emit == Some(EmitKind::Asm) {
+        println!("{:#?}", validated_program);
+        report_warnings(diag);
+        return;
+    }
+
+    if object {
+        let object = assembler.object(program_path.display().to_string(), validated_program)
+            .unwrap_or_else(|_| abort(diag));
+        if check {
+            report_warnings(diag);
+            return;
+        }
+
+        let output_file = File::create(&output_path)
+            .unwrap_or_else(|err| quit!(diag, "Could not open output path `{}`: {}", output_path.display(), err));
+        bincode::serialize_into(output_file, &object)
+            .unwrap_or_else(|err| quit!(diag, "Unable to write object file `{}`: {}", output_path.display(), err));
+        report_warnings(diag);
+        return;
+    }
+
+    let label_offsets = assembler.label_offsets(&validated_program);
+    // Source locations must be recorded before the program is consumed by layout
+    let listing = listing_path.as_ref().map(|_| Listing::new(&validated_program, &label_offsets));
+    let exec = assembler.layout(validated_program, &label_offsets).unwrap_or_else(|_| abort(diag));
+
+    if check {
+        report_warnings(diag);
+        return;
+    }
+
+    if let (Some(listing_path), Some(listing)) = (&listing_path, listing) {
+        let listing_file = File::create(listing_path)
+            .unwrap_or_else(|err| quit!(diag, "Could not open listing path `{}`: {}", listing_path.display(), err));
+        listing.write(BufWriter::new(listing_file), &exec, &source_files.read())
+            .unwrap_or_else(|err| quit!(diag, "Unable to write listing `{}`: {}", listing_path.display(), err));
+    }
+
+    if let Some(map_path) = &map_path {
+        let map_file = File::create(map_path)
+            .unwrap_or_else(|err| quit!(diag, "Could not open map path `{}`: {}", map_path.display(), err));
+        label_offsets.write_map(BufWriter::new(map_file))
+            .unwrap_or_else(|err| quit!(diag, "Unable to write map file `{}`: {}", map_path.display(), err));
+    }
+
+    if let Some(verify_path) = verify_path {
+        verify_executable(diag, &exec, program_path, &verify_path);
+        report_warnings(diag);
+        return;
+    }
+
+    match format {
+        OutputFormat::Exec => {
+            let output_file = File::create(&output_path)
+                .unwrap_or_else(|err| quit!(diag, "Could not open output path `{}`: {}", output_path.display(), err));
+            exec.write_to(output_file)
+                .unwrap_or_else(|err| quit!(diag, "Unable to write executable `{}`: {}", output_path.display(), err));
+        },
+        OutputFormat::Bin => {
+            fs::write(&output_path, exec.to_flat_binary())
+                .unwrap_or_else(|err| quit!(diag, "Unable to write binary `{}`: {}", output_path.display(), err));
+        },
+        OutputFormat::Ihex | OutputFormat::Srec => {
+            let output_file = File::create(&output_path)
+                .unwrap_or_else(|err| quit!(diag, "Could not open output path `{}`: {}", output_path.display(), err));
+            let output = BufWriter::new(output_file);
+            let bytes = exec.to_flat_binary();
+            let result = if format == OutputFormat::Ihex {
+                hex_formats::write_ihex(output, exec.base_addr, &bytes)
+            } else {
+                hex_formats::write_srec(output, exec.base_addr, &bytes, &program_stem.to_string_lossy())
+            };
+            result.unwrap_or_else(|err| quit!(diag, "Unable to write `{}`: {}", output_path.display(), err));
+        },
+        OutputFormat::Elf => {
+            let output_file = File::create(&output_path)
+                .unwrap_or_else(|err| quit!(diag, "Could not open output path `{}`: {}", output_path.display(), err));
+            elf::write_elf(BufWriter::new(output_file), &exec, &label_offsets)
+                .unwrap_or_else(|err| quit!(diag, "Unable to write ELF file `{}`: {}", output_path.display(), err));
+        },
+    }
+
+    report_warnings(diag);
+}
+
+/// Writes a Makefile rule with the input files and every file they include as dependencies of
+/// `target`
+///
+/// Each included file also gets an empty rule of its own so that `make` does not fail if that
+/// file is later deleted or renamed.
+fn write_deps<W: Write>(mut out: W, target: &Path, input_paths: &[PathBuf], files: &SourceFiles) -> io::Result<()> {
+    // Source from stdin and the command line has no file to depend on
+    let is_file = |path: &Path| path != Path::new(STDIN_PATH) && path != Path::new(DEFINES_PATH);
+
+    let inputs: Vec<&Path> = input_paths.iter().map(PathBuf::as_path).filter(|&path| is_file(path)).collect();
+    let mut included: Vec<&Path> = Vec::new();
+    for path in files.paths() {
+        // The same file may be included more than once
+        if is_file(path) && !inputs.contains(&path) && !included.contains(&path) {
+            included.push(path);
+        }
+    }
+
+    write!(out, "{}:", escape_make_path(target))?;
+    for dep in inputs.iter().chain(&included) {
+        write!(out, " \\\n  {}", escape_make_path(dep))?;
+    }
+    writeln!(out)?;
 
-    let validated_program = validate_program(expanded_program, &diag);
-    check_errors!(&diag);
+    for dep in &included {
+        writeln!(out, "\n{}:", escape_make_path(dep))?;
+    }
+
+    out.flush()
+}
+
+/// Escapes the characters in a path that have a special meaning in a Makefile rule
+fn escape_make_path(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    let mut escaped = String::with_capacity(path.len());
+    for ch in path.chars() {
+        match ch {
+            ' ' | '#' => escaped.push('\\'),
+            '$' => escaped.push('$'),
+            _ => {},
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
 
-    let label_offsets = LabelOffsets::new(&validated_program);
-    let exec = Executable::layout_executable(validated_program, &diag, &label_offsets);
-    check_errors!(&diag);
+/// Checks that the given executable has exactly the same bytes as the existing executable at
+/// `verify_path`, reporting the first difference and exiting with an error if it does not
+fn verify_executable(diag: &Diagnostics, exec: &Executable, program_path: &Path, verify_path: &Path) {
+    let expected = exec.to_bytes()
+        .unwrap_or_else(|err| quit!(diag, "Unable to serialize executable: {}", err));
+    let actual = fs::read(verify_path)
+        .unwrap_or_else(|err| quit!(diag, "Could not read executable `{}`: {}", verify_path.display(), err));
+
+    let offset = match expected.iter().zip(&actual).position(|(a, b)| a != b) {
+        Some(offset) => offset,
+        None if expected.len() == actual.len() => return,
+        // One executable is a prefix of the other
+        None => expected.len().min(actual.len()),
+    };
+
+    diag.error(format!("executable `{}` does not match the executable assembled from `{}`",
+        verify_path.display(), program_path.display())).emit();
+    diag.note(format!("first difference at byte offset {} ({:#x})", offset, offset)).emit();
+
+    let describe = |exec: &Executable| match exec.part_at_offset(offset as u64) {
+        Some(part) => part.to_string(),
+        None => "end of executable".to_string(),
+    };
+    diag.note(format!("assembled: {}", describe(exec))).emit();
+    match Executable::read_from(&*actual) {
+        Ok(actual_exec) => diag.note(format!("existing: {}", describe(&actual_exec))).emit(),
+        Err(err) => diag.note(format!("existing: unable to decode executable: {}", err)).emit(),
+    }
 
-    let output_file = File::create(&output_path)
-        .unwrap_or_else(|err| quit!(&diag, "Could not open output path `{}`: {}", output_path.display(), err));
-    bincode::serialize_into(output_file, &exec)
-        .unwrap_or_else(|err| quit!(&diag, "Unable to write executable `{}`: {}", output_path.display(), err));
+    process::exit(1);
 }
@@ -0,0 +1,97 @@
+//! wolf-ld - The Wolf Assembly Language linker
+//!
+//! Links object files generated by `wolf-asm -c` into an executable for the wolf virtual machine
+
+#![deny(unused_must_use)]
+
+use std::process;
+use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::fs::File;
+
+use parking_lot::RwLock;
+use structopt::StructOpt;
+
+use wolf_asm::{
+    cli::{ColorArg, parse_addr},
+    diagnostics::Diagnostics,
+    parser::SourceFiles,
+    executable::DEFAULT_BASE_ADDR,
+    object::Object,
+    link::link,
+};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "wolf-ld", about)]
+struct LinkerOptions {
+    /// The object files to link, generated by `wolf-asm -c` (the object
+    /// containing the entry point must be first)
+    #[structopt(name = "input", parse(from_os_str), required = true)]
+    object_paths: Vec<PathBuf>,
+    /// Write output to <file> [default: the first input path without its extension]
+    #[structopt(short = "o", name = "file")]
+    output_path: Option<PathBuf>,
+    /// Configure coloring of output
+    #[structopt(long = "color", parse(try_from_str), default_value = "auto",
+        possible_values = ColorArg::VARIANTS, case_insensitive = true)]
+    pub color: ColorArg,
+    /// The address that the code section will be loaded at [default: 0x1000]
+    #[structopt(long = "base-addr", name = "addr", parse(try_from_str = parse_addr))]
+    base_addr: Option<u64>,
+    /// Record the immediates that hold the addresses of labels so the
+    /// executable can be loaded at a different address (see `wolf-vm --load-addr`)
+    #[structopt(long = "relocatable")]
+    relocatable: bool,
+}
+
+macro_rules! quit {
+    ($diag:expr, $($args:tt)*) => {
+        {
+            $diag.error(format!($($args)*)).emit();
+            process::exit(1);
+        }
+    };
+}
+
+macro_rules! check_errors {
+    ($diag:expr) => {
+        let diag = $diag;
+        match diag.emitted_errors() {
+            0 => {},
+            1 => quit!(diag, "aborting due to 1 previous error"),
+            errors => quit!(diag, "aborting due to {} previous errors", errors),
+        }
+    };
+}
+
+fn main() {
+    let LinkerOptions {object_paths, output_path, color, base_addr, relocatable} = LinkerOptions::from_args();
+
+    // There are no source files, but diagnostics still need somewhere to look them up
+    let source_files = Arc::new(RwLock::new(SourceFiles::default()));
+    let diag = Diagnostics::stderr(source_files, color.into());
+
+    let objects: Vec<Object> = object_paths.iter().map(|path| {
+        let file = File::open(path)
+            .unwrap_or_else(|err| quit!(&diag, "Could not read object file `{}`: {}", path.display(), err));
+        bincode::deserialize_from(file)
+            .unwrap_or_else(|err| quit!(&diag, "Unable to read object file `{}`: {}", path.display(), err))
+    }).collect();
+
+    let exec = link(&objects, base_addr.unwrap_or(DEFAULT_BASE_ADDR), relocatable, &diag);
+    check_errors!(&diag);
+
+    // Default output path is the first input path without its extension
+    let output_path = output_path.unwrap_or_else(|| {
+        let first_path = &object_paths[0];
+        match first_path.file_stem() {
+            Some(stem) => first_path.with_file_name(stem),
+            None => Path::new("a.out").to_path_buf(),
+        }
+    });
+
+    let output_file = File::create(&output_path)
+        .unwrap_or_else(|err| quit!(&diag, "Could not open output path `{}`: {}", output_path.display(), err));
+    exec.write_to(output_file)
+        .unwrap_or_else(|err| quit!(&diag, "Unable to write executable `{}`: {}", output_path.display(), err));
+}
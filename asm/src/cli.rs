@@ -0,0 +1,118 @@
+//! Command line argument types shared by the `wolf-asm`, `wolf-ld`, and `wolf-vm` binaries
+
+use std::ops::Range;
+use std::str::FromStr;
+use std::num::ParseIntError;
+
+use termcolor::ColorChoice;
+
+/// A command line argument that configures the coloring of the output
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ColorArg(pub ColorChoice);
+
+impl Default for ColorArg {
+    fn default() -> Self {
+        ColorArg(ColorChoice::Auto)
+    }
+}
+
+impl ColorArg {
+    /// Allowed values the argument
+    pub const VARIANTS: &'static [&'static str] = &["auto", "always", "ansi", "never"];
+}
+
+impl FromStr for ColorArg {
+    type Err = &'static str;
+
+    fn from_str(src: &str) -> Result<ColorArg, &'static str> {
+        match src {
+            _ if src.eq_ignore_ascii_case("auto") => Ok(ColorArg(ColorChoice::Auto)),
+            _ if src.eq_ignore_ascii_case("always") => Ok(ColorArg(ColorChoice::Always)),
+            _ if src.eq_ignore_ascii_case("ansi") => Ok(ColorArg(ColorChoice::AlwaysAnsi)),
+            _ if src.eq_ignore_ascii_case("never") => Ok(ColorArg(ColorChoice::Never)),
+            _ => Err("valid values: auto, always, ansi, never"),
+        }
+    }
+}
+
+/// Parses an address written in decimal or in hexadecimal with a `0x` prefix
+pub fn parse_addr(src: &str) -> Result<u64, ParseIntError> {
+    let src = src.replace('_', "");
+    if src.starts_with("0x") || src.starts_with("0X") {
+        u64::from_str_radix(&src[2..], 16)
+    } else {
+        src.parse()
+    }
+}
+
+/// Parses a number of bytes with an optional `K`, `M`, or `G` suffix (e.g. `16M`), where each
+/// suffix multiplies the number by a power of 1024
+///
+/// The suffix is case-insensitive and may be followed by `B` (e.g. `64KB`).
+pub fn parse_size(src: &str) -> Result<u64, String> {
+    let lower = src.to_ascii_lowercase();
+    let suffixes = [("kb", 1 << 10), ("k", 1 << 10), ("mb", 1 << 20), ("m", 1 << 20), ("gb", 1 << 30), ("g", 1 << 30)];
+    let (digits, multiplier) = suffixes.iter()
+        .find(|(suffix, _)| lower.ends_with(suffix))
+        .map(|&(suffix, multiplier)| (&src[..src.len()-suffix.len()], multiplier))
+        .unwrap_or((src, 1));
+
+    let value = parse_addr(digits)
+        .map_err(|err| format!("invalid size `{}`: {} (expected a number of bytes like `4096` or `16M`)", src, err))?;
+    value.checked_mul(multiplier)
+        .ok_or_else(|| format!("invalid size `{}`: the size is too large", src))
+}
+
+/// Parses a range of addresses written as `start..end`, where `end` is not included in the range
+/// (e.g. `0x1000..0x1100`)
+pub fn parse_addr_range(src: &str) -> Result<Range<u64>, String> {
+    let invalid = |message: &str| format!("invalid address range `{}`: {} (expected a range like `0x1000..0x1100`)", src, message);
+
+    let (start, end) = src.split_once("..")
+        .ok_or_else(|| invalid("missing `..`"))?;
+    let start = parse_addr(start).map_err(|err| invalid(&err.to_string()))?;
+    let end = parse_addr(end).map_err(|err| invalid(&err.to_string()))?;
+    if start > end {
+        return Err(invalid("the start of the range is after the end"));
+    }
+
+    Ok(start..end)
+}
+
+impl Into<ColorChoice> for ColorArg {
+    fn into(self) -> ColorChoice {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("0x1000"), Ok(4096));
+        assert_eq!(parse_size("64K"), Ok(64 * 1024));
+        assert_eq!(parse_size("16m"), Ok(16 * 1024 * 1024));
+        assert_eq!(parse_size("2GB"), Ok(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("1_024kb"), Ok(1024 * 1024));
+        assert_eq!(parse_size("0x1B"), Ok(27));
+
+        assert!(parse_size("").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("16T").is_err());
+        assert!(parse_size("0xffffffffffffffffG").is_err());
+    }
+
+    #[test]
+    fn addr_ranges() {
+        assert_eq!(parse_addr_range("0x1000..0x1100"), Ok(0x1000..0x1100));
+        assert_eq!(parse_addr_range("16..32"), Ok(16..32));
+        assert_eq!(parse_addr_range("0x10..0x10"), Ok(0x10..0x10));
+
+        assert!(parse_addr_range("0x1000").is_err());
+        assert!(parse_addr_range("0x1000..").is_err());
+        assert!(parse_addr_range("32..16").is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashSet;
+
+use crate::ast;
+use crate::parser::Span;
+use crate::diagnostics::Diagnostics;
+
+/// The names that are considered defined by `.ifdef` and `.ifndef` directives
+///
+/// Names are stored in lowercase and compared case-insensitively, even when other identifiers are
+/// case-sensitive.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Defines {
+    names: HashSet<String>,
+}
+
+impl Defines {
+    /// Defines the given name
+    pub fn define(&mut self, name: &str) {
+        self.names.insert(name.to_ascii_lowercase());
+    }
+
+    /// Returns true if the given name has been defined
+    pub fn is_defined(&self, name: &ast::Ident) -> bool {
+        self.names.contains(&name.value.to_ascii_lowercase())
+    }
+}
+
+/// An `.ifdef` or `.ifndef` block that has not been closed by `.endif` yet
+struct Block {
+    /// The span of the `.ifdef` or `.ifndef` directive
+    span: Span,
+    /// The span of the `.else` directive, if one has been found
+    else_span: Option<Span>,
+    /// True if the statements currently being read in this block should be kept
+    active: bool,
+    /// True if the statements surrounding this block are being kept
+    parent_active: bool,
+}
+
+/// Removes all conditional assembly directives from a program, keeping only the statements in
+/// blocks whose conditions are true
+///
+/// Every `.ifdef` and `.ifndef` must be closed by an `.endif` in the same file, so this should be
+/// run on each file before its `.include` directives are expanded. That way, files that are only
+/// included in a disabled block never need to be read.
+///
+/// Errors are produced for unbalanced directives. Blocks that are never closed are treated as if
+/// they end at the end of the program.
+pub fn expand_conditionals(prog: ast::Program, defines: &Defines, diag: &Diagnostics) -> ast::Program {
+    let ast::Program {stmts} = prog;
+
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut expanded_stmts = Vec::with_capacity(stmts.len());
+    for stmt in stmts {
+        let active = blocks.last().map(|block| block.active).unwrap_or(true);
+
+        let cond = match stmt {
+            ast::Stmt::Conditional(cond) => cond,
+            stmt => {
+                if active {
+                    expanded_stmts.push(stmt);
+                }
+                continue;
+            },
+        };
+
+        match cond {
+            ast::Conditional::IfDef(ast::IfDef {name, negated, span}) => {
+                let is_defined = defines.is_defined(&name);
+                blocks.push(Block {
+                    span,
+                    else_span: None,
+                    active: active && is_defined != negated,
+                    parent_active: active,
+                });
+            },
+
+            ast::Conditional::Else(span) => match blocks.last_mut() {
+                Some(block) => match block.else_span {
+                    Some(else_span) => {
+                        diag.span_error(span, "found more than one `.else` for the same `.ifdef`")
+                            .span_note(else_span, "first `.else` found here")
+                            .emit();
+                    },
+
+                    None => {
+                        block.else_span = Some(span);
+                        block.active = block.parent_active && !block.active;
+                    },
+                },
+
+                None => diag.span_error(span, "found `.else` without a matching `.ifdef` or `.ifndef`").emit(),
+            },
+
+            ast::Conditional::EndIf(span) => if blocks.pop().is_none() {
+                diag.span_error(span, "found `.endif` without a matching `.ifdef` or `.ifndef`").emit();
+            },
+        }
+    }
+
+    for block in blocks {
+        diag.span_error(block.span, "unterminated conditional block, expected a matching `.endif`").emit();
+    }
+
+    ast::Program {stmts: expanded_stmts}
+}
@@ -1,41 +1,21 @@
-use std::borrow::Borrow;
-use std::hash::{Hash, Hasher};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use crate::ast;
-use crate::diagnostics::Diagnostics;
-
-#[derive(Debug, Clone)]
-struct ConstEntry(ast::Const);
-
-impl PartialEq for ConstEntry {
-    fn eq(&self, other: &Self) -> bool {
-        self.0.name.eq(&other.0.name)
-    }
-}
-
-impl Eq for ConstEntry {}
-
-impl Hash for ConstEntry {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.0.name.hash(state);
-    }
-}
-
-impl Borrow<ast::Ident> for ConstEntry {
-    fn borrow(&self) -> &ast::Ident {
-        &self.0.name
-    }
-}
+use crate::expr;
+use crate::diagnostics::{Diagnostics, Lint, closest_match};
+use crate::interner::InternedStr;
 
 #[derive(Debug)]
 pub struct ConstTable {
-    const_values: HashSet<ConstEntry>,
+    const_values: HashMap<InternedStr, ast::Const>,
+    /// The name of every label in the program, used to explain why a label can't be used in an
+    /// expression
+    labels: HashSet<InternedStr>,
 }
 
 impl ConstTable {
     pub fn new(prog: &ast::Program, diag: &Diagnostics, labels: &HashSet<ast::Ident>) -> Self {
-        let mut const_values = HashSet::new();
+        let mut const_values: HashMap<InternedStr, ast::Const> = HashMap::new();
 
         for stmt in &prog.stmts {
             let const_stmt = match stmt {
@@ -49,25 +29,140 @@ impl ConstTable {
                     .emit();
             }
 
-            if let Some(ConstEntry(prev_const)) = const_values.get(&const_stmt.name) {
+            if let Some(prev_const) = const_values.get(&const_stmt.name.value) {
                 if prev_const.value != const_stmt.value {
-                    diag.span_warning(const_stmt.span, format!("constant named `{}` was redefined", const_stmt.name))
+                    diag.span_lint(Lint::ConstantRedefined, const_stmt.span, format!("constant named `{}` was redefined", const_stmt.name))
                         .span_note(prev_const.span, "the previous declaration of this constant")
                         .emit();
                 }
             }
 
             // Insert or overwrite the constant to update the span
-            const_values.replace(ConstEntry(const_stmt.clone()));
+            const_values.insert(const_stmt.name.value, const_stmt.clone());
+        }
+
+        let mut table = Self {
+            const_values,
+            labels: labels.iter().map(|label| label.value).collect(),
+        };
+
+        // Evaluate every expression so that each constant is either an immediate or a distance
+        // between two labels. Sorting makes sure errors are reported in the order they appear.
+        let mut names: Vec<_> = table.const_values.values().map(|const_stmt| const_stmt.name.clone()).collect();
+        names.sort_by_key(|name| (name.span.file(), name.span.start));
+        for name in names {
+            table.eval_const(&name, &mut Vec::new(), diag);
+        }
+
+        table
+    }
+
+    /// Evaluates the constant with the given name (if it is an expression) and returns its value
+    ///
+    /// `in_progress` contains the constants currently being evaluated, used to detect constants
+    /// that are defined in terms of themselves.
+    fn eval_const(&mut self, name: &ast::Ident, in_progress: &mut Vec<InternedStr>, diag: &Diagnostics) -> Option<i128> {
+        let const_stmt = self.const_values.get(&name.value)?;
+        let expr = match &const_stmt.value {
+            ast::ConstValue::Immediate(value) => return Some(value.value),
+            ast::ConstValue::Distance(dist) => self.distance_expr(dist)?,
+            ast::ConstValue::Expr(expr) => expr.clone(),
+        };
+
+        if in_progress.contains(&name.value) {
+            diag.span_error(name.span, format!("constant `{}` is defined in terms of itself", name))
+                .span_note(const_stmt.span, "the declaration of this constant")
+                .emit();
+            return None;
+        }
+
+        in_progress.push(name.value);
+        let value = expr::eval(&expr, diag, &mut |name| self.lookup(name, in_progress, diag));
+        in_progress.pop();
+
+        // Error recovery: default to zero if the expression couldn't be evaluated so every other
+        // use of this constant doesn't produce the same error
+        let value = value.unwrap_or(ast::Integer {value: 0, span: expr.span()});
+        if let Some(const_stmt) = self.const_values.get_mut(&name.value) {
+            const_stmt.value = ast::ConstValue::Immediate(value.clone());
+        }
+        Some(value.value)
+    }
+
+    /// Returns the value of a name used in an expression while the constants are being evaluated
+    fn lookup(&mut self, name: &ast::Ident, in_progress: &mut Vec<InternedStr>, diag: &Diagnostics) -> Option<i128> {
+        self.expr_const(name, diag)?;
+        self.eval_const(name, in_progress, diag)
+    }
+
+    /// Returns the declaration of a constant used in an expression, or reports an error if the
+    /// name does not refer to a constant whose value is known before the program is laid out
+    fn expr_const(&self, name: &ast::Ident, diag: &Diagnostics) -> Option<&ast::Const> {
+        let const_stmt = match self.const_values.get(&name.value) {
+            Some(const_stmt) => const_stmt,
+
+            None if self.labels.contains(&name.value) => {
+                diag.span_error(name.span, format!("label `{}` cannot be used in an arithmetic expression", name))
+                    .span_note(name.span, "the address of a label is only known once the program has been laid out")
+                    .emit();
+                return None;
+            },
+
+            None => {
+                let mut writer = diag.span_error(name.span, format!("unknown constant `{}`", name));
+                if let Some(const_name) = closest_match(&name.value, self.const_values.keys().map(|name| name.as_str())) {
+                    writer = writer.span_help(name.span, format!("did you mean `{}`?", const_name));
+                }
+                writer.emit();
+                return None;
+            },
+        };
+
+        if let ast::ConstValue::Distance(dist) = &const_stmt.value {
+            if self.distance_expr(dist).is_none() {
+                diag.span_error(name.span, format!("constant `{}` is the distance between two labels, so it cannot be used in an arithmetic expression", name))
+                    .span_note(const_stmt.span, "the declaration of this constant")
+                    .emit();
+                return None;
+            }
         }
 
-        Self {const_values}
+        Some(const_stmt)
+    }
+
+    /// Evaluates an expression once every constant has been evaluated
+    fn eval_expr(&self, expr: &ast::Expr, diag: &Diagnostics) -> ast::Integer {
+        let value = expr::eval(expr, diag, &mut |name| match &self.expr_const(name, diag)?.value {
+            ast::ConstValue::Immediate(value) => Some(value.value),
+            _ => unreachable!("bug: every constant used in an expression should have been evaluated"),
+        });
+
+        // Error recovery: default to zero so we can keep checking for more errors
+        value.unwrap_or(ast::Integer {value: 0, span: expr.span()})
+    }
+
+    /// Returns the subtraction that a distance represents if both of its names refer to constants
+    /// instead of labels
+    fn distance_expr(&self, dist: &ast::Distance) -> Option<ast::Expr> {
+        let is_const = |name: &ast::Ident| self.const_values.contains_key(&name.value) && !self.labels.contains(&name.value);
+        match (&dist.end, &dist.start) {
+            (ast::Symbol::Label(end), ast::Symbol::Label(start)) if is_const(end) && is_const(start) => {
+                Some(ast::Expr::Binary(Box::new(ast::BinaryExpr {
+                    op: ast::BinOp::Sub,
+                    lhs: ast::Expr::Name(end.clone()),
+                    rhs: ast::Expr::Name(start.clone()),
+                    span: dist.span,
+                })))
+            },
+            _ => None,
+        }
     }
 
-    /// Replaces all constant names with the immediate values that they map to
-    pub fn subst_instr(&self, instr: ast::Instr) -> ast::Instr {
-        // Fast path for instructions without names in them
-        if !instr.args.iter().any(|arg| arg.is_name()) {
+    /// Replaces all constant names with the immediate values that they map to and evaluates every
+    /// expression
+    pub fn subst_instr(&self, instr: ast::Instr, diag: &Diagnostics) -> ast::Instr {
+        // Fast path for instructions without names or expressions in them
+        if !instr.args.iter().any(|arg| matches!(arg, ast::InstrArg::Name(_) | ast::InstrArg::Distance(_) | ast::InstrArg::Expr(_))) {
             return instr;
         }
 
@@ -75,8 +170,8 @@ impl ConstTable {
         ast::Instr {
             name,
             args: args.into_iter().map(|arg| match arg {
-                ast::InstrArg::Name(name) => match self.const_values.get(&name) {
-                    Some(ConstEntry(ast::Const {value: const_value, ..}))  => {
+                ast::InstrArg::Name(name) => match self.const_values.get(&name.value) {
+                    Some(ast::Const {value: ast::ConstValue::Immediate(const_value), ..}) => {
                         ast::InstrArg::Immediate(ast::Integer {
                             value: const_value.value,
                             // Preserve the span of the replaced value so error messages point to
@@ -85,9 +180,26 @@ impl ConstTable {
                         })
                     },
 
+                    Some(ast::Const {value: ast::ConstValue::Distance(dist), ..}) => {
+                        // The labels keep their spans so unknown labels are reported where the
+                        // constant was declared
+                        ast::InstrArg::Distance(ast::Distance {span: name.span, ..dist.clone()})
+                    },
+
+                    Some(ast::Const {value: ast::ConstValue::Expr(_), ..}) => {
+                        unreachable!("bug: every constant should have been evaluated")
+                    },
+
                     None => ast::InstrArg::Name(name),
                 },
 
+                ast::InstrArg::Distance(dist) => match self.distance_expr(&dist) {
+                    Some(expr) => ast::InstrArg::Immediate(self.eval_expr(&expr, diag)),
+                    None => ast::InstrArg::Distance(dist),
+                },
+
+                ast::InstrArg::Expr(expr) => ast::InstrArg::Immediate(self.eval_expr(&expr, diag)),
+
                 arg => arg,
             }).collect(),
         }
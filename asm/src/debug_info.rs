@@ -0,0 +1,233 @@
+//! Debug information that can be embedded in an executable (`wolf-asm -g`) so that addresses can
+//! be traced back to labels and source lines
+
+use std::fmt;
+
+use serde::{Serialize, Deserialize};
+
+use crate::asm;
+use crate::parser::{FileId, SourceFiles};
+use crate::label_offsets::LabelOffsets;
+use crate::executable::SectionId;
+use crate::demangle::Demangler;
+
+/// The labels and source lines of every statement in an executable
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DebugInfo {
+    /// Every label written in the program (not the labels generated for `$here`), sorted by
+    /// address
+    pub symbols: Vec<DebugSymbol>,
+    /// The paths of the source files referred to by `lines`
+    pub files: Vec<String>,
+    /// The source line of every statement, sorted by address
+    pub lines: Vec<LineEntry>,
+    /// The address just past the end of the last statement
+    pub end_addr: u64,
+    /// Used to show labels when describing addresses (see `set_demangler`)
+    ///
+    /// This is chosen by the tool using the debug info, so it is not part of the executable.
+    #[serde(skip)]
+    demangler: Demangler,
+}
+
+/// A label and the address it refers to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DebugSymbol {
+    pub name: String,
+    pub addr: u64,
+    pub section: SectionId,
+}
+
+/// The source line of the statement that starts at the given address
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LineEntry {
+    pub addr: u64,
+    /// The index of the file in `DebugInfo::files`
+    pub file: usize,
+    /// The line number (starting at 1)
+    pub line: usize,
+}
+
+impl DebugInfo {
+    /// Records the labels and source lines of every statement in the program
+    ///
+    /// This must be done before the program is laid out, since the executable does not contain
+    /// any source locations.
+    pub fn new(prog: &asm::Program, labels: &LabelOffsets, source_files: &SourceFiles) -> Self {
+        let symbols = labels.sorted_labels().into_iter().map(|(addr, name, section)| DebugSymbol {
+            name: name.to_string(),
+            addr,
+            section,
+        }).collect();
+
+        let mut file_ids: Vec<FileId> = Vec::new();
+        let mut lines = Vec::new();
+        let mut addr = labels.base_addr();
+        for stmt in prog.iter_all_stmts() {
+            let span = stmt.kind.span();
+            let file = match file_ids.iter().position(|&id| id == span.file()) {
+                Some(index) => index,
+                None => {
+                    file_ids.push(span.file());
+                    file_ids.len() - 1
+                },
+            };
+            let line = source_files.pos(span).start_line;
+            lines.push(LineEntry {addr, file, line});
+
+            addr += stmt.size_bytes();
+        }
+
+        let files = file_ids.into_iter()
+            .map(|id| source_files.path(id).display().to_string())
+            .collect();
+
+        Self {symbols, files, lines, end_addr: addr, demangler: Demangler::default()}
+    }
+
+    /// Adds the given amount to every address, wrapping around on overflow
+    ///
+    /// Used when the executable is relocated.
+    pub fn offset_addrs(&mut self, delta: u64) {
+        for symbol in &mut self.symbols {
+            symbol.addr = symbol.addr.wrapping_add(delta);
+        }
+        for line in &mut self.lines {
+            line.addr = line.addr.wrapping_add(delta);
+        }
+        self.end_addr = self.end_addr.wrapping_add(delta);
+    }
+
+    /// Sets the demangler used to show labels in the descriptions returned by `describe`
+    pub fn set_demangler(&mut self, demangler: Demangler) {
+        self.demangler = demangler;
+    }
+
+    /// Returns the label with the given name, if any
+    ///
+    /// The name may either be the label as it was written in the program or the name it is
+    /// demangled to.
+    pub fn symbol(&self, name: &str) -> Option<&DebugSymbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+            .or_else(|| self.symbols.iter().find(|symbol| self.demangler.demangle(&symbol.name) == name))
+    }
+
+    /// Returns the closest label at or before the given address, or None if the address is not
+    /// part of the executable
+    pub fn symbol_at(&self, addr: u64) -> Option<&DebugSymbol> {
+        if addr >= self.end_addr {
+            return None;
+        }
+        let index = self.symbols.partition_point(|symbol| symbol.addr <= addr);
+        index.checked_sub(1).map(|index| &self.symbols[index])
+    }
+
+    /// Returns the path and line number of the statement containing the given address
+    pub fn line_at(&self, addr: u64) -> Option<(&str, usize)> {
+        if addr >= self.end_addr {
+            return None;
+        }
+        let index = self.lines.partition_point(|line| line.addr <= addr);
+        let entry = &self.lines[index.checked_sub(1)?];
+        let path = self.files.get(entry.file)?;
+        Some((path, entry.line))
+    }
+
+    /// Returns a description of the given address using the closest label and the source line
+    /// (e.g. `main+0x10 at hello.wa:12`), if any are known
+    pub fn describe(&self, addr: u64) -> Option<AddrDescription<'_>> {
+        let symbol = self.symbol_at(addr);
+        let line = self.line_at(addr);
+        if symbol.is_none() && line.is_none() {
+            return None;
+        }
+
+        Some(AddrDescription {addr, symbol, line, demangler: &self.demangler})
+    }
+}
+
+/// The label and source line of an address (see `DebugInfo::describe`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddrDescription<'a> {
+    addr: u64,
+    symbol: Option<&'a DebugSymbol>,
+    line: Option<(&'a str, usize)>,
+    demangler: &'a Demangler,
+}
+
+impl<'a> fmt::Display for AddrDescription<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {addr, symbol, line, demangler} = self;
+
+        if let Some(symbol) = symbol {
+            write!(f, "{}", demangler.demangle(&symbol.name))?;
+            if *addr != symbol.addr {
+                write!(f, "+0x{:x}", addr - symbol.addr)?;
+            }
+            if line.is_some() {
+                write!(f, " ")?;
+            }
+        }
+        if let Some((path, line)) = line {
+            write!(f, "at {}:{}", path, line)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe() {
+        let debug_info = DebugInfo {
+            symbols: vec![
+                DebugSymbol {name: "main".to_string(), addr: 0x1000, section: SectionId::Code},
+                DebugSymbol {name: "value".to_string(), addr: 0x1010, section: SectionId::Static},
+            ],
+            files: vec!["main.wa".to_string()],
+            lines: vec![
+                LineEntry {addr: 0x1000, file: 0, line: 3},
+                LineEntry {addr: 0x1008, file: 0, line: 4},
+                LineEntry {addr: 0x1010, file: 0, line: 7},
+            ],
+            end_addr: 0x1018,
+            demangler: Demangler::default(),
+        };
+
+        let describe = |addr| debug_info.describe(addr).map(|desc| desc.to_string());
+        assert_eq!(describe(0xff8), None);
+        assert_eq!(describe(0x1000).as_deref(), Some("main at main.wa:3"));
+        assert_eq!(describe(0x1008).as_deref(), Some("main+0x8 at main.wa:4"));
+        assert_eq!(describe(0x1014).as_deref(), Some("value+0x4 at main.wa:7"));
+        assert_eq!(describe(0x1018), None);
+
+        // Labels are shown demangled, and can be looked up by either name
+        let mut debug_info = debug_info.clone();
+        debug_info.set_demangler(Demangler::new(vec!["m*=entry_$1".parse().unwrap()]));
+        let desc = debug_info.describe(0x1008).map(|desc| desc.to_string());
+        assert_eq!(desc.as_deref(), Some("entry_ain+0x8 at main.wa:4"));
+        assert_eq!(debug_info.symbol("main").map(|symbol| symbol.addr), Some(0x1000));
+        assert_eq!(debug_info.symbol("entry_ain").map(|symbol| symbol.addr), Some(0x1000));
+    }
+
+    #[test]
+    fn no_generated_labels() {
+        use crate::assemble::{assemble_str, AssembleOptions};
+
+        let options = AssembleOptions {debug_info: true, ..AssembleOptions::default()};
+        let source = "section .code\nmain:\n  mov $1, 1\n  mov $2, $here\n  ret\n";
+        let exec = assemble_str("test.wa", source, &options)
+            .unwrap_or_else(|diag| panic!("{} errors", diag.emitted_errors())).exec;
+
+        // Addresses after a use of `$here` are still described using the closest label written
+        // in the program
+        let debug_info = exec.debug_info.expect("debug info should be embedded");
+        let names: Vec<_> = debug_info.symbols.iter().map(|symbol| symbol.name.as_str()).collect();
+        assert_eq!(names, ["main"]);
+        let desc = debug_info.describe(0x1010).map(|desc| desc.to_string());
+        assert_eq!(desc.as_deref(), Some("main+0x10 at test.wa:5"));
+    }
+}
@@ -0,0 +1,159 @@
+//! Rules for turning the mangled labels emitted by compilers back into readable names in tool
+//! output (e.g. `wolf-vm --demangle`)
+
+use std::borrow::Cow;
+use std::str::FromStr;
+
+/// A rule that rewrites every label matching `pattern` using `replacement`
+///
+/// Written as `PATTERN=REPLACEMENT`. Each `*` in the pattern matches any number of characters,
+/// and `$1` to `$9` in the replacement are replaced with the text matched by the first to the
+/// ninth `*`. `$$` is a literal `$`. For example, `_W*_*=$1::$2` turns `_Wlist_push` into
+/// `list::push`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DemangleRule {
+    /// The literal parts of the pattern, separated by each `*`
+    parts: Vec<String>,
+    replacement: Vec<ReplacementPart>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ReplacementPart {
+    Literal(String),
+    /// The index of a wildcard in the pattern
+    Capture(usize),
+}
+
+impl FromStr for DemangleRule {
+    type Err = String;
+
+    fn from_str(src: &str) -> Result<DemangleRule, String> {
+        let (pattern, replacement) = src.split_once('=')
+            .ok_or_else(|| format!("`{}` is not a rule of the form `PATTERN=REPLACEMENT`", src))?;
+        if pattern.is_empty() {
+            return Err(format!("the pattern of `{}` is empty", src));
+        }
+        let parts: Vec<_> = pattern.split('*').map(str::to_string).collect();
+        let wildcards = parts.len() - 1;
+
+        let mut replacement_parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = replacement.chars();
+        while let Some(ch) = chars.next() {
+            if ch != '$' {
+                literal.push(ch);
+                continue;
+            }
+
+            match chars.next() {
+                Some('$') => literal.push('$'),
+                Some(digit @ '1'..='9') if (digit as usize - '0' as usize) <= wildcards => {
+                    if !literal.is_empty() {
+                        replacement_parts.push(ReplacementPart::Literal(literal.split_off(0)));
+                    }
+                    replacement_parts.push(ReplacementPart::Capture(digit as usize - '1' as usize));
+                },
+                Some(digit @ '1'..='9') => {
+                    return Err(format!("`${}` in `{}` does not refer to a `*` in the pattern", digit, src));
+                },
+                _ => return Err(format!("`$` in `{}` must be followed by a digit from 1 to 9 or by `$`", src)),
+            }
+        }
+        if !literal.is_empty() {
+            replacement_parts.push(ReplacementPart::Literal(literal));
+        }
+
+        Ok(DemangleRule {parts, replacement: replacement_parts})
+    }
+}
+
+impl DemangleRule {
+    /// Returns the text matched by each `*` in the pattern, or None if the name does not match
+    ///
+    /// Each `*` matches as few characters as possible, except for the last one, which matches
+    /// everything up to the final part of the pattern.
+    fn captures<'a>(&self, name: &'a str) -> Option<Vec<&'a str>> {
+        let (first, rest) = self.parts.split_first()?;
+        let mut remaining = name.strip_prefix(first.as_str())?;
+        let (last, middle) = match rest.split_last() {
+            Some(parts) => parts,
+            // No wildcards, so the name must match the pattern exactly
+            None => return if remaining.is_empty() { Some(Vec::new()) } else { None },
+        };
+
+        let mut captures = Vec::new();
+        for part in middle {
+            let index = remaining.find(part.as_str())?;
+            captures.push(&remaining[..index]);
+            remaining = &remaining[index + part.len()..];
+        }
+        captures.push(remaining.strip_suffix(last.as_str())?);
+
+        Some(captures)
+    }
+
+    /// Returns the demangled name, or None if the name does not match the pattern
+    fn apply(&self, name: &str) -> Option<String> {
+        let captures = self.captures(name)?;
+        Some(self.replacement.iter().map(|part| match part {
+            ReplacementPart::Literal(text) => text.as_str(),
+            ReplacementPart::Capture(index) => captures[*index],
+        }).collect())
+    }
+}
+
+/// Demangles labels using the first of its rules that matches, leaving labels that don't match
+/// any of them as they are
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Demangler {
+    rules: Vec<DemangleRule>,
+}
+
+impl Demangler {
+    pub fn new(rules: Vec<DemangleRule>) -> Self {
+        Self {rules}
+    }
+
+    /// Returns true if there are no rules, so every label is left as it is
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns the readable name of the given label
+    pub fn demangle<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        self.rules.iter()
+            .find_map(|rule| rule.apply(name))
+            .map(Cow::Owned)
+            .unwrap_or(Cow::Borrowed(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn demangler(rules: &[&str]) -> Demangler {
+        Demangler::new(rules.iter().map(|rule| rule.parse().unwrap()).collect())
+    }
+
+    #[test]
+    fn demangle() {
+        let demangler = demangler(&["_W*_*=$1::$2", "*$closure*={closure in $1}", "exact=renamed"]);
+        assert_eq!(demangler.demangle("_Wlist_push"), "list::push");
+        // The first `*` matches as little as possible
+        assert_eq!(demangler.demangle("_Wlist_push_back"), "list::push_back");
+        assert_eq!(demangler.demangle("main$closure0"), "{closure in main}");
+        assert_eq!(demangler.demangle("exact"), "renamed");
+        assert_eq!(demangler.demangle("exactly"), "exactly");
+        assert_eq!(demangler.demangle("main"), "main");
+        assert_eq!(Demangler::default().demangle("_Wlist_push"), "_Wlist_push");
+    }
+
+    #[test]
+    fn invalid_rules() {
+        for &rule in &["no_replacement", "=empty_pattern", "a*=$2", "a*=$x", "a*=$"] {
+            assert!(rule.parse::<DemangleRule>().is_err(), "`{}` should not be a valid rule", rule);
+        }
+        assert_eq!(demangler(&["a*=$$$1"]).demangle("ab"), "$b");
+    }
+}
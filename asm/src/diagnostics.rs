@@ -1,21 +1,56 @@
 mod writer;
 mod diagnostic;
+mod lint;
+mod error_code;
+mod suggest;
 
 pub use diagnostic::*;
+pub use lint::*;
+pub use error_code::*;
+pub use suggest::*;
+pub use writer::*;
 
+use std::mem;
+use std::ops::Range;
 use std::borrow::Cow;
+use std::str::FromStr;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 use parking_lot::{Mutex, RwLock};
 use termcolor::ColorChoice;
 
-use crate::parser::{Span, SourceFiles};
+use crate::parser::{Span, FileId, SourceFiles};
 
-#[cfg(not(test))]
-type OutputStream = termcolor::StandardStream;
-#[cfg(test)]
-type OutputStream = writer::NullWriter;
+type OutputStream = Box<dyn DiagnosticsWriter + Send>;
+
+/// The format that diagnostics are written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageFormat {
+    /// Colored text meant to be read by a person
+    Human,
+    /// One JSON object per line for each diagnostic, meant to be read by other programs (e.g.
+    /// editor plugins)
+    Json,
+}
+
+impl MessageFormat {
+    /// Allowed values the argument
+    pub const VARIANTS: &'static [&'static str] = &["human", "json"];
+}
+
+impl FromStr for MessageFormat {
+    type Err = &'static str;
+
+    fn from_str(src: &str) -> Result<MessageFormat, &'static str> {
+        match src {
+            _ if src.eq_ignore_ascii_case("human") => Ok(MessageFormat::Human),
+            _ if src.eq_ignore_ascii_case("json") => Ok(MessageFormat::Json),
+            _ => Err("valid values: human, json"),
+        }
+    }
+}
 
 pub struct Diagnostics {
     source_files: Arc<RwLock<SourceFiles>>,
@@ -23,25 +58,131 @@ pub struct Diagnostics {
     out: Mutex<OutputStream>,
     /// The number of errors that have been emitted
     errors: AtomicUsize,
+    /// The number of warnings that have been emitted
+    warnings: AtomicUsize,
+    /// If true, only errors are emitted
+    quiet: bool,
+    /// The level of each lint that was configured (other lints use their default level)
+    lint_levels: HashMap<Lint, LintLevel>,
+    /// If true, lints at the `Warn` level are reported as errors
+    deny_warnings: bool,
+    /// The range of indexes in each file where a lint is allowed (e.g. by an `.allow` directive)
+    allowed_regions: RwLock<Vec<(Lint, FileId, Range<usize>)>>,
+    /// The format that diagnostics are written to `out` in
+    message_format: MessageFormat,
+    /// The number of errors written before any further errors are left out, or `None` to write
+    /// every error
+    error_limit: Option<usize>,
+    /// If true, diagnostics are stored in `collected` instead of being written to `out`
+    collect: bool,
+    /// The diagnostics that have been emitted while `collect` is true
+    collected: Mutex<Vec<Diagnostic<'static>>>,
 }
 
 impl Diagnostics {
-    pub fn new(source_files: Arc<RwLock<SourceFiles>>, color_choice: ColorChoice) -> Self {
+    /// Creates diagnostics that are written to stderr
+    pub fn stderr(source_files: Arc<RwLock<SourceFiles>>, color_choice: ColorChoice) -> Self {
+        Self::new(source_files, Box::new(termcolor::StandardStream::stderr(color_choice)))
+    }
+
+    /// Creates diagnostics that are written to the given writer
+    pub fn new(source_files: Arc<RwLock<SourceFiles>>, out: Box<dyn DiagnosticsWriter + Send>) -> Self {
         Self {
             source_files,
-            #[cfg(not(test))]
-            out: Mutex::new(termcolor::StandardStream::stderr(color_choice)),
-            #[cfg(test)]
-            out: Mutex::new(writer::NullWriter::new(color_choice)),
+            out: Mutex::new(out),
             errors: AtomicUsize::default(),
+            warnings: AtomicUsize::default(),
+            quiet: false,
+            lint_levels: HashMap::new(),
+            deny_warnings: false,
+            allowed_regions: RwLock::default(),
+            message_format: MessageFormat::Human,
+            error_limit: None,
+            collect: false,
+            collected: Mutex::default(),
         }
     }
 
+    /// Configures the format that diagnostics are written in
+    pub fn set_message_format(&mut self, message_format: MessageFormat) {
+        self.message_format = message_format;
+    }
+
+    /// If true, only errors are emitted and every other diagnostic (e.g. a warning) is ignored
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    /// Configures the number of errors that are written before any further errors are left out
+    ///
+    /// Errors past the limit are still counted by `emitted_errors`. A message is written in place
+    /// of the first error left out. Errors that do not point at any code are always written.
+    pub fn set_error_limit(&mut self, error_limit: Option<usize>) {
+        self.error_limit = error_limit;
+    }
+
+    /// If true, emitted diagnostics are stored so they can be retrieved with `take_collected`
+    /// instead of being written to stderr
+    pub fn set_collect(&mut self, collect: bool) {
+        self.collect = collect;
+    }
+
+    /// Removes and returns every diagnostic that was stored because `set_collect` was enabled,
+    /// in the order they were emitted
+    ///
+    /// The spans of each diagnostic can be resolved to file positions with `source_files`.
+    pub fn take_collected(&self) -> Vec<Diagnostic<'static>> {
+        mem::take(&mut *self.collected.lock())
+    }
+
+    /// Returns the source files that the spans of each diagnostic refer to
+    pub fn source_files(&self) -> &Arc<RwLock<SourceFiles>> {
+        &self.source_files
+    }
+
+    /// Configures the level that the given lint is reported at
+    pub fn set_lint_level(&mut self, lint: Lint, level: LintLevel) {
+        self.lint_levels.insert(lint, level);
+    }
+
+    /// If true, every lint that would be reported as a warning is reported as an error instead
+    pub fn set_deny_warnings(&mut self, deny_warnings: bool) {
+        self.deny_warnings = deny_warnings;
+    }
+
+    /// Returns the level that the given lint is reported at
+    pub fn lint_level(&self, lint: Lint) -> LintLevel {
+        let level = self.lint_levels.get(&lint).copied().unwrap_or_else(|| lint.default_level());
+        match level {
+            LintLevel::Warn if self.deny_warnings => LintLevel::Deny,
+            level => level,
+        }
+    }
+
+    /// Allows the given lint for every span that starts within the given range of indexes in the
+    /// given file, no matter what level the lint is configured at
+    pub fn allow_lint_in(&self, lint: Lint, file: FileId, range: Range<usize>) {
+        self.allowed_regions.write().push((lint, file, range));
+    }
+
+    /// Returns true if the given lint is allowed at the given span by `allow_lint_in`
+    fn is_allowed_at(&self, lint: Lint, span: Span) -> bool {
+        self.allowed_regions.read().iter().any(|(allowed, file, range)| {
+            *allowed == lint && *file == span.file() && range.contains(&span.start)
+        })
+    }
+
     /// Returns the number of errors that have been emitted
     pub fn emitted_errors(&self) -> usize {
         self.errors.load(Ordering::SeqCst)
     }
 
+    /// Returns the number of warnings that have been emitted (not including any lints that were
+    /// reported as errors)
+    pub fn emitted_warnings(&self) -> usize {
+        self.warnings.load(Ordering::SeqCst)
+    }
+
     pub fn error<'a>(&'a self, message: impl Into<Cow<'a, str>>) -> DiagnosticWriter<'a> {
         self.level(Level::Error, message)
     }
@@ -69,6 +210,8 @@ impl Diagnostics {
                 label: message.into(),
             },
             fragments: Vec::new(),
+            labels: Vec::new(),
+            code: None,
         })
     }
 
@@ -87,12 +230,47 @@ impl Diagnostics {
             .span_level(level, span, message)
     }
 
+    /// Reports a diagnostic for the given lint at the level configured for that lint
+    ///
+    /// The name of the lint is added to the message so it can be configured. Nothing is emitted
+    /// if the lint is allowed, either everywhere or at the given span.
+    pub fn span_lint<'a>(&'a self, lint: Lint, span: Span, message: impl Into<Cow<'a, str>>) -> DiagnosticWriter<'a> {
+        let level = if self.is_allowed_at(lint, span) {
+            LintLevel::Allow
+        } else {
+            self.lint_level(lint)
+        };
+        let message = format!("{} [{}]", message.into(), lint);
+        let mut writer = match level {
+            LintLevel::Allow | LintLevel::Warn => self.span_level(Level::Warning, span, message),
+            LintLevel::Deny => self.span_level(Level::Error, span, message),
+        };
+        writer.data.code = Some(DiagnosticCode::Lint(lint));
+        writer.suppressed |= level == LintLevel::Allow;
+        writer
+    }
+
+    /// Reports an error with the given error code, which is added to the message so that it can
+    /// be looked up with `wolf-asm --explain`
+    pub fn span_error_code<'a>(&'a self, code: ErrorCode, span: Span, message: impl Into<Cow<'a, str>>) -> DiagnosticWriter<'a> {
+        let message = format!("{} [{}]", message.into(), code);
+        let mut writer = self.span_error(span, message);
+        writer.data.code = Some(DiagnosticCode::Error(code));
+        writer
+    }
+
     fn diagnostic_writer<'a>(&'a self, data: Diagnostic<'a>) -> DiagnosticWriter<'a> {
+        let data_level = data.title.level;
         DiagnosticWriter {
             source_files: self.source_files.read(),
             out: self.out.lock(),
             errors: &self.errors,
+            warnings: &self.warnings,
             data,
+            message_format: self.message_format,
+            error_limit: self.error_limit,
+            collected: if self.collect { Some(&self.collected) } else { None },
+            suppressed: self.quiet && data_level != Level::Error,
         }
     }
 }
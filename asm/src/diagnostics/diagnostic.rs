@@ -1,12 +1,12 @@
 use std::borrow::Cow;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
-use parking_lot::{MutexGuard, RwLockReadGuard};
+use parking_lot::{Mutex, MutexGuard, RwLockReadGuard};
+use serde::Serialize;
 
 use crate::parser::{Span, SourceFiles};
 
-use super::OutputStream;
-use super::writer::DiagnosticsWriter;
+use super::{OutputStream, DiagnosticCode, MessageFormat};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Level {
@@ -17,6 +17,20 @@ pub enum Level {
     Help,
 }
 
+impl Level {
+    /// The name of this level, as used in the prefix of a message
+    pub fn name(self) -> &'static str {
+        use Level::*;
+        match self {
+            Error => "error",
+            Warning => "warning",
+            Info => "info",
+            Note => "note",
+            Help => "help",
+        }
+    }
+}
+
 /// A labelled piece of text, prefixed and colored by its label
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Message<'a> {
@@ -24,6 +38,14 @@ pub struct Message<'a> {
     pub label: Cow<'a, str>,
 }
 
+impl<'a> Message<'a> {
+    /// Copies the label so the message no longer borrows from anything
+    pub fn into_owned(self) -> Message<'static> {
+        let Self {level, label} = self;
+        Message {level, label: Cow::Owned(label.into_owned())}
+    }
+}
+
 /// A fragment of code with any number of annotations
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Fragment<'a> {
@@ -33,10 +55,51 @@ pub struct Fragment<'a> {
     pub message: Message<'a>,
 }
 
+/// A short message attached to an additional span of code that the title refers to (e.g. the
+/// extra argument in "expected 2 arguments")
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SpanLabel<'a> {
+    pub span: Span,
+    pub label: Cow<'a, str>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Diagnostic<'a> {
     pub title: Message<'a>,
     pub fragments: Vec<Fragment<'a>>,
+    /// Additional spans that the title refers to, written directly after the title
+    pub labels: Vec<SpanLabel<'a>>,
+    /// The lint that produced this diagnostic or the kind of error it reports, if any
+    pub code: Option<DiagnosticCode>,
+}
+
+impl<'a> Diagnostic<'a> {
+    /// Returns the span of the title and the remaining fragments
+    ///
+    /// The first fragment is the span of the title if it has the same message.
+    fn split_primary(&self) -> (Option<Span>, &[Fragment<'a>]) {
+        match self.fragments.split_first() {
+            Some((frag, others)) if frag.message == self.title => (Some(frag.span), others),
+            _ => (None, &self.fragments[..]),
+        }
+    }
+
+    /// Copies every message so the diagnostic no longer borrows from anything
+    pub fn into_owned(self) -> Diagnostic<'static> {
+        let Self {title, fragments, labels, code} = self;
+        Diagnostic {
+            title: title.into_owned(),
+            fragments: fragments.into_iter().map(|Fragment {span, message}| Fragment {
+                span,
+                message: message.into_owned(),
+            }).collect(),
+            labels: labels.into_iter().map(|SpanLabel {span, label}| SpanLabel {
+                span,
+                label: Cow::Owned(label.into_owned()),
+            }).collect(),
+            code,
+        }
+    }
 }
 
 #[must_use]
@@ -44,7 +107,17 @@ pub struct DiagnosticWriter<'a> {
     pub(super) source_files: RwLockReadGuard<'a, SourceFiles>,
     pub(super) out: MutexGuard<'a, OutputStream>,
     pub(super) errors: &'a AtomicUsize,
+    pub(super) warnings: &'a AtomicUsize,
     pub(super) data: Diagnostic<'a>,
+    pub(super) message_format: MessageFormat,
+    /// The number of errors written before any further errors are left out (see
+    /// `Diagnostics::set_error_limit`)
+    pub(super) error_limit: Option<usize>,
+    /// If not `None`, the diagnostic is stored here instead of being written to `out`
+    pub(super) collected: Option<&'a Mutex<Vec<Diagnostic<'static>>>>,
+    /// If true, nothing is written when the diagnostic is emitted (see `Diagnostics::span_lint`
+    /// and `Diagnostics::set_quiet`)
+    pub(super) suppressed: bool,
 }
 
 impl<'a> DiagnosticWriter<'a> {
@@ -79,29 +152,81 @@ impl<'a> DiagnosticWriter<'a> {
         self
     }
 
+    /// Points at another span that the title refers to, with a short label explaining its role
+    pub fn span_label(mut self, span: Span, label: impl Into<Cow<'a, str>>) -> Self {
+        self.data.labels.push(SpanLabel {span, label: label.into()});
+        self
+    }
+
     pub fn emit(self) {
-        let Self {source_files, mut out, errors, data} = self;
-        let Diagnostic {title, fragments} = &data;
+        let Self {source_files, mut out, errors, warnings, data, message_format, error_limit, collected, suppressed} = self;
 
-        if title.level == Level::Error {
-            errors.fetch_add(1, Ordering::SeqCst);
+        if suppressed {
+            return;
         }
 
-        if let Some(frag) = fragments.get(0) {
-            // Skip the title if it is the same as the first fragment
-            if frag.message != *title {
-                emit_message(&source_files, &mut out, None, title);
-            }
-        } else {
-            emit_message(&source_files, &mut out, None, title);
+        let prev_errors = match data.title.level {
+            Level::Error => errors.fetch_add(1, Ordering::SeqCst),
+            Level::Warning => {
+                warnings.fetch_add(1, Ordering::SeqCst);
+                errors.load(Ordering::SeqCst)
+            },
+            _ => errors.load(Ordering::SeqCst),
+        };
+
+        if let Some(collected) = collected {
+            collected.lock().push(data.into_owned());
+            return;
         }
 
-        for frag in fragments {
-            let &Fragment {span, ref message} = frag;
-            emit_message(&source_files, &mut out, Some(span), message);
+        // Errors that point at code are still counted after the limit is reached, but they are not
+        // written. Errors without a span (e.g. the final "aborting due to..." message) are always
+        // written.
+        if let Some(limit) = error_limit {
+            if data.title.level == Level::Error && !data.fragments.is_empty() && prev_errors >= limit {
+                if prev_errors == limit {
+                    let too_many = Diagnostic {
+                        title: Message {
+                            level: Level::Error,
+                            label: format!("too many errors, stopped reporting errors after the first {}", limit).into(),
+                        },
+                        fragments: Vec::new(),
+                        labels: Vec::new(),
+                        code: None,
+                    };
+                    write_diagnostic(&source_files, &mut out, message_format, &too_many);
+                }
+                return;
+            }
         }
-        out.write_newline().expect("IO error");
+
+        write_diagnostic(&source_files, &mut out, message_format, &data);
+    }
+}
+
+fn write_diagnostic(
+    source_files: &SourceFiles,
+    out: &mut OutputStream,
+    message_format: MessageFormat,
+    data: &Diagnostic,
+) {
+    if message_format == MessageFormat::Json {
+        emit_json(source_files, out, data);
+        return;
+    }
+
+    let (primary, others) = data.split_primary();
+    emit_message(source_files, out, primary, &data.title);
+    for label in &data.labels {
+        let pos = source_files.pos(label.span);
+        out.write_label(pos, &label.label).expect("IO error");
+    }
+
+    for frag in others {
+        let &Fragment {span, ref message} = frag;
+        emit_message(source_files, out, Some(span), message);
     }
+    out.write_newline().expect("IO error");
 }
 
 fn emit_message(
@@ -122,3 +247,85 @@ fn emit_message(
         Help => out.write_help(pos, &label).expect("IO error"),
     }
 }
+
+/// A diagnostic as it is written with `MessageFormat::Json`
+#[derive(Serialize)]
+struct JsonDiagnostic<'a> {
+    level: &'static str,
+    message: &'a str,
+    /// The name of the lint that produced the diagnostic or its error code
+    code: Option<&'static str>,
+    /// The file and span that the message refers to, if any
+    file: Option<String>,
+    span: Option<JsonSpan>,
+    /// The additional spans that the message refers to
+    labels: Vec<JsonLabel<'a>>,
+    /// The messages for every other span annotated by the diagnostic
+    notes: Vec<JsonNote<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonLabel<'a> {
+    label: &'a str,
+    file: String,
+    span: JsonSpan,
+}
+
+#[derive(Serialize)]
+struct JsonNote<'a> {
+    level: &'static str,
+    message: &'a str,
+    file: String,
+    span: JsonSpan,
+}
+
+/// The 1-based position of a span, where each end refers to the first and last character
+#[derive(Serialize)]
+struct JsonSpan {
+    start_line: usize,
+    start_column: usize,
+    end_line: usize,
+    end_column: usize,
+}
+
+/// Writes the diagnostic as a single line of JSON
+fn emit_json(source_files: &SourceFiles, out: &mut OutputStream, data: &Diagnostic) {
+    let Diagnostic {title, code, ..} = data;
+    let file_span = |span: Span| {
+        let pos = source_files.pos(span);
+        let json_span = JsonSpan {
+            start_line: pos.start_line,
+            start_column: pos.start_column,
+            end_line: pos.end_line,
+            end_column: pos.end_column,
+        };
+        (pos.path.display().to_string(), json_span)
+    };
+
+    let (primary, others) = data.split_primary();
+    let (file, span) = match primary.map(file_span) {
+        Some((file, span)) => (Some(file), Some(span)),
+        None => (None, None),
+    };
+    let labels = data.labels.iter().map(|label| {
+        let (file, span) = file_span(label.span);
+        JsonLabel {label: &label.label, file, span}
+    }).collect();
+    let notes = others.iter().map(|frag| {
+        let (file, span) = file_span(frag.span);
+        JsonNote {level: frag.message.level.name(), message: &frag.message.label, file, span}
+    }).collect();
+
+    let json = JsonDiagnostic {
+        level: title.level.name(),
+        message: &title.label,
+        code: code.map(DiagnosticCode::name),
+        file,
+        span,
+        labels,
+        notes,
+    };
+    // unwrap() is safe because serializing these types can't fail
+    let line = serde_json::to_string(&json).unwrap();
+    out.write_line(&line).expect("IO error");
+}
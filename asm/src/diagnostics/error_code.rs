@@ -0,0 +1,158 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A stable code that identifies a kind of error so it can be looked up with `wolf-asm --explain`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// An instruction name that is not part of the instruction set
+    UnknownInstruction,
+    /// An instruction was given too few or too many arguments
+    WrongArgumentCount,
+    /// A label was used but never defined
+    UnknownLabel,
+    /// The same label was defined more than once
+    DuplicateLabel,
+    /// An immediate does not fit in the bits available for it in the instruction's encoding
+    ImmediateOutOfRange,
+    /// A register offset does not fit in a 16-bit signed integer
+    OffsetOutOfRange,
+    /// A register that does not exist
+    InvalidRegister,
+    /// A statement that is not inside any section
+    StatementOutsideSection,
+    /// Sections that are declared in the wrong order
+    SectionOrder,
+    /// The same section was declared more than once
+    DuplicateSection,
+    /// A `.b1`, `.b2`, `.b4`, `.b8`, or `.f4` value that is out of range for its size
+    DataOutOfRange,
+    /// A statement that is not allowed in the section it is in
+    StatementNotAllowedInSection,
+    /// A file included with `.include` could not be read
+    IncludeNotFound,
+    /// A jump or call to a label that is not an instruction
+    InvalidJumpTarget,
+}
+
+impl ErrorCode {
+    /// Every error code, in order
+    pub const ALL: [ErrorCode; 14] = [
+        ErrorCode::UnknownInstruction,
+        ErrorCode::WrongArgumentCount,
+        ErrorCode::UnknownLabel,
+        ErrorCode::DuplicateLabel,
+        ErrorCode::ImmediateOutOfRange,
+        ErrorCode::OffsetOutOfRange,
+        ErrorCode::InvalidRegister,
+        ErrorCode::StatementOutsideSection,
+        ErrorCode::SectionOrder,
+        ErrorCode::DuplicateSection,
+        ErrorCode::DataOutOfRange,
+        ErrorCode::StatementNotAllowedInSection,
+        ErrorCode::IncludeNotFound,
+        ErrorCode::InvalidJumpTarget,
+    ];
+
+    /// The code shown in diagnostics and passed to `--explain` (e.g. `E0001`)
+    ///
+    /// Codes are never reused, so new errors must be added at the end of the list.
+    pub fn name(self) -> &'static str {
+        use ErrorCode::*;
+        match self {
+            UnknownInstruction => "E0001",
+            WrongArgumentCount => "E0002",
+            UnknownLabel => "E0003",
+            DuplicateLabel => "E0004",
+            ImmediateOutOfRange => "E0005",
+            OffsetOutOfRange => "E0006",
+            InvalidRegister => "E0007",
+            StatementOutsideSection => "E0008",
+            SectionOrder => "E0009",
+            DuplicateSection => "E0010",
+            DataOutOfRange => "E0011",
+            StatementNotAllowedInSection => "E0012",
+            IncludeNotFound => "E0013",
+            InvalidJumpTarget => "E0014",
+        }
+    }
+
+    /// The extended documentation for this error, with examples of how it can be fixed
+    pub fn explanation(self) -> &'static str {
+        use ErrorCode::*;
+        match self {
+            UnknownInstruction => include_str!("explanations/E0001.md"),
+            WrongArgumentCount => include_str!("explanations/E0002.md"),
+            UnknownLabel => include_str!("explanations/E0003.md"),
+            DuplicateLabel => include_str!("explanations/E0004.md"),
+            ImmediateOutOfRange => include_str!("explanations/E0005.md"),
+            OffsetOutOfRange => include_str!("explanations/E0006.md"),
+            InvalidRegister => include_str!("explanations/E0007.md"),
+            StatementOutsideSection => include_str!("explanations/E0008.md"),
+            SectionOrder => include_str!("explanations/E0009.md"),
+            DuplicateSection => include_str!("explanations/E0010.md"),
+            DataOutOfRange => include_str!("explanations/E0011.md"),
+            StatementNotAllowedInSection => include_str!("explanations/E0012.md"),
+            IncludeNotFound => include_str!("explanations/E0013.md"),
+            InvalidJumpTarget => include_str!("explanations/E0014.md"),
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for ErrorCode {
+    type Err = String;
+
+    fn from_str(src: &str) -> Result<ErrorCode, String> {
+        ErrorCode::ALL.iter().copied()
+            .find(|code| code.name().eq_ignore_ascii_case(src))
+            .ok_or_else(|| format!("unknown error code `{}` (error codes are `E0001` to `{}`)",
+                src, ErrorCode::ALL[ErrorCode::ALL.len()-1]))
+    }
+}
+
+/// The code of a diagnostic: either the lint that produced it or the kind of error it reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DiagnosticCode {
+    Lint(super::Lint),
+    Error(ErrorCode),
+}
+
+impl DiagnosticCode {
+    /// The name shown in brackets after the message (e.g. `unused-label` or `E0001`)
+    pub fn name(self) -> &'static str {
+        match self {
+            DiagnosticCode::Lint(lint) => lint.name(),
+            DiagnosticCode::Error(code) => code.name(),
+        }
+    }
+}
+
+impl fmt::Display for DiagnosticCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes() {
+        for (i, &code) in ErrorCode::ALL.iter().enumerate() {
+            // Codes are numbered in the order they are listed
+            assert_eq!(code.name(), format!("E{:04}", i+1));
+            assert_eq!(code.name().parse::<ErrorCode>(), Ok(code));
+            assert_eq!(code.name().to_lowercase().parse::<ErrorCode>(), Ok(code));
+            assert!(!code.explanation().is_empty());
+        }
+
+        assert!("E0000".parse::<ErrorCode>().is_err());
+        assert!("unused-label".parse::<ErrorCode>().is_err());
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A named category of warnings whose level can be configured (e.g. with `-A` or `-W`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    /// A constant is declared more than once with different values
+    ConstantRedefined,
+    /// A register alias is declared more than once with different registers
+    RegisterRedefined,
+    /// A label is never used by any instruction, constant, or `.export` directive
+    UnusedLabel,
+    /// A value cannot be stored exactly and is rounded or truncated (e.g. a `.f4` value that is
+    /// too small to be represented and becomes zero)
+    Truncation,
+    /// An instruction can never be executed because it follows a `jmp` or `ret` and has no label
+    UnreachableCode,
+    /// Two labels or constants have names that only differ in case (only possible when
+    /// identifiers are case-sensitive)
+    CaseCollision,
+}
+
+impl Lint {
+    /// Every lint, in the order they are listed in the help text
+    pub const ALL: [Lint; 6] = [
+        Lint::ConstantRedefined,
+        Lint::RegisterRedefined,
+        Lint::UnusedLabel,
+        Lint::Truncation,
+        Lint::UnreachableCode,
+        Lint::CaseCollision,
+    ];
+
+    /// The name used to refer to this lint on the command line
+    pub fn name(self) -> &'static str {
+        use Lint::*;
+        match self {
+            ConstantRedefined => "constant-redefined",
+            RegisterRedefined => "register-redefined",
+            UnusedLabel => "unused-label",
+            Truncation => "truncation",
+            UnreachableCode => "unreachable-code",
+            CaseCollision => "case-collision",
+        }
+    }
+
+    /// The level of this lint if it is not configured
+    pub fn default_level(self) -> LintLevel {
+        use Lint::*;
+        match self {
+            ConstantRedefined |
+            RegisterRedefined |
+            Truncation => LintLevel::Warn,
+            // Most programs have labels (like `main`) that are never used
+            UnusedLabel => LintLevel::Allow,
+            // Code is often only reached by computed jumps (e.g. through a register)
+            UnreachableCode => LintLevel::Allow,
+            // Generated code often relies on case to keep symbols apart
+            CaseCollision => LintLevel::Allow,
+        }
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Lint {
+    type Err = String;
+
+    fn from_str(src: &str) -> Result<Lint, String> {
+        Lint::ALL.iter().copied().find(|lint| lint.name() == src).ok_or_else(|| {
+            let names: Vec<_> = Lint::ALL.iter().map(|lint| lint.name()).collect();
+            format!("unknown warning `{}` (valid warnings: {})", src, names.join(", "))
+        })
+    }
+}
+
+/// Configures how the diagnostics for a lint are reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LintLevel {
+    /// The diagnostics are not reported
+    Allow,
+    /// The diagnostics are reported as warnings
+    Warn,
+    /// The diagnostics are reported as errors
+    Deny,
+}
@@ -0,0 +1,78 @@
+/// Returns the candidate closest to `name` (by edit distance) if it is close enough to be a
+/// likely typo, for use in "did you mean" suggestions
+///
+/// Names are compared case-insensitively. If several candidates are equally close, the one that
+/// comes first alphabetically is returned so that suggestions do not depend on iteration order.
+pub fn closest_match<'a, I>(name: &str, candidates: I) -> Option<&'a str>
+    where I: IntoIterator<Item=&'a str>,
+{
+    let name = name.to_ascii_lowercase();
+    // Allow about one edit for every three characters
+    let max_distance = (name.chars().count() / 3).max(1);
+
+    candidates.into_iter()
+        .filter(|candidate| !candidate.eq_ignore_ascii_case(&name))
+        .map(|candidate| (edit_distance(&name, &candidate.to_ascii_lowercase()), candidate))
+        .filter(|&(distance, _)| distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// The number of characters that must be inserted, removed, replaced, or swapped with the
+/// character next to them to turn one string into the other (the optimal string alignment
+/// distance)
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // distances[i][j] is the distance between the first `i` characters of `a` and the first `j`
+    // characters of `b`
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let replace_cost = if a[i-1] == b[j-1] { 0 } else { 1 };
+            let mut distance = (distances[i-1][j-1] + replace_cost)
+                .min(distances[i-1][j] + 1)
+                .min(distances[i][j-1] + 1);
+            if i > 1 && j > 1 && a[i-1] == b[j-2] && a[i-2] == b[j-1] {
+                distance = distance.min(distances[i-2][j-2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+
+    distances[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distances() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("mov", "move"), 1);
+        assert_eq!(edit_distance("lod8", "load8"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        // Swapping two characters is a single edit
+        assert_eq!(edit_distance("mian", "main"), 1);
+    }
+
+    #[test]
+    fn suggestions() {
+        let instrs = ["mov", "load8", "load4", "add", "ret"];
+        assert_eq!(closest_match("move", instrs.iter().copied()), Some("mov"));
+        assert_eq!(closest_match("LOD8", instrs.iter().copied()), Some("load8"));
+        // Ties are broken alphabetically
+        assert_eq!(closest_match("load", instrs.iter().copied()), Some("load4"));
+        assert_eq!(closest_match("xyzzy", instrs.iter().copied()), None);
+        // An exact match is not a suggestion
+        assert_eq!(closest_match("ret", instrs.iter().copied()), None);
+    }
+}
@@ -1,57 +1,74 @@
-use std::io::{self, Write};
+use std::io;
 
-use termcolor::{StandardStream, StandardStreamLock, ColorSpec, Color, WriteColor};
+use termcolor::{ColorSpec, Color, WriteColor};
 
 use crate::parser::FilePos;
 
+/// A destination that diagnostics are written to as text
+///
+/// Implement this to capture diagnostics anywhere other than a terminal or buffer (e.g. a log),
+/// then pass it to `Diagnostics::new`.
 pub trait DiagnosticsWriter {
     fn write_error(&mut self, pos: Option<FilePos>, message: &str) -> io::Result<()>;
     fn write_warning(&mut self, pos: Option<FilePos>, message: &str) -> io::Result<()>;
     fn write_info(&mut self, pos: Option<FilePos>, message: &str) -> io::Result<()>;
     fn write_note(&mut self, pos: Option<FilePos>, message: &str) -> io::Result<()>;
     fn write_help(&mut self, pos: Option<FilePos>, message: &str) -> io::Result<()>;
+    /// Writes a label for an additional span of the diagnostic whose title was just written
+    fn write_label(&mut self, pos: FilePos, label: &str) -> io::Result<()>;
     fn write_newline(&mut self) -> io::Result<()>;
+    /// Writes the given text followed by a newline, without any formatting
+    fn write_line(&mut self, line: &str) -> io::Result<()>;
 }
 
-impl DiagnosticsWriter for StandardStream {
+/// Writes colored text to any stream that supports it (e.g. `termcolor::StandardStream`, or
+/// `termcolor::NoColor` to write plain text into a buffer)
+impl<W: WriteColor> DiagnosticsWriter for W {
     fn write_error(&mut self, pos: Option<FilePos>, message: &str) -> io::Result<()> {
-        write_message(self.lock(), pos, "error:", Color::Red, message)
+        write_message(self, pos, "error:", Color::Red, message)
     }
 
     fn write_warning(&mut self, pos: Option<FilePos>, message: &str) -> io::Result<()> {
-        write_message(self.lock(), pos, "warning:", Color::Yellow, message)
+        write_message(self, pos, "warning:", Color::Yellow, message)
     }
 
     fn write_info(&mut self, pos: Option<FilePos>, message: &str) -> io::Result<()> {
-        write_message(self.lock(), pos, "info:", Color::White, message)
+        write_message(self, pos, "info:", Color::White, message)
     }
 
     fn write_note(&mut self, pos: Option<FilePos>, message: &str) -> io::Result<()> {
-        write_message(self.lock(), pos, "note:", Color::Green, message)
+        write_message(self, pos, "note:", Color::Green, message)
     }
 
     fn write_help(&mut self, pos: Option<FilePos>, message: &str) -> io::Result<()> {
-        write_message(self.lock(), pos, "help:", Color::Blue, message)
+        write_message(self, pos, "help:", Color::Blue, message)
+    }
+
+    fn write_label(&mut self, pos: FilePos, label: &str) -> io::Result<()> {
+        write_message(self, Some(pos), "-", Color::Cyan, label)
     }
 
     fn write_newline(&mut self) -> io::Result<()> {
-        writeln!(self.lock())
+        writeln!(self)
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self, "{}", line)
     }
 }
 
-fn write_message(
-    mut out: StandardStreamLock,
+fn write_message<W: WriteColor>(
+    out: &mut W,
     pos: Option<FilePos>,
     prefix: &str,
     prefix_color: Color,
     message: &str,
 ) -> io::Result<()> {
-    if let Some(FilePos {path, start_line, start_offset, end_line, end_offset}) = pos {
-        if start_line == end_line && start_offset == end_offset {
-            write!(out, "[{}:{}:{}] ", path.display(), start_line, start_offset)?;
+    if let Some(FilePos {path, start_line, start_column, end_line, end_column, ..}) = pos {
+        if start_line == end_line && start_column == end_column {
+            write!(out, "[{}:{}:{}] ", path.display(), start_line, start_column)?;
         } else {
-            // end offset is always one past the end
-            write!(out, "[{}:{}:{}-{}:{}] ", path.display(), start_line, start_offset, end_line, end_offset)?;
+            write!(out, "[{}:{}:{}-{}:{}] ", path.display(), start_line, start_column, end_line, end_column)?;
         }
     }
 
@@ -62,18 +79,9 @@ fn write_message(
     writeln!(out, "{}", message)
 }
 
-#[cfg(test)]
+/// Discards every diagnostic
 pub struct NullWriter;
 
-#[cfg(test)]
-impl NullWriter {
-    pub fn new(_color_choice: termcolor::ColorChoice) -> Self {
-        // This impl exists to silence an unused parameter warning
-        NullWriter
-    }
-}
-
-#[cfg(test)]
 impl DiagnosticsWriter for NullWriter {
     fn write_error(&mut self, _pos: Option<FilePos>, _message: &str) -> io::Result<()> {
         Ok(())
@@ -95,7 +103,69 @@ impl DiagnosticsWriter for NullWriter {
         Ok(())
     }
 
+    fn write_label(&mut self, _pos: FilePos, _label: &str) -> io::Result<()> {
+        Ok(())
+    }
+
     fn write_newline(&mut self) -> io::Result<()> {
         Ok(())
     }
+
+    fn write_line(&mut self, _line: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+    use std::sync::Arc;
+
+    use parking_lot::{Mutex, RwLock};
+    use termcolor::NoColor;
+
+    use crate::parser::{Span, SourceFiles};
+    use crate::diagnostics::Diagnostics;
+
+    /// A buffer that can still be read after it is given to `Diagnostics`
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn capture_output() {
+        let source_files = Arc::new(RwLock::new(SourceFiles::default()));
+        let file = source_files.write().add_source("test.wa", b"section .code\n  foo $1\n");
+        let buffer = SharedBuffer::default();
+        let diag = Diagnostics::new(source_files.clone(), Box::new(NoColor::new(buffer.clone())));
+
+        let span = Span {file: file.id(), start: 16, end: 19};
+        let arg_span = Span {file: file.id(), start: 20, end: 22};
+        diag.span_error(span, "unknown instruction `foo`")
+            .span_label(arg_span, "argument")
+            .span_note(span, "a note")
+            .emit();
+        diag.error("aborting").emit();
+
+        let output = String::from_utf8(buffer.0.lock().clone()).unwrap();
+        assert_eq!(output, "\
+[test.wa:2:3-2:5] error: unknown instruction `foo`
+[test.wa:2:7-2:8] - argument
+[test.wa:2:3-2:5] note: a note
+
+error: aborting
+
+");
+    }
 }
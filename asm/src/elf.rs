@@ -0,0 +1,440 @@
+//! A writer for a minimal ELF file (`wolf-asm --format elf`) that contains the sections and labels
+//! of an executable so that it can be inspected with tools such as `readelf`, `objdump`, and `gdb`,
+//! and a reader that turns such a file back into an executable that can be run by the VM
+//!
+//! The file is a 64-bit little-endian executable with no machine type (`EM_NONE`). Each non-empty
+//! section of the executable is placed in its own loadable segment at the address it would be
+//! loaded at by the VM.
+
+use std::fmt;
+use std::error::Error;
+use std::io::{self, Write};
+use std::convert::TryInto;
+
+use crate::executable::{Executable, SectionId, Stmt, StaticData, StaticByteStr, StaticZero, StaticUninit};
+use crate::label_offsets::LabelOffsets;
+
+const ELF_HEADER_SIZE: u64 = 64;
+const PROGRAM_HEADER_SIZE: u64 = 56;
+const SECTION_HEADER_SIZE: u64 = 64;
+const SYMBOL_SIZE: u64 = 24;
+
+/// The bytes at the start of every ELF file
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+// Segment types
+const PT_LOAD: u32 = 1;
+
+// Section types
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_NOBITS: u32 = 8;
+
+// Section flags
+const SHF_WRITE: u64 = 0x1;
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+
+// Segment permissions
+const PF_X: u32 = 0x1;
+const PF_W: u32 = 0x2;
+const PF_R: u32 = 0x4;
+
+// Symbol bindings and types
+const STB_GLOBAL: u8 = 1;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+/// The ELF name, type, flags, and segment permissions of each section of an executable
+fn section_kind(id: SectionId) -> (&'static str, u32, u64, u32) {
+    match id {
+        SectionId::Code => (".text", SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, PF_R | PF_X),
+        SectionId::RoData => (".rodata", SHT_PROGBITS, SHF_ALLOC, PF_R),
+        SectionId::Static => (".data", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, PF_R | PF_W),
+        SectionId::Bss => (".bss", SHT_NOBITS, SHF_ALLOC | SHF_WRITE, PF_R | PF_W),
+    }
+}
+
+/// A section of the executable and where it is placed in memory and in the file
+struct SectionInfo {
+    id: SectionId,
+    addr: u64,
+    size: u64,
+    /// The offset of the section data in the file (`.bss` takes up no space in the file)
+    offset: u64,
+}
+
+/// A table of null-terminated strings, referred to by their offset into the table
+struct StrTab {
+    bytes: Vec<u8>,
+}
+
+impl StrTab {
+    fn new() -> Self {
+        // Offset zero is always the empty string
+        Self {bytes: vec![0]}
+    }
+
+    fn add(&mut self, name: &str) -> u32 {
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(name.as_bytes());
+        self.bytes.push(0);
+        offset
+    }
+}
+
+/// Writes the given executable as an ELF file with a symbol for every label
+///
+/// The sections are named `.text`, `.rodata`, `.data` (for `.static`), and `.bss`. Labels in the
+/// code section are function symbols and all other labels are object symbols. The size of each
+/// symbol extends to the next label in the same section (or to the end of the section). The
+/// labels generated for `$here` have no symbols and do not shorten the symbols before them.
+pub fn write_elf<W: Write>(mut out: W, exec: &Executable, labels: &LabelOffsets) -> io::Result<()> {
+    let image = exec.to_flat_binary();
+
+    // The section data is placed right after the ELF header and program headers
+    let segments = SectionId::ALL.iter()
+        .filter(|&&id| !exec.section(id).is_empty())
+        .count() as u64;
+    let data_offset = ELF_HEADER_SIZE + segments * PROGRAM_HEADER_SIZE;
+
+    let mut sections = Vec::new();
+    let mut addr = exec.base_addr;
+    let mut offset = data_offset;
+    for &id in &SectionId::ALL {
+        let size: u64 = exec.section(id).iter().map(|stmt| stmt.size_bytes()).sum();
+        sections.push(SectionInfo {id, addr, size, offset});
+        addr += size;
+        if id != SectionId::Bss {
+            offset += size;
+        }
+    }
+    // Every section except `.bss` is stored in the file
+    let file_image = &image[..(offset - data_offset) as usize];
+
+    let mut strtab = StrTab::new();
+    let mut symtab = Vec::new();
+    // The first symbol is always the null symbol
+    symtab.extend_from_slice(&[0; SYMBOL_SIZE as usize]);
+    let sorted_labels = labels.sorted_labels();
+    for (i, &(addr, name, id)) in sorted_labels.iter().enumerate() {
+        let section = &sections[id.order()];
+        let end_addr = sorted_labels[i + 1..].iter()
+            .find(|&&(next_addr, _, next_id)| next_id == id && next_addr > addr)
+            .map(|&(next_addr, _, _)| next_addr)
+            .unwrap_or(section.addr + section.size);
+        let symbol_type = if id == SectionId::Code { STT_FUNC } else { STT_OBJECT };
+
+        symtab.extend_from_slice(&strtab.add(name.as_str()).to_le_bytes());
+        symtab.push(STB_GLOBAL << 4 | symbol_type);
+        // st_other (default visibility)
+        symtab.push(0);
+        // st_shndx (section header 0 is the null section)
+        symtab.extend_from_slice(&(id.order() as u16 + 1).to_le_bytes());
+        symtab.extend_from_slice(&addr.to_le_bytes());
+        symtab.extend_from_slice(&(end_addr - addr).to_le_bytes());
+    }
+
+    let mut shstrtab = StrTab::new();
+    let section_names: Vec<_> = sections.iter()
+        .map(|section| shstrtab.add(section_kind(section.id).0))
+        .collect();
+    let symtab_name = shstrtab.add(".symtab");
+    let strtab_name = shstrtab.add(".strtab");
+    let shstrtab_name = shstrtab.add(".shstrtab");
+
+    let symtab_offset = align8(data_offset + file_image.len() as u64);
+    let strtab_offset = symtab_offset + symtab.len() as u64;
+    let shstrtab_offset = strtab_offset + strtab.bytes.len() as u64;
+    let section_headers_offset = align8(shstrtab_offset + shstrtab.bytes.len() as u64);
+    // The null section, the sections of the executable, `.symtab`, `.strtab`, and `.shstrtab`
+    let section_headers = 1 + sections.len() as u16 + 3;
+    let symtab_index = 1 + sections.len() as u32;
+
+    // ELF header
+    out.write_all(ELF_MAGIC)?;
+    // 64-bit, little-endian, version 1, System V ABI, padding
+    out.write_all(&[2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0])?;
+    // e_type (ET_EXEC), e_machine (EM_NONE), e_version
+    out.write_all(&2u16.to_le_bytes())?;
+    out.write_all(&0u16.to_le_bytes())?;
+    out.write_all(&1u32.to_le_bytes())?;
+    // e_entry, e_phoff, e_shoff, e_flags
+    out.write_all(&exec.base_addr.to_le_bytes())?;
+    out.write_all(&ELF_HEADER_SIZE.to_le_bytes())?;
+    out.write_all(&section_headers_offset.to_le_bytes())?;
+    out.write_all(&0u32.to_le_bytes())?;
+    // e_ehsize, e_phentsize, e_phnum, e_shentsize, e_shnum, e_shstrndx
+    out.write_all(&(ELF_HEADER_SIZE as u16).to_le_bytes())?;
+    out.write_all(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes())?;
+    out.write_all(&(segments as u16).to_le_bytes())?;
+    out.write_all(&(SECTION_HEADER_SIZE as u16).to_le_bytes())?;
+    out.write_all(&section_headers.to_le_bytes())?;
+    out.write_all(&(section_headers - 1).to_le_bytes())?;
+
+    // Program headers
+    for section in &sections {
+        if exec.section(section.id).is_empty() {
+            continue;
+        }
+        let (_, _, _, permissions) = section_kind(section.id);
+        let file_size = if section.id == SectionId::Bss { 0 } else { section.size };
+
+        // p_type, p_flags
+        out.write_all(&PT_LOAD.to_le_bytes())?;
+        out.write_all(&permissions.to_le_bytes())?;
+        // p_offset, p_vaddr, p_paddr, p_filesz, p_memsz, p_align
+        out.write_all(&section.offset.to_le_bytes())?;
+        out.write_all(&section.addr.to_le_bytes())?;
+        out.write_all(&section.addr.to_le_bytes())?;
+        out.write_all(&file_size.to_le_bytes())?;
+        out.write_all(&section.size.to_le_bytes())?;
+        out.write_all(&1u64.to_le_bytes())?;
+    }
+
+    out.write_all(file_image)?;
+    write_padding(&mut out, symtab_offset - (data_offset + file_image.len() as u64))?;
+    out.write_all(&symtab)?;
+    out.write_all(&strtab.bytes)?;
+    out.write_all(&shstrtab.bytes)?;
+    write_padding(&mut out, section_headers_offset - (shstrtab_offset + shstrtab.bytes.len() as u64))?;
+
+    // Section headers
+    write_section_header(&mut out, SectionHeader::default())?;
+    for (section, &name) in sections.iter().zip(&section_names) {
+        let (_, kind, flags, _) = section_kind(section.id);
+        write_section_header(&mut out, SectionHeader {
+            name,
+            kind,
+            flags,
+            addr: section.addr,
+            offset: section.offset,
+            size: section.size,
+            align: 1,
+            ..SectionHeader::default()
+        })?;
+    }
+    write_section_header(&mut out, SectionHeader {
+        name: symtab_name,
+        kind: SHT_SYMTAB,
+        offset: symtab_offset,
+        size: symtab.len() as u64,
+        // The string table of the symbol names
+        link: symtab_index + 1,
+        // The index of the first non-local symbol
+        info: 1,
+        align: 8,
+        entry_size: SYMBOL_SIZE,
+        ..SectionHeader::default()
+    })?;
+    write_section_header(&mut out, SectionHeader {
+        name: strtab_name,
+        kind: SHT_STRTAB,
+        offset: strtab_offset,
+        size: strtab.bytes.len() as u64,
+        align: 1,
+        ..SectionHeader::default()
+    })?;
+    write_section_header(&mut out, SectionHeader {
+        name: shstrtab_name,
+        kind: SHT_STRTAB,
+        offset: shstrtab_offset,
+        size: shstrtab.bytes.len() as u64,
+        align: 1,
+        ..SectionHeader::default()
+    })?;
+
+    out.flush()
+}
+
+#[derive(Default)]
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    addr: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entry_size: u64,
+}
+
+fn write_section_header<W: Write>(out: &mut W, header: SectionHeader) -> io::Result<()> {
+    let SectionHeader {name, kind, flags, addr, offset, size, link, info, align, entry_size} = header;
+    out.write_all(&name.to_le_bytes())?;
+    out.write_all(&kind.to_le_bytes())?;
+    out.write_all(&flags.to_le_bytes())?;
+    out.write_all(&addr.to_le_bytes())?;
+    out.write_all(&offset.to_le_bytes())?;
+    out.write_all(&size.to_le_bytes())?;
+    out.write_all(&link.to_le_bytes())?;
+    out.write_all(&info.to_le_bytes())?;
+    out.write_all(&align.to_le_bytes())?;
+    out.write_all(&entry_size.to_le_bytes())
+}
+
+fn write_padding<W: Write>(out: &mut W, len: u64) -> io::Result<()> {
+    out.write_all(&vec![0; len as usize])
+}
+
+fn align8(offset: u64) -> u64 {
+    (offset + 7) & !7
+}
+
+/// An error that occurs while reading an ELF file (see `read_elf`)
+#[derive(Debug, Clone, PartialEq)]
+pub enum ElfError {
+    /// The file does not start with the ELF magic number
+    NotElf,
+    /// The file is a kind of ELF file that cannot be run by the VM
+    Unsupported(String),
+    /// The file ends before the end of the given part of it
+    Truncated(&'static str),
+    /// The segment loaded at the given address cannot be loaded by the VM
+    InvalidSegment {addr: u64, reason: String},
+    /// The entry point is not in an executable segment
+    EntryNotInCode(u64),
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElfError::NotElf => write!(f, "the file is not an ELF file"),
+            ElfError::Unsupported(reason) => write!(f, "unsupported ELF file: {}", reason),
+            ElfError::Truncated(part) => write!(f, "the file ends before the end of the {} (it may be truncated)", part),
+            ElfError::InvalidSegment {addr, reason} => write!(f, "cannot load the segment at `0x{:x}`: {}", addr, reason),
+            ElfError::EntryNotInCode(addr) => write!(f, "the entry point `0x{:x}` is not in the executable segment at the start of the program", addr),
+        }
+    }
+}
+
+impl Error for ElfError {}
+
+/// Returns true if the given bytes start with the ELF magic number
+pub fn is_elf(bytes: &[u8]) -> bool {
+    bytes.starts_with(ELF_MAGIC)
+}
+
+/// A loadable segment of an ELF file
+struct Segment<'a> {
+    addr: u64,
+    flags: u32,
+    /// The bytes stored in the file, which are followed by zeros up to `mem_size`
+    data: &'a [u8],
+    mem_size: u64,
+}
+
+/// Reads an ELF file with the format written by `write_elf`, returning the executable and the
+/// address of its entry point
+///
+/// Only the loadable segments are used. Executable segments become the code section, read-only
+/// segments become the `.rodata` section, and writable segments become the `.static` section
+/// (with any zero-filled bytes at the end of a writable segment in the `.bss` section). Since the
+/// sections of an executable are laid out one after the other, the segments must be in that
+/// order. Any gaps between segments are uninitialized.
+pub fn read_elf(bytes: &[u8]) -> Result<(Executable, u64), ElfError> {
+    if !is_elf(bytes) {
+        return Err(ElfError::NotElf);
+    }
+    if bytes.len() < ELF_HEADER_SIZE as usize {
+        return Err(ElfError::Truncated("ELF header"));
+    }
+    let read_u16 = |offset: usize| u16::from_le_bytes(bytes[offset..offset+2].try_into().unwrap());
+    let read_u64 = |offset: usize| u64::from_le_bytes(bytes[offset..offset+8].try_into().unwrap());
+
+    if bytes[4] != 2 {
+        return Err(ElfError::Unsupported("only 64-bit ELF files are supported".to_string()));
+    }
+    if bytes[5] != 1 {
+        return Err(ElfError::Unsupported("only little-endian ELF files are supported".to_string()));
+    }
+    if read_u16(16) != 2 {
+        return Err(ElfError::Unsupported("only executable ELF files (`ET_EXEC`) are supported".to_string()));
+    }
+    let machine = read_u16(18);
+    if machine != 0 {
+        return Err(ElfError::Unsupported(format!("machine type {} is not supported (the VM can only run ELF files with no machine type)", machine)));
+    }
+    let entry = read_u64(24);
+    let program_headers_offset = read_u64(32);
+    if read_u16(54) as u64 != PROGRAM_HEADER_SIZE {
+        return Err(ElfError::Unsupported(format!("program headers must be {} bytes", PROGRAM_HEADER_SIZE)));
+    }
+    let program_headers = read_u16(56) as u64;
+
+    let mut segments = Vec::new();
+    for i in 0..program_headers {
+        let header = program_headers_offset.checked_add(i * PROGRAM_HEADER_SIZE)
+            .and_then(|start| Some(start..start.checked_add(PROGRAM_HEADER_SIZE)?))
+            .and_then(|range| bytes.get(range.start as usize..range.end as usize))
+            .ok_or(ElfError::Truncated("program headers"))?;
+        let read_u32 = |offset: usize| u32::from_le_bytes(header[offset..offset+4].try_into().unwrap());
+        let read_u64 = |offset: usize| u64::from_le_bytes(header[offset..offset+8].try_into().unwrap());
+
+        let (kind, flags, offset, addr, file_size, mem_size) = (read_u32(0), read_u32(4), read_u64(8), read_u64(16), read_u64(32), read_u64(40));
+        if kind != PT_LOAD || mem_size == 0 {
+            continue;
+        }
+        if file_size > mem_size {
+            return Err(ElfError::InvalidSegment {addr, reason: "the segment is larger in the file than in memory".to_string()});
+        }
+        let data = offset.checked_add(file_size)
+            .and_then(|end| bytes.get(offset as usize..end as usize))
+            .ok_or(ElfError::Truncated("segment data"))?;
+        segments.push(Segment {addr, flags, data, mem_size});
+    }
+    segments.sort_by_key(|segment| segment.addr);
+
+    let base_addr = segments.first().map(|segment| segment.addr).unwrap_or(entry);
+    let mut exec = Executable::from_flat_binary(base_addr, Vec::new());
+    let mut addr = base_addr;
+    let mut current = SectionId::Code;
+    for segment in &segments {
+        let (data_section, zero_section) = if segment.flags & PF_X != 0 {
+            (SectionId::Code, SectionId::Code)
+        } else if segment.flags & PF_W == 0 {
+            (SectionId::RoData, SectionId::RoData)
+        } else {
+            (SectionId::Static, SectionId::Bss)
+        };
+        let first_section = if segment.data.is_empty() { zero_section } else { data_section };
+
+        if segment.addr < addr {
+            return Err(ElfError::InvalidSegment {addr: segment.addr, reason: "the segment overlaps the previous segment".to_string()});
+        }
+        if first_section.order() < current.order() {
+            return Err(ElfError::InvalidSegment {addr: segment.addr, reason: format!(
+                "a `{}` segment cannot be loaded after a `{}` segment (segments must be ordered: code, read-only data, writable data)",
+                section_kind(first_section).0, section_kind(current).0,
+            )});
+        }
+
+        let gap = segment.addr - addr;
+        if gap > 0 {
+            exec.section_mut(current).push(Stmt::StaticData(StaticData::StaticUninit(StaticUninit {nbytes: gap})));
+        }
+        if !segment.data.is_empty() {
+            let bytes = StaticByteStr {bytes: segment.data.into()};
+            exec.section_mut(data_section).push(Stmt::StaticData(StaticData::StaticByteStr(bytes)));
+            current = data_section;
+        }
+        let zeros = segment.mem_size - segment.data.len() as u64;
+        if zeros > 0 {
+            exec.section_mut(zero_section).push(Stmt::StaticData(StaticData::StaticZero(StaticZero {nbytes: zeros})));
+            current = zero_section;
+        }
+
+        addr = segment.addr.checked_add(segment.mem_size)
+            .ok_or_else(|| ElfError::InvalidSegment {addr: segment.addr, reason: "the segment extends past the end of memory".to_string()})?;
+    }
+
+    let code_size: u64 = exec.code_section.iter().map(|stmt| stmt.size_bytes()).sum();
+    if entry < base_addr || entry - base_addr >= code_size {
+        return Err(ElfError::EntryNotInCode(entry));
+    }
+
+    Ok((exec, entry))
+}
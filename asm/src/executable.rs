@@ -2,33 +2,672 @@ mod binary_format;
 
 pub use binary_format::*;
 
+use std::fmt;
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::convert::TryInto;
+
 use serde::{Serialize, Deserialize};
 
 use crate::asm;
 use crate::label_offsets::LabelOffsets;
 use crate::diagnostics::Diagnostics;
+use crate::debug_info::DebugInfo;
+
+/// The default address that the start of the code section is loaded at
+///
+/// Nothing is placed below this address, so any access to the first page of memory (including
+/// address zero) is always a bug.
+pub const DEFAULT_BASE_ADDR: u64 = 0x1000;
+
+/// The bytes at the start of every executable written by `Executable::write_to`
+///
+/// Executables written by older versions of the assembler start with the base address instead.
+/// Read as a base address, these bytes would be far outside of any memory the VM can allocate, so
+/// the two formats can always be told apart.
+const EXEC_MAGIC: &[u8; 8] = b"\0wolfexe";
+
+/// The size in bytes of the header at the start of every executable: the magic bytes followed by
+/// the content hash
+const HEADER_SIZE: usize = 16;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Executable {
+    /// The address that the start of the code section must be loaded at
+    ///
+    /// All label offsets are relative to address zero, so the executable must be loaded at exactly
+    /// this address. Execution begins at this address.
+    pub base_addr: u64,
+    /// The sections of the executable, laid out one after the other in this order
     pub code_section: Vec<Stmt>,
+    pub rodata_section: Vec<Stmt>,
     pub static_section: Vec<Stmt>,
+    pub bss_section: Vec<Stmt>,
+    /// Every immediate that holds the address of a label, or `None` if the executable was not
+    /// assembled to be relocatable
+    ///
+    /// These are written in a trailer after the rest of the executable (see `write_to`).
+    #[serde(skip)]
+    pub relocations: Option<Vec<Relocation>>,
+    /// The labels and source lines of the program, or `None` if the executable was not assembled
+    /// with debug info
+    ///
+    /// This is written after the rest of the executable along with the relocations.
+    #[serde(skip)]
+    pub debug_info: Option<DebugInfo>,
+}
+
+/// The data written after the rest of an executable (see `Executable::write_to`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Trailer {
+    relocations: Option<Vec<Relocation>>,
+    debug_info: Option<DebugInfo>,
 }
 
 impl Executable {
-    pub fn layout_executable(prog: asm::Program, diag: &Diagnostics, labels: &LabelOffsets) -> Self {
-        let asm::Program {code_section, static_section} = prog;
+    /// Lays out every section of the program, recording relocations if `relocatable` is true
+    pub fn layout_executable(prog: asm::Program, diag: &Diagnostics, labels: &LabelOffsets, relocatable: bool) -> Self {
+        let relocations = if relocatable {
+            let sections = [
+                (SectionId::Code, &prog.code_section),
+                (SectionId::RoData, &prog.rodata_section),
+                (SectionId::Static, &prog.static_section),
+                (SectionId::Bss, &prog.bss_section),
+            ];
+            let relocations = sections.iter()
+                .filter_map(|(id, section)| section.as_ref().map(|section| section_relocations(*id, section)))
+                .flatten()
+                .collect();
+            Some(relocations)
+        } else {
+            None
+        };
+
+        let asm::Program {code_section, rodata_section, static_section, bss_section, exports: _, externs: _} = prog;
 
+        let base_addr = labels.base_addr();
         let code_section = code_section.map(|section| layout_section(section, diag, labels)).unwrap_or_default();
+        let rodata_section = rodata_section.map(|section| layout_section(section, diag, labels)).unwrap_or_default();
         let static_section = static_section.map(|section| layout_section(section, diag, labels)).unwrap_or_default();
+        let bss_section = bss_section.map(|section| layout_section(section, diag, labels)).unwrap_or_default();
+
+        Self {base_addr, code_section, rodata_section, static_section, bss_section, relocations, debug_info: None}
+    }
+
+    /// Returns the statements in the given section
+    pub fn section(&self, id: SectionId) -> &[Stmt] {
+        match id {
+            SectionId::Code => &self.code_section,
+            SectionId::RoData => &self.rodata_section,
+            SectionId::Static => &self.static_section,
+            SectionId::Bss => &self.bss_section,
+        }
+    }
+
+    pub(crate) fn section_mut(&mut self, id: SectionId) -> &mut Vec<Stmt> {
+        match id {
+            SectionId::Code => &mut self.code_section,
+            SectionId::RoData => &mut self.rodata_section,
+            SectionId::Static => &mut self.static_section,
+            SectionId::Bss => &mut self.bss_section,
+        }
+    }
+
+    /// Checks that the executable can be loaded into `memory_size` bytes of memory and started at
+    /// its base address
+    ///
+    /// Executables that fail these checks cannot run correctly. Apart from programs with no code,
+    /// the assembler never produces them, so this mostly catches files that were corrupted or
+    /// generated by something else.
+    pub fn validate(&self, memory_size: u64) -> Result<(), ValidationError> {
+        // Execution starts at the beginning of the code section
+        if self.code_section.is_empty() {
+            return Err(ValidationError::NoCode);
+        }
+
+        for &section in &[SectionId::RoData, SectionId::Bss] {
+            if let Some(index) = self.section(section).iter().position(|stmt| matches!(stmt, Stmt::Instr(_))) {
+                return Err(ValidationError::InstrNotAllowed {section, index});
+            }
+        }
+        let initialized_bss = self.bss_section.iter().position(|stmt| !matches!(stmt,
+            Stmt::StaticData(StaticData::StaticZero(_)) | Stmt::StaticData(StaticData::StaticUninit(_))));
+        if let Some(index) = initialized_bss {
+            return Err(ValidationError::InitializedBss {index});
+        }
+
+        // Computed with 128 bits so that a corrupted size cannot overflow
+        let size: u128 = SectionId::ALL.iter()
+            .flat_map(|&id| self.section(id))
+            .map(|stmt| stmt.size_bytes() as u128)
+            .sum();
+        let end_addr = self.base_addr as u128 + size;
+        if end_addr > memory_size as u128 {
+            return Err(ValidationError::DoesNotFit {base_addr: self.base_addr, end_addr, memory_size});
+        }
+
+        Ok(())
+    }
+
+    /// Updates the address of every label so the executable can be loaded at `base_addr`
+    /// instead of the address it was assembled for
+    pub fn relocate(&mut self, base_addr: u64) -> Result<(), RelocationError> {
+        // Wrapping arithmetic since the difference may be negative
+        let delta = base_addr.wrapping_sub(self.base_addr) as i64 as i128;
+
+        let relocations = self.relocations.take().ok_or(RelocationError::NotRelocatable)?;
+        for reloc in &relocations {
+            let layout = match self.section_mut(reloc.section).get_mut(reloc.index) {
+                Some(Stmt::Instr(instr)) => &mut instr.layout,
+                _ => return Err(RelocationError::InvalidRelocation(reloc.clone())),
+            };
+
+            let value = layout.immediate(reloc.imm_index)
+                .ok_or_else(|| RelocationError::InvalidRelocation(reloc.clone()))?;
+            if !layout.set_immediate(reloc.imm_index, value + delta) {
+                return Err(RelocationError::DoesNotFit {relocation: reloc.clone(), value: value + delta});
+            }
+        }
+
+        if let Some(debug_info) = &mut self.debug_info {
+            debug_info.offset_addrs(delta as u64);
+        }
+
+        self.base_addr = base_addr;
+        self.relocations = Some(relocations);
+        Ok(())
+    }
+}
+
+/// A problem with an executable that would prevent it from being run (see `Executable::validate`)
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    /// The code section is empty, so there is no instruction to start executing at
+    NoCode,
+    /// A section contains an instruction even though it may only contain data
+    InstrNotAllowed {section: SectionId, index: usize},
+    /// The `.bss` section contains data other than `.zero` and `.uninit`
+    InitializedBss {index: usize},
+    /// The executable extends past the end of memory when it is loaded at its base address
+    DoesNotFit {base_addr: u64, end_addr: u128, memory_size: u64},
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::NoCode => write!(f, "the executable has no instructions (its `.code` section is empty)"),
+            ValidationError::InstrNotAllowed {section, index} => write!(f, "statement {} of section {} is an instruction, but instructions are not allowed in that section",
+                index, section),
+            ValidationError::InitializedBss {index} => write!(f, "statement {} of section .bss initializes data, but only `.zero` and `.uninit` are allowed in that section",
+                index),
+            ValidationError::DoesNotFit {base_addr, end_addr, memory_size} => write!(f, "the executable is loaded at `0x{:x}` and ends at `0x{:x}`, past the end of memory at `0x{:x}`",
+                base_addr, end_addr, memory_size),
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+/// An error that occurs while relocating an executable
+#[derive(Debug, Clone, PartialEq)]
+pub enum RelocationError {
+    /// The executable was not assembled to be relocatable
+    NotRelocatable,
+    /// The relocation does not refer to an immediate in an instruction
+    InvalidRelocation(Relocation),
+    /// The relocated address does not fit in the immediate it is stored in
+    DoesNotFit {relocation: Relocation, value: i128},
+}
 
-        Self {code_section, static_section}
+impl fmt::Display for RelocationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RelocationError::NotRelocatable => write!(f, "executable does not contain relocations (assemble it with `--relocatable`)"),
+            RelocationError::InvalidRelocation(reloc) => write!(f, "invalid relocation: no immediate {} in instruction {} of section {}",
+                reloc.imm_index, reloc.index, reloc.section),
+            RelocationError::DoesNotFit {relocation, value} => write!(f, "relocated address `0x{:x}` does not fit in the immediate of instruction {} of section {}",
+                value, relocation.index, relocation.section),
+        }
     }
 }
 
-fn layout_section(section: asm::Section, diag: &Diagnostics, labels: &LabelOffsets) -> Vec<Stmt> {
+impl Error for RelocationError {}
+
+/// A part of an executable, as found in its serialized form
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExecutablePart<'a> {
+    /// The address that the executable must be loaded at
+    BaseAddr(u64),
+    /// The number of statements in the given section
+    SectionLen {section: &'static str, len: usize},
+    /// The statement at the given index in the given section
+    Stmt {section: &'static str, index: usize, stmt: &'a Stmt},
+    /// The number of relocations
+    RelocationsLen(usize),
+    /// The relocation at the given index
+    Relocation {index: usize, relocation: &'a Relocation},
+    /// The debug info
+    DebugInfo,
+    /// The magic bytes at the start of the executable
+    Magic,
+    /// The content hash in the header of the executable
+    ContentHash,
+}
+
+impl<'a> fmt::Display for ExecutablePart<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecutablePart::BaseAddr(addr) => write!(f, "base address {:#x}", addr),
+            ExecutablePart::SectionLen {section, len} => write!(f, "length of section .{} ({} statements)", section, len),
+            ExecutablePart::Stmt {section, index, stmt} => write!(f, "statement {} of section .{}: {:?}", index, section, stmt),
+            ExecutablePart::RelocationsLen(len) => write!(f, "number of relocations ({})", len),
+            ExecutablePart::Relocation {index, relocation} => write!(f, "relocation {}: {:?}", index, relocation),
+            ExecutablePart::DebugInfo => write!(f, "debug info"),
+            ExecutablePart::Magic => write!(f, "magic bytes"),
+            ExecutablePart::ContentHash => write!(f, "content hash"),
+        }
+    }
+}
+
+impl Executable {
+    /// Serializes the executable into the format written by the assembler
+    pub fn to_bytes(&self) -> bincode::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.write_to(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Writes the executable in the format written by the assembler
+    ///
+    /// The executable starts with a header containing `EXEC_MAGIC` and the content hash of
+    /// everything after the header (see `content_hash`). The relocations and debug info (if any)
+    /// are written in a trailer after the sections. The output only depends on the contents of the
+    /// executable, so assembling the same input always produces exactly the same bytes.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> bincode::Result<()> {
+        let content = self.content_bytes()?;
+        writer.write_all(EXEC_MAGIC)?;
+        writer.write_all(&content_hash(&content).to_le_bytes())?;
+        writer.write_all(&content)?;
+        Ok(())
+    }
+
+    /// Returns the content hash stored in the header of the executable
+    ///
+    /// This is a 64-bit FNV-1a hash of every byte after the header, so two executables have the
+    /// same hash if and only if (barring collisions) they have the same contents.
+    pub fn content_hash(&self) -> bincode::Result<u64> {
+        Ok(content_hash(&self.content_bytes()?))
+    }
+
+    /// Returns the serialized executable and trailer, without the header
+    fn content_bytes(&self) -> bincode::Result<Vec<u8>> {
+        let mut bytes = bincode::serialize(self)?;
+        let trailer = Trailer {
+            relocations: self.relocations.clone(),
+            debug_info: self.debug_info.clone(),
+        };
+        bincode::serialize_into(&mut bytes, &trailer)?;
+        Ok(bytes)
+    }
+
+    /// Reads an executable in the format written by `write_to`
+    ///
+    /// The content hash in the header must match the rest of the executable. Executables written
+    /// by older versions of the assembler are also accepted (see `read_headerless`).
+    pub fn read_from<R: Read>(mut reader: R) -> bincode::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        if bytes.is_empty() {
+            return Err(Box::new(bincode::ErrorKind::Custom("the file is empty".to_string())));
+        }
+
+        if !bytes.starts_with(EXEC_MAGIC) {
+            return Self::read_headerless(&bytes);
+        }
+        if bytes.len() < HEADER_SIZE {
+            return Err(truncated_error(bytes.len()));
+        }
+
+        let (header, content) = bytes.split_at(HEADER_SIZE);
+        // Decoded before checking the hash so that a truncated executable is reported as such
+        let mut rest = content;
+        let mut exec = deserialize_sections(&mut rest, bytes.len())?;
+        let Trailer {relocations, debug_info} = bincode::deserialize_from(&mut rest)
+            .map_err(|err| eof_as_truncated(err, bytes.len()))?;
+        exec.relocations = relocations;
+        exec.debug_info = debug_info;
+
+        let expected_hash = u64::from_le_bytes(header[EXEC_MAGIC.len()..].try_into().unwrap());
+        let actual_hash = content_hash(content);
+        if expected_hash != actual_hash {
+            return Err(Box::new(bincode::ErrorKind::Custom(format!(
+                "content hash `{:016x}` does not match the contents of the executable (`{:016x}`)",
+                expected_hash, actual_hash,
+            ))));
+        }
+
+        Ok(exec)
+    }
+
+    /// Reads an executable written before the header was added
+    ///
+    /// These executables start directly with the sections, which may be followed by one of:
+    ///
+    /// * nothing
+    /// * the relocations
+    /// * a trailer with the relocations and debug info
+    /// * a trailer followed by the content hash of everything before it
+    fn read_headerless(bytes: &[u8]) -> bincode::Result<Self> {
+        let mut rest = bytes;
+        let mut exec = deserialize_sections(&mut rest, bytes.len())?;
+        if rest.is_empty() {
+            return Ok(exec);
+        }
+
+        // Each kind of data after the sections is only accepted if it takes up exactly the rest of
+        // the file
+        if rest.len() >= 8 {
+            let (trailer, hash) = rest.split_at(rest.len() - 8);
+            let content_len = bytes.len() - hash.len();
+            if u64::from_le_bytes(hash.try_into().unwrap()) == content_hash(&bytes[..content_len]) {
+                let Trailer {relocations, debug_info} = bincode::deserialize(trailer)?;
+                exec.relocations = relocations;
+                exec.debug_info = debug_info;
+                return Ok(exec);
+            }
+        }
+
+        let mut trailer_bytes = rest;
+        if let Ok(Trailer {relocations, debug_info}) = bincode::deserialize_from(&mut trailer_bytes) {
+            if trailer_bytes.is_empty() {
+                exec.relocations = relocations;
+                exec.debug_info = debug_info;
+                return Ok(exec);
+            }
+        }
+
+        let relocations = bincode::deserialize_from(&mut rest)
+            .map_err(|err| eof_as_truncated(err, bytes.len()))?;
+        if !rest.is_empty() {
+            return Err(Box::new(bincode::ErrorKind::Custom(
+                "the file contains unexpected data after the end of the executable".to_string(),
+            )));
+        }
+        exec.relocations = Some(relocations);
+
+        Ok(exec)
+    }
+
+    /// Returns the bytes of every section exactly as they are loaded into memory, starting from
+    /// the base address
+    ///
+    /// `.uninit` bytes have no defined value, so they are written as zeros.
+    pub fn to_flat_binary(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for &id in &SectionId::ALL {
+            for stmt in self.section(id) {
+                write_flat_stmt(stmt, &mut bytes);
+            }
+        }
+        bytes
+    }
+
+    /// Creates an executable from the bytes of a flat binary image (e.g. one written by
+    /// `to_flat_binary`) that should be loaded at `base_addr`
+    ///
+    /// A flat binary does not record where its sections start, so the entire image becomes the
+    /// code section. Execution begins at the first byte.
+    pub fn from_flat_binary(base_addr: u64, bytes: Vec<u8>) -> Self {
+        let code_section = if bytes.is_empty() {
+            Vec::new()
+        } else {
+            vec![Stmt::StaticData(StaticData::StaticByteStr(StaticByteStr {bytes: bytes.into()}))]
+        };
+
+        Self {
+            base_addr,
+            code_section,
+            rodata_section: Vec::new(),
+            static_section: Vec::new(),
+            bss_section: Vec::new(),
+            relocations: None,
+            debug_info: None,
+        }
+    }
+
+    /// Returns the part of the executable that contains the byte at the given offset into the
+    /// serialized executable (see `to_bytes`), or `None` if the offset is past the end
+    pub fn part_at_offset(&self, offset: u64) -> Option<ExecutablePart<'_>> {
+        // The size in bytes of the base address and of the length that precedes each section
+        const LEN_SIZE: u64 = 8;
+
+        if offset < EXEC_MAGIC.len() as u64 {
+            return Some(ExecutablePart::Magic);
+        }
+        if offset < HEADER_SIZE as u64 {
+            return Some(ExecutablePart::ContentHash);
+        }
+        let mut start = HEADER_SIZE as u64 + LEN_SIZE;
+        if offset < start {
+            return Some(ExecutablePart::BaseAddr(self.base_addr));
+        }
+
+        let sections = [
+            ("code", &self.code_section),
+            ("rodata", &self.rodata_section),
+            ("static", &self.static_section),
+            ("bss", &self.bss_section),
+        ];
+        for &(section, stmts) in &sections {
+            start += LEN_SIZE;
+            if offset < start {
+                return Some(ExecutablePart::SectionLen {section, len: stmts.len()});
+            }
+
+            for (index, stmt) in stmts.iter().enumerate() {
+                start += bincode::serialized_size(stmt).ok()?;
+                if offset < start {
+                    return Some(ExecutablePart::Stmt {section, index, stmt});
+                }
+            }
+        }
+
+        // The size in bytes of the tag that indicates whether an `Option` is present
+        const OPTION_TAG_SIZE: u64 = 1;
+
+        start += OPTION_TAG_SIZE;
+        if let Some(relocations) = &self.relocations {
+            start += LEN_SIZE;
+            if offset < start {
+                return Some(ExecutablePart::RelocationsLen(relocations.len()));
+            }
+
+            for (index, relocation) in relocations.iter().enumerate() {
+                start += bincode::serialized_size(relocation).ok()?;
+                if offset < start {
+                    return Some(ExecutablePart::Relocation {index, relocation});
+                }
+            }
+        } else if offset < start {
+            return Some(ExecutablePart::RelocationsLen(0));
+        }
+
+        start += bincode::serialized_size(&self.debug_info).ok()?;
+        if offset < start {
+            return Some(ExecutablePart::DebugInfo);
+        }
+
+        None
+    }
+}
+
+/// The format of executables generated before the base address was stored in the executable
+///
+/// These executables must always be loaded at address zero.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LegacyExecutable {
+    pub code_section: Vec<Stmt>,
+    pub static_section: Vec<Stmt>,
+}
+
+impl From<LegacyExecutable> for Executable {
+    fn from(exec: LegacyExecutable) -> Self {
+        let LegacyExecutable {code_section, static_section} = exec;
+        Self {
+            base_addr: 0,
+            code_section,
+            rodata_section: Vec::new(),
+            static_section,
+            bss_section: Vec::new(),
+            relocations: None,
+            debug_info: None,
+        }
+    }
+}
+
+/// Computes the 64-bit FNV-1a hash of the given bytes
+///
+/// Unlike the hashers in the standard library, this is guaranteed to produce the same value on
+/// every platform and with every version of Rust.
+fn content_hash(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    bytes.iter().fold(OFFSET_BASIS, |hash, &byte| (hash ^ byte as u64).wrapping_mul(PRIME))
+}
+
+/// Deserializes the base address and sections of an executable of `len` bytes, leaving `bytes`
+/// at the data after them
+fn deserialize_sections(bytes: &mut &[u8], len: usize) -> bincode::Result<Executable> {
+    bincode::deserialize_from(bytes).map_err(|err| match *err {
+        bincode::ErrorKind::Io(ref io_err) if io_err.kind() == io::ErrorKind::UnexpectedEof => truncated_error(len),
+        _ => Box::new(bincode::ErrorKind::Custom(format!(
+            "the file is not an executable generated by wolf-asm or it is corrupted ({})",
+            err,
+        ))),
+    })
+}
+
+/// Returns the error for an executable that ends after `len` bytes, before all of its contents
+fn truncated_error(len: usize) -> bincode::Error {
+    Box::new(bincode::ErrorKind::Custom(format!(
+        "the file ends after {} bytes, before the end of the executable (it may be truncated)",
+        len,
+    )))
+}
+
+/// Replaces an error caused by reaching the end of an executable of `len` bytes with one that
+/// explains that the executable may be truncated
+fn eof_as_truncated(err: bincode::Error, len: usize) -> bincode::Error {
+    match *err {
+        bincode::ErrorKind::Io(ref io_err) if io_err.kind() == io::ErrorKind::UnexpectedEof => truncated_error(len),
+        _ => err,
+    }
+}
+
+/// Appends the bytes of the given statement to `bytes` as they are loaded into memory
+fn write_flat_stmt(stmt: &Stmt, bytes: &mut Vec<u8>) {
+    match stmt {
+        // Instructions are stored in little-endian byte order, like any other 64-bit value
+        Stmt::Instr(instr) => bytes.extend_from_slice(&instr.to_binary().to_le_bytes()),
+        Stmt::StaticData(StaticData::StaticBytes(data)) => match data {
+            StaticBytes::B1(data) => bytes.extend_from_slice(data),
+            StaticBytes::B2(data) => bytes.extend_from_slice(data),
+            StaticBytes::B4(data) => bytes.extend_from_slice(data),
+            StaticBytes::B8(data) => bytes.extend_from_slice(data),
+        },
+        Stmt::StaticData(StaticData::StaticZero(StaticZero {nbytes})) |
+        Stmt::StaticData(StaticData::StaticUninit(StaticUninit {nbytes})) => {
+            bytes.resize(bytes.len() + *nbytes as usize, 0);
+        },
+        Stmt::StaticData(StaticData::StaticByteStr(data)) => bytes.extend_from_slice(&data.bytes),
+    }
+}
+
+pub(crate) fn layout_section(section: asm::Section, diag: &Diagnostics, labels: &LabelOffsets) -> Vec<Stmt> {
     let asm::Section {section_header_span: _, stmts} = section;
     stmts.into_iter().map(|stmt| match stmt.kind {
         asm::StmtKind::StaticData(data) => Stmt::StaticData(data.into()),
-        asm::StmtKind::Instr(instr) => Stmt::Instr(instr.layout(diag, labels)),
+        asm::StmtKind::Instr(instr) => {
+            if let Some(asm::Location::Label(label)) = instr.jump_target() {
+                labels.check_jump_target(label, diag);
+            }
+            Stmt::Instr(instr.layout(diag, labels))
+        },
     }).collect()
 }
+
+/// Returns a relocation for every immediate in the section that holds the address of a label
+///
+/// Distances between labels do not depend on where the executable is loaded, so they are not
+/// relocated.
+fn section_relocations(id: SectionId, section: &asm::Section) -> Vec<Relocation> {
+    section.stmts.iter().enumerate().flat_map(|(index, stmt)| match &stmt.kind {
+        asm::StmtKind::Instr(instr) => instr.label_refs(),
+        asm::StmtKind::StaticData(_) => Vec::new(),
+    }.into_iter().filter_map(move |(imm_index, label_ref)| match label_ref {
+        asm::LabelRef::Label(_) => Some(Relocation {section: id, index, imm_index}),
+        asm::LabelRef::Distance(_) => None,
+    })).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::assemble::{assemble_str, AssembleOptions};
+
+    fn relocatable_exec() -> Executable {
+        let options = AssembleOptions {relocatable: true, ..AssembleOptions::default()};
+        let source = "section .code\nmain:\n  mov $1, value\n  ret\nsection .static\nvalue: .b8 1\n";
+        assemble_str("test.wa", source, &options)
+            .unwrap_or_else(|diag| panic!("{} errors", diag.emitted_errors())).exec
+    }
+
+    #[test]
+    fn header() {
+        let exec = relocatable_exec();
+        let bytes = exec.to_bytes().unwrap();
+        assert_eq!(&bytes[..8], EXEC_MAGIC);
+        let hash = u64::from_le_bytes(bytes[8..16].try_into().unwrap());
+        assert_eq!(hash, content_hash(&bytes[HEADER_SIZE..]));
+        assert_eq!(exec.content_hash().unwrap(), hash);
+        assert_eq!(Executable::read_from(&bytes[..]).unwrap(), exec);
+
+        // A corrupted executable is detected by its hash
+        let mut corrupted = bytes.clone();
+        corrupted[HEADER_SIZE + 4] ^= 0xff;
+        assert!(Executable::read_from(&corrupted[..]).is_err());
+        assert!(Executable::read_from(&bytes[..12]).is_err());
+    }
+
+    #[test]
+    fn headerless_formats() {
+        let exec = relocatable_exec();
+        let relocations = exec.relocations.clone().unwrap();
+        let sections = bincode::serialize(&exec).unwrap();
+
+        // Only the sections
+        let read = Executable::read_from(&sections[..]).unwrap();
+        assert_eq!(read, Executable {relocations: None, ..exec.clone()});
+
+        // The sections followed by the relocations
+        let mut bytes = sections.clone();
+        bincode::serialize_into(&mut bytes, &relocations).unwrap();
+        assert_eq!(Executable::read_from(&bytes[..]).unwrap(), exec);
+
+        // The sections followed by a trailer
+        let mut bytes = sections.clone();
+        bincode::serialize_into(&mut bytes, &Trailer {relocations: Some(relocations), debug_info: None}).unwrap();
+        assert_eq!(Executable::read_from(&bytes[..]).unwrap(), exec);
+
+        // The sections followed by a trailer and the content hash
+        let hash = content_hash(&bytes);
+        bytes.extend_from_slice(&hash.to_le_bytes());
+        assert_eq!(Executable::read_from(&bytes[..]).unwrap(), exec);
+
+        // Unexpected data at the end is still an error
+        bytes.push(0);
+        assert!(Executable::read_from(&bytes[..]).is_err());
+    }
+}
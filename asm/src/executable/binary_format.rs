@@ -1,17 +1,79 @@
 //! A version the statements without any spans or other source-related info
 
+use std::fmt;
 use std::sync::Arc;
 
 use serde::{Serialize, Deserialize};
 
 use crate::asm::{self, layout::InstrLayout};
 
+/// One of the sections of an executable or object file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SectionId {
+    Code,
+    RoData,
+    Static,
+    Bss,
+}
+
+impl fmt::Display for SectionId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use SectionId::*;
+        match self {
+            Code => write!(f, ".code"),
+            RoData => write!(f, ".rodata"),
+            Static => write!(f, ".static"),
+            Bss => write!(f, ".bss"),
+        }
+    }
+}
+
+impl SectionId {
+    /// Every section, in the order that sections are laid out in an executable
+    pub const ALL: [SectionId; 4] = [SectionId::Code, SectionId::RoData, SectionId::Static, SectionId::Bss];
+
+    /// The position of this section in the executable (and in `ALL`)
+    pub fn order(self) -> usize {
+        use SectionId::*;
+        match self {
+            Code => 0,
+            RoData => 1,
+            Static => 2,
+            Bss => 3,
+        }
+    }
+}
+
+/// An immediate in an instruction whose value is the address of a label
+///
+/// When an executable is loaded at a different address than the one it was assembled for, the
+/// difference between the two addresses must be added to each of these immediates.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Relocation {
+    pub section: SectionId,
+    /// The index of the instruction in its section
+    pub index: usize,
+    /// The index of the immediate in the instruction (see `Layout::set_immediate`)
+    pub imm_index: usize,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Stmt {
     StaticData(StaticData),
     Instr(InstrLayout),
 }
 
+impl Stmt {
+    /// Returns the size in bytes that this statement takes up in memory
+    pub fn size_bytes(&self) -> u64 {
+        match self {
+            Stmt::StaticData(data) => data.size_bytes(),
+            // All instructions are currently 8 bytes
+            Stmt::Instr(_) => 8,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum StaticData {
     StaticBytes(StaticBytes),
@@ -20,6 +82,19 @@ pub enum StaticData {
     StaticByteStr(StaticByteStr),
 }
 
+impl StaticData {
+    /// Returns the size in bytes that this data takes up in memory
+    pub fn size_bytes(&self) -> u64 {
+        use StaticData::*;
+        match self {
+            StaticBytes(data) => data.size_bytes(),
+            StaticZero(data) => data.nbytes,
+            StaticUninit(data) => data.nbytes,
+            StaticByteStr(data) => data.bytes.len() as u64,
+        }
+    }
+}
+
 impl From<asm::StaticData> for StaticData {
     fn from(data: asm::StaticData) -> Self {
         use asm::StaticData::*;
@@ -43,6 +118,19 @@ pub enum StaticBytes {
     B8([u8; 8]),
 }
 
+impl StaticBytes {
+    /// Returns the size in bytes that this data takes up in memory
+    pub fn size_bytes(&self) -> u64 {
+        use StaticBytes::*;
+        match self {
+            B1(_) => 1,
+            B2(_) => 2,
+            B4(_) => 4,
+            B8(_) => 8,
+        }
+    }
+}
+
 impl From<asm::StaticBytes> for StaticBytes {
     fn from(data: asm::StaticBytes) -> Self {
         use asm::StaticBytesValue::*;
@@ -0,0 +1,70 @@
+//! Evaluation of arithmetic expressions on integers (e.g. `(SIZE + 1) * 8`)
+
+use crate::ast;
+use crate::diagnostics::Diagnostics;
+
+/// The smallest value that any integer in the program may have
+const MIN_VALUE: i128 = i64::MIN as i128;
+/// The largest value that any integer in the program may have
+const MAX_VALUE: i128 = u64::MAX as i128;
+
+/// Computes the value of an expression
+///
+/// The value of each name is returned by `lookup`, which is responsible for reporting an error if
+/// the name cannot be used. Operations are performed on signed integers and division rounds toward
+/// zero. Every intermediate result must be in the range [i64::min(), u64::max()], which is the
+/// range of every other integer in the program.
+///
+/// Returns `None` if any errors were reported. The value returned preserves the span of the
+/// expression so later error messages point to the right place.
+pub fn eval<F>(expr: &ast::Expr, diag: &Diagnostics, lookup: &mut F) -> Option<ast::Integer>
+    where F: FnMut(&ast::Ident) -> Option<i128>
+{
+    let value = match expr {
+        ast::Expr::Integer(value) => value.value,
+        ast::Expr::Name(name) => lookup(name)?,
+        ast::Expr::Binary(bin) => eval_binary(bin, diag, lookup)?,
+    };
+
+    Some(ast::Integer {value, span: expr.span()})
+}
+
+fn eval_binary<F>(expr: &ast::BinaryExpr, diag: &Diagnostics, lookup: &mut F) -> Option<i128>
+    where F: FnMut(&ast::Ident) -> Option<i128>
+{
+    let ast::BinaryExpr {op, lhs, rhs, span} = expr;
+
+    // Both operands are evaluated (even if one fails) so that errors in each are reported
+    let lhs_value = eval(lhs, diag, lookup);
+    let rhs_value = eval(rhs, diag, lookup);
+    let (lhs_value, rhs_value) = (lhs_value?.value, rhs_value?.value);
+
+    use ast::BinOp::*;
+    let value = match op {
+        Add => lhs_value.checked_add(rhs_value),
+        Sub => lhs_value.checked_sub(rhs_value),
+        Mul => lhs_value.checked_mul(rhs_value),
+        Div | Rem if rhs_value == 0 => {
+            let message = match op {
+                Div => "attempt to divide by zero",
+                _ => "attempt to calculate the remainder with a divisor of zero",
+            };
+            let mut writer = diag.span_error(*span, message);
+            if !matches!(rhs, ast::Expr::Integer(_)) {
+                writer = writer.span_note(rhs.span(), format!("`{}` evaluates to zero", rhs));
+            }
+            writer.emit();
+            return None;
+        },
+        Div => lhs_value.checked_div(rhs_value),
+        Rem => lhs_value.checked_rem(rhs_value),
+    };
+
+    match value {
+        Some(value) if (MIN_VALUE..=MAX_VALUE).contains(&value) => Some(value),
+        _ => {
+            diag.span_error(*span, format!("arithmetic overflow: the value of `{}` does not fit in 64 bits", expr)).emit();
+            None
+        },
+    }
+}
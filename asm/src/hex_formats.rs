@@ -0,0 +1,187 @@
+//! Writers for the Intel HEX and Motorola S-record formats (`wolf-asm --format ihex|srec`), which
+//! are used by third-party tools such as EEPROM programmers
+//!
+//! Both formats store the memory image of the program (see `Executable::to_flat_binary`) as lines
+//! of hex text, each containing an address, some data, and a checksum.
+
+use std::io::{self, Write};
+
+/// The number of data bytes written in each record
+const RECORD_DATA_LEN: usize = 16;
+
+/// Returns an error if the image does not fit in a 32-bit address space, which is the largest
+/// address space supported by either format
+fn check_addr_range(base_addr: u64, bytes: &[u8]) -> io::Result<()> {
+    let end_addr = base_addr.checked_add(bytes.len() as u64);
+    if end_addr.map(|end_addr| end_addr > 1 << 32).unwrap_or(true) {
+        let message = format!("program at `0x{:x}` does not fit in a 32-bit address space", base_addr);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    }
+
+    Ok(())
+}
+
+/// Writes the given bytes, starting at the given address, in the Intel HEX format
+///
+/// Extended linear address records are written whenever the upper 16 bits of the address change,
+/// and the base address is written as the start address.
+pub fn write_ihex<W: Write>(mut out: W, base_addr: u64, bytes: &[u8]) -> io::Result<()> {
+    check_addr_range(base_addr, bytes)?;
+
+    let mut upper_addr = 0;
+    for (index, chunk) in bytes.chunks(RECORD_DATA_LEN).enumerate() {
+        let addr = base_addr + (index * RECORD_DATA_LEN) as u64;
+        // A record cannot cross a 64 KB boundary, so split the chunk if it would
+        let boundary = ((addr | 0xffff) + 1 - addr) as usize;
+        let (first, second) = chunk.split_at(chunk.len().min(boundary));
+
+        for (addr, data) in [(addr, first), (addr + first.len() as u64, second)] {
+            if data.is_empty() {
+                continue;
+            }
+
+            if addr >> 16 != upper_addr {
+                upper_addr = addr >> 16;
+                write_ihex_record(&mut out, 0, 0x04, &(upper_addr as u16).to_be_bytes())?;
+            }
+            write_ihex_record(&mut out, addr as u16, 0x00, data)?;
+        }
+    }
+
+    // Start linear address record
+    write_ihex_record(&mut out, 0, 0x05, &(base_addr as u32).to_be_bytes())?;
+    // End of file record
+    write_ihex_record(&mut out, 0, 0x01, &[])
+}
+
+fn write_ihex_record<W: Write>(out: &mut W, addr: u16, record_type: u8, data: &[u8]) -> io::Result<()> {
+    let [addr_hi, addr_lo] = addr.to_be_bytes();
+    let mut sum = data.len() as u8;
+    sum = sum.wrapping_add(addr_hi).wrapping_add(addr_lo).wrapping_add(record_type);
+
+    write!(out, ":{:02X}{:04X}{:02X}", data.len(), addr, record_type)?;
+    for &byte in data {
+        write!(out, "{:02X}", byte)?;
+        sum = sum.wrapping_add(byte);
+    }
+    // The checksum is the two's complement of the sum of every other byte in the record
+    writeln!(out, "{:02X}", sum.wrapping_neg())
+}
+
+/// Writes the given bytes, starting at the given address, in the Motorola S-record format
+///
+/// The header record contains `header` and the smallest address size that fits every address is
+/// used. The base address is written as the start address.
+pub fn write_srec<W: Write>(mut out: W, base_addr: u64, bytes: &[u8], header: &str) -> io::Result<()> {
+    check_addr_range(base_addr, bytes)?;
+
+    let end_addr = base_addr + bytes.len() as u64;
+    // The number of bytes in each address and the record types that use that many bytes
+    let (addr_len, data_type, count_type, start_type) = if end_addr <= 1 << 16 {
+        (2, 1, 5, 9)
+    } else if end_addr <= 1 << 24 {
+        (3, 2, 6, 8)
+    } else {
+        (4, 3, 6, 7)
+    };
+
+    write_srec_record(&mut out, 0, 0, 2, header.as_bytes())?;
+
+    let mut records = 0u64;
+    for (index, chunk) in bytes.chunks(RECORD_DATA_LEN).enumerate() {
+        let addr = base_addr + (index * RECORD_DATA_LEN) as u64;
+        write_srec_record(&mut out, data_type, addr, addr_len, chunk)?;
+        records += 1;
+    }
+
+    // The record count is optional, so it is left out if it does not fit
+    let max_count = if count_type == 5 { 0xffff } else { 0xff_ffff };
+    if records <= max_count {
+        write_srec_record(&mut out, count_type, records, addr_len.clamp(2, 3), &[])?;
+    }
+
+    write_srec_record(&mut out, start_type, base_addr, addr_len, &[])
+}
+
+fn write_srec_record<W: Write>(out: &mut W, record_type: u8, addr: u64, addr_len: usize, data: &[u8]) -> io::Result<()> {
+    let addr_bytes = &addr.to_be_bytes()[8 - addr_len..];
+    // The count includes the address, the data, and the checksum
+    let count = (addr_len + data.len() + 1) as u8;
+    let mut sum = count;
+
+    write!(out, "S{}{:02X}", record_type, count)?;
+    for &byte in addr_bytes.iter().chain(data) {
+        write!(out, "{:02X}", byte)?;
+        sum = sum.wrapping_add(byte);
+    }
+    // The checksum is the one's complement of the sum of every other byte in the record
+    writeln!(out, "{:02X}", !sum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ihex(base_addr: u64, bytes: &[u8]) -> String {
+        let mut out = Vec::new();
+        write_ihex(&mut out, base_addr, bytes).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn srec(base_addr: u64, bytes: &[u8]) -> String {
+        let mut out = Vec::new();
+        write_srec(&mut out, base_addr, bytes, "HDR").unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn ihex_records() {
+        let data = [0x21, 0x46, 0x01, 0x36, 0x01, 0x21, 0x47, 0x01, 0x36, 0x00, 0x7E, 0xFE, 0x09, 0xD2, 0x19, 0x01];
+        assert_eq!(ihex(0x0100, &data), "\
+:10010000214601360121470136007EFE09D2190140
+:0400000500000100F6
+:00000001FF
+");
+    }
+
+    #[test]
+    fn ihex_extended_addrs() {
+        // The record starting just before the 64 KB boundary is split in two
+        assert_eq!(ihex(0xfffc, &[1, 2, 3, 4, 5, 6]), "\
+:04FFFC0001020304F7
+:020000040001F9
+:020000000506F3
+:040000050000FFFCFC
+:00000001FF
+");
+    }
+
+    #[test]
+    fn srec_records() {
+        let data = [0x0A, 0x0A, 0x0D, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(srec(0x7AF0, &data), "\
+S00600004844521B
+S1137AF00A0A0D0000000000000000000000000061
+S5030001FB
+S9037AF092
+");
+    }
+
+    #[test]
+    fn srec_addr_sizes() {
+        // Addresses that do not fit in 16 bits use 24-bit records
+        assert_eq!(srec(0x10000, &[0xff]), "\
+S00600004844521B
+S205010000FFFA
+S604000001FA
+S804010000FA
+");
+    }
+
+    #[test]
+    fn addr_out_of_range() {
+        let mut out = Vec::new();
+        assert!(write_ihex(&mut out, 0xffff_ffff, &[1, 2]).is_err());
+        assert!(write_srec(&mut out, 0xffff_ffff, &[1, 2], "").is_err());
+    }
+}
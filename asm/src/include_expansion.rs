@@ -1,14 +1,17 @@
+use std::fs;
 use std::str;
 use std::sync::Arc;
 use std::path::{Path, PathBuf};
+use std::collections::HashSet;
 use std::borrow::Cow;
 use std::fmt::Write;
 
 use parking_lot::RwLock;
 
 use crate::ast;
-use crate::parser::{SourceFiles, collect_tokens, parse_program};
-use crate::diagnostics::Diagnostics;
+use crate::parser::{SourceFiles, Lexer, parse_tokens};
+use crate::conditional_assembly::{Defines, expand_conditionals};
+use crate::diagnostics::{Diagnostics, ErrorCode};
 
 /// Attempts to expand all `.include` directives in a program
 ///
@@ -20,18 +23,70 @@ use crate::diagnostics::Diagnostics;
 ///
 /// If no errors occur, the returned program is guaranteed to not have any remaining `.include`
 /// directives in it.
+///
+/// Relative include paths are first resolved against each directory in `include_dirs` (in order)
+/// and then against the directory of the file containing the `.include` directive. The first path
+/// that refers to an existing file is used.
+///
+/// Conditional assembly directives in each included file are expanded (using `defines`) before
+/// the `.include` directives in that file. The program passed in should already have had its
+/// conditional assembly directives expanded.
+///
+/// An `.include_once` directive is skipped if the same file (determined using its canonicalized
+/// path) has already been included or is the program itself.
 pub fn expand_includes(
     prog_path: &Path,
     prog: ast::Program,
     source_files: &Arc<RwLock<SourceFiles>>,
     diag: &Diagnostics,
+    include_dirs: &[PathBuf],
+    defines: &Defines,
     depth: usize,
 ) -> ast::Program {
-    let mut path_stack = vec![prog_path.to_path_buf()];
+    let mut progs = expand_includes_all(vec![(prog_path, prog)], source_files, diag, include_dirs, defines, depth);
+    progs.pop().expect("bug: expected exactly one expanded program")
+}
+
+/// Attempts to expand all `.include` directives in several programs that are assembled together
+///
+/// Behaves like `expand_includes` on each program, except that an `.include_once` directive is
+/// also skipped if the same file is one of the programs or has been included by any of them.
+pub fn expand_includes_all(
+    progs: Vec<(&Path, ast::Program)>,
+    source_files: &Arc<RwLock<SourceFiles>>,
+    diag: &Diagnostics,
+    include_dirs: &[PathBuf],
+    defines: &Defines,
+    depth: usize,
+) -> Vec<ast::Program> {
+    let mut included_paths = HashSet::new();
+    // The programs themselves count as included
+    for (prog_path, _) in &progs {
+        if let Ok(canonical_path) = fs::canonicalize(prog_path) {
+            included_paths.insert(canonical_path);
+        }
+    }
+
     // Since we know the maximum number of items that can be added, let's allocate immediately
-    path_stack.reserve_exact(depth+1);
+    let path_stack = Vec::with_capacity(depth+1);
+    let mut state = ExpansionState {include_dirs, defines, path_stack, included_paths};
+    progs.into_iter().map(|(prog_path, prog)| {
+        state.path_stack.clear();
+        state.path_stack.push(prog_path.to_path_buf());
+        expand_includes_impl(prog_path, prog, source_files, diag, depth, &mut state)
+    }).collect()
+}
 
-    expand_includes_impl(prog_path, prog, source_files, diag, depth, &mut path_stack)
+/// The state shared between each recursive call to `expand_includes_impl`
+struct ExpansionState<'a> {
+    /// The directories searched for relative include paths
+    include_dirs: &'a [PathBuf],
+    /// The names defined for conditional assembly directives
+    defines: &'a Defines,
+    /// The paths of the files currently being expanded, used to report recursion errors
+    path_stack: Vec<PathBuf>,
+    /// The canonicalized paths of every file included so far
+    included_paths: HashSet<PathBuf>,
 }
 
 fn expand_includes_impl(
@@ -40,7 +95,7 @@ fn expand_includes_impl(
     source_files: &Arc<RwLock<SourceFiles>>,
     diag: &Diagnostics,
     depth: usize,
-    path_stack: &mut Vec<PathBuf>,
+    state: &mut ExpansionState,
 ) -> ast::Program {
     // This avoids a lot of unnecessary copying in exchange for an extra pass over the statements
     let has_includes = prog.stmts.iter().any(|stmt| stmt.is_include());
@@ -50,7 +105,7 @@ fn expand_includes_impl(
 
     if depth == 0 {
         let mut msg = "maximum `.include` recursion depth reached while reading files:\n".to_string();
-        for (i, path) in path_stack.iter().enumerate().rev() {
+        for (i, path) in state.path_stack.iter().enumerate().rev() {
             // unwrap() is safe because writing to a String can't fail
             write!(msg, "    {:2}. {}", i+1, path.display()).unwrap();
             if i != 0 {
@@ -69,7 +124,7 @@ fn expand_includes_impl(
         // Record the initial error count so we can determine if any *new* errors were produced
         let init_errors = diag.emitted_errors();
 
-        let ast::Include {path: included_path, span: _} = match stmt {
+        let ast::Include {path: included_path, once, span: _} = match stmt {
             ast::Stmt::Include(include) => include,
             stmt => {
                 expanded_stmts.push(stmt);
@@ -90,50 +145,62 @@ fn expand_includes_impl(
 
         // Note that we don't validate the extension of included files since that can be anything
 
-        // Included paths are resolved relative to the file they are included in
+        // Included paths are resolved relative to the include directories and then relative to
+        // the file they are included in
         let included_path = if included_path.is_relative() {
-            // Even `Path::new("foo.wa").parent()` will return `Some(Path::new(""))`
-            let parent_dir = prog_path.parent()
-                .expect("bug: if a source file has been read, it must have a parent directory");
-            Cow::Owned(parent_dir.join(included_path))
+            let search_path = state.include_dirs.iter()
+                .map(|dir| dir.join(included_path))
+                .find(|path| path.is_file());
+            match search_path {
+                Some(path) => Cow::Owned(path),
+                None => {
+                    // Even `Path::new("foo.wa").parent()` will return `Some(Path::new(""))`
+                    let parent_dir = prog_path.parent()
+                        .expect("bug: if a source file has been read, it must have a parent directory");
+                    Cow::Owned(parent_dir.join(included_path))
+                },
+            }
         } else {
             Cow::Borrowed(included_path)
         };
 
+        // If the path can't be canonicalized, the file can't be read and that error is reported below
+        if let Ok(canonical_path) = fs::canonicalize(&included_path) {
+            let is_new = state.included_paths.insert(canonical_path);
+            if once && !is_new {
+                continue;
+            }
+        }
+
         // Need this separate variable so that the lock on source files ends before diag.span_error()
         let included_file = source_files.write().add_file(&included_path);
         let included_file = match included_file {
             Ok(file_handle) => file_handle,
             Err(err) => {
-                diag.span_error(path_span, format!("unable to read included source file: `{}`: {}", included_path.display(), err)).emit();
+                diag.span_error_code(ErrorCode::IncludeNotFound, path_span, format!("unable to read included source file: `{}`: {}", included_path.display(), err)).emit();
                 // Finish this pass before stopping in case there are further errors
                 continue;
             },
         };
 
-        let tokens = collect_tokens(source_files.read().source(included_file), diag);
-        if diag.emitted_errors() > init_errors {
-            // Finish this pass before stopping in case there are further errors
-            continue;
-        }
-
-        let included_prog = parse_program(&tokens, diag);
+        let included_prog = parse_tokens(Lexer::new(source_files.read().source(included_file), diag), diag);
+        let included_prog = expand_conditionals(included_prog, state.defines, diag);
         if diag.emitted_errors() > init_errors {
             // Finish this pass before stopping in case there are further errors
             continue;
         }
 
         // Recurse and expand the included program
-        path_stack.push(included_path.to_path_buf());
+        state.path_stack.push(included_path.to_path_buf());
         let ast::Program {stmts: included_stmts} = expand_includes_impl(
             &included_path,
             included_prog,
             source_files,
             diag,
             depth-1,
-            path_stack,
+            state,
         );
-        path_stack.pop();
+        state.path_stack.pop();
         // Even if this expansion ends with errors, we still want to include as much in the final
         // result as we can, that's why we aren't checking `diag.emitted_errors()` here.
 
@@ -0,0 +1,138 @@
+//! A crate-wide string interner
+//!
+//! Every distinct string is stored exactly once and is identified by a small integer. Interned
+//! strings can be compared and hashed without looking at their characters, which makes them much
+//! cheaper than `Arc<str>` as keys in the tables used throughout the assembler.
+
+use std::fmt;
+use std::ops::Deref;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use parking_lot::{const_mutex, Mutex};
+
+/// The global interner, created the first time a string is interned
+static INTERNER: Mutex<Option<Interner>> = const_mutex(None);
+
+thread_local! {
+    /// A copy of the part of the global interner that this thread has already used
+    ///
+    /// Every interned string is immutable and lives until the end of the program, so a copy never
+    /// goes out of date. Looking up a string that is already in the copy does not need the lock on
+    /// the global interner, so threads only wait for each other when a new string is interned.
+    static LOCAL: RefCell<Interner> = RefCell::new(Interner::default());
+}
+
+#[derive(Debug, Default)]
+struct Interner {
+    ids: HashMap<&'static str, InternedStr>,
+    strings: Vec<&'static str>,
+}
+
+impl Interner {
+    fn intern(&mut self, value: &str) -> InternedStr {
+        if let Some(&interned) = self.ids.get(value) {
+            return interned;
+        }
+
+        // Interned strings live until the end of the program, so leaking them allows them to be
+        // borrowed without holding the lock. Each distinct string is only leaked once, so the
+        // memory used only grows with the number of distinct names ever interned.
+        let value: &'static str = Box::leak(value.into());
+        let interned = InternedStr(self.strings.len() as u32);
+        self.strings.push(value);
+        self.ids.insert(value, interned);
+        interned
+    }
+}
+
+/// A string that has been interned
+///
+/// Two interned strings are equal if and only if the strings they were interned from are equal.
+/// Hashing an interned string only hashes its integer key.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InternedStr(u32);
+
+impl InternedStr {
+    /// Interns the given string, or returns the existing key if it was already interned
+    pub fn intern(value: &str) -> Self {
+        LOCAL.with(|local| {
+            if let Some(&interned) = local.borrow().ids.get(value) {
+                return interned;
+            }
+
+            let mut global = INTERNER.lock();
+            let global = global.get_or_insert_with(Interner::default);
+            let interned = global.intern(value);
+            let value = global.strings[interned.0 as usize];
+            local.borrow_mut().ids.insert(value, interned);
+            interned
+        })
+    }
+
+    /// Returns the string that was interned
+    pub fn as_str(self) -> &'static str {
+        let index = self.0 as usize;
+        LOCAL.with(|local| {
+            if let Some(&value) = local.borrow().strings.get(index) {
+                return value;
+            }
+
+            let global = INTERNER.lock();
+            // An `InternedStr` can only be created by interning, so the interner must already exist
+            let global = global.as_ref().expect("bug: interned string used before the interner was created");
+            let mut local = local.borrow_mut();
+            let copied = local.strings.len();
+            local.strings.extend_from_slice(&global.strings[copied..]);
+            local.strings[index]
+        })
+    }
+}
+
+impl<'a> From<&'a str> for InternedStr {
+    fn from(value: &'a str) -> Self {
+        InternedStr::intern(value)
+    }
+}
+
+impl Deref for InternedStr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Debug for InternedStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl fmt::Display for InternedStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn shared_between_threads() {
+        let here = InternedStr::intern("interner_test_shared");
+        let (there, other) = thread::spawn(move || {
+            // Strings interned by another thread can be looked up here, and vice versa
+            assert_eq!(here.as_str(), "interner_test_shared");
+            (InternedStr::intern("interner_test_shared"), InternedStr::intern("interner_test_other"))
+        }).join().unwrap();
+
+        assert_eq!(here, there);
+        assert_ne!(here, other);
+        assert_eq!(other.as_str(), "interner_test_other");
+        assert_eq!(InternedStr::intern("interner_test_other"), other);
+    }
+}
@@ -1,35 +1,116 @@
-use std::collections::HashMap;
+use std::io::{self, Write};
+use std::collections::{HashMap, HashSet};
 
 use crate::asm;
-use crate::diagnostics::Diagnostics;
+use crate::diagnostics::{Diagnostics, ErrorCode, closest_match};
+use crate::interner::InternedStr;
+use crate::executable::SectionId;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct LabelOffsets {
-    offsets: HashMap<asm::Ident, u64>,
+    base_addr: u64,
+    offsets: HashMap<InternedStr, u64>,
+    /// The section that each label is defined in
+    sections: HashMap<InternedStr, SectionId>,
+    /// The labels generated for uses of `$here`, which are left out of `sorted_labels`
+    generated: HashSet<InternedStr>,
+    /// The address of every instruction
+    instr_addrs: HashSet<u64>,
+    /// If true, every label has the address zero and unknown labels are not an error
+    placeholder: bool,
 }
 
 impl LabelOffsets {
-    pub fn new(prog: &asm::Program) -> Self {
+    /// Computes the address of every label, given the address that the start of the code section
+    /// will be loaded at
+    pub fn new(prog: &asm::Program, base_addr: u64) -> Self {
         let mut offsets = HashMap::new();
-        let mut current_offset = 0;
+        let mut sections = HashMap::new();
+        let mut generated = HashSet::new();
+        let mut instr_addrs = HashSet::new();
+        let mut current_offset = base_addr;
 
-        for stmt in prog.iter_all_stmts() {
-            for label in &stmt.labels {
-                offsets.insert(label.clone(), current_offset);
+        let prog_sections = [
+            (SectionId::Code, &prog.code_section),
+            (SectionId::RoData, &prog.rodata_section),
+            (SectionId::Static, &prog.static_section),
+            (SectionId::Bss, &prog.bss_section),
+        ];
+        for (id, section) in &prog_sections {
+            let stmts = section.iter().flat_map(|section| &section.stmts);
+            for stmt in stmts {
+                for label in &stmt.labels {
+                    offsets.insert(label.value, current_offset);
+                    sections.insert(label.value, *id);
+                    if label.generated {
+                        generated.insert(label.value);
+                    }
+                }
+                if let asm::StmtKind::Instr(_) = stmt.kind {
+                    instr_addrs.insert(current_offset);
+                }
+
+                current_offset += stmt.size_bytes();
             }
+        }
+
+        Self {base_addr, offsets, sections, generated, instr_addrs, placeholder: false}
+    }
+
+    /// Creates a table where every label (including labels that are not defined anywhere) has
+    /// the address zero
+    ///
+    /// This is used to lay out object files, since the addresses of their labels are only known
+    /// once they are linked.
+    pub fn placeholder() -> Self {
+        Self {
+            base_addr: 0,
+            offsets: HashMap::new(),
+            sections: HashMap::new(),
+            generated: HashSet::new(),
+            instr_addrs: HashSet::new(),
+            placeholder: true,
+        }
+    }
+
+    /// Returns the address that the start of the code section will be loaded at
+    pub fn base_addr(&self) -> u64 {
+        self.base_addr
+    }
+
+    /// Returns the address, name, and section of every label written in the program, sorted by
+    /// address
+    ///
+    /// Labels with the same address are sorted by name. Labels generated for `$here` are left out.
+    pub fn sorted_labels(&self) -> Vec<(u64, InternedStr, SectionId)> {
+        let mut labels: Vec<_> = self.offsets.iter()
+            .filter(|(name, _)| !self.generated.contains(name))
+            .map(|(&name, &addr)| (addr, name, self.sections[&name]))
+            .collect();
+        labels.sort_by(|(addr1, name1, _), (addr2, name2, _)| addr1.cmp(addr2).then_with(|| name1.as_str().cmp(name2.as_str())));
+        labels
+    }
 
-            current_offset += stmt.size_bytes();
+    /// Writes a map file listing the address, section, and name of every label, sorted by address
+    pub fn write_map<W: Write>(&self, mut out: W) -> io::Result<()> {
+        for (addr, name, section) in self.sorted_labels() {
+            writeln!(out, "{:08x}  {:<8}  {}", addr, section.to_string(), name)?;
         }
 
-        Self {offsets}
+        Ok(())
     }
 
     /// Looks up a label name and returns the immediate value of its offset
     pub fn lookup(&self, name: &asm::Ident, diag: &Diagnostics) -> asm::Immediate {
-        let value = match self.offsets.get(name).copied() {
+        let value = match self.offsets.get(&name.value).copied() {
             Some(value) => value,
+            None if self.placeholder => 0,
             None => {
-                diag.span_error(name.span, format!("unknown label `{}`", name)).emit();
+                let mut writer = diag.span_error_code(ErrorCode::UnknownLabel, name.span, format!("unknown label `{}`", name));
+                if let Some(label) = closest_match(&name.value, self.offsets.keys().map(|label| label.as_str())) {
+                    writer = writer.span_help(name.span, format!("did you mean `{}`?", label));
+                }
+                writer.emit();
 
                 // Error Recovery: default to zero if the label isn't found so we can keep checking
                 // for more errors
@@ -46,4 +127,36 @@ impl LabelOffsets {
             span: name.span,
         }
     }
+
+    /// Checks that a label used as the target of a jump or call refers to an instruction in the
+    /// `.code` section
+    ///
+    /// Unknown labels are not reported here since they are already reported by `lookup`.
+    pub fn check_jump_target(&self, name: &asm::Ident, diag: &Diagnostics) {
+        let (addr, section) = match (self.offsets.get(&name.value), self.sections.get(&name.value)) {
+            (Some(&addr), Some(&section)) => (addr, section),
+            _ => return,
+        };
+
+        if section != SectionId::Code {
+            diag.span_error_code(ErrorCode::InvalidJumpTarget, name.span, format!("jump target `{}` must be in the `.code` section, but it is in the `{}` section", name, section)).emit();
+        } else if !self.instr_addrs.contains(&addr) {
+            diag.span_error_code(ErrorCode::InvalidJumpTarget, name.span, format!("jump target `{}` must refer to an instruction, but it refers to data in the `.code` section", name)).emit();
+        }
+    }
+
+    /// Looks up both labels in a distance and returns the immediate value of the number of bytes
+    /// between them
+    pub fn distance(&self, dist: &asm::Distance, diag: &Diagnostics) -> asm::Immediate {
+        let end = self.lookup(&dist.end, diag);
+        let start = self.lookup(&dist.start, diag);
+
+        asm::Integer {
+            // Both values are in the range [0, u64::max()], so the difference always fits in an
+            // i128. It is negative if `end` comes before `start` (e.g. if `start` is in a later
+            // section), and it is range-checked like any other immediate when it is encoded.
+            value: end.value - start.value,
+            span: dist.span,
+        }
+    }
 }
@@ -1,11 +1,27 @@
 #![deny(unused_must_use)]
 
+pub mod cli;
 pub mod diagnostics;
+pub mod interner;
 pub mod ast;
 pub mod parser;
+pub mod conditional_assembly;
 pub mod include_expansion;
+pub mod program_merge;
 pub mod asm;
+pub mod expr;
 pub mod const_table;
+pub mod reg_table;
 pub mod validate;
+pub mod analysis;
+pub mod optimize;
 pub mod label_offsets;
 pub mod executable;
+pub mod debug_info;
+pub mod demangle;
+pub mod object;
+pub mod link;
+pub mod listing;
+pub mod hex_formats;
+pub mod elf;
+pub mod assemble;
@@ -0,0 +1,118 @@
+//! Links object files generated by `wolf-asm -c` into a single executable
+
+use std::collections::HashMap;
+
+use crate::executable::{Executable, Stmt, SectionId, Relocation};
+use crate::object::{Object, FixupTarget};
+use crate::diagnostics::Diagnostics;
+
+/// Links the given objects into an executable that will be loaded at `base_addr`
+///
+/// Each section of the executable contains that section from every object, in the order the
+/// objects were given. Since execution begins at the start of the code section, the object
+/// containing the entry point must be first.
+///
+/// Labels are looked up in the object that refers to them first, and then in the labels exported
+/// by every object. Errors are produced for undefined labels, labels exported more than once, and
+/// addresses that do not fit in the instruction that refers to them.
+///
+/// If `relocatable` is true, every immediate filled in with the address of a label is recorded as
+/// a relocation in the executable.
+pub fn link(objects: &[Object], base_addr: u64, relocatable: bool, diag: &Diagnostics) -> Executable {
+    // The address of each section of each object, indexed by `SectionId::order`
+    let mut section_addrs = vec![[0u64; 4]; objects.len()];
+    let mut current_addr = base_addr;
+    for &id in &SectionId::ALL {
+        for (obj, addrs) in objects.iter().zip(&mut section_addrs) {
+            addrs[id.order()] = current_addr;
+            current_addr += obj.section(id).iter().map(Stmt::size_bytes).sum::<u64>();
+        }
+    }
+
+    // The address of every label, separately for each object
+    let local_labels: Vec<HashMap<&str, u64>> = objects.iter().zip(&section_addrs).map(|(obj, addrs)| {
+        obj.labels.iter()
+            .map(|label| (&*label.name, addrs[label.section.order()] + label.offset))
+            .collect()
+    }).collect();
+
+    // The address of every exported label and the name of the object that exported it
+    let mut exported_labels: HashMap<&str, (u64, &str)> = HashMap::new();
+    for (obj, labels) in objects.iter().zip(&local_labels) {
+        for name in &obj.exports {
+            let addr = match labels.get(&**name) {
+                Some(&addr) => addr,
+                None => {
+                    diag.error(format!("`{}` exports unknown label `{}`", obj.name, name)).emit();
+                    continue;
+                },
+            };
+
+            match exported_labels.get(&**name) {
+                Some((_, other_obj)) => {
+                    diag.error(format!("label `{}` is exported by both `{}` and `{}`", name, other_obj, obj.name)).emit();
+                },
+                None => {
+                    exported_labels.insert(name, (addr, &obj.name));
+                },
+            }
+        }
+    }
+
+    let mut sections: [Vec<Stmt>; 4] = Default::default();
+    let mut relocations = Vec::new();
+    for (obj, labels) in objects.iter().zip(&local_labels) {
+        let lookup = |name: &str| -> i128 {
+            let addr = labels.get(name).copied()
+                .or_else(|| exported_labels.get(name).map(|&(addr, _)| addr));
+
+            match addr {
+                Some(addr) => addr as i128,
+                None => {
+                    diag.error(format!("undefined label `{}` referenced in `{}`", name, obj.name)).emit();
+
+                    // Error Recovery: default to zero so we can keep checking for more errors
+                    0
+                },
+            }
+        };
+
+        let mut obj_sections = [
+            obj.code_section.clone(),
+            obj.rodata_section.clone(),
+            obj.static_section.clone(),
+            obj.bss_section.clone(),
+        ];
+        for fixup in &obj.fixups {
+            let value = match &fixup.target {
+                FixupTarget::Label(name) => {
+                    // The statements of this object come after those of every previous object
+                    let index = sections[fixup.section.order()].len() + fixup.index;
+                    relocations.push(Relocation {section: fixup.section, index, imm_index: fixup.imm_index});
+                    lookup(name)
+                },
+                FixupTarget::Distance {end, start} => lookup(end) - lookup(start),
+            };
+
+            match obj_sections[fixup.section.order()].get_mut(fixup.index) {
+                Some(Stmt::Instr(instr)) => if !instr.layout.set_immediate(fixup.imm_index, value) {
+                    diag.error(format!("value `{}` (`0x{:x}`) does not fit in the immediate of instruction {} of section {} in `{}`",
+                        value, value, fixup.index, fixup.section, obj.name)).emit();
+                },
+
+                _ => {
+                    diag.error(format!("`{}` is not a valid object file: no instruction {} in section {} to fix up",
+                        obj.name, fixup.index, fixup.section)).emit();
+                },
+            }
+        }
+
+        for (section, obj_section) in sections.iter_mut().zip(obj_sections.iter_mut()) {
+            section.append(obj_section);
+        }
+    }
+
+    let [code_section, rodata_section, static_section, bss_section] = sections;
+    let relocations = if relocatable { Some(relocations) } else { None };
+    Executable {base_addr, code_section, rodata_section, static_section, bss_section, relocations, debug_info: None}
+}
@@ -0,0 +1,131 @@
+//! Listings generated by `wolf-asm --listing`, which show the address and encoding of every
+//! statement next to the source line it came from
+
+use std::io::{self, Write};
+
+use crate::asm;
+use crate::parser::{Span, FileId, SourceFiles};
+use crate::label_offsets::LabelOffsets;
+use crate::executable::{Executable, Stmt, StaticData, StaticBytes};
+
+/// The maximum number of bytes of static data shown for a single statement
+const MAX_DATA_BYTES: usize = 8;
+
+/// The address and source location of a single statement
+#[derive(Debug, Clone, PartialEq)]
+struct ListingEntry {
+    addr: u64,
+    span: Span,
+    /// The spans of the labels preceding the statement
+    label_spans: Vec<Span>,
+}
+
+/// The address and source location of every statement in a program, in the order they appear
+/// in the executable
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listing {
+    entries: Vec<ListingEntry>,
+}
+
+impl Listing {
+    /// Records the address and source location of every statement in the program
+    ///
+    /// This must be done before the program is laid out, since the executable does not contain
+    /// any source locations.
+    pub fn new(prog: &asm::Program, labels: &LabelOffsets) -> Self {
+        let mut addr = labels.base_addr();
+        let entries = prog.iter_all_stmts().map(|stmt| {
+            let entry = ListingEntry {
+                addr,
+                span: stmt.kind.span(),
+                label_spans: stmt.labels.iter().map(|label| label.span).collect(),
+            };
+            addr += stmt.size_bytes();
+            entry
+        }).collect();
+
+        Self {entries}
+    }
+
+    /// Writes the listing, using the given executable (laid out from the same program) for the
+    /// encoding of each statement
+    ///
+    /// Each line contains an address, the encoding of the statement at that address (in hex), the
+    /// line number, and the source line. Labels on their own line are listed with the address
+    /// they refer to. A line with the path of the source file is written whenever the file changes.
+    pub fn write<W: Write>(&self, mut out: W, exec: &Executable, source_files: &SourceFiles) -> io::Result<()> {
+        let stmts = exec.code_section.iter()
+            .chain(&exec.rodata_section)
+            .chain(&exec.static_section)
+            .chain(&exec.bss_section);
+
+        let mut current_file = None;
+        for (entry, stmt) in self.entries.iter().zip(stmts) {
+            let stmt_line = source_files.pos(entry.span).start_line;
+
+            for &label_span in &entry.label_spans {
+                let label_line = source_files.pos(label_span).start_line;
+                // Labels on the same line as their statement are shown with the statement
+                if label_span.file() != entry.span.file() || label_line != stmt_line {
+                    write_line(&mut out, source_files, &mut current_file, entry.addr, "", label_span)?;
+                }
+            }
+
+            write_line(&mut out, source_files, &mut current_file, entry.addr, &encoding(stmt), entry.span)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_line<W: Write>(
+    out: &mut W,
+    source_files: &SourceFiles,
+    current_file: &mut Option<FileId>,
+    addr: u64,
+    encoding: &str,
+    span: Span,
+) -> io::Result<()> {
+    if *current_file != Some(span.file()) {
+        if current_file.is_some() {
+            writeln!(out)?;
+        }
+        writeln!(out, "# {}", source_files.path(span.file()).display())?;
+        *current_file = Some(span.file());
+    }
+
+    let line = source_files.pos(span).start_line;
+    let source = String::from_utf8_lossy(source_files.line(span.file(), line));
+    writeln!(out, "{:08x}  {:<19}  {:>5}  {}", addr, encoding, line, source)
+}
+
+/// Returns the hex encoding of a statement
+///
+/// Instructions are shown as the 64-bit number they are encoded as. Static data is shown as its
+/// first few bytes, followed by `...` if there are more.
+fn encoding(stmt: &Stmt) -> String {
+    let data = match stmt {
+        Stmt::Instr(instr) => return format!("{:016x}", instr.to_binary()),
+        Stmt::StaticData(data) => data,
+    };
+
+    let (bytes, len): (&[u8], u64) = match data {
+        StaticData::StaticBytes(StaticBytes::B1(bytes)) => (bytes, 1),
+        StaticData::StaticBytes(StaticBytes::B2(bytes)) => (bytes, 2),
+        StaticData::StaticBytes(StaticBytes::B4(bytes)) => (bytes, 4),
+        StaticData::StaticBytes(StaticBytes::B8(bytes)) => (bytes, 8),
+        StaticData::StaticZero(zero) => (&[0; MAX_DATA_BYTES], zero.nbytes),
+        StaticData::StaticByteStr(byte_str) => (&byte_str.bytes, byte_str.bytes.len() as u64),
+        // Uninitialized bytes have no defined value
+        StaticData::StaticUninit(uninit) => {
+            let shown = uninit.nbytes.min(MAX_DATA_BYTES as u64) as usize;
+            let more = if uninit.nbytes > MAX_DATA_BYTES as u64 { "..." } else { "" };
+            return format!("{}{}", "??".repeat(shown), more);
+        },
+    };
+
+    let shown = len.min(MAX_DATA_BYTES as u64) as usize;
+    let hex: String = bytes[..shown].iter().map(|byte| format!("{:02x}", byte)).collect();
+    let more = if len > MAX_DATA_BYTES as u64 { "..." } else { "" };
+    format!("{}{}", hex, more)
+}
@@ -0,0 +1,154 @@
+//! Object files generated by `wolf-asm -c`, which are linked together into an executable by
+//! `wolf-ld`
+
+use std::collections::HashSet;
+
+use serde::{Serialize, Deserialize};
+
+use crate::asm;
+use crate::executable::{self, Stmt, SectionId};
+use crate::label_offsets::LabelOffsets;
+use crate::diagnostics::{Diagnostics, ErrorCode};
+
+/// A single assembled source file whose label addresses have not been determined yet
+///
+/// Every immediate that depends on the address of a label is zero until the object is linked.
+/// The fixups list every immediate that must be filled in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Object {
+    /// The path of the source file this object was assembled from, used in error messages
+    pub name: String,
+    /// The sections of the object, laid out one after the other in this order
+    pub code_section: Vec<Stmt>,
+    pub rodata_section: Vec<Stmt>,
+    pub static_section: Vec<Stmt>,
+    pub bss_section: Vec<Stmt>,
+    /// Every label defined in this object
+    pub labels: Vec<ObjectLabel>,
+    /// The names of the labels that other objects are allowed to refer to
+    pub exports: Vec<String>,
+    /// The immediates that must be filled in once the addresses of labels are known
+    pub fixups: Vec<Fixup>,
+}
+
+/// A label defined in an object
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObjectLabel {
+    pub name: String,
+    pub section: SectionId,
+    /// The offset of the label (in bytes) from the start of its section
+    pub offset: u64,
+}
+
+/// An immediate in an instruction that must be replaced with a value derived from labels
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fixup {
+    pub section: SectionId,
+    /// The index of the instruction in its section
+    pub index: usize,
+    /// The index of the immediate in the instruction (see `Layout::set_immediate`)
+    pub imm_index: usize,
+    pub target: FixupTarget,
+}
+
+/// The value that a fixup will be replaced with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FixupTarget {
+    /// The address of the given label
+    Label(String),
+    /// The number of bytes between two labels, `end - start`
+    Distance {end: String, start: String},
+}
+
+impl<'a> From<asm::LabelRef<'a>> for FixupTarget {
+    fn from(label_ref: asm::LabelRef<'a>) -> Self {
+        match label_ref {
+            asm::LabelRef::Label(label) => FixupTarget::Label(label.value.to_string()),
+            asm::LabelRef::Distance(dist) => FixupTarget::Distance {
+                end: dist.end.value.to_string(),
+                start: dist.start.value.to_string(),
+            },
+        }
+    }
+}
+
+impl Object {
+    /// Lays out a program as an object file, recording the labels and fixups needed to link it
+    ///
+    /// Labels that are not defined in this program must be declared with `.extern`. Those are
+    /// checked when the object is linked.
+    pub fn new(name: String, prog: asm::Program, diag: &Diagnostics) -> Self {
+        let defined: HashSet<_> = prog.iter_all_stmts()
+            .flat_map(|stmt| &stmt.labels)
+            .map(|label| label.value)
+            .collect();
+        let check_label = |label: &asm::Ident| {
+            if !defined.contains(&label.value) && !prog.externs.contains(label) {
+                diag.span_error_code(ErrorCode::UnknownLabel, label.span, format!("unknown label `{}`", label))
+                    .span_help(label.span, format!("labels defined in other object files must be declared with `.extern {}`", label))
+                    .emit();
+            }
+        };
+        for stmt in prog.iter_all_stmts() {
+            if let asm::StmtKind::Instr(instr) = &stmt.kind {
+                for (_, label_ref) in instr.label_refs() {
+                    match label_ref {
+                        asm::LabelRef::Label(label) => check_label(label),
+                        asm::LabelRef::Distance(dist) => {
+                            check_label(&dist.end);
+                            check_label(&dist.start);
+                        },
+                    }
+                }
+            }
+        }
+
+        let asm::Program {code_section, rodata_section, static_section, bss_section, exports, externs: _} = prog;
+
+        let mut labels = Vec::new();
+        let mut fixups = Vec::new();
+        let placeholder_labels = LabelOffsets::placeholder();
+        let mut layout_section = |id, section: Option<asm::Section>| {
+            let section = match section {
+                Some(section) => section,
+                None => return Vec::new(),
+            };
+
+            let mut offset = 0;
+            for (index, stmt) in section.stmts.iter().enumerate() {
+                for label in &stmt.labels {
+                    labels.push(ObjectLabel {name: label.value.to_string(), section: id, offset});
+                }
+
+                if let asm::StmtKind::Instr(instr) = &stmt.kind {
+                    for (imm_index, label_ref) in instr.label_refs() {
+                        fixups.push(Fixup {section: id, index, imm_index, target: label_ref.into()});
+                    }
+                }
+
+                offset += stmt.size_bytes();
+            }
+
+            executable::layout_section(section, diag, &placeholder_labels)
+        };
+
+        let code_section = layout_section(SectionId::Code, code_section);
+        let rodata_section = layout_section(SectionId::RoData, rodata_section);
+        let static_section = layout_section(SectionId::Static, static_section);
+        let bss_section = layout_section(SectionId::Bss, bss_section);
+
+        let exports = exports.into_iter().map(|name| name.value.to_string()).collect();
+
+        Self {name, code_section, rodata_section, static_section, bss_section, labels, exports, fixups}
+    }
+
+    /// Returns the statements in the given section
+    pub fn section(&self, id: SectionId) -> &[Stmt] {
+        match id {
+            SectionId::Code => &self.code_section,
+            SectionId::RoData => &self.rodata_section,
+            SectionId::Static => &self.static_section,
+            SectionId::Bss => &self.bss_section,
+        }
+    }
+}
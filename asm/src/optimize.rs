@@ -0,0 +1,360 @@
+//! Simple peephole optimizations of a validated program (`wolf-asm -O`)
+//!
+//! Every rewrite preserves the observable behaviour of the program as long as the program does
+//! not depend on the exact address of an instruction or data except through its labels (e.g. by
+//! jumping to a hard-coded address). Only the bytes below the stack pointer may differ.
+
+use std::collections::HashSet;
+
+use crate::asm::{self, Instr, Source, Destination, RegisterKind, LabelRef};
+use crate::interner::InternedStr;
+
+/// Applies every peephole optimization to each section of the program
+///
+/// The following rewrites are performed:
+///
+/// * `mov $r, $r` is removed
+/// * `add $r, 0` and `sub $r, 0` are removed if the next instruction overwrites every flag
+///   (`add`, `sub`, `and`, `or`, `xor`, `test`, or `cmp`), since they would otherwise still
+///   update the flags
+/// * `push $a` followed by `pop $b` becomes `mov $b, $a` (or is removed if `$a` is `$b`)
+///
+/// The labels of a removed statement are moved to the statement after it. A statement is never
+/// removed if that isn't possible or if another label could jump between the statements being
+/// combined.
+///
+/// Data in the `.rodata`, `.static`, and `.bss` sections that is never referenced is also
+/// removed (see `remove_unused_data`).
+pub fn optimize_program(prog: asm::Program) -> asm::Program {
+    let prog = remove_unused_data(prog);
+    let asm::Program {code_section, rodata_section, static_section, bss_section, exports, externs} = prog;
+
+    asm::Program {
+        code_section: code_section.map(optimize_section),
+        rodata_section,
+        static_section: static_section.map(optimize_section),
+        bss_section,
+        exports,
+        externs,
+    }
+}
+
+/// Removes the data in the `.rodata`, `.static`, and `.bss` sections whose labels are never used
+///
+/// Each labeled statement is grouped with the unlabeled statements after it, since those are
+/// only accessible through its label. A group is kept if any of its labels is used by an
+/// instruction or `.export` directive. If both labels of a distance are in the same section,
+/// every group between them is kept as well. Statements before the first label of a section are
+/// always kept.
+fn remove_unused_data(prog: asm::Program) -> asm::Program {
+    let mut used = HashSet::new();
+    let mut distances = Vec::new();
+    for export in &prog.exports {
+        used.insert(export.value);
+    }
+    for stmt in prog.iter_all_stmts() {
+        if let asm::StmtKind::Instr(instr) = &stmt.kind {
+            for (_, label_ref) in instr.label_refs() {
+                match label_ref {
+                    LabelRef::Label(label) => {
+                        used.insert(label.value);
+                    },
+                    LabelRef::Distance(dist) => {
+                        used.insert(dist.start.value);
+                        used.insert(dist.end.value);
+                        distances.push((dist.start.value, dist.end.value));
+                    },
+                }
+            }
+        }
+    }
+
+    let asm::Program {code_section, rodata_section, static_section, bss_section, exports, externs} = prog;
+    let remove = |section: asm::Section| remove_unused_section_data(section, &used, &distances);
+
+    asm::Program {
+        code_section,
+        rodata_section: rodata_section.map(remove),
+        static_section: static_section.map(remove),
+        bss_section: bss_section.map(remove),
+        exports,
+        externs,
+    }
+}
+
+fn remove_unused_section_data(
+    section: asm::Section,
+    used: &HashSet<InternedStr>,
+    distances: &[(InternedStr, InternedStr)],
+) -> asm::Section {
+    let asm::Section {section_header_span, stmts} = section;
+
+    // Each group of statements and whether it is used
+    let mut groups: Vec<(Vec<asm::Stmt>, bool)> = Vec::new();
+    for stmt in stmts {
+        match groups.last_mut() {
+            Some((group, _)) if stmt.labels.is_empty() => group.push(stmt),
+            _ => {
+                // Statements before the first label are always kept
+                let group_used = groups.is_empty() && stmt.labels.is_empty() ||
+                    stmt.labels.iter().any(|label| used.contains(&label.value));
+                groups.push((vec![stmt], group_used));
+            },
+        }
+    }
+
+    let group_of = |name: InternedStr| groups.iter().position(|(group, _)| {
+        group[0].labels.iter().any(|label| label.value == name)
+    });
+    let mut kept: Vec<_> = groups.iter().map(|&(_, group_used)| group_used).collect();
+    for &(start, end) in distances {
+        if let (Some(start), Some(end)) = (group_of(start), group_of(end)) {
+            for group_kept in &mut kept[start.min(end)..=start.max(end)] {
+                *group_kept = true;
+            }
+        }
+    }
+
+    let stmts = groups.into_iter().zip(kept)
+        .filter(|&(_, kept)| kept)
+        .flat_map(|((group, _), _)| group)
+        .collect();
+
+    asm::Section {section_header_span, stmts}
+}
+
+fn optimize_section(section: asm::Section) -> asm::Section {
+    let asm::Section {section_header_span, stmts} = section;
+
+    asm::Section {
+        section_header_span,
+        stmts: remove_no_ops(merge_push_pop(stmts)),
+    }
+}
+
+/// Rewrites each `push $a` directly followed by an unlabeled `pop $b` to `mov $b, $a`
+fn merge_push_pop(stmts: Vec<asm::Stmt>) -> Vec<asm::Stmt> {
+    let mut optimized: Vec<asm::Stmt> = Vec::with_capacity(stmts.len());
+    for stmt in stmts {
+        let merged = match (optimized.last(), &stmt) {
+            (
+                Some(asm::Stmt {kind: asm::StmtKind::Instr(Instr::Push(push)), ..}),
+                asm::Stmt {labels, kind: asm::StmtKind::Instr(Instr::Pop(pop))},
+            ) if labels.is_empty() => match (&push.source, &pop.dest) {
+                // `push $sp` stores the value of `$sp` before it is decremented
+                (Source::Register(source), Destination::Register(dest))
+                    if source.kind != RegisterKind::StackPointer && dest.kind != RegisterKind::StackPointer => {
+                    Some(Instr::Mov(asm::Mov {
+                        dest: pop.dest.clone(),
+                        source: push.source.clone(),
+                        span: push.span.to(pop.span),
+                    }))
+                },
+                _ => None,
+            },
+            _ => None,
+        };
+
+        match merged {
+            Some(instr) => {
+                let push = optimized.last_mut().expect("bug: checked that there is a `push`");
+                push.kind = asm::StmtKind::Instr(instr);
+            },
+            None => optimized.push(stmt),
+        }
+    }
+
+    optimized
+}
+
+/// Removes instructions that have no effect on the program
+fn remove_no_ops(stmts: Vec<asm::Stmt>) -> Vec<asm::Stmt> {
+    let mut optimized = Vec::with_capacity(stmts.len());
+    // Labels of removed statements that still need to be placed on the next statement
+    let mut pending_labels = Vec::new();
+
+    let mut stmts = stmts.into_iter().peekable();
+    while let Some(mut stmt) = stmts.next() {
+        let next = stmts.peek();
+        let removable = match &stmt.kind {
+            asm::StmtKind::Instr(instr) => is_no_op(instr, next),
+            asm::StmtKind::StaticData(_) => false,
+        };
+        // The labels of the last statement have nowhere else to go
+        let has_labels = !stmt.labels.is_empty() || !pending_labels.is_empty();
+        if removable && (next.is_some() || !has_labels) {
+            pending_labels.append(&mut stmt.labels);
+            continue;
+        }
+
+        if !pending_labels.is_empty() {
+            pending_labels.append(&mut stmt.labels);
+            stmt.labels = std::mem::take(&mut pending_labels);
+        }
+        optimized.push(stmt);
+    }
+
+    optimized
+}
+
+/// Returns true if removing the given instruction (followed by `next`) does not change the
+/// behaviour of the program
+fn is_no_op(instr: &Instr, next: Option<&asm::Stmt>) -> bool {
+    match instr {
+        Instr::Mov(asm::Mov {dest: Destination::Register(dest), source: Source::Register(source), ..}) => {
+            dest.kind == source.kind
+        },
+
+        Instr::Add(asm::Add {source: Source::Immediate(imm), ..}) |
+        Instr::Sub(asm::Sub {source: Source::Immediate(imm), ..}) if imm.value == 0 => {
+            match next.map(|stmt| &stmt.kind) {
+                Some(asm::StmtKind::Instr(next)) => overwrites_flags(next),
+                _ => false,
+            }
+        },
+
+        _ => false,
+    }
+}
+
+/// Returns true if the instruction sets every flag without reading any of them
+fn overwrites_flags(instr: &Instr) -> bool {
+    matches!(instr,
+        Instr::Add(_) | Instr::Sub(_) | Instr::And(_) | Instr::Or(_) | Instr::Xor(_) |
+        Instr::Test(_) | Instr::Cmp(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use parking_lot::RwLock;
+
+    use crate::diagnostics::{Diagnostics, NullWriter};
+    use crate::parser::{self, SourceFiles};
+    use crate::validate::validate_program;
+    use crate::label_offsets::LabelOffsets;
+    use crate::executable::{Executable, DEFAULT_BASE_ADDR};
+
+    fn validate(source: &str) -> asm::Program {
+        let source_files = Arc::new(RwLock::new(SourceFiles::default()));
+        let diag = Diagnostics::new(source_files.clone(), Box::new(NullWriter));
+        let file = source_files.write().add_source("test.wa", source.as_bytes());
+        let tokens = parser::collect_tokens(source_files.read().source(file), &diag);
+        let prog = validate_program(parser::parse_program(&tokens, &diag), &diag);
+        assert_eq!(diag.emitted_errors(), 0, "failed to assemble:\n{}", source);
+        prog
+    }
+
+    /// Returns the bytes of the assembled program, which depend on the addresses of its labels
+    fn assemble(prog: asm::Program) -> Vec<u8> {
+        let source_files = Arc::new(RwLock::new(SourceFiles::default()));
+        let diag = Diagnostics::new(source_files, Box::new(NullWriter));
+        let labels = LabelOffsets::new(&prog, DEFAULT_BASE_ADDR);
+        Executable::layout_executable(prog, &diag, &labels, false).to_flat_binary()
+    }
+
+    /// Checks that optimizing the first program produces exactly the second program
+    fn assert_optimizes_to(source: &str, expected: &str) {
+        let optimized = assemble(optimize_program(validate(source)));
+        assert_eq!(optimized, assemble(validate(expected)), "unexpected result of optimizing:\n{}", source);
+    }
+
+    #[test]
+    fn remove_mov_same_register() {
+        assert_optimizes_to(
+            "section .code\n  mov $1, $1\n  mov $1, $2\n  mov $sp, $sp\n  ret\n",
+            "section .code\n  mov $1, $2\n  ret\n",
+        );
+    }
+
+    #[test]
+    fn remove_add_zero() {
+        assert_optimizes_to(
+            "section .code\n  add $1, 0\n  sub $2, 0\n  cmp $1, $2\n  ret\n",
+            "section .code\n  cmp $1, $2\n  ret\n",
+        );
+        // The flags set by `add` are used by `jz`
+        assert_optimizes_to(
+            "section .code\nloop:\n  add $1, 0\n  jz loop\n  ret\n",
+            "section .code\nloop:\n  add $1, 0\n  jz loop\n  ret\n",
+        );
+    }
+
+    #[test]
+    fn merge_push_pop() {
+        assert_optimizes_to(
+            "section .code\n  push $1\n  pop $2\n  push $3\n  pop $3\n  ret\n",
+            "section .code\n  mov $2, $1\n  ret\n",
+        );
+        // Another instruction may jump to the `pop`
+        assert_optimizes_to(
+            "section .code\n  push $1\nafter_push:\n  pop $2\n  jmp after_push\n",
+            "section .code\n  push $1\nafter_push:\n  pop $2\n  jmp after_push\n",
+        );
+        // The stack pointer changes between the `push` and the `pop`
+        assert_optimizes_to(
+            "section .code\n  push $sp\n  pop $1\n  push $1\n  pop $sp\n  ret\n",
+            "section .code\n  push $sp\n  pop $1\n  push $1\n  pop $sp\n  ret\n",
+        );
+    }
+
+    #[test]
+    fn remove_unused_data() {
+        assert_optimizes_to(
+            "\
+section .code
+  load8 $1, used
+  mov $2, end - start
+  ret
+
+section .rodata
+unused: .bytes \"unused\"
+used: .b8 1
+  .b8 2
+section .static
+start: .b8 3
+between: .b8 4
+end: .b8 5
+after: .b8 6
+section .bss
+exported: .zero 8
+buffer: .zero 8
+.export exported
+",
+            "\
+section .code
+  load8 $1, used
+  mov $2, end - start
+  ret
+
+section .rodata
+used: .b8 1
+  .b8 2
+section .static
+start: .b8 3
+between: .b8 4
+end: .b8 5
+section .bss
+exported: .zero 8
+.export exported
+",
+        );
+    }
+
+    #[test]
+    fn move_labels() {
+        // The labels of removed instructions refer to the next instruction
+        assert_optimizes_to(
+            "section .code\nstart:\n  mov $1, $1\nnext:\n  mov $2, $2\n  jmp start\n  jmp next\n",
+            "section .code\nstart:\nnext:\n  jmp start\n  jmp next\n",
+        );
+        // The last instruction is kept if it has a label
+        assert_optimizes_to(
+            "section .code\n  jmp end\nend:\n  mov $1, $1\n",
+            "section .code\n  jmp end\nend:\n  mov $1, $1\n",
+        );
+    }
+}
@@ -7,15 +7,14 @@ mod lexer;
 pub use span::*;
 pub use source_files::*;
 pub use token::*;
+pub use lexer::Lexer;
 
 use std::fmt;
 
 use crate::ast;
-use crate::diagnostics::Diagnostics;
+use crate::diagnostics::{Diagnostics, closest_match};
 
-use scanner::Scanner;
 use token::{Token, TokenKind, Keyword, LitKind};
-use lexer::Lexer;
 
 type Input<'a> = &'a [Token];
 
@@ -149,6 +148,20 @@ impl<'a> fmt::Display for ParseError<'a> {
 }
 
 impl<'a> ParseError<'a> {
+    /// If an unknown directive was found where a directive was expected, returns the expected
+    /// directive with the closest name
+    pub fn suggested_directive(&self) -> Option<&'static str> {
+        if self.actual.kind != TokenKind::DotIdent {
+            return None;
+        }
+
+        let directives = self.expected.iter().filter_map(|expected| match expected {
+            Expected::Syntax(syntax) if syntax.starts_with('.') => Some(*syntax),
+            _ => None,
+        });
+        closest_match(&self.actual.unwrap_ident(), directives)
+    }
+
     pub fn merge(self, other: Self) -> Self {
         let Self {mut expected, actual} = self;
         let Self {expected: other_expected, actual: other_actual} = other;
@@ -170,44 +183,51 @@ impl<'a> ParseError<'a> {
 }
 
 pub fn collect_tokens(source: FileSource, diag: &Diagnostics) -> Vec<Token> {
-    let scanner = Scanner::new(source);
-    let mut lexer = Lexer::new(scanner, diag);
-
-    let mut tokens = Vec::new();
-    loop {
-        let token = lexer.next();
-        if token.kind == TokenKind::Eof {
-            tokens.push(token);
-            break;
-        }
-        tokens.push(token);
-    }
-
-    tokens
+    Lexer::new(source, diag).collect()
 }
 
 pub fn parse_program(tokens: &[Token], diag: &Diagnostics) -> ast::Program {
-    let (input, prog) = program(&tokens, diag);
-    assert!(input.is_empty(), "bug: parser did not consume all input");
-    prog
+    parse_tokens(tokens.iter().cloned(), diag)
 }
 
-fn program<'a>(mut input: Input<'a>, diag: &Diagnostics) -> (Input<'a>, ast::Program) {
+/// Parses a program from tokens that are produced as they are needed (e.g. by a `Lexer`)
+///
+/// The tokens must end with an `Eof` token. They are read one line at a time, so only the tokens
+/// of the line currently being parsed are stored, no matter how large the program is.
+pub fn parse_tokens<I: IntoIterator<Item=Token>>(tokens: I, diag: &Diagnostics) -> ast::Program {
+    let mut tokens = tokens.into_iter();
     let mut stmts = Vec::new();
 
-    while input.get(0).map(|tk| tk.kind != TokenKind::Eof).unwrap_or(false) {
-        input = extend_stmts(input, diag, &mut stmts);
-    }
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        for token in &mut tokens {
+            let end_of_line = token.kind == TokenKind::Newline || token.kind == TokenKind::Eof;
+            line.push(token);
+            if end_of_line {
+                break;
+            }
+        }
+        let reached_eof = match line.last() {
+            Some(token) => token.kind == TokenKind::Eof,
+            None => panic!("bug: tokens should end with an `Eof` token"),
+        };
+
+        // The lexer has already reported an error for any invalid tokens, so parsing this line
+        // would only produce more errors about the same problem
+        if line.iter().all(|token| token.kind != TokenKind::Error) {
+            let mut input = &line[..];
+            while input.get(0).map(|tk| tk.kind != TokenKind::Eof).unwrap_or(false) {
+                input = extend_stmts(input, diag, &mut stmts);
+            }
+        }
 
-    // After all the statements have been exhausted, the program should end with EOF
-    match tk(input, TokenKind::Eof) {
-        Ok((next_input, _)) => input = next_input,
-        Err((_, err)) => {
-            diag.span_error(err.actual.span, err.to_string()).emit();
-        },
+        if reached_eof {
+            break;
+        }
     }
 
-    (input, ast::Program {stmts})
+    ast::Program {stmts}
 }
 
 /// Parses a single `stmt` rule in the grammar
@@ -244,22 +264,90 @@ fn extend_stmts<'a>(
             input
         },
 
-        Err((mut input, err)) => {
-            diag.span_error(err.actual.span, err.to_string()).emit();
-
+        Err((err_input, err)) => {
             // Error recovery is done at a statement level. Read until the end of the line and keep trying
             // to parse the remainder of the file.
-            while input.get(0).map(|tk| tk.kind != TokenKind::Newline).unwrap_or(false) {
-                let (next_input, _) = advance(input);
-                input = next_input;
+            let mut line_end = err_input;
+            while line_end.first().map(|tk| tk.kind != TokenKind::Newline && tk.kind != TokenKind::Eof).unwrap_or(false) {
+                let (next_input, _) = advance(line_end);
+                line_end = next_input;
+            }
+
+            if missing_comma(err_input, &err) {
+                // Include the newline (or the end of the file) so the statement can be parsed again
+                let stmt_tokens = input[..input.len() - line_end.len() + 1].to_vec();
+                let offset = input.len() - err_input.len();
+                recover_missing_commas(stmt_tokens, offset, err.actual.span, diag, stmts);
+            } else {
+                report_parse_error(&err, diag);
+            }
+
+            // Advance past new line (the end of the file is left for the caller to find)
+            match newline(line_end) {
+                Ok((next_input, _)) => next_input,
+                Err(_) => line_end,
             }
-            // Advance past new line
-            let (next_input, _) = advance(input);
-            next_input
         },
     }
 }
 
+fn report_parse_error(err: &ParseError, diag: &Diagnostics) {
+    let mut writer = diag.span_error(err.actual.span, err.to_string());
+    if let Some(directive) = err.suggested_directive() {
+        writer = writer.span_help(err.actual.span, format!("did you mean `{}`?", directive));
+    }
+    writer.emit();
+}
+
+/// Returns true if the error occurred because a `,` was left out between two arguments
+fn missing_comma(input: Input, err: &ParseError) -> bool {
+    err.expected.contains(&Expected::Kind(TokenKind::Comma)) && instr_arg(input).is_ok()
+}
+
+/// Reports a missing `,` before the token at `offset` and then parses the statement again as if
+/// the `,` had been there, so any other errors in the statement are still reported
+///
+/// The tokens must contain exactly one statement body followed by a newline or the end of the file.
+fn recover_missing_commas(
+    mut tokens: Vec<Token>,
+    mut offset: usize,
+    mut span: Span,
+    diag: &Diagnostics,
+    stmts: &mut Vec<ast::Stmt>,
+) {
+    loop {
+        let mut writer = diag.span_error(span, "missing `,` between arguments")
+            .span_help(span, "add a `,` before this argument");
+        // `5 -1` is two arguments, since `-1` is only subtracted when written as `5-1` or `5 - 1`
+        let after_operand = matches!(tokens[offset - 1].kind, TokenKind::Ident | TokenKind::Here
+            | TokenKind::ParenClose | TokenKind::Literal(LitKind::Integer));
+        if let Some(TokenValue::Integer(value)) = tokens[offset].value {
+            if after_operand && value < 0 {
+                writer = writer.span_help(span, format!("to subtract, add a space after the `-`: `- {}`", -value));
+            }
+        }
+        writer.emit();
+        tokens.insert(offset, Token {kind: TokenKind::Comma, span, value: None});
+
+        match stmt_body(&tokens).and_parse(newline) {
+            Ok((_, (stmt, _))) => {
+                stmts.push(stmt);
+                break;
+            },
+
+            Err((err_input, err)) if missing_comma(err_input, &err) => {
+                offset = tokens.len() - err_input.len();
+                span = err.actual.span;
+            },
+
+            Err((_, err)) => {
+                report_parse_error(&err, diag);
+                break;
+            },
+        }
+    }
+}
+
 fn label(input: Input) -> ParseResult<ast::Ident> {
     ident(input)
         .and_parse(|input| tk(input, TokenKind::Colon))
@@ -270,15 +358,22 @@ fn label(input: Input) -> ParseResult<ast::Ident> {
 fn stmt_body(input: Input) -> ParseResult<ast::Stmt> {
     section_header(input).map_output(ast::Stmt::Section)
         .or_parse(|| include(input).map_output(ast::Stmt::Include))
+        .or_parse(|| conditional(input).map_output(ast::Stmt::Conditional))
+        .or_parse(|| export(input).map_output(ast::Stmt::Export))
+        .or_parse(|| extern_directive(input).map_output(ast::Stmt::Extern))
+        .or_parse(|| lint_directive(input).map_output(ast::Stmt::Lint))
         .or_parse(|| const_directive(input).map_output(ast::Stmt::Const))
+        .or_parse(|| reg_directive(input).map_output(ast::Stmt::Reg))
         .or_parse(|| static_data(input).map_output(ast::Stmt::StaticData))
         .or_parse(|| instr(input).map_output(ast::Stmt::Instr))
 }
 
 fn section_header(input: Input) -> ParseResult<ast::Section> {
     tk(input, TokenKind::Keyword(Keyword::Section)).and_parse(|input| {
-        dot_ident(input, ".static").map_output(|token| ast::SectionKind::Static(token.span))
-            .or_parse(|| dot_ident(input, ".code").map_output(|token| ast::SectionKind::Code(token.span)))
+        dot_ident(input, ".code").map_output(|token| ast::SectionKind::Code(token.span))
+            .or_parse(|| dot_ident(input, ".rodata").map_output(|token| ast::SectionKind::RoData(token.span)))
+            .or_parse(|| dot_ident(input, ".static").map_output(|token| ast::SectionKind::Static(token.span)))
+            .or_parse(|| dot_ident(input, ".bss").map_output(|token| ast::SectionKind::Bss(token.span)))
     }).map_output(|(kw, kind)| ast::Section {
         kind,
         span: kw.span.to(kind.span()),
@@ -286,26 +381,123 @@ fn section_header(input: Input) -> ParseResult<ast::Section> {
 }
 
 fn include(input: Input) -> ParseResult<ast::Include> {
-    dot_ident(input, ".include").and_parse(bytes_lit)
-        .map_output(|(dir, path)| {
+    dot_ident(input, ".include").map_output(|dir| (dir, false))
+        .or_parse(|| dot_ident(input, ".include_once").map_output(|dir| (dir, true)))
+        .and_parse(bytes_lit)
+        .map_output(|((dir, once), path)| {
             let span = dir.span.to(path.span);
-            ast::Include {path, span}
+            ast::Include {path, once, span}
         })
 }
 
+fn conditional(input: Input) -> ParseResult<ast::Conditional> {
+    ifdef(input).map_output(ast::Conditional::IfDef)
+        .or_parse(|| dot_ident(input, ".else").map_output(|dir| ast::Conditional::Else(dir.span)))
+        .or_parse(|| dot_ident(input, ".endif").map_output(|dir| ast::Conditional::EndIf(dir.span)))
+}
+
+fn ifdef(input: Input) -> ParseResult<ast::IfDef> {
+    dot_ident(input, ".ifdef").map_output(|dir| (dir, false))
+        .or_parse(|| dot_ident(input, ".ifndef").map_output(|dir| (dir, true)))
+        .and_parse(ident)
+        .map_output(|((dir, negated), name)| {
+            let span = dir.span.to(name.span);
+            ast::IfDef {name, negated, span}
+        })
+}
+
+fn export(input: Input) -> ParseResult<ast::Export> {
+    dot_ident(input, ".export")
+        .or_parse(|| dot_ident(input, ".global"))
+        .and_parse(ident)
+        .map_output(|(dir, name)| {
+            let span = dir.span.to(name.span);
+            ast::Export {name, span}
+        })
+}
+
+fn extern_directive(input: Input) -> ParseResult<ast::Extern> {
+    dot_ident(input, ".extern").and_parse(ident)
+        .map_output(|(dir, name)| {
+            let span = dir.span.to(name.span);
+            ast::Extern {name, span}
+        })
+}
+
+fn lint_directive(input: Input) -> ParseResult<ast::LintDirective> {
+    allow(input).map_output(ast::LintDirective::Allow)
+        .or_parse(|| dot_ident(input, ".endallow").map_output(|dir| ast::LintDirective::EndAllow(dir.span)))
+}
+
+fn allow(input: Input) -> ParseResult<ast::Allow> {
+    dot_ident(input, ".allow").and_parse(lint_name)
+        .map_output(|(dir, lint)| {
+            let span = dir.span.to(lint.span);
+            ast::Allow {lint, span}
+        })
+}
+
+/// Parses the name of a lint, e.g. `constant-redefined`
+///
+/// Lint names are lexed as identifiers separated by `-` tokens, so every part of the name must
+/// directly follow the previous part with no whitespace in between.
+fn lint_name(input: Input) -> ParseResult<ast::Ident> {
+    let (mut input, mut name) = ident(input)?;
+    let mut value = name.value.to_ascii_lowercase();
+
+    loop {
+        let (next_input, minus) = match tk(input, TokenKind::Minus) {
+            Ok(output) if output.1.span.start == name.span.end => output,
+            _ => break,
+        };
+        let (next_input, part) = match ident(next_input) {
+            Ok(output) if output.1.span.start == minus.span.end => output,
+            _ => break,
+        };
+
+        value.push('-');
+        value.push_str(&part.value.to_ascii_lowercase());
+        name.span = name.span.to(part.span);
+        input = next_input;
+    }
+
+    name.value = value.as_str().into();
+    Ok((input, name))
+}
+
 fn const_directive(input: Input) -> ParseResult<ast::Const> {
-    dot_ident(input, ".const").and_parse(ident).and_parse(immediate)
+    dot_ident(input, ".const").and_parse(ident).and_parse(const_value)
         .map_output(|((dir, name), value)| {
-            let span = dir.span.to(value.span);
+            let span = dir.span.to(value.span());
             ast::Const {name, value, span}
         })
 }
 
+fn const_value(input: Input) -> ParseResult<ast::ConstValue> {
+    expr(input).map_output(|expr| match expr {
+        ast::Expr::Integer(value) => ast::ConstValue::Immediate(value),
+        expr => match into_distance(expr) {
+            Ok(dist) => ast::ConstValue::Distance(dist),
+            Err(expr) => ast::ConstValue::Expr(expr),
+        },
+    }).or_parse(|| distance(input).map_output(ast::ConstValue::Distance))
+}
+
+fn reg_directive(input: Input) -> ParseResult<ast::Reg> {
+    dot_ident(input, ".reg").and_parse(ident).and_parse(register)
+        .map_output(|((dir, name), reg)| {
+            let span = dir.span.to(reg.span);
+            // Register names are case-insensitive even when other identifiers are not
+            ast::Reg {name: lowercase_ident(name), reg, span}
+        })
+}
+
 fn static_data(input: Input) -> ParseResult<ast::StaticData> {
     static_bytes(input).map_output(ast::StaticData::StaticBytes)
         .or_parse(|| static_zero(input).map_output(ast::StaticData::StaticZero))
         .or_parse(|| static_uninit(input).map_output(ast::StaticData::StaticUninit))
         .or_parse(|| static_byte_string(input).map_output(ast::StaticData::StaticByteStr))
+        .or_parse(|| static_float(input).map_output(ast::StaticData::StaticFloat))
 }
 
 fn static_bytes(input: Input) -> ParseResult<ast::StaticBytes> {
@@ -320,6 +512,16 @@ fn static_bytes(input: Input) -> ParseResult<ast::StaticBytes> {
         })
 }
 
+fn static_float(input: Input) -> ParseResult<ast::StaticFloat> {
+    dot_ident(input, ".f4").map_output(|tk| (4, tk.span))
+        .or_parse(|| dot_ident(input, ".f8").map_output(|tk| (8, tk.span)))
+        .and_parse(float)
+        .map_output(|((size, dir_span), value)| {
+            let span = dir_span.to(value.span);
+            ast::StaticFloat {size, value, span}
+        })
+}
+
 fn static_zero(input: Input) -> ParseResult<ast::StaticZero> {
     dot_ident(input, ".zero").and_parse(integer_lit)
         .map_output(|(dir, nbytes)| {
@@ -346,6 +548,8 @@ fn static_byte_string(input: Input) -> ParseResult<ast::StaticByteStr> {
 
 fn instr(input: Input) -> ParseResult<ast::Instr> {
     let (mut input, name) = ident(input)?;
+    // Instruction names are case-insensitive even when other identifiers are not
+    let name = lowercase_ident(name);
     let mut args = Vec::new();
 
     // Note: This code will need to change if instructions can ever end with something other than a
@@ -375,13 +579,20 @@ fn instr(input: Input) -> ParseResult<ast::Instr> {
                     Err(newline_err) => Err(newline_err),
                 };
 
-                // Otherwise, there must be a comma (no trailing commas allowed)
+                // Otherwise, there must be a comma
                 //
                 // Incorporating the newline error gives a better error message
                 let (next_input, _) = newline_res.clone().map(|_| panic!())
                     .or_parse(|| tk(input, TokenKind::Comma))?;
                 input = next_input;
 
+                // A trailing comma may be followed by a newline
+                newline_res = match newline(input) {
+                    // Do not update `input` so another parser up the stack can consume the newline
+                    Ok(_) => break,
+                    Err(newline_err) => Err(newline_err),
+                };
+
                 // Since a comma was found, we loop back around and expect there to be an argument
             }
         },
@@ -393,8 +604,105 @@ fn instr(input: Input) -> ParseResult<ast::Instr> {
 fn instr_arg(input: Input) -> ParseResult<ast::InstrArg> {
     offset_register(input).map_output(ast::InstrArg::Register)
         .or_parse(|| register(input).map_output(ast::InstrArg::Register))
-        .or_parse(|| immediate(input).map_output(ast::InstrArg::Immediate))
-        .or_parse(|| ident(input).map_output(ast::InstrArg::Name))
+        .or_parse(|| expr(input).map_output(|expr| match expr {
+            ast::Expr::Integer(value) => ast::InstrArg::Immediate(value),
+            ast::Expr::Name(name) => ast::InstrArg::Name(name),
+            expr => match into_distance(expr) {
+                Ok(dist) => ast::InstrArg::Distance(dist),
+                Err(expr) => ast::InstrArg::Expr(expr),
+            },
+        }))
+        .or_parse(|| distance(input).map_output(ast::InstrArg::Distance))
+        .or_parse(|| here(input).map_output(ast::InstrArg::Here))
+}
+
+/// Converts an expression of the form `end - start` into the distance between two labels
+///
+/// Whether the names actually refer to constants is not known until the whole program has been
+/// parsed, so `ConstTable` evaluates any distance between two constants as a subtraction.
+fn into_distance(expr: ast::Expr) -> Result<ast::Distance, ast::Expr> {
+    match expr {
+        ast::Expr::Binary(bin) => match *bin {
+            ast::BinaryExpr {op: ast::BinOp::Sub, lhs: ast::Expr::Name(end), rhs: ast::Expr::Name(start), span} => {
+                Ok(ast::Distance {end: ast::Symbol::Label(end), start: ast::Symbol::Label(start), span})
+            },
+            bin => Err(ast::Expr::Binary(Box::new(bin))),
+        },
+        expr => Err(expr),
+    }
+}
+
+/// Parses an arithmetic expression, where `*`, `/`, and `%` are applied before `+` and `-`
+fn expr(input: Input) -> ParseResult<ast::Expr> {
+    let (mut input, mut lhs) = term(input)?;
+
+    while let Ok((next_input, op)) = add_op(input) {
+        let (next_input, rhs) = term(next_input)?;
+        lhs = binary_expr(op, lhs, rhs);
+        input = next_input;
+    }
+
+    Ok((input, lhs))
+}
+
+fn term(input: Input) -> ParseResult<ast::Expr> {
+    let (input, lhs) = atom(input)?;
+    term_rest(input, lhs)
+}
+
+/// Parses any operations applied to the first operand of a term
+fn term_rest(mut input: Input, mut lhs: ast::Expr) -> ParseResult<ast::Expr> {
+    while let Ok((next_input, op)) = mul_op(input) {
+        let (next_input, rhs) = atom(next_input)?;
+        lhs = binary_expr(op, lhs, rhs);
+        input = next_input;
+    }
+
+    Ok((input, lhs))
+}
+
+fn atom(input: Input) -> ParseResult<ast::Expr> {
+    immediate(input).map_output(ast::Expr::Integer)
+        .or_parse(|| ident(input).map_output(ast::Expr::Name))
+        .or_parse(|| tk(input, TokenKind::ParenOpen)
+            .and_parse(expr)
+            .and_parse(|input| tk(input, TokenKind::ParenClose))
+            .map_output(|((_, expr), _)| expr))
+}
+
+fn binary_expr(op: ast::BinOp, lhs: ast::Expr, rhs: ast::Expr) -> ast::Expr {
+    let span = lhs.span().to(rhs.span());
+    ast::Expr::Binary(Box::new(ast::BinaryExpr {op, lhs, rhs, span}))
+}
+
+fn add_op(input: Input) -> ParseResult<ast::BinOp> {
+    tk(input, TokenKind::Plus).map_output(|_| ast::BinOp::Add)
+        .or_parse(|| tk(input, TokenKind::Minus).map_output(|_| ast::BinOp::Sub))
+}
+
+fn mul_op(input: Input) -> ParseResult<ast::BinOp> {
+    tk(input, TokenKind::Star).map_output(|_| ast::BinOp::Mul)
+        .or_parse(|| tk(input, TokenKind::Slash).map_output(|_| ast::BinOp::Div))
+        .or_parse(|| tk(input, TokenKind::Percent).map_output(|_| ast::BinOp::Rem))
+}
+
+fn distance(input: Input) -> ParseResult<ast::Distance> {
+    symbol(input)
+        .and_parse(|input| tk(input, TokenKind::Minus))
+        .and_parse(symbol)
+        .map_output(|((end, _), start)| {
+            let span = end.span().to(start.span());
+            ast::Distance {end, start, span}
+        })
+}
+
+fn symbol(input: Input) -> ParseResult<ast::Symbol> {
+    ident(input).map_output(ast::Symbol::Label)
+        .or_parse(|| here(input).map_output(ast::Symbol::Here))
+}
+
+fn here(input: Input) -> ParseResult<Span> {
+    tk(input, TokenKind::Here).map_output(|token| token.span)
 }
 
 fn offset_register(input: Input) -> ParseResult<ast::Register> {
@@ -413,10 +721,13 @@ fn immediate(input: Input) -> ParseResult<ast::Immediate> {
 }
 
 fn ident(input: Input) -> ParseResult<ast::Ident> {
-    tk(input, TokenKind::Ident).map_output(|token| ast::Ident {
-        value: token.unwrap_ident().clone(),
-        span: token.span,
-    })
+    tk(input, TokenKind::Ident).map_output(|token| ast::Ident::new(token.unwrap_ident(), token.span))
+}
+
+/// Converts an identifier to lowercase, for names that are case-insensitive even when
+/// identifiers are lexed case-sensitively
+fn lowercase_ident(ident: ast::Ident) -> ast::Ident {
+    ast::Ident {value: ident.value.to_ascii_lowercase().as_str().into(), ..ident}
 }
 
 fn register(input: Input) -> ParseResult<ast::Register> {
@@ -434,6 +745,24 @@ fn bytes_lit(input: Input) -> ParseResult<ast::Bytes> {
     })
 }
 
+/// Parses a floating-point literal or an integer literal (converted to a floating-point number)
+fn float(input: Input) -> ParseResult<ast::Float> {
+    float_lit(input)
+        .or_parse(|| integer_lit(input).map_output(|int| ast::Float {
+            value: int.value as f64,
+            integer: Some(int.value),
+            span: int.span,
+        }))
+}
+
+fn float_lit(input: Input) -> ParseResult<ast::Float> {
+    tk(input, TokenKind::Literal(LitKind::Float)).map_output(|token| ast::Float {
+        value: token.unwrap_float(),
+        integer: None,
+        span: token.span,
+    })
+}
+
 fn integer_lit(input: Input) -> ParseResult<ast::Integer> {
     tk(input, TokenKind::Literal(LitKind::Integer)).map_output(|token| ast::Integer {
         value: token.unwrap_integer(),
@@ -441,14 +770,22 @@ fn integer_lit(input: Input) -> ParseResult<ast::Integer> {
     })
 }
 
+/// Parses the end of a statement: a newline or the end of the file
+///
+/// The end of the file is not consumed so that it can still be found once every statement has
+/// been parsed.
 fn newline(input: Input) -> ParseResult<()> {
-    tk(input, TokenKind::Newline).map_output(|_| ())
+    match tk(input, TokenKind::Newline) {
+        Ok((next_input, _)) => Ok((next_input, ())),
+        Err(_) if input.first().map(|tk| tk.kind == TokenKind::Eof).unwrap_or(false) => Ok((input, ())),
+        Err(err) => Err(err),
+    }
 }
 
 /// Attempts to parse a dot_ident with the given name
 fn dot_ident<'a>(input: Input<'a>, name: &'static str) -> ParseResult<'a, &'a Token> {
     tk(input, TokenKind::DotIdent).and_then(|(next_input, token)| {
-        if &**token.unwrap_ident() == name {
+        if token.unwrap_ident().as_str() == name {
             Ok((next_input, token))
         } else {
             Err((input, ParseError {
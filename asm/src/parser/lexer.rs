@@ -1,33 +1,67 @@
-use std::sync::Arc;
-use std::collections::HashSet;
-
 use crate::diagnostics::Diagnostics;
+use crate::interner::InternedStr;
 
+use super::FileSource;
 use super::scanner::Scanner;
 use super::token::{self, *};
 
 use TokenKind::*;
 
+/// The value of a decimal number literal
+enum DecimalLit {
+    Integer(i128),
+    Float(f64),
+}
+
+/// Produces the tokens of a source file one at a time, reporting any invalid tokens as errors
+///
+/// As an `Iterator`, the last token produced is always an `Eof` token.
 pub struct Lexer<'a> {
     scanner: Scanner<'a>,
     diag: &'a Diagnostics,
-    interned_strings: HashSet<Arc<str>>,
+    /// True if identifiers keep their case instead of being converted to lowercase
+    case_sensitive: bool,
+    /// True once the iterator has produced the `Eof` token
+    finished: bool,
+    /// True if the previous token can end the operand of a subtraction (e.g. `SIZE` or `)`)
+    ends_operand: bool,
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        if self.finished {
+            return None;
+        }
+
+        let token = self.next_token();
+        self.finished = token.kind == Eof;
+        Some(token)
+    }
 }
 
 impl<'a> Lexer<'a> {
-    pub fn new(scanner: Scanner<'a>, diag: &'a Diagnostics) -> Self {
+    pub fn new(source: FileSource<'a>, diag: &'a Diagnostics) -> Self {
         Self {
-            scanner,
+            scanner: Scanner::new(source),
             diag,
-            interned_strings: HashSet::new(),
+            case_sensitive: source.case_sensitive(),
+            finished: false,
+            ends_operand: false,
         }
     }
 
     /// Returns the next token in the input
-    pub fn next(&mut self) -> Token {
+    ///
+    /// Once the end of the input is reached, an `Eof` token is returned every time.
+    pub fn next_token(&mut self) -> Token {
+        let prev_end = self.scanner.current_pos();
         self.ignore_whitespace_comments();
 
         let start = self.scanner.current_pos();
+        // The `-` in `SIZE-1` is a subtraction, but the `-1` in `SIZE -1` is a negative integer
+        let after_operand = self.ends_operand && start == prev_end;
         let current_char = match self.scanner.next() {
             Some(current_char) => current_char,
             None => return self.empty_token(start, Eof),
@@ -42,13 +76,20 @@ impl<'a> Lexer<'a> {
             (b'(', _) => Ok(self.byte_token(start, ParenOpen)),
             (b')', _) => Ok(self.byte_token(start, ParenClose)),
 
-            (b'\n', _) => Ok(self.byte_token(start, Newline)),
+            // `\` separates statements on the same line
+            (b'\n', _) |
+            (b'\\', _) => Ok(self.byte_token(start, Newline)),
 
             (b'"', _) |
             (b'\'', _) => self.bytes_lit(start, current_char),
 
             (b'0' ..= b'9', _) |
-            (b'-', Some(b'0' ..= b'9')) => self.integer_lit(start, current_char),
+            (b'-', Some(b'0' ..= b'9')) if !after_operand => self.integer_lit(start, current_char),
+            (b'-', _) => Ok(self.byte_token(start, Minus)),
+            (b'+', _) => Ok(self.byte_token(start, Plus)),
+            (b'*', _) => Ok(self.byte_token(start, Star)),
+            (b'/', _) => Ok(self.byte_token(start, Slash)),
+            (b'%', _) => Ok(self.byte_token(start, Percent)),
 
             (b'.', Some(b'a' ..= b'z')) |
             (b'.', Some(b'A' ..= b'Z')) |
@@ -67,7 +108,9 @@ impl<'a> Lexer<'a> {
             },
         };
 
-        res.unwrap_or_else(|err| err)
+        let token = res.unwrap_or_else(|err| err);
+        self.ends_operand = matches!(token.kind, Ident | Here | ParenClose | Literal(LitKind::Integer));
+        token
     }
 
     fn ignore_whitespace_comments(&mut self) {
@@ -127,8 +170,7 @@ impl<'a> Lexer<'a> {
                 Some(ch) if ch == quote => break,
 
                 Some(b'\\') => {
-                    let unescaped_byte = self.unescape_byte(start)?;
-                    unescaped_text.push(unescaped_byte);
+                    self.unescape_bytes(start, &mut unescaped_text)?;
                 },
 
                 // Unescaped newlines are not allowed
@@ -156,6 +198,74 @@ impl<'a> Lexer<'a> {
         Ok(self.token_to_current(start, Literal(LitKind::Bytes), value))
     }
 
+    /// Interprets an escape sequence assuming the starting `\` has already been parsed, appending
+    /// the unescaped bytes to `unescaped_text`
+    fn unescape_bytes(&mut self, start: usize, unescaped_text: &mut Vec<u8>) -> Result<(), Token> {
+        // A `\` at the end of a line continues the literal on the next line, skipping the newline
+        // and any whitespace at the start of the next line
+        let is_line_end = matches!((self.scanner.peek(), self.scanner.peek_next()),
+            (Some(b'\n'), _) | (Some(b'\r'), Some(b'\n')));
+        if is_line_end {
+            while let Some(b' ') | Some(b'\t') | Some(b'\r') | Some(b'\n') = self.scanner.peek() {
+                self.scanner.next();
+            }
+            return Ok(());
+        }
+
+        // Unicode escapes may produce more than one byte
+        if self.scanner.peek() == Some(b'u') {
+            self.scanner.next();
+            let ch = self.unescape_unicode(start)?;
+            let mut buf = [0; 4];
+            unescaped_text.extend(ch.encode_utf8(&mut buf).as_bytes());
+            return Ok(());
+        }
+
+        let byte = self.unescape_byte(start)?;
+        unescaped_text.push(byte);
+        Ok(())
+    }
+
+    /// Interprets a unicode escape sequence assuming that the starting `\u` has already been parsed
+    fn unescape_unicode(&mut self, start: usize) -> Result<char, Token> {
+        if !matches!(self.scanner.next(), Some(b'{')) {
+            let token = self.byte_token(start, Error);
+            self.diag.span_error(token.span, "invalid unicode escape, must look like: `\\u{41}`, `\\u{1F600}`").emit();
+            return Err(token);
+        }
+
+        let mut digits_buf = String::new();
+        let digits = self.digits(true, Some(&mut digits_buf));
+        if digits == 0 {
+            let token = self.byte_token(start, Error);
+            self.diag.span_error(token.span, "empty unicode escape, must look like: `\\u{41}`, `\\u{1F600}`").emit();
+            return Err(token);
+        } else if digits > 6 {
+            let token = self.byte_token(start, Error);
+            self.diag.span_error(token.span, "unicode escape must be 1-6 digits long, e.g. `\\u{41}`, `\\u{1F600}`").emit();
+            return Err(token);
+        }
+
+        if !matches!(self.scanner.next(), Some(b'}')) {
+            let token = self.byte_token(start, Error);
+            self.diag.span_error(token.span, "invalid unicode escape, must look like: `\\u{41}`, `\\u{1F600}`").emit();
+            return Err(token);
+        }
+
+        // The code above guarantees that we will have a valid number within the range of u32
+        let value = u32::from_str_radix(&digits_buf, 16)
+            .expect("bug: should have had a valid u32");
+
+        match std::char::from_u32(value) {
+            Some(ch) => Ok(ch),
+            None => {
+                let token = self.byte_token(start, Error);
+                self.diag.span_error(token.span, format!("invalid unicode escape: `{:X}` is not a unicode scalar value", value)).emit();
+                Err(token)
+            },
+        }
+    }
+
     /// Interprets a byte escape sequence assuming the starting `\` has already been parsed
     fn unescape_byte(&mut self, start: usize) -> Result<u8, Token> {
         match self.scanner.next() {
@@ -244,15 +354,20 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    /// Parses an integer literal, given a starting digit or negative sign
+    /// Parses an integer or floating-point literal, given a starting digit or negative sign
     ///
-    /// The produced value will be 128-bits, but it will not exceed the range [i64::min(), u64::max()]
+    /// The produced integer value will be 128-bits, but it will not exceed the range
+    /// [i64::min(), u64::max()]
     fn integer_lit(&mut self, start: usize, start_byte: u8) -> Result<Token, Token> {
-        // If the start digit is zero, we may have a hex or binary literal
+        // If the start digit is zero, we may have a hex, octal, or binary literal
         let value = match (start_byte, self.scanner.peek()) {
-            (b'0', Some(b'x')) => self.hex_lit_value(start)?,
-            (b'0', Some(b'b')) => self.binary_lit_value(start)?,
-            _ => self.decimal_lit_value(start, start_byte)?,
+            (b'0', Some(b'x')) | (b'0', Some(b'X')) => self.hex_lit_value(start)?,
+            (b'0', Some(b'o')) | (b'0', Some(b'O')) => self.octal_lit_value(start)?,
+            (b'0', Some(b'b')) | (b'0', Some(b'B')) => self.binary_lit_value(start)?,
+            _ => match self.decimal_lit_value(start, start_byte)? {
+                DecimalLit::Integer(value) => value,
+                DecimalLit::Float(value) => return self.float_lit(start, value),
+            },
         };
 
         if value < i64::MIN as i128 || value > u64::MAX as i128 {
@@ -261,7 +376,30 @@ impl<'a> Lexer<'a> {
             return Err(token);
         }
 
-        // An integer cannot be directly followed by an identifier with no whitespace in between
+        self.no_trailing_ident(start)?;
+
+        let value = TokenValue::Integer(value);
+        Ok(self.token_to_current(start, Literal(LitKind::Integer), value))
+    }
+
+    /// Produces a floating-point literal token for the value parsed starting at `start`
+    fn float_lit(&mut self, start: usize, value: f64) -> Result<Token, Token> {
+        if value.is_infinite() {
+            let token = self.token_to_current(start, Error, None);
+            self.diag.span_error(token.span, "floating-point literal out of 64-bit range").emit();
+            return Err(token);
+        }
+
+        self.no_trailing_ident(start)?;
+
+        let value = TokenValue::Float(value);
+        Ok(self.token_to_current(start, Literal(LitKind::Float), value))
+    }
+
+    /// Produces an error if the number literal starting at `start` is directly followed by an
+    /// identifier
+    fn no_trailing_ident(&mut self, start: usize) -> Result<(), Token> {
+        // A number cannot be directly followed by an identifier with no whitespace in between
         if matches!(self.scanner.peek(), Some(b'a'..=b'z') | Some(b'A'..=b'Z')) {
             // Skip the first character
             self.scanner.next();
@@ -279,12 +417,11 @@ impl<'a> Lexer<'a> {
             }
 
             let token = self.token_to_current(start, Error, None);
-            self.diag.span_error(token.span, "invalid integer literal").emit();
+            self.diag.span_error(token.span, "invalid number literal").emit();
             return Err(token);
         }
 
-        let value = TokenValue::Integer(value);
-        Ok(self.token_to_current(start, Literal(LitKind::Integer), value))
+        Ok(())
     }
 
     fn hex_lit_value(&mut self, start: usize) -> Result<i128, Token> {
@@ -309,6 +446,28 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    fn octal_lit_value(&mut self, start: usize) -> Result<i128, Token> {
+        // Skip `o` character
+        self.scanner.next();
+
+        let mut digits_buf = String::new();
+        let digits = self.digits(false, Some(&mut digits_buf));
+        if digits == 0 {
+            let token = self.token_to_current(start, Error, None);
+            self.diag.span_error(token.span, "invalid octal number literal").emit();
+            return Err(token);
+        }
+
+        match i128::from_str_radix(&digits_buf, 8) {
+            Ok(value) => Ok(value),
+            Err(_) => {
+                let token = self.token_to_current(start, Error, None);
+                self.diag.span_error(token.span, "invalid octal number literal").emit();
+                Err(token)
+            },
+        }
+    }
+
     fn binary_lit_value(&mut self, start: usize) -> Result<i128, Token> {
         // Skip `b` character
         self.scanner.next();
@@ -333,7 +492,10 @@ impl<'a> Lexer<'a> {
 
     /// Parses a decimal number literal assuming that either a digit or a negative sign has already
     /// been parsed
-    fn decimal_lit_value(&mut self, start: usize, start_byte: u8) -> Result<i128, Token> {
+    ///
+    /// If the digits are followed by a decimal point and another digit, the literal is parsed as a
+    /// floating-point number.
+    fn decimal_lit_value(&mut self, start: usize, start_byte: u8) -> Result<DecimalLit, Token> {
         let mut digits_buf = String::new();
 
         // Add the start digit or negative sign
@@ -346,8 +508,12 @@ impl<'a> Lexer<'a> {
             return Err(token);
         }
 
-        match i128::from_str_radix(&digits_buf, 10) {
-            Ok(value) => Ok(value),
+        if self.scanner.peek() == Some(b'.') && matches!(self.scanner.peek_next(), Some(b'0' ..= b'9')) {
+            return self.float_lit_value(start, digits_buf).map(DecimalLit::Float);
+        }
+
+        match digits_buf.parse() {
+            Ok(value) => Ok(DecimalLit::Integer(value)),
             Err(_) => {
                 let token = self.token_to_current(start, Error, None);
                 self.diag.span_error(token.span, "invalid decimal number literal").emit();
@@ -356,6 +522,42 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /// Parses the fractional part and optional exponent of a floating-point literal, assuming that
+    /// the integer part has already been parsed into `digits_buf` and that the next character is
+    /// the decimal point
+    fn float_lit_value(&mut self, start: usize, mut digits_buf: String) -> Result<f64, Token> {
+        // Skip `.` character
+        self.scanner.next();
+        digits_buf.push('.');
+        self.digits(false, Some(&mut digits_buf));
+
+        if matches!(self.scanner.peek(), Some(b'e') | Some(b'E')) {
+            self.scanner.next();
+            digits_buf.push('e');
+
+            if let Some(sign@b'+') | Some(sign@b'-') = self.scanner.peek() {
+                self.scanner.next();
+                digits_buf.push(sign as char);
+            }
+
+            let digits = self.digits(false, Some(&mut digits_buf));
+            if digits == 0 {
+                let token = self.token_to_current(start, Error, None);
+                self.diag.span_error(token.span, "expected at least one digit in the exponent of floating-point literal").emit();
+                return Err(token);
+            }
+        }
+
+        match digits_buf.parse() {
+            Ok(value) => Ok(value),
+            Err(_) => {
+                let token = self.token_to_current(start, Error, None);
+                self.diag.span_error(token.span, "invalid floating-point number literal").emit();
+                Err(token)
+            },
+        }
+    }
+
     /// Advances the scanner until no more digits are found. Returns the number of digits found.
     ///
     /// The final, non-digit character is NOT consumed
@@ -394,7 +596,7 @@ impl<'a> Lexer<'a> {
         let value = self.scanner.slice(start, self.scanner.current_pos());
         // Identifiers are case-insensitive
         let value = value.to_ascii_lowercase();
-        let value = TokenValue::Ident(self.intern_str(value));
+        let value = TokenValue::Ident(InternedStr::intern(&value));
         self.token_to_current(start, TokenKind::DotIdent, value)
     }
 
@@ -414,10 +616,26 @@ impl<'a> Lexer<'a> {
         let value = self.scanner.slice(start, self.scanner.current_pos());
         match token::Keyword::from_str(value) {
             Some(kw) => self.token_to_current(start, Keyword(kw), None),
+
+            // The current line and file are substituted as literals
+            None if value.eq_ignore_ascii_case("__line__") => {
+                let value = TokenValue::Integer(self.scanner.current_line() as i128);
+                self.token_to_current(start, Literal(LitKind::Integer), value)
+            },
+            None if value.eq_ignore_ascii_case("__file__") => {
+                let path = self.scanner.path().to_string_lossy();
+                let value = TokenValue::Bytes(path.as_bytes().into());
+                self.token_to_current(start, Literal(LitKind::Bytes), value)
+            },
+
             None => {
-                // Identifiers are case-insensitive
-                let value = value.to_ascii_lowercase();
-                let value = TokenValue::Ident(self.intern_str(value));
+                // Identifiers are case-insensitive unless configured otherwise
+                let value = if self.case_sensitive {
+                    InternedStr::intern(value)
+                } else {
+                    InternedStr::intern(&value.to_ascii_lowercase())
+                };
+                let value = TokenValue::Ident(value);
                 self.token_to_current(start, TokenKind::Ident, value)
             },
         }
@@ -429,9 +647,18 @@ impl<'a> Lexer<'a> {
         match self.scanner.next() {
             Some(b'a' ..= b'z') => {
                 let name_token = self.ident(reg_name_start);
-                match name_token.kind {
-                    TokenKind::Ident => {
-                        let name = name_token.unwrap_ident().clone();
+                // Register names are case-insensitive even when other identifiers are not
+                let name = match name_token.kind {
+                    TokenKind::Ident => Some(InternedStr::intern(&name_token.unwrap_ident().to_ascii_lowercase())),
+                    _ => None,
+                };
+                match name {
+                    // `$here` is a symbol, not a register
+                    Some(name) if name.as_str() == "here" => {
+                        Ok(self.token_to_current(start, Here, None))
+                    },
+
+                    Some(name) => {
                         let value = TokenValue::Register(token::Register::Named(name));
                         Ok(self.token_to_current(start, Register, value))
                     },
@@ -511,39 +738,32 @@ impl<'a> Lexer<'a> {
         let span = self.scanner.span(start, self.scanner.current_pos());
         Token {kind, span, value: value.into()}
     }
-
-    fn intern_str(&mut self, value: String) -> Arc<str> {
-        match self.interned_strings.get(&*value) {
-            Some(interned) => interned.clone(),
-            None => {
-                let interned: Arc<str> = value.into();
-                self.interned_strings.insert(interned.clone());
-                interned
-            },
-        }
-    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use std::sync::Arc;
+
     use parking_lot::RwLock;
 
-    use super::super::{Span, SourceFiles};
+    use crate::diagnostics::NullWriter;
+
+    use super::super::{Span, FileId, SourceFiles};
 
     macro_rules! t {
         ($kind:expr) => (
             Token {
                 kind: $kind,
-                span: Span {start: 0, end: 0},
+                span: Span {file: FileId(0), start: 0, end: 0},
                 value: None,
             }
         );
         ($kind:expr, $value:expr) => (
             Token {
                 kind: $kind,
-                span: Span {start: 0, end: 0},
+                span: Span {file: FileId(0), start: 0, end: 0},
                 value: Some($value),
             }
         );
@@ -583,15 +803,14 @@ mod tests {
         ($source:literal, $expected:expr) => {
             let source_files = Arc::new(RwLock::new(SourceFiles::default()));
             let root_file = source_files.write().add_source("test.rs", $source);
-            let diag = Diagnostics::new(source_files.clone(), termcolor::ColorChoice::Auto);
+            let diag = Diagnostics::new(source_files.clone(), Box::new(NullWriter));
             let files = source_files.read();
-            let scanner = Scanner::new(files.source(root_file));
-            let mut lexer = Lexer::new(scanner, &diag);
-            let token = lexer.next();
+            let mut lexer = Lexer::new(files.source(root_file), &diag);
+            let token = lexer.next_token();
             let expected = $expected;
             assert_eq!(token.kind, expected.kind);
             assert_eq!(token.value, expected.value);
-            let token = lexer.next();
+            let token = lexer.next_token();
             assert_eq!(token.kind, Eof);
         };
     }
@@ -600,18 +819,17 @@ mod tests {
         ($source:literal, $expected:expr) => {
             let source_files = Arc::new(RwLock::new(SourceFiles::default()));
             let root_file = source_files.write().add_source("test.rs", $source);
-            let diag = Diagnostics::new(source_files.clone(), termcolor::ColorChoice::Auto);
+            let diag = Diagnostics::new(source_files.clone(), Box::new(NullWriter));
             let files = source_files.read();
-            let scanner = Scanner::new(files.source(root_file));
-            let mut lexer = Lexer::new(scanner, &diag);
+            let mut lexer = Lexer::new(files.source(root_file), &diag);
             let expected_tokens: &[Token] = $expected;
             for expected_token in expected_tokens {
-                let token = lexer.next();
+                let token = lexer.next_token();
                 assert_eq!(token.kind, expected_token.kind);
                 assert_eq!(token.value, expected_token.value);
             }
             // Ensure that the input is exhausted
-            let token = lexer.next();
+            let token = lexer.next_token();
             assert_eq!(token.kind, Eof);
         };
     }
@@ -682,6 +900,43 @@ mod tests {
         expect_error!(b"1844A674f4073709C551616");
     }
 
+    macro_rules! float {
+        ($value:expr) => (
+            t!(Literal(LitKind::Float), TokenValue::Float($value))
+        );
+    }
+
+    #[test]
+    fn float_literals() {
+        expect_token!(b"0.0", float!(0.0));
+        expect_token!(b"0.5", float!(0.5));
+        expect_token!(b"-12.25", float!(-12.25));
+        expect_token!(b"1_000.000_1", float!(1000.0001));
+        expect_token!(b"2.5", float!(2.5));
+        expect_token!(b"6.02e23", float!(6.02e23));
+        expect_token!(b"6.02E+23", float!(6.02e23));
+        expect_token!(b"1.5e-3", float!(1.5e-3));
+        expect_token!(b"-1.0E-3_0", float!(-1.0e-30));
+    }
+
+    #[test]
+    fn float_literals_invalid() {
+        // out of range
+        expect_error!(b"1.0e309");
+        expect_error!(b"-1.0e309");
+
+        // empty exponent
+        expect_error!(b"1.0e");
+        expect_error!(b"1.0e+");
+
+        // followed by an identifier
+        expect_error!(b"1.5f");
+
+        // a decimal point must be followed by a digit
+        expect_tokens!(b"1.", &[int!(1), t!(Error)]);
+        expect_tokens!(b"1.e5", &[int!(1), dot_ident!(".e5")]);
+    }
+
     #[test]
     fn hex_literals() {
         expect_token!(b"0x0", int!(0x0));
@@ -693,6 +948,8 @@ mod tests {
         expect_token!(b"0xffff", int!(0xffff));
         expect_token!(b"0xffff_ffff_ffff_ffff", int!(u64::MAX as i128));
         expect_token!(b"0xFFFF_FFFF_FFFF_FFFF", int!(u64::MAX as i128));
+        expect_token!(b"0X1f3", int!(0x1f3));
+        expect_token!(b"0XFFFF_FFFF_FFFF_FFFF", int!(u64::MAX as i128));
     }
 
     #[test]
@@ -711,12 +968,40 @@ mod tests {
         expect_tokens!(b"0xg", &[t!(Error), ident!("g")]);
     }
 
+    #[test]
+    fn octal_literals() {
+        expect_token!(b"0o0", int!(0o0));
+        expect_token!(b"0o000", int!(0o0));
+        expect_token!(b"0o17", int!(0o17));
+        expect_token!(b"0o755", int!(0o755));
+        expect_token!(b"0O644", int!(0o644));
+        expect_token!(b"0o1_777_777_777_777_777_777_777", int!(u64::MAX as i128));
+    }
+
+    #[test]
+    fn octal_literals_invalid() {
+        // out of range
+        expect_error!(b"0o2_000_000_000_000_000_000_000");
+
+        // cannot be negative
+        expect_error!(b"-0o0");
+        expect_error!(b"-0o7");
+
+        // empty
+        expect_error!(b"0o");
+
+        // decimal or hex digit
+        expect_error!(b"0o8");
+        expect_tokens!(b"0oF", &[t!(Error), ident!("f")]);
+    }
+
     #[test]
     fn binary_literals() {
         expect_token!(b"0b0", int!(0b0));
         expect_token!(b"0b000", int!(0b000));
         expect_token!(b"0b0001", int!(0b0001));
         expect_token!(b"0b1", int!(0b1));
+        expect_token!(b"0B101", int!(0b101));
         expect_token!(b"0b11111111_11111111_11111111_11111111_11111111_11111111_11111111_11111111", int!(u64::MAX as i128));
     }
 
@@ -763,6 +1048,25 @@ mod tests {
         expect_tokens!(b"\"\\b{100000000}\"", &[t!(Error), t!(Error), t!(Error)]);
     }
 
+    #[test]
+    fn bytes_unicode_escape() {
+        let bytes_value = |bytes: &[u8]| TokenValue::Bytes(bytes.into());
+
+        expect_token!(b"'\\u{41}'", t!(Literal(LitKind::Bytes), bytes_value(b"A")));
+        expect_token!(b"'\\u{e9}\\u{3bb}'", t!(Literal(LitKind::Bytes), bytes_value("\u{e9}\u{3bb}".as_bytes())));
+        expect_token!(b"\"\\u{1F600} \\u{0}\"", t!(Literal(LitKind::Bytes), bytes_value("\u{1F600} \0".as_bytes())));
+        expect_token!(b"'\\u{10_FFFF}'", t!(Literal(LitKind::Bytes), bytes_value("\u{10FFFF}".as_bytes())));
+    }
+
+    #[test]
+    fn bytes_invalid_unicode_escape() {
+        expect_tokens!(b"\"\\u{}\"", &[t!(Error), t!(Error), t!(Error)]);
+        expect_tokens!(b"\"\\u{1000000}\"", &[t!(Error), t!(Error), t!(Error)]);
+        // surrogates and values past the end of the unicode range are not valid scalar values
+        expect_tokens!(b"\"\\u{D800}\"", &[t!(Error), t!(Error)]);
+        expect_tokens!(b"\"\\u{110000}\"", &[t!(Error), t!(Error)]);
+    }
+
     #[test]
     fn bytes_multiline() {
         expect_error!(b"\"abc\n\"");
@@ -773,6 +1077,19 @@ mod tests {
         def\"'");
     }
 
+    #[test]
+    fn bytes_line_continuation() {
+        let bytes_value = |bytes: &[u8]| TokenValue::Bytes(bytes.into());
+
+        expect_token!(b"'abc\\\ndef'", t!(Literal(LitKind::Bytes), bytes_value(b"abcdef")));
+        expect_token!(b"\"abc \\\r\n    \t def\"", t!(Literal(LitKind::Bytes), bytes_value(b"abc def")));
+        // Any blank lines after the `\` are skipped too
+        expect_token!(b"'abc\\\n\n  def\\n'", t!(Literal(LitKind::Bytes), bytes_value(b"abcdef\n")));
+        expect_token!(b"'abc\\\n'", t!(Literal(LitKind::Bytes), bytes_value(b"abc")));
+        // A `\` followed by other whitespace is still an invalid escape
+        expect_tokens!(b"'abc\\ \n'", &[t!(Error), t!(Newline), t!(Error)]);
+    }
+
     #[test]
     fn bytes_mismatched_quotes() {
         expect_error!(b"\"'");
@@ -893,8 +1210,53 @@ mod tests {
             t!(Comma), int!(-128), t!(ParenOpen), reg!("sp"), t!(ParenClose)]);
     }
 
+    #[test]
+    fn statement_separator() {
+        expect_tokens!(b"nop \\ ret\n", &[ident!("nop"), t!(Newline), ident!("ret"), t!(Newline)]);
+        expect_tokens!(b"a:\\b:", &[ident!("a"), t!(Colon), t!(Newline), ident!("b"), t!(Colon)]);
+    }
+
+    #[test]
+    fn file_and_line() {
+        expect_token!(b"__LINE__", int!(1));
+        expect_tokens!(b"\n# comment\n  __line__, __Line__", &[t!(Newline), t!(Newline), int!(3),
+            t!(Comma), int!(3)]);
+        expect_token!(b"__FILE__", t!(Literal(LitKind::Bytes), TokenValue::Bytes((&b"test.rs"[..]).into())));
+        expect_token!(b"__line", ident!("__line"));
+    }
+
+    #[test]
+    fn here_symbol() {
+        expect_token!(b"$here", t!(Here));
+        expect_token!(b"$here_", reg!("here_"));
+        expect_tokens!(b"$here - start", &[t!(Here), t!(Minus), ident!("start")]);
+        expect_tokens!(b"$here-start", &[t!(Here), t!(Minus), ident!("start")]);
+        expect_tokens!(b"$here-1", &[t!(Here), t!(Minus), int!(1)]);
+        expect_tokens!(b"$here -1", &[t!(Here), int!(-1)]);
+    }
+
+    #[test]
+    fn operators() {
+        expect_tokens!(b"a + 2*3 / (4 % b)", &[ident!("a"), t!(Plus), int!(2), t!(Star), int!(3),
+            t!(Slash), t!(ParenOpen), int!(4), t!(Percent), ident!("b"), t!(ParenClose)]);
+        expect_tokens!(b"a+-2", &[ident!("a"), t!(Plus), int!(-2)]);
+        expect_tokens!(b"a-2 (b)-2 5-2", &[ident!("a"), t!(Minus), int!(2), t!(ParenOpen), ident!("b"),
+            t!(ParenClose), t!(Minus), int!(2), int!(5), t!(Minus), int!(2)]);
+        expect_tokens!(b"a -2, -2", &[ident!("a"), int!(-2), t!(Comma), int!(-2)]);
+    }
+
     #[test]
     fn unknown_token_start() {
         expect_tokens!(b"123\0456", &[int!(123), t!(Error), int!(456)]);
     }
+
+    #[test]
+    fn iterator_ends_with_eof() {
+        let source_files = Arc::new(RwLock::new(SourceFiles::default()));
+        let root_file = source_files.write().add_source("test.rs", b"add $1, 2\n");
+        let diag = Diagnostics::new(source_files.clone(), Box::new(NullWriter));
+        let files = source_files.read();
+        let kinds: Vec<_> = Lexer::new(files.source(root_file), &diag).map(|token| token.kind).collect();
+        assert_eq!(kinds, &[Ident, Register, Comma, Literal(LitKind::Integer), Newline, Eof]);
+    }
 }
@@ -1,4 +1,5 @@
 use std::str;
+use std::path::Path;
 
 use super::span::Span;
 use super::source_files::FileSource;
@@ -7,6 +8,8 @@ use super::source_files::FileSource;
 pub struct Scanner<'a> {
     source: FileSource<'a>,
     current: usize,
+    /// The line number (1-based) of the current position
+    line: usize,
 }
 
 impl<'a> Scanner<'a> {
@@ -14,6 +17,7 @@ impl<'a> Scanner<'a> {
         Self {
             source,
             current: source.start_index(),
+            line: 1,
         }
     }
 
@@ -22,10 +26,23 @@ impl<'a> Scanner<'a> {
         self.current
     }
 
+    /// Returns the line number (1-based) of the current position in the source
+    pub fn current_line(&self) -> usize {
+        self.line
+    }
+
+    /// Returns the path of the file being scanned
+    pub fn path(&self) -> &'a Path {
+        self.source.path()
+    }
+
     /// Returns the next character in the source text or returns None if there are no more left
     pub fn next(&mut self) -> Option<u8> {
         let ch = self.peek()?;
         self.current += 1;
+        if ch == b'\n' {
+            self.line += 1;
+        }
         Some(ch)
     }
 
@@ -34,6 +51,12 @@ impl<'a> Scanner<'a> {
         self.source.get(self.current)
     }
 
+    /// Returns the character after the next character in the source text, but does not advance
+    /// the scanner
+    pub fn peek_next(&self) -> Option<u8> {
+        self.source.get(self.current + 1)
+    }
+
     /// Creates a new span that is empty (from `index` to `index`)
     pub fn empty_span(&self, index: usize) -> Span {
         self.span(index, index)
@@ -48,7 +71,7 @@ impl<'a> Scanner<'a> {
     ///
     /// `start` is included in the range, `end` is not.
     pub fn span(&self, start: usize, end: usize) -> Span {
-        Span {start, end}
+        Span {file: self.source.file_id(), start, end}
     }
 
     /// Creates a new slice of the source between the given byte indexes and parse it as unicode
@@ -3,11 +3,13 @@ use std::io::{self, Read};
 use std::path::{Path, PathBuf};
 use std::ops::Range;
 
-use super::span::Span;
+use super::span::{Span, FileId};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct FileHandle {
-    /// The index of the first byte of the file in `SourceFiles::source`
+    /// The unique ID of the file
+    id: FileId,
+    /// The global offset of the first byte of the file
     start: usize,
     /// The number of bytes in the file
     len: usize,
@@ -16,13 +18,42 @@ pub struct FileHandle {
 /// The source for a file, represented as a slice of bytes and indexed from `start_index()` onwards
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct FileSource<'a> {
-    /// A slice of `SourceFiles::source`
+    /// The unique ID of the file
+    file: FileId,
+    /// The path of the file
+    path: &'a Path,
+    /// The bytes of the file
     bytes: &'a [u8],
-    /// The offset at which the slice of bytes was extracted from `SourceFiles::source`
+    /// The global offset of the first byte of the file
     offset: usize,
+    /// True if identifiers in the file should keep their case (see
+    /// `SourceFiles::set_case_sensitive`)
+    case_sensitive: bool,
+}
+
+impl FileHandle {
+    /// Returns the unique ID of the file
+    pub fn id(&self) -> FileId {
+        self.id
+    }
 }
 
 impl<'a> FileSource<'a> {
+    /// Returns the unique ID of the file
+    pub fn file_id(&self) -> FileId {
+        self.file
+    }
+
+    /// Returns the path of the file
+    pub fn path(&self) -> &'a Path {
+        self.path
+    }
+
+    /// Returns true if identifiers in this file are case-sensitive
+    pub fn case_sensitive(&self) -> bool {
+        self.case_sensitive
+    }
+
     /// Returns the first index into this slice
     pub fn start_index(&self) -> usize {
         self.offset
@@ -44,7 +75,7 @@ impl<'a> FileSource<'a> {
 
     /// Slices from the bytes of this file's source
     pub fn slice(&self, range: Range<usize>) -> &'a [u8] {
-        let Self {bytes, offset} = self;
+        let Self {bytes, offset, ..} = self;
         let Range {start, end} = range;
 
         &bytes[start-offset..end-offset]
@@ -58,7 +89,7 @@ impl<'a> FileSource<'a> {
 
 #[derive(Debug)]
 pub struct LineNumbers {
-    /// The index in `SourceFiles::source` of the first byte in each line
+    /// The global offset of the first byte in each line
     ///
     /// The first byte of a line is defined as either the first byte in the file or a byte
     /// immediately after a b`\n`.
@@ -110,103 +141,305 @@ impl LineNumbers {
 
         (line, offset)
     }
+
+    /// Returns the index in the source file of the first byte of the given 1-based line number
+    ///
+    /// The line after the last line starts one past the end of the file.
+    pub fn line_start(&self, line: usize) -> usize {
+        self.offsets[line-1]
+    }
+
+    /// Returns the range of indexes in the source file that make up the given 1-based line
+    /// number, including its newline (if any)
+    pub fn line_range(&self, line: usize) -> Range<usize> {
+        self.offsets[line-1]..self.offsets[line]
+    }
 }
 
 #[derive(Debug)]
 struct File {
+    id: FileId,
     path: PathBuf,
-    /// The index into `SourceFiles::source` that represents the start of this file
+    /// The global offset that represents the start of this file
+    ///
+    /// Every file is assigned a unique, non-overlapping range of offsets so that spans across
+    /// files remain uniquely identifiable even though each file has its own buffer.
     start_offset: usize,
+    /// The source code of this file
+    source: Vec<u8>,
     /// An index of the line numbers for all offsets in the file
     line_numbers: LineNumbers,
 }
 
+impl File {
+    fn source(&self) -> FileSource<'_> {
+        FileSource {
+            file: self.id,
+            path: &self.path,
+            bytes: &self.source,
+            offset: self.start_offset,
+            case_sensitive: false,
+        }
+    }
+}
+
+/// The position of a span within a file
+///
+/// Lines, offsets, and columns are all 1-based. Offsets count bytes from the start of the line
+/// while columns count characters, so they only differ on lines with non-ASCII characters. The end
+/// position refers to the last byte/character of the span (not one past it).
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct FilePos<'a> {
     pub path: &'a Path,
     pub start_line: usize,
     pub start_offset: usize,
+    pub start_column: usize,
     pub end_line: usize,
     pub end_offset: usize,
+    pub end_column: usize,
 }
 
 #[derive(Debug, Default)]
 pub struct SourceFiles {
-    /// The source code of all files concatenated together.
+    /// The source code and metadata of each file
     ///
-    /// This allows spans across files to be uniquely identifiable
-    source: Vec<u8>,
-    /// Metadata about each file stored in `source`
-    ///
-    /// Sorted by the offset
+    /// Sorted by the file ID (and thus also by the offset). Each file owns its own buffer, so removing or replacing a file frees
+    /// the memory used by its previous contents.
     files: Vec<File>,
+    /// The global offset that will be assigned to the start of the next file that is added
+    ///
+    /// Offsets are never reused, even when a file is replaced.
+    next_offset: usize,
+    /// The ID that will be assigned to the next file that is added
+    next_id: usize,
+    /// True if identifiers are case-sensitive in every file
+    case_sensitive: bool,
 }
 
 impl SourceFiles {
+    /// Sets whether identifiers (e.g. labels and constant names) keep their case when any of
+    /// these files are lexed
+    ///
+    /// Identifiers are case-insensitive by default. Instruction names, register names, and
+    /// directives are always case-insensitive.
+    pub fn set_case_sensitive(&mut self, case_sensitive: bool) {
+        self.case_sensitive = case_sensitive;
+    }
+
     /// Reads a file and adds it to the set of source files. Returns a handle to that file's
     /// contents.
     pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<FileHandle> {
         let path = path.as_ref();
-        let start = self.source.len();
 
+        let mut source = Vec::new();
         let mut file = fs::File::open(path)?;
-        file.read_to_end(&mut self.source)?;
+        file.read_to_end(&mut source)?;
 
-        let len = self.source.len() - start;
-        Ok(self.create_handle(path, start, len))
+        Ok(self.push_file(path, source))
     }
 
     /// Adds the given source to the set of source files. Returns a handle to that file's
     /// contents.
     pub fn add_source<P: AsRef<Path>>(&mut self, path: P, source: &[u8]) -> FileHandle {
-        let path = path.as_ref();
-        let start = self.source.len();
+        self.push_file(path.as_ref(), source.to_vec())
+    }
 
-        self.source.extend(source);
+    /// Replaces the contents of a previously added file with the given source. Returns a new
+    /// handle to the file's updated contents.
+    ///
+    /// The previous contents are freed and the old handle (and any spans within it) must no longer
+    /// be used.
+    pub fn replace_source(&mut self, handle: FileHandle, source: &[u8]) -> FileHandle {
+        let file_index = self.file_index(handle.id);
+        let File {path, ..} = self.files.remove(file_index);
 
-        let len = source.len();
-        self.create_handle(path, start, len)
+        self.push_file(&path, source.to_vec())
     }
 
-    fn create_handle(&mut self, path: &Path, start: usize, len: usize) -> FileHandle {
-        let handle = FileHandle {start, len};
-        let source = self.source(handle);
-        let line_numbers = LineNumbers::new(source);
-        self.files.push(File {
+    fn push_file(&mut self, path: &Path, source: Vec<u8>) -> FileHandle {
+        let id = FileId(self.next_id);
+        self.next_id += 1;
+
+        let start = self.next_offset;
+        let len = source.len();
+        // Leave a gap of one offset after each file so that an empty span at the very end of a
+        // file (e.g. for EOF) is never confused with the start of the next file
+        self.next_offset = start + len + 1;
+
+        let mut file = File {
+            id,
             path: path.to_path_buf(),
             start_offset: start,
-            line_numbers,
-        });
-        handle
+            source,
+            line_numbers: LineNumbers {offsets: Vec::new()},
+        };
+        file.line_numbers = LineNumbers::new(file.source());
+        self.files.push(file);
+
+        FileHandle {id, start, len}
+    }
+
+    /// Returns the path of every file, in the order the files were added
+    pub fn paths(&self) -> impl Iterator<Item=&Path> + '_ {
+        self.files.iter().map(|file| file.path.as_path())
     }
 
     /// Returns the resolved file and position information for a span
     pub fn pos(&self, span: Span) -> FilePos {
-        let File {path, line_numbers, ..} = self.file(span.start);
+        let file = self.file(span.file);
+        assert!(span.start >= file.start_offset && span.end <= file.start_offset + file.source.len(),
+            "bug: span is not contained within the file it refers to");
+
+        let File {path, line_numbers, source, start_offset: file_start, ..} = file;
         let (start_line, start_offset) = line_numbers.number_offset(span.start);
         // Subtract 1 because end actually represents one past the end of the span
         let (end_line, end_offset) = line_numbers.number_offset(span.end - 1);
 
-        FilePos {path, start_line, start_offset, end_line, end_offset}
+        // The number of characters from the start of the given line up to (not including) index
+        let chars_before = |line: usize, index: usize| {
+            let line_start = line_numbers.line_start(line);
+            count_chars(&source[line_start - file_start..index - file_start])
+        };
+        let start_column = chars_before(start_line, span.start) + 1;
+        // Counting up to the end of the span gives the column of the character containing its
+        // last byte, even if that character is more than one byte long
+        let end_column = chars_before(end_line, span.end);
+
+        FilePos {path, start_line, start_offset, start_column, end_line, end_offset, end_column}
+    }
+
+    /// Returns the text of the given 1-based line number in the given file, without its newline
+    pub fn line(&self, file: FileId, line: usize) -> &[u8] {
+        let file = self.file(file);
+        let range = file.line_numbers.line_range(line);
+        let line = &file.source[range.start - file.start_offset..range.end - file.start_offset];
+
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        line.strip_suffix(b"\r").unwrap_or(line)
     }
 
-    /// Returns the path of the file whose source contains the given index
-    pub fn path(&self, index: usize) -> &Path {
-        &self.file(index).path
+    /// Returns the path of the given file
+    pub fn path(&self, file: FileId) -> &Path {
+        &self.file(file).path
     }
 
     /// Returns the source for the given file handle
     pub fn source(&self, handle: FileHandle) -> FileSource {
-        let FileHandle {start, len} = handle;
-        FileSource {
-            bytes: &self.source[start..start+len],
-            offset: start,
-        }
+        let FileHandle {id, start, len} = handle;
+        let file = self.file(id);
+        assert!(file.start_offset == start && file.source.len() == len,
+            "bug: file handle does not refer to a file in this set of source files");
+
+        FileSource {case_sensitive: self.case_sensitive, ..file.source()}
+    }
+
+    fn file(&self, id: FileId) -> &File {
+        &self.files[self.file_index(id)]
+    }
+
+    fn file_index(&self, id: FileId) -> usize {
+        self.files.binary_search_by_key(&id, |file| file.id)
+            .expect("bug: file is no longer in the set of source files")
+    }
+}
+
+/// Returns the number of UTF-8 characters in the given bytes
+///
+/// Invalid UTF-8 is counted as if each byte that is not a continuation byte starts a character.
+fn count_chars(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|&&byte| byte & 0b1100_0000 != 0b1000_0000).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spans_across_files() {
+        let mut files = SourceFiles::default();
+        let a = files.add_source("a.wa", b"abc\ndef");
+        let b = files.add_source("b.wa", b"ghi");
+
+        let a_source = files.source(a);
+        let b_source = files.source(b);
+        assert_eq!(a_source.slice(a_source.start_index()..a_source.start_index()+3), b"abc");
+        assert_eq!(b_source.slice(b_source.start_index()..b_source.start_index()+3), b"ghi");
+
+        let start = a_source.start_index() + 4;
+        let pos = files.pos(Span {file: a.id(), start, end: start + 3});
+        assert_eq!(pos.path, Path::new("a.wa"));
+        assert_eq!((pos.start_line, pos.start_offset, pos.end_line, pos.end_offset), (2, 1, 2, 3));
+
+        assert_eq!(files.path(b.id()), Path::new("b.wa"));
+    }
+
+    #[test]
+    fn lines() {
+        let mut files = SourceFiles::default();
+        files.add_source("a.wa", b"xyz");
+        let b = files.add_source("b.wa", b"abc\r\n\ndef");
+
+        assert_eq!(files.line(b.id(), 1), b"abc");
+        assert_eq!(files.line(b.id(), 2), b"");
+        assert_eq!(files.line(b.id(), 3), b"def");
+    }
+
+    #[test]
+    #[should_panic(expected = "not contained")]
+    fn span_outside_file() {
+        let mut files = SourceFiles::default();
+        let a = files.add_source("a.wa", b"abc");
+        let b = files.add_source("b.wa", b"def");
+
+        // A span with offsets from `b` but claiming to be in `a`
+        let start = files.source(b).start_index();
+        files.pos(Span {file: a.id(), start, end: start + 1});
+    }
+
+    #[test]
+    fn replace_source() {
+        let mut files = SourceFiles::default();
+        let a = files.add_source("a.wa", b"abc");
+        let b = files.add_source("b.wa", b"def");
+
+        let a = files.replace_source(a, b"xyz\nw");
+        let a_source = files.source(a);
+        assert_eq!(a_source.len(), 5);
+        assert_eq!(a_source.get(a_source.start_index()), Some(b'x'));
+        assert_eq!(files.path(a.id()), Path::new("a.wa"));
+
+        let b_source = files.source(b);
+        assert_eq!(b_source.get(b_source.start_index()), Some(b'd'));
+
+        let start = a_source.start_index();
+        let pos = files.pos(Span {file: a.id(), start, end: start + 5});
+        assert_eq!((pos.start_line, pos.end_line), (1, 2));
+        assert_eq!(files.path(b.id()), Path::new("b.wa"));
     }
 
-    fn file(&self, index: usize) -> &File {
-        let file_index = self.files.binary_search_by_key(&index, |file| file.start_offset)
-            .unwrap_or_else(|index| index - 1);
-        &self.files[file_index]
+    #[test]
+    fn columns() {
+        let mut files = SourceFiles::default();
+        let source = "mov $1, 2\n.bytes 'h\u{e9}llo' # \u{1f43a}\n";
+        let a = files.add_source("a.wa", source.as_bytes());
+
+        let start_index = files.source(a).start_index();
+        let span_of = |text: &str| {
+            let start = start_index + source.find(text).unwrap();
+            Span {file: a.id(), start, end: start + text.len()}
+        };
+
+        let pos = files.pos(span_of("$1"));
+        assert_eq!((pos.start_line, pos.start_column, pos.end_line, pos.end_column), (1, 5, 1, 6));
+        assert_eq!((pos.start_offset, pos.end_offset), (5, 6));
+
+        // Columns count characters while offsets count bytes
+        let pos = files.pos(span_of("'h\u{e9}llo'"));
+        assert_eq!((pos.start_line, pos.start_column, pos.end_column), (2, 8, 14));
+        assert_eq!((pos.start_offset, pos.end_offset), (8, 15));
+
+        let pos = files.pos(span_of("\u{1f43a}"));
+        assert_eq!((pos.start_column, pos.end_column), (18, 18));
+        assert_eq!((pos.start_offset, pos.end_offset), (19, 22));
     }
 }
@@ -1,9 +1,15 @@
+/// Uniquely identifies a file in `SourceFiles`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileId(pub(super) usize);
+
 /// A span of a source file
 ///
 /// The only invariant is that a span `start` and `end` indexes MUST remain within the boundaries
 /// of a single file. That is, you can never span two files at the same time.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Span {
+    /// The file that this span is in
+    pub file: FileId,
     /// The start index of the span (inclusive)
     pub start: usize,
     /// The end index of the span (exclusive)
@@ -11,11 +17,18 @@ pub struct Span {
 }
 
 impl Span {
+    /// Returns the file that this span is in
+    pub fn file(self) -> FileId {
+        self.file
+    }
+
     /// Creates a span from the start of `self` to the end of `other`
     pub fn to(self, other: Self) -> Self {
+        assert!(self.file == other.file, "bug: span cannot cross between files");
         assert!(other.end >= self.start, "bug: span should have at least zero size");
 
         Self {
+            file: self.file,
             start: self.start,
             end: other.end,
         }
@@ -2,6 +2,7 @@ use std::fmt;
 use std::sync::Arc;
 
 use crate::ast;
+use crate::interner::InternedStr;
 use super::span::Span;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -29,9 +30,12 @@ impl fmt::Display for Keyword {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LitKind {
-    /// An integer literal, e.g. `0`, `1`, `-402`, `1_000_000`, `0x1f3`, `0b0100_1000`
+    /// An integer literal, e.g. `0`, `1`, `-402`, `1_000_000`, `0x1f3`, `0o755`, `0b0100_1000`
     Integer,
 
+    /// A floating-point literal, e.g. `0.5`, `-12.25`, `1_000.0`, `6.02e23`, `1.5E-3`
+    Float,
+
     /// A string literal, interpreted as a series of bytes
     ///
     /// The literal may contain escaped characters which will be unescaped during lexing.
@@ -45,6 +49,7 @@ impl fmt::Display for LitKind {
         use LitKind::*;
         match self {
             Integer => write!(f, "an integer"),
+            Float => write!(f, "a floating-point number"),
             Bytes => write!(f, "a byte string literal"),
         }
     }
@@ -62,6 +67,8 @@ pub enum TokenKind {
 
     /// A register, e.g. `$0`, `$1`, `$63`, `$sp`, `$fp`, etc.
     Register,
+    /// The `$here` symbol, which evaluates to the address of the current statement
+    Here,
 
     /// A literal
     Literal(LitKind),
@@ -70,13 +77,23 @@ pub enum TokenKind {
     Colon,
     /// A `,` character
     Comma,
+    /// A `-` character that is not part of an integer literal
+    Minus,
+    /// A `+` character
+    Plus,
+    /// A `*` character
+    Star,
+    /// A `/` character
+    Slash,
+    /// A `%` character
+    Percent,
 
     /// A `(` character
     ParenOpen,
     /// A `)` character
     ParenClose,
 
-    /// The `\n` character
+    /// The `\n` character or the `\` statement separator
     Newline,
 
     /// The end of a file
@@ -94,9 +111,15 @@ impl fmt::Display for TokenKind {
             DotIdent => write!(f, "`.`"),
             Ident => write!(f, "an identifier"),
             Register => write!(f, "a register"),
+            Here => write!(f, "`$here`"),
             Literal(lit) => write!(f, "{}", lit),
             Colon => write!(f, "`:`"),
             Comma => write!(f, "`,`"),
+            Minus => write!(f, "`-`"),
+            Plus => write!(f, "`+`"),
+            Star => write!(f, "`*`"),
+            Slash => write!(f, "`/`"),
+            Percent => write!(f, "`%`"),
             ParenOpen => write!(f, "`(`"),
             ParenClose => write!(f, "`)`"),
             Newline => write!(f, "a newline"),
@@ -110,7 +133,7 @@ impl fmt::Display for TokenKind {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Register {
     /// A named register like `$sp` or `$fp`
-    Named(Arc<str>),
+    Named(InternedStr),
     /// A numbered register like `$0`, `$1`, `$63`
     Numbered(u8),
 }
@@ -118,9 +141,9 @@ pub enum Register {
 impl<'a> From<&'a Register> for ast::RegisterKind {
     fn from(reg: &'a Register) -> Self {
         use Register::*;
-        match reg {
-            Named(name) => ast::RegisterKind::Named(name.clone()),
-            &Numbered(name) => ast::RegisterKind::Numbered(name),
+        match *reg {
+            Named(name) => ast::RegisterKind::Named(name),
+            Numbered(name) => ast::RegisterKind::Numbered(name),
         }
     }
 }
@@ -140,7 +163,7 @@ impl<'a> From<&'a str> for Register {
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenValue {
     /// An interned string representing the identifier, e.g. `label_name`, `.const`
-    Ident(Arc<str>),
+    Ident(InternedStr),
 
     /// The value of a named or numbered register
     Register(Register),
@@ -150,6 +173,9 @@ pub enum TokenValue {
     /// Needs to be 128 bits to fit the range of both i64 and u64
     Integer(i128),
 
+    /// A floating-point literal value
+    Float(f64),
+
     /// The unescaped bytes from a byte string literal
     Bytes(Arc<[u8]>),
 }
@@ -164,8 +190,8 @@ pub struct Token {
 
 impl Token {
     /// Returns the value of this token as an identifier or panics
-    pub fn unwrap_ident(&self) -> &Arc<str> {
-        match &self.value {
+    pub fn unwrap_ident(&self) -> InternedStr {
+        match self.value {
             Some(TokenValue::Ident(ident)) => ident,
             _ => unreachable!("bug: expected an identifier"),
         }
@@ -187,6 +213,14 @@ impl Token {
         }
     }
 
+    /// Returns the value of this token as a floating-point number or panics
+    pub fn unwrap_float(&self) -> f64 {
+        match self.value {
+            Some(TokenValue::Float(value)) => value,
+            _ => unreachable!("bug: expected a floating-point number"),
+        }
+    }
+
     /// Returns the value of this token as a byte string or panics
     pub fn unwrap_bytes(&self) -> &Arc<[u8]> {
         match &self.value {
@@ -0,0 +1,65 @@
+use crate::ast;
+use crate::parser::Span;
+use crate::diagnostics::Diagnostics;
+use crate::validate::check_section_header;
+
+/// Merges several programs into a single program that can be validated and assembled as a whole
+///
+/// The sections of each program are combined in the order the programs are given: the `.code`
+/// section of the result contains the statements in the `.code` section of the first program,
+/// followed by the statements in the `.code` section of the second program, and so on. The
+/// section header of the first program that declares each section is kept.
+///
+/// Since sections are reordered, the order of the sections within each program is checked here.
+/// Statements that occur before the first section of a program are placed at the start of the
+/// result.
+///
+/// A single program is returned unchanged, so that its sections are checked by validation.
+///
+/// All `.include` and conditional assembly directives should already have been expanded.
+pub fn merge_programs(mut progs: Vec<ast::Program>, diag: &Diagnostics) -> ast::Program {
+    if progs.len() == 1 {
+        return progs.pop().expect("bug: checked that there is one program");
+    }
+
+    // Statements that occur before the first section of each program
+    let mut preamble = Vec::new();
+    // The header and statements of each section, indexed by `ast::SectionKind::order`
+    let mut sections: [Option<(ast::Section, Vec<ast::Stmt>)>; 4] = [None, None, None, None];
+
+    for prog in progs {
+        // The sections declared in this program
+        let mut declared: [Option<(ast::SectionKind, Span)>; 4] = [None; 4];
+        let mut current_section = None;
+
+        for stmt in prog.stmts {
+            match stmt {
+                ast::Stmt::Section(section) => {
+                    let order = section.kind.order();
+                    check_section_header(&declared, &section, diag);
+                    declared[order].get_or_insert((section.kind, section.span));
+
+                    current_section = Some(order);
+                    sections[order].get_or_insert_with(|| (section, Vec::new()));
+                },
+
+                stmt => match current_section {
+                    Some(order) => {
+                        let (_, stmts) = sections[order].as_mut()
+                            .expect("bug: current section should have been declared");
+                        stmts.push(stmt);
+                    },
+                    None => preamble.push(stmt),
+                },
+            }
+        }
+    }
+
+    let mut stmts = preamble;
+    for (section, section_stmts) in IntoIterator::into_iter(sections).flatten() {
+        stmts.push(ast::Stmt::Section(section));
+        stmts.extend(section_stmts);
+    }
+
+    ast::Program {stmts}
+}
@@ -0,0 +1,127 @@
+use std::borrow::Borrow;
+use std::hash::{Hash, Hasher};
+use std::collections::HashSet;
+
+use crate::ast;
+use crate::asm;
+use crate::diagnostics::{Diagnostics, Lint};
+use crate::interner::InternedStr;
+
+#[derive(Debug, Clone)]
+struct RegEntry(ast::Reg);
+
+impl PartialEq for RegEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.name.eq(&other.0.name)
+    }
+}
+
+impl Eq for RegEntry {}
+
+impl Hash for RegEntry {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.name.hash(state);
+    }
+}
+
+impl Borrow<InternedStr> for RegEntry {
+    fn borrow(&self) -> &InternedStr {
+        &self.0.name.value
+    }
+}
+
+#[derive(Debug)]
+pub struct RegTable {
+    reg_aliases: HashSet<RegEntry>,
+}
+
+impl RegTable {
+    pub fn new(prog: &ast::Program, diag: &Diagnostics) -> Self {
+        let mut reg_aliases = HashSet::new();
+
+        for stmt in &prog.stmts {
+            let reg_stmt = match stmt {
+                ast::Stmt::Reg(reg_stmt) => reg_stmt,
+                _ => continue,
+            };
+
+            let name = &*reg_stmt.name.value;
+            if name == "sp" || name == "fp" || asm::abi_register(name).is_some() {
+                diag.span_error(reg_stmt.name.span, format!("register alias `{}` conflicts with the built-in register `${}`", name, name)).emit();
+                continue;
+            } else if name == "here" {
+                diag.span_error(reg_stmt.name.span, "register alias `here` conflicts with the built-in `$here` symbol").emit();
+                continue;
+            }
+
+            // Aliases must refer to a valid register and may not refer to other aliases
+            let target = match &reg_stmt.reg.kind {
+                ast::RegisterKind::Named(target) if reg_aliases.contains(target) => {
+                    diag.span_error(reg_stmt.reg.span, format!("register alias `{}` must refer to a register, not another alias", name))
+                        .emit();
+                    continue;
+                },
+
+                _ => asm::Register::validate(reg_stmt.reg.clone(), diag).0,
+            };
+
+            // Store the validated register so that substituted registers are always valid
+            let reg = ast::Register {
+                kind: match target.kind {
+                    asm::RegisterKind::StackPointer => ast::RegisterKind::Named("sp".into()),
+                    asm::RegisterKind::FramePointer => ast::RegisterKind::Named("fp".into()),
+                    asm::RegisterKind::Numbered(num) => ast::RegisterKind::Numbered(num),
+                },
+                ..reg_stmt.reg.clone()
+            };
+
+            if let Some(RegEntry(prev_reg)) = reg_aliases.get(&reg_stmt.name.value) {
+                if prev_reg.reg.kind != reg.kind {
+                    diag.span_lint(Lint::RegisterRedefined, reg_stmt.span, format!("register alias `{}` was redefined", name))
+                        .span_note(prev_reg.span, "the previous declaration of this register alias")
+                        .emit();
+                }
+            }
+
+            // Insert or overwrite the alias to update the span
+            reg_aliases.replace(RegEntry(ast::Reg {reg, ..reg_stmt.clone()}));
+        }
+
+        Self {reg_aliases}
+    }
+
+    /// Replaces all register aliases with the registers that they refer to
+    pub fn subst_instr(&self, instr: ast::Instr) -> ast::Instr {
+        // Fast path for instructions without named registers in them
+        if !instr.args.iter().any(|arg| arg.is_named_register()) {
+            return instr;
+        }
+
+        let ast::Instr {name, args} = instr;
+        ast::Instr {
+            name,
+            args: args.into_iter().map(|arg| match arg {
+                ast::InstrArg::Register(reg) => ast::InstrArg::Register(self.subst_register(reg)),
+                arg => arg,
+            }).collect(),
+        }
+    }
+
+    fn subst_register(&self, reg: ast::Register) -> ast::Register {
+        let alias = match &reg.kind {
+            ast::RegisterKind::Named(name) => self.reg_aliases.get(name),
+            ast::RegisterKind::Numbered(_) => None,
+        };
+
+        match alias {
+            Some(RegEntry(ast::Reg {reg: target, ..})) => ast::Register {
+                kind: target.kind.clone(),
+                // Preserve the offset and span of the replaced register so error messages point to
+                // the right place
+                ..reg
+            },
+
+            None => reg,
+        }
+    }
+}
@@ -1,9 +1,12 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, hash_map::Entry};
 
 use crate::ast;
-use crate::asm;
-use crate::diagnostics::Diagnostics;
+use crate::asm::{self, layout::{self, ImmSize}};
+use crate::parser::{Span, FileId};
+use crate::diagnostics::{Diagnostics, ErrorCode, Lint, LintLevel, closest_match};
+use crate::interner::InternedStr;
 use crate::const_table::ConstTable;
+use crate::reg_table::RegTable;
 
 /// Validates the program to ensure that it is valid assembly
 ///
@@ -11,87 +14,329 @@ use crate::const_table::ConstTable;
 /// in the body of a statement will be a label name. The remaining label names will still need to
 /// be checked later to make sure that they are defined somewhere in the program.
 pub fn validate_program(prog: ast::Program, diag: &Diagnostics) -> asm::Program {
-    let labels = unique_labels(&prog, &diag);
+    let prog = replace_here(prog);
+    // Must happen first so that every lint below can be allowed
+    allow_lints(&prog, diag);
+    let all_labels = unique_labels(&prog, &diag);
+    check_unused_labels(&prog, &all_labels, diag);
+    check_case_collisions(&prog, diag);
     // Error recovery: No checking if the unique labels generated errors because we can still
     // continue processing the program even if errors occurred during that process.
-    let consts = ConstTable::new(&prog, diag, &labels);
+    let consts = ConstTable::new(&prog, diag, &all_labels);
     // Error recovery: No checking if the constant table generated errors because we still want to
     // continue and potentially find more errors if we can during the validation process. This may
     // result in some false negatives, but is still a better user experience overall in many cases.
-
-    let mut code_section: Option<asm::Section> = None;
-    let mut static_section: Option<asm::Section> = None;
-    let mut stmts = None;
+    let regs = RegTable::new(&prog, diag);
+    // Error recovery: No checking if the register table generated errors for the same reasons as
+    // above.
+
+    // The sections of the program, indexed by `ast::SectionKind::order`
+    let mut sections: [Option<(ast::SectionKind, asm::Section)>; 4] = [None, None, None, None];
+    // The section that statements are currently being added to
+    let mut current_section: Option<ast::SectionKind> = None;
     let mut labels = Vec::new();
+    let mut exports: Vec<asm::Ident> = Vec::new();
+    let mut externs: Vec<asm::Ident> = Vec::new();
     for stmt in prog.stmts {
-        let kind = match stmt {
+        let kinds = match stmt {
             ast::Stmt::Label(label) => {
                 labels.push(label);
                 continue;
             },
 
-            ast::Stmt::Section(section) => match section.kind {
-                ast::SectionKind::Code(_) => {
-                    if static_section.is_some() {
-                        diag.span_error(section.span, "the `.code` section must occur before the `.static` section").emit();
-                    }
+            ast::Stmt::Section(section) => {
+                let order = section.kind.order();
 
-                    match &code_section {
-                        Some(prev) => diag.span_error(section.span, "duplicate `.code` section")
-                            .span_note(prev.section_header_span, "previously declared here").emit(),
-                        None => code_section = Some(asm::Section {
-                            section_header_span: section.span,
-                            stmts: Vec::new(),
-                        }),
-                    }
-
-                    stmts = Some(&mut code_section.as_mut().unwrap().stmts);
-                    continue;
-                },
-
-                ast::SectionKind::Static(_) => {
-                    match &static_section {
-                        Some(prev) => diag.span_error(section.span, "duplicate `.static` section")
-                            .span_note(prev.section_header_span, "previously declared here").emit(),
-                        None => static_section = Some(asm::Section {
-                            section_header_span: section.span,
-                            stmts: Vec::new(),
-                        }),
-                    }
+                let declared = sections.each_ref()
+                    .map(|section| section.as_ref().map(|(kind, section)| (*kind, section.section_header_span)));
+                check_section_header(&declared, &section, diag);
+                if sections[order].is_none() {
+                    sections[order] = Some((section.kind, asm::Section {
+                        section_header_span: section.span,
+                        stmts: Vec::new(),
+                    }));
+                }
 
-                    stmts = Some(&mut static_section.as_mut().unwrap().stmts);
-                    continue;
-                },
+                current_section = Some(section.kind);
+                continue;
             },
 
             ast::Stmt::Include(_) => unreachable!("bug: all includes should be resolved by now"),
+            ast::Stmt::Conditional(_) => unreachable!("bug: all conditionals should be resolved by now"),
+
+            ast::Stmt::Export(ast::Export {name, span: _}) => {
+                if !all_labels.contains(&name) {
+                    let mut writer = diag.span_error_code(ErrorCode::UnknownLabel, name.span, format!("cannot export unknown label `{}`", name));
+                    if let Some(label) = closest_match(&name.value, all_labels.iter().map(|label| label.value.as_str())) {
+                        writer = writer.span_help(name.span, format!("did you mean `{}`?", label));
+                    }
+                    writer.emit();
+                } else if !exports.contains(&name) {
+                    exports.push(name);
+                }
+                continue;
+            },
+
+            ast::Stmt::Extern(ast::Extern {name, span: _}) => {
+                if let Some(label) = all_labels.get(&name) {
+                    diag.span_error_code(ErrorCode::DuplicateLabel, name.span, format!("label `{}` is declared with `.extern` but is defined in this program", name))
+                        .span_note(label.span, "defined here")
+                        .emit();
+                } else if !externs.contains(&name) {
+                    externs.push(name);
+                }
+                continue;
+            },
 
             // Already handled above
-            ast::Stmt::Const(_) => continue,
+            ast::Stmt::Lint(_) |
+            ast::Stmt::Const(_) |
+            ast::Stmt::Reg(_) => continue,
 
             ast::Stmt::StaticData(static_data) => {
-                asm::StmtKind::StaticData(validate_static_data(static_data, diag))
+                vec![asm::StmtKind::StaticData(validate_static_data(static_data, diag))]
             },
 
             ast::Stmt::Instr(instr) => {
-                let instr = consts.subst_instr(instr);
-                asm::StmtKind::Instr(asm::Instr::validate(instr, diag))
+                let instr = consts.subst_instr(instr, diag);
+                let instr = regs.subst_instr(instr);
+                validate_instr(instr, diag).into_iter().map(asm::StmtKind::Instr).collect()
             },
         };
 
         // Error recovery: No quitting early if errors were produced above because we want to
         // get through as many statements as possible before exiting.
 
-        match &mut stmts {
-            Some(stmts) => {
-                stmts.push(asm::Stmt {labels, kind});
-                labels = Vec::new();
+        let section_kind = match current_section {
+            Some(section_kind) => section_kind,
+            None => {
+                diag.span_error_code(ErrorCode::StatementOutsideSection, kinds[0].span(), "all assembly statements must occur within a section, e.g. `section .code`").emit();
+                continue;
+            },
+        };
+
+        for kind in &kinds {
+            check_section_stmt(section_kind, kind, diag);
+        }
+
+        let (_, section) = sections[section_kind.order()].as_mut()
+            .expect("bug: current section should have been declared");
+        // Any labels refer to the first statement
+        for kind in kinds {
+            section.stmts.push(asm::Stmt {labels, kind});
+            labels = Vec::new();
+        }
+    }
+
+    // Labels at the end of the program refer to the end of the last section
+    if let (false, Some(section_kind)) = (labels.is_empty(), current_section) {
+        let (_, section) = sections[section_kind.order()].as_mut()
+            .expect("bug: current section should have been declared");
+        let span = labels[labels.len() - 1].span;
+        let nbytes = asm::Size {value: 0, span};
+        let kind = asm::StmtKind::StaticData(asm::StaticData::StaticZero(asm::StaticZero {nbytes, span}));
+        section.stmts.push(asm::Stmt {labels, kind});
+    }
+
+    let [code_section, rodata_section, static_section, bss_section] = sections;
+    asm::Program {
+        code_section: code_section.map(|(_, section)| section),
+        rodata_section: rodata_section.map(|(_, section)| section),
+        static_section: static_section.map(|(_, section)| section),
+        bss_section: bss_section.map(|(_, section)| section),
+        exports,
+        externs,
+    }
+}
+
+/// Allows the lint named by each `.allow` directive from that directive to the next `.endallow` in
+/// the same file, or to the end of that file if there is no `.endallow`
+///
+/// Each `.endallow` ends the most recent `.allow` in its file that has not been ended yet.
+fn allow_lints(prog: &ast::Program, diag: &Diagnostics) {
+    // The lint (if it is valid) and start index of each `.allow` that has not been ended yet
+    let mut open_allows: HashMap<FileId, Vec<(Option<Lint>, usize)>> = HashMap::new();
+    for stmt in &prog.stmts {
+        match stmt {
+            ast::Stmt::Lint(ast::LintDirective::Allow(allow)) => {
+                let lint = match allow.lint.value.parse() {
+                    Ok(lint) => Some(lint),
+                    Err(err) => {
+                        let mut writer = diag.span_error(allow.lint.span, err);
+                        let names = Lint::ALL.iter().map(|lint| lint.name());
+                        if let Some(name) = closest_match(&allow.lint.value, names) {
+                            writer = writer.span_help(allow.lint.span, format!("did you mean `{}`?", name));
+                        }
+                        writer.emit();
+
+                        // Error recovery: keep track of the directive so it can still be ended by
+                        // the next `.endallow`
+                        None
+                    },
+                };
+                open_allows.entry(allow.span.file()).or_default().push((lint, allow.span.start));
+            },
+
+            &ast::Stmt::Lint(ast::LintDirective::EndAllow(span)) => {
+                match open_allows.get_mut(&span.file()).and_then(|allows| allows.pop()) {
+                    Some((lint, start)) => if let Some(lint) = lint {
+                        diag.allow_lint_in(lint, span.file(), start..span.end);
+                    },
+                    None => diag.span_error(span, "`.endallow` without a matching `.allow`").emit(),
+                }
+            },
+
+            _ => {},
+        }
+    }
+
+    for (file, allows) in open_allows {
+        for (lint, start) in allows {
+            if let Some(lint) = lint {
+                diag.allow_lint_in(lint, file, start..usize::MAX);
+            }
+        }
+    }
+}
+
+/// Replaces every use of `$here` with a generated label attached to the statement it is used in
+///
+/// The generated label names start with `$`, so they can never conflict with a label declared in
+/// the program. The labels are marked as generated so they are left out of diagnostics, symbol
+/// tables, and analyses that only apply to labels written in the program.
+fn replace_here(prog: ast::Program) -> ast::Program {
+    let mut next_id = 0;
+    let mut stmts = Vec::with_capacity(prog.stmts.len());
+    for mut stmt in prog.stmts {
+        // Every use of `$here` in a statement refers to the same address
+        let mut here_label: Option<ast::Ident> = None;
+        let mut subst_here = |span: Span| {
+            let label = here_label.get_or_insert_with(|| {
+                next_id += 1;
+                ast::Ident {value: InternedStr::intern(&format!("$here{}", next_id)), span, generated: true}
+            });
+            // Preserve the span of each use of `$here` so error messages point to the right place
+            ast::Ident {span, ..label.clone()}
+        };
+
+        match &mut stmt {
+            ast::Stmt::Instr(instr) => for arg in &mut instr.args {
+                match arg {
+                    &mut ast::InstrArg::Here(span) => *arg = ast::InstrArg::Name(subst_here(span)),
+                    ast::InstrArg::Distance(dist) => replace_here_distance(dist, &mut subst_here),
+                    _ => {},
+                }
+            },
+
+            ast::Stmt::Const(ast::Const {value: ast::ConstValue::Distance(dist), ..}) => {
+                replace_here_distance(dist, &mut subst_here);
             },
-            None => diag.span_error(kind.span(), "all assembly statements must occur within a section, e.g. `section .code`").emit(),
+
+            _ => {},
+        }
+
+        if let Some(label) = here_label {
+            stmts.push(ast::Stmt::Label(label));
+        }
+        stmts.push(stmt);
+    }
+
+    ast::Program {stmts}
+}
+
+fn replace_here_distance(dist: &mut ast::Distance, mut subst_here: impl FnMut(Span) -> ast::Ident) {
+    for sym in &mut [&mut dist.end, &mut dist.start] {
+        if let &mut ast::Symbol::Here(span) = &mut **sym {
+            **sym = ast::Symbol::Label(subst_here(span));
         }
     }
+}
+
+/// Checks that a section is not declared more than once or after a section that must be declared
+/// after it
+///
+/// `declared` contains the kind and header span of each section declared so far, indexed by
+/// `ast::SectionKind::order`.
+pub(crate) fn check_section_header(declared: &[Option<(ast::SectionKind, Span)>; 4], section: &ast::Section, diag: &Diagnostics) {
+    let order = section.kind.order();
+
+    // Find the first section that should have been declared after this one
+    let later_section = declared[order+1..].iter().flatten().next();
+    if let Some((later_kind, _)) = later_section {
+        diag.span_error_code(ErrorCode::SectionOrder, section.span, format!("the `{}` section must occur before the `{}` section", section.kind, later_kind)).emit();
+    }
+
+    if let Some((_, prev_span)) = declared[order] {
+        diag.span_error_code(ErrorCode::DuplicateSection, section.span, format!("duplicate `{}` section", section.kind))
+            .span_note(prev_span, "previously declared here").emit();
+    }
+}
+
+/// Checks that the given statement is allowed in the given section
+fn check_section_stmt(section_kind: ast::SectionKind, kind: &asm::StmtKind, diag: &Diagnostics) {
+    match (section_kind, kind) {
+        (ast::SectionKind::RoData(_), asm::StmtKind::Instr(instr)) => {
+            diag.span_error_code(ErrorCode::StatementNotAllowedInSection, instr.span(), "instructions are not allowed in the `.rodata` section").emit();
+        },
+
+        (ast::SectionKind::Bss(_), asm::StmtKind::StaticData(asm::StaticData::StaticZero(_))) |
+        (ast::SectionKind::Bss(_), asm::StmtKind::StaticData(asm::StaticData::StaticUninit(_))) => {},
+        (ast::SectionKind::Bss(_), kind) => {
+            diag.span_error_code(ErrorCode::StatementNotAllowedInSection, kind.span(), "only `.zero` and `.uninit` are allowed in the `.bss` section").emit();
+        },
+
+        _ => {},
+    }
+}
 
-    asm::Program {code_section, static_section}
+/// Validates an instruction, expanding pseudo-instructions into one or more real instructions
+fn validate_instr(instr: ast::Instr, diag: &Diagnostics) -> Vec<asm::Instr> {
+    match &*instr.name.value {
+        "li" => validate_li(instr, diag),
+        _ => vec![asm::Instr::validate(instr, diag)],
+    }
+}
+
+/// Validates the `li dest, imm64` pseudo-instruction
+///
+/// If the immediate fits in the immediate field of `mov`, this is just a `mov`. Otherwise, the
+/// upper 32 bits of the value are loaded, shifted into place with `mul`, and then combined with
+/// the lower 32 bits using `or`.
+fn validate_li(instr: ast::Instr, diag: &Diagnostics) -> Vec<asm::Instr> {
+    let value = match instr.args.as_slice() {
+        [ast::InstrArg::Register(ast::Register {offset: None, ..}), ast::InstrArg::Immediate(imm)] => imm.value,
+        // Anything else is validated exactly like `mov` (including any errors)
+        _ => return vec![asm::Instr::Mov(asm::Mov::validate(instr, diag))],
+    };
+
+    let mov_bits = layout::S46::size_bits() as u32;
+    if value >= -2i128.pow(mov_bits - 1) && value < 2i128.pow(mov_bits - 1) {
+        return vec![asm::Instr::Mov(asm::Mov::validate(instr, diag))];
+    }
+
+    let ast::Instr {name, args} = instr;
+    let dest = args[0].clone();
+    let imm_span = args[1].span();
+
+    // Literals are guaranteed to be in the range [i64::min(), u64::max()], so this is lossless
+    let value = value as u64;
+    // Sign-extend the upper 32 bits so the value fits in a signed immediate
+    let upper = (value as i64 >> 32) as i128;
+    let lower = (value & 0xffff_ffff) as i128;
+
+    let real_instr = |real_name: &str, value: i128| {
+        let instr = ast::Instr {
+            name: ast::Ident::new(real_name.into(), name.span),
+            args: vec![dest.clone(), ast::InstrArg::Immediate(ast::Integer {value, span: imm_span})],
+        };
+        asm::Instr::validate(instr, diag)
+    };
+
+    vec![
+        real_instr("mov", upper),
+        real_instr("mul", 1 << 32),
+        real_instr("or", lower),
+    ]
 }
 
 /// Attempts to ensure that all label names are unique
@@ -109,7 +354,7 @@ fn unique_labels(prog: &ast::Program, diag: &Diagnostics) -> HashSet<ast::Ident>
 
         match labels.get(label) {
             Some(other_label) => {
-                diag.span_error(label.span, format!("duplicate label name `{}`", label))
+                diag.span_error_code(ErrorCode::DuplicateLabel, label.span, format!("duplicate label name `{}`", label))
                     .span_note(other_label.span, "originally defined here")
                     .emit();
                 continue;
@@ -125,6 +370,88 @@ fn unique_labels(prog: &ast::Program, diag: &Diagnostics) -> HashSet<ast::Ident>
     labels
 }
 
+/// Reports every label or constant whose name only differs in case from one declared before it
+///
+/// Names that only differ in case can only be distinct when identifiers are case-sensitive.
+fn check_case_collisions(prog: &ast::Program, diag: &Diagnostics) {
+    // Avoid the extra pass over the program if the lint is not reported anyway
+    if diag.lint_level(Lint::CaseCollision) == LintLevel::Allow {
+        return;
+    }
+
+    // The first name declared with each lowercase spelling and the kind of item it names
+    let mut names: HashMap<String, (&ast::Ident, &str)> = HashMap::new();
+    for stmt in &prog.stmts {
+        let (name, kind) = match stmt {
+            ast::Stmt::Label(label) => (label, "label"),
+            ast::Stmt::Const(const_stmt) => (&const_stmt.name, "constant"),
+            _ => continue,
+        };
+        // Labels generated for `$here` never collide
+        if name.generated {
+            continue;
+        }
+
+        match names.entry(name.value.to_ascii_lowercase()) {
+            Entry::Occupied(entry) => {
+                let &(other, other_kind) = entry.get();
+                if other.value != name.value {
+                    diag.span_lint(Lint::CaseCollision, name.span, format!("{} `{}` only differs in case from {} `{}`", kind, name, other_kind, other))
+                        .span_note(other.span, format!("`{}` is declared here", other))
+                        .emit();
+                }
+            },
+            Entry::Vacant(entry) => {
+                entry.insert((name, kind));
+            },
+        }
+    }
+}
+
+/// Reports every label that is not used by any instruction, constant, or `.export` directive
+fn check_unused_labels(prog: &ast::Program, labels: &HashSet<ast::Ident>, diag: &Diagnostics) {
+    // Avoid the extra pass over the program if the lint is not reported anyway
+    if diag.lint_level(Lint::UnusedLabel) == LintLevel::Allow {
+        return;
+    }
+
+    let mut used = HashSet::new();
+    let use_dist = |dist: &ast::Distance, used: &mut HashSet<InternedStr>| {
+        for sym in &[&dist.end, &dist.start] {
+            if let ast::Symbol::Label(label) = sym {
+                used.insert(label.value);
+            }
+        }
+    };
+    for stmt in &prog.stmts {
+        match stmt {
+            ast::Stmt::Instr(instr) => for arg in &instr.args {
+                match arg {
+                    ast::InstrArg::Name(name) => {
+                        used.insert(name.value);
+                    },
+                    ast::InstrArg::Distance(dist) => use_dist(dist, &mut used),
+                    _ => {},
+                }
+            },
+            ast::Stmt::Const(ast::Const {value: ast::ConstValue::Distance(dist), ..}) => use_dist(dist, &mut used),
+            ast::Stmt::Export(export) => {
+                used.insert(export.name.value);
+            },
+            _ => {},
+        }
+    }
+
+    // Labels generated for `$here` are always used
+    let mut unused: Vec<_> = labels.iter()
+        .filter(|label| !label.generated && !used.contains(&label.value))
+        .collect();
+    unused.sort_by_key(|label| (label.span.file(), label.span.start));
+    for label in unused {
+        diag.span_lint(Lint::UnusedLabel, label.span, format!("label `{}` is never used", label)).emit();
+    }
+}
+
 /// Validates a static data directive to ensure that it is valid assembly language
 fn validate_static_data(stmt: ast::StaticData, diag: &Diagnostics) -> asm::StaticData {
     match stmt {
@@ -143,6 +470,10 @@ fn validate_static_data(stmt: ast::StaticData, diag: &Diagnostics) -> asm::Stati
         ast::StaticData::StaticByteStr(ast::StaticByteStr {bytes, span}) => {
             asm::StaticData::StaticByteStr(asm::StaticByteStr {bytes, span})
         },
+
+        ast::StaticData::StaticFloat(static_float) => {
+            asm::StaticData::StaticBytes(validate_static_float(static_float, diag))
+        },
     }
 }
 
@@ -153,7 +484,7 @@ fn validate_static_bytes(static_bytes: ast::StaticBytes, diag: &Diagnostics) ->
     match size {
         1 => {
             if value < 0 || value > u8::max_value() as i128 {
-                diag.span_error(span, format!("value `{}` for `.b1` must be in the range `0` to `{}`", value, u8::max_value())).emit();
+                diag.span_error_code(ErrorCode::DataOutOfRange, span, format!("value `{}` for `.b1` must be in the range `0` to `{}`", value, u8::max_value())).emit();
             }
 
             asm::StaticBytes {
@@ -166,7 +497,7 @@ fn validate_static_bytes(static_bytes: ast::StaticBytes, diag: &Diagnostics) ->
 
         2 => {
             if value < 0 || value > u16::max_value() as i128 {
-                diag.span_error(span, format!("value `{}` for `.b2` must be in the range `0` to `{}`", value, u16::max_value())).emit();
+                diag.span_error_code(ErrorCode::DataOutOfRange, span, format!("value `{}` for `.b2` must be in the range `0` to `{}`", value, u16::max_value())).emit();
             }
 
             asm::StaticBytes {
@@ -179,7 +510,7 @@ fn validate_static_bytes(static_bytes: ast::StaticBytes, diag: &Diagnostics) ->
 
         4 => {
             if value < 0 || value > u32::max_value() as i128 {
-                diag.span_error(span, format!("value `{}` for `.b4` must be in the range `0` to `{}`", value, u32::max_value())).emit();
+                diag.span_error_code(ErrorCode::DataOutOfRange, span, format!("value `{}` for `.b4` must be in the range `0` to `{}`", value, u32::max_value())).emit();
             }
 
             asm::StaticBytes {
@@ -192,7 +523,7 @@ fn validate_static_bytes(static_bytes: ast::StaticBytes, diag: &Diagnostics) ->
 
         8 => {
             if value < 0 || value > u64::max_value() as i128 {
-                diag.span_error(span, format!("value `{}` for `.b8` must be in the range `0` to `{}`", value, u64::max_value())).emit();
+                diag.span_error_code(ErrorCode::DataOutOfRange, span, format!("value `{}` for `.b8` must be in the range `0` to `{}`", value, u64::max_value())).emit();
             }
 
             asm::StaticBytes {
@@ -207,6 +538,52 @@ fn validate_static_bytes(static_bytes: ast::StaticBytes, diag: &Diagnostics) ->
     }
 }
 
+fn validate_static_float(static_float: ast::StaticFloat, diag: &Diagnostics) -> asm::StaticBytes {
+    let ast::StaticFloat {size, value, span} = static_float;
+    let ast::Float {value, integer, span: value_span} = value;
+
+    match size {
+        4 => {
+            // Converting directly from the integer avoids rounding twice
+            let value_f32 = integer.map(|integer| integer as f32).unwrap_or(value as f32);
+            if value_f32.is_infinite() {
+                diag.span_error_code(ErrorCode::DataOutOfRange, span, format!("value `{:e}` for `.f4` must be in the range `{:e}` to `{:e}`", value, f32::MIN, f32::MAX)).emit();
+            } else if value != 0.0 && value_f32 == 0.0 {
+                diag.span_lint(Lint::Truncation, value_span, format!("value `{:e}` is too small for `.f4` and will be stored as zero", value)).emit();
+            } else if let Some(integer) = integer {
+                check_float_integer(integer, value_f32 as i128, size, value_span, diag);
+            }
+
+            asm::StaticBytes {
+                // Error recovery: if an error is produced above, we'll just end up with infinity
+                value: asm::StaticBytesValue::B4(value_f32.to_le_bytes(), value_span),
+                span,
+            }
+        },
+
+        8 => {
+            if let Some(integer) = integer {
+                check_float_integer(integer, value as i128, size, value_span, diag);
+            }
+
+            asm::StaticBytes {
+                value: asm::StaticBytesValue::B8(value.to_le_bytes(), value_span),
+                span,
+            }
+        },
+
+        _ => unreachable!("bug: unexpected size of static float: `{}`", size),
+    }
+}
+
+/// Warns if an integer given to `.f4` or `.f8` was rounded when it was converted to a
+/// floating-point number
+fn check_float_integer(integer: i128, stored: i128, size: u8, span: Span, diag: &Diagnostics) {
+    if integer != stored {
+        diag.span_lint(Lint::Truncation, span, format!("integer `{}` cannot be represented exactly by `.f{}` and will be stored as `{}`", integer, size, stored)).emit();
+    }
+}
+
 fn validate_static_zero(static_zero: ast::StaticZero, diag: &Diagnostics) -> asm::StaticZero {
     let ast::StaticZero {nbytes, span} = static_zero;
 
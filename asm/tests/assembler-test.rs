@@ -1,13 +1,18 @@
 use std::fs;
 use std::env;
 use std::ffi::OsStr;
-use std::path::Path;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+use std::time::Instant;
+use std::convert::TryInto;
 
 use rayon::prelude::*;
-use tempfile::{NamedTempFile, TempPath};
+use tempfile::{NamedTempFile, TempDir, TempPath};
+use wolf_asm::executable::Executable;
 
 const EXEC_PATH: &str = env!("CARGO_BIN_EXE_wolf-asm");
+const LINKER_EXEC_PATH: &str = env!("CARGO_BIN_EXE_wolf-ld");
 
 #[test]
 fn ui() {
@@ -89,6 +94,715 @@ fn run_pass() {
     });
 }
 
+#[test]
+fn verify() {
+    let source_path = Path::new("../tests/run-pass/here.wa");
+    let (exec_path, _) = run_assembler(source_path)
+        .unwrap_or_else(|err| panic!("Assembler failed for '{}': {}", source_path.display(), err));
+
+    let verify = |exec_path: &Path| Command::new(EXEC_PATH)
+        .arg(source_path)
+        .arg("--color=never")
+        .arg("--verify")
+        .arg(exec_path)
+        .output()
+        .unwrap_or_else(|err| panic!("Failed to run assembler: {}", err));
+
+    let output = verify(&exec_path);
+    assert!(output.status.success(), "Verifying an unchanged executable should succeed");
+
+    // Change a single byte of the executable
+    let mut bytes = fs::read(&exec_path)
+        .unwrap_or_else(|err| panic!("Failed to read '{}': {}", exec_path.display(), err));
+    bytes[20] ^= 0xff;
+    fs::write(&exec_path, &bytes)
+        .unwrap_or_else(|err| panic!("Failed to write '{}': {}", exec_path.display(), err));
+
+    let output = verify(&exec_path);
+    assert!(!output.status.success(), "Verifying a modified executable should fail");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("first difference at byte offset 20"), "Unexpected error message:\n{}", stderr);
+}
+
+#[test]
+fn include_dirs() {
+    // The include directories are searched before the directory of the including file
+    let dir = TestDir::new(&[
+        ("main.wa", "section .code\nmain:\n  mov $1, 3\n  call print_int\n  ret\n.include 'print.wa'\n"),
+        ("print.wa", "not valid assembly\n"),
+    ]);
+
+    let include_dir = Path::new("../tests/run-pass/lib").canonicalize()
+        .unwrap_or_else(|err| panic!("Failed to find include directory: {}", err));
+    let include_dir = include_dir.to_str().expect("bug: include directory should be valid UTF-8");
+    dir.assemble_ok(&["main.wa", "-I", "does-not-exist", "-I", include_dir]);
+}
+
+#[test]
+fn listing() {
+    let dir = assemble_source("listing.wa", "section .code\nmain:\n  mov $1, value\n  ret\n\nsection .static\nvalue: .b2 0x1234\nmsg:\n  .bytes 'abcdefghij'\n",
+        &["--listing", "listing.lst"]);
+
+    assert_eq!(dir.read_to_string("listing.lst"), "\
+# listing.wa
+00001000                           2  main:
+00001000  0e50400000001010         3    mov $1, value
+00001008  2700000000000000         4    ret
+00001010  3412                     7  value: .b2 0x1234
+00001012                           8  msg:
+00001012  6162636465666768...      9    .bytes 'abcdefghij'
+");
+}
+
+#[test]
+fn map_file() {
+    let dir = assemble_source("map.wa", "section .code\nstart:\nmain:\n  mov $1, $here\n  ret\n\nsection .static\nvalue: .b8 1\n\nsection .bss\nbuf: .zero 8\n",
+        &["--map", "map.map"]);
+
+    // Labels generated for `$here` are not listed
+    assert_eq!(dir.read_to_string("map.map"), "\
+00001000  .code     main
+00001000  .code     start
+00001010  .static   value
+00001018  .bss      buf
+");
+}
+
+#[test]
+fn flat_binary() {
+    let dir = assemble_source("flat.wa", "section .code\n  ret\n\nsection .static\n  .b2 0x1234\n  .uninit 2\n  .bytes 'ab'\n\nsection .bss\n  .zero 3\n",
+        &["--format", "bin"]);
+
+    assert_eq!(dir.read("flat.bin"), [
+        // ret
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x27,
+        // .b2 0x1234
+        0x34, 0x12,
+        // .uninit 2
+        0x00, 0x00,
+        // .bytes 'ab'
+        b'a', b'b',
+        // .zero 3
+        0x00, 0x00, 0x00,
+    ]);
+}
+
+#[test]
+fn hex_formats() {
+    let dir = TestDir::new(&[("prog.wa", "section .code\n  ret\n\nsection .static\n  .b2 0x1234\n")]);
+
+    let formats = [
+        ("ihex", "prog.hex", ":0A1000000000000000000027341279\n:0400000500001000E7\n:00000001FF\n"),
+        ("srec", "prog.srec", "S007000070726F6740\nS10D10000000000000000027341275\nS5030001FB\nS9031000EC\n"),
+    ];
+    for &(format, file_name, expected) in &formats {
+        dir.assemble_ok(&["prog.wa", "--format", format]);
+        assert_eq!(dir.read_to_string(file_name), expected, "unexpected `{}` output", format);
+    }
+}
+
+#[test]
+fn elf_format() {
+    let dir = assemble_source("prog.wa", "section .code\nmain:\n  mov $1, $here\n  ret\n\nsection .static\nvalue:\n  .b2 0x1234\n",
+        &["--format", "elf"]);
+
+    let bytes = dir.read("prog.elf");
+    let read_u64 = |offset: usize| u64::from_le_bytes(bytes[offset..offset+8].try_into().unwrap());
+    let read_u32 = |offset: usize| u32::from_le_bytes(bytes[offset..offset+4].try_into().unwrap());
+    let read_u16 = |offset: usize| u16::from_le_bytes(bytes[offset..offset+2].try_into().unwrap());
+    assert_eq!(&bytes[..6], b"\x7fELF\x02\x01");
+    // Entry point
+    assert_eq!(read_u64(24), 0x1000);
+    // Two segments: code and static
+    assert_eq!(read_u16(56), 2);
+
+    // The first segment ends with the `ret` instruction
+    let code_offset = read_u64(64 + 8) as usize;
+    assert_eq!(read_u64(64 + 16), 0x1000);
+    assert_eq!(bytes[code_offset+8..code_offset+16], [0, 0, 0, 0, 0, 0, 0, 0x27]);
+    // The second segment contains the static data
+    let static_offset = read_u64(64 + 56 + 8) as usize;
+    assert_eq!(read_u64(64 + 56 + 16), 0x1010);
+    assert_eq!(bytes[static_offset..static_offset+2], [0x34, 0x12]);
+
+    // The symbol names are in the string table, without the labels generated for `$here`
+    let contains = |name: &[u8]| bytes.windows(name.len()).any(|window| window == name);
+    assert!(contains(b"\0main\0value\0"));
+    assert!(!contains(b"$here"));
+
+    // The size of `main` extends past the use of `$here` to the end of the code section
+    let section_headers = read_u64(40) as usize;
+    let symtab = (0..read_u16(60) as usize)
+        .map(|i| section_headers + i * 64)
+        .find(|&header| read_u32(header + 4) == 2)
+        .expect("ELF file should have a symbol table");
+    let main_symbol = read_u64(symtab + 24) as usize + 24;
+    assert_eq!(read_u64(main_symbol + 8), 0x1000);
+    assert_eq!(read_u64(main_symbol + 16), 16);
+}
+
+#[test]
+fn emit() {
+    let dir = TestDir::new(&[("emit.wa", "section .code\nmain:\n  ret\n")]);
+    let emit = |stage: &str| String::from_utf8(dir.assemble_ok(&["emit.wa", "--emit", stage]).stdout).unwrap();
+
+    let tokens = emit("tokens");
+    assert!(tokens.starts_with("1:1: Keyword(Section)\n1:9: DotIdent Ident(\".code\")\n"), "unexpected tokens:\n{}", tokens);
+    assert!(emit("ast").starts_with("Program {\n    stmts: ["));
+    assert!(emit("asm").contains("Ret("));
+
+    // Nothing else is written
+    assert!(!dir.path("emit").exists());
+}
+
+#[test]
+fn stdin_input() {
+    let source = "section .code\n  load1 $1, value\n  ret\n\nsection .static\nvalue:\n  .b1 7\n";
+    let dir = assemble_source("prog.wa", source, &[]);
+
+    let assemble_stdin = |source: &str, output_path: &Path| {
+        let mut child = Command::new(EXEC_PATH)
+            .arg("-")
+            .arg("--color=never")
+            .arg("-o")
+            .arg(output_path)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap_or_else(|err| panic!("Failed to run assembler: {}", err));
+        child.stdin.take().unwrap().write_all(source.as_bytes())
+            .unwrap_or_else(|err| panic!("Failed to write to assembler stdin: {}", err));
+        child.wait_with_output()
+            .unwrap_or_else(|err| panic!("Failed to run assembler: {}", err))
+    };
+
+    // Source from stdin assembles to the same executable as the same source in a file
+    let stdin_exec_path = dir.path("stdin");
+    let output = assemble_stdin(source, &stdin_exec_path);
+    assert!(output.status.success(), "Assembler failed:\n{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(dir.read("stdin"), dir.read("prog"));
+
+    // Errors refer to the source as `<stdin>`
+    let output = assemble_stdin("section .code\n  rett\n", &stdin_exec_path);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("[<stdin>:2:3-2:6]"));
+}
+
+#[test]
+fn multiple_inputs() {
+    let dir = TestDir::new(&[
+        ("common.wa", ".const SEVEN 7\n"),
+        ("a.wa", ".include_once 'common.wa'\n\nsection .code\nmain:\n  call helper\n  mov $1, SEVEN\n  ret\n\nsection .static\nvalue:\n  .b1 1\n"),
+        // The const from the shared file is only included once
+        ("b.wa", ".include_once 'common.wa'\n\nsection .code\nhelper:\n  mov $2, SEVEN\n  ret\n\nsection .static\nother:\n  .b1 2\n"),
+        // The sections of both files combined into one
+        ("combined.wa", ".const SEVEN 7\n\nsection .code\nmain:\n  call helper\n  mov $1, SEVEN\n  ret\nhelper:\n  mov $2, SEVEN\n  ret\n\nsection .static\nvalue:\n  .b1 1\nother:\n  .b1 2\n"),
+    ]);
+
+    // The output is named after the first file
+    dir.assemble_ok(&["a.wa", "b.wa"]);
+    dir.assemble_ok(&["combined.wa"]);
+    assert_eq!(dir.read("a"), dir.read("combined"));
+}
+
+#[test]
+fn check_only() {
+    let dir = TestDir::new(&[]);
+    let check = |source: &str| {
+        dir.write("check.wa", source);
+        dir.assemble(&["check.wa", "--check"])
+    };
+
+    let output = check("section .code\n  jmp end\nend:\n  ret\n");
+    assert!(output.status.success(), "Assembler failed:\n{}", String::from_utf8_lossy(&output.stderr));
+
+    // Unknown labels are only found during layout
+    let output = check("section .code\n  jmp missing\n");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown label `missing`"));
+
+    // Nothing is written either way
+    let entries: Vec<_> = fs::read_dir(dir.path(".")).unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(entries, [OsStr::new("check.wa")]);
+}
+
+#[test]
+fn define_consts() {
+    let dir = TestDir::new(&[
+        ("defines.wa", ".const SIZE 8\n\nsection .code\n  mov $1, SIZE\n  mov $2, OFFSET\n  ret\n"),
+        ("consts.wa", "section .code\n  mov $1, 0x40\n  mov $2, -3\n  ret\n"),
+    ]);
+
+    // The values from the command line override the values in the program
+    let output = dir.assemble_ok(&["defines.wa", "-D", "SIZE=0x40", "-D", "OFFSET=-3"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("[<command line>:1:1-1:16] warning: constant named"));
+    dir.assemble_ok(&["consts.wa"]);
+    assert_eq!(dir.read("defines"), dir.read("consts"));
+
+    // Errors in the values refer to the command line
+    let output = dir.assemble(&["defines.wa", "-D", "SIZE=1", "-D", "OFFSET=99999999999999999999999"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("[<command line>:2:15-2:37] error: integer literal out of 64-bit range"));
+}
+
+#[test]
+fn lint_levels() {
+    let dir = TestDir::new(&[("lints.wa", ".const A 1\n.const A 2\n\nsection .code\n  jmp used\nused:\n  ret\nunused:\n")]);
+    let check = |args: &[&str]| {
+        let output = dir.assemble(&[&["lints.wa", "--check"], args].concat());
+        (output.status.success(), String::from_utf8(output.stderr).unwrap())
+    };
+
+    let constant_warning = "warning: constant named `a` was redefined [constant-redefined]";
+    let label_warning = "warning: label `unused` is never used [unused-label]";
+
+    // Unused labels are not reported by default
+    let (success, stderr) = check(&[]);
+    assert!(success);
+    assert!(stderr.contains(constant_warning) && !stderr.contains(label_warning), "{}", stderr);
+
+    let (success, stderr) = check(&["-W", "unused-label", "-A", "constant-redefined"]);
+    assert!(success);
+    assert!(!stderr.contains(constant_warning) && stderr.contains(label_warning), "{}", stderr);
+
+    let (success, stderr) = check(&["-A", "warnings"]);
+    assert!(success);
+    assert_eq!(stderr, "");
+
+    // Only the warnings that would be reported become errors
+    let (success, stderr) = check(&["--deny", "warnings"]);
+    assert!(!success);
+    assert!(stderr.contains("error: constant named `a` was redefined [constant-redefined]"), "{}", stderr);
+    assert!(!stderr.contains("unused-label"), "{}", stderr);
+
+    let (success, stderr) = check(&["--deny", "unused-label"]);
+    assert!(!success);
+    assert!(stderr.contains("error: label `unused` is never used [unused-label]"), "{}", stderr);
+}
+
+#[test]
+fn unreachable_code() {
+    let dir = TestDir::new(&[("unreachable.wa", "\
+section .code
+main:
+  jmp end
+  add $1, 1
+  add $1, 2
+end:
+  ret
+  .bytes \"data\"
+  mov $1, $here
+loop:
+  jmp loop
+")]);
+    let check = |args: &[&str]| {
+        let output = dir.assemble(&[&["unreachable.wa", "--check"], args].concat());
+        (output.status.success(), String::from_utf8(output.stderr).unwrap())
+    };
+
+    // Unreachable code is not reported by default
+    let (success, stderr) = check(&[]);
+    assert!(success);
+    assert_eq!(stderr, "");
+
+    // Only the first instruction after each `jmp` or `ret` is reported, and neither data nor the
+    // label generated for `$here` make the instructions after it reachable
+    let (success, stderr) = check(&["-W", "unreachable-code"]);
+    assert!(success);
+    assert_eq!(stderr.matches("warning: unreachable instruction [unreachable-code]").count(), 2, "{}", stderr);
+    assert!(stderr.contains(":4:3-4:11] warning: unreachable instruction"), "{}", stderr);
+    assert!(stderr.contains(":9:3-9:15] warning: unreachable instruction"), "{}", stderr);
+
+    let (success, stderr) = check(&["--deny", "unreachable-code"]);
+    assert!(!success);
+    assert!(stderr.contains("error: unreachable instruction [unreachable-code]"), "{}", stderr);
+}
+
+#[test]
+fn case_sensitive() {
+    let dir = TestDir::new(&[("case.wa", "\
+.const Size 8
+.reg Tmp $5
+section .code
+main:
+  MOV $tmp, Size
+  jmp Main
+Main:
+  .ifdef fast
+  nop
+  .endif
+  ret
+")]);
+    let check = |args: &[&str]| {
+        let output = dir.assemble(&[&["case.wa", "--check"], args].concat());
+        (output.status.success(), String::from_utf8(output.stderr).unwrap())
+    };
+
+    // Labels that only differ in case are the same label by default
+    let (success, stderr) = check(&[]);
+    assert!(!success);
+    assert!(stderr.contains("error: duplicate label name `main`"), "{}", stderr);
+
+    // Instruction names, register aliases, and defines are still case-insensitive
+    let (success, stderr) = check(&["--case-sensitive", "-D", "FAST"]);
+    assert!(success, "{}", stderr);
+    assert_eq!(stderr, "");
+
+    let (success, stderr) = check(&["--case-sensitive", "-W", "case-collision"]);
+    assert!(success, "{}", stderr);
+    assert!(stderr.contains(":7:1-7:4] warning: label `Main` only differs in case from label `main` [case-collision]"), "{}", stderr);
+}
+
+#[test]
+fn stack_usage() {
+    let dir = assemble_source("stack.wa", "\
+section .code
+main:
+  push $fp
+  mov $fp, $sp
+  sub $sp, 16
+  call leaf
+  call fact
+  mov $sp, $fp
+  pop $fp
+  ret
+
+leaf:
+  push $0
+  pop $0
+  ret
+
+fact:
+  cmp $1, 1
+  jle fact_done
+  push $1
+  sub $1, 1
+  call fact
+  pop $1
+fact_done:
+  ret
+", &["--stack-usage", "stack.txt"]);
+
+    assert_eq!(dir.read_to_string("stack.txt"), "\
+main  unbounded (recursive)
+leaf  8 bytes
+fact  unbounded (recursive)
+recursion: fact -> fact
+");
+}
+
+#[test]
+fn deterministic_output() {
+    // Many labels, so any output that depends on the order of a hash table would differ
+    let mut source = String::from("section .code\nmain:\n");
+    for i in 0..200 {
+        source.push_str(&format!("  load8 $1, value{}\n  call func{}\n", i, i));
+    }
+    source.push_str("  ret\n");
+    for i in 0..200 {
+        source.push_str(&format!("func{}:\n  ret\n", i));
+    }
+    source.push_str("section .static\n");
+    for i in 0..200 {
+        source.push_str(&format!("value{}: .b8 {}\n", i, i));
+    }
+    let dir = TestDir::new(&[("labels.wa", &source)]);
+
+    let assemble = |name: &str, args: &[&str]| {
+        dir.assemble_ok(&[&["labels.wa", "-o", name], args].concat());
+        dir.read(name)
+    };
+
+    for args in &[&["-g", "--relocatable"][..], &["-c"], &["--format", "elf"]] {
+        let first = assemble("first", args);
+        for _ in 0..3 {
+            assert!(first == assemble("again", args), "output for {:?} was not identical", args);
+        }
+    }
+
+    // The header of an executable is 8 magic bytes followed by the FNV-1a hash of the rest of the
+    // file
+    let exec = assemble("exec", &["-g"]);
+    assert_eq!(&exec[..8], b"\0wolfexe");
+    let (hash, content) = exec[8..].split_at(8);
+    let expected_hash = content.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    assert_eq!(u64::from_le_bytes(hash.try_into().unwrap()), expected_hash);
+
+    let decoded = Executable::read_from(&exec[..]).expect("failed to read executable");
+    assert_eq!(decoded.content_hash().unwrap(), expected_hash);
+
+    // A corrupted executable is detected by its hash
+    let mut corrupted = exec.clone();
+    corrupted[16 + content.len() / 2] ^= 0xff;
+    assert!(Executable::read_from(&corrupted[..]).is_err());
+}
+
+#[test]
+fn dependency_file() {
+    let dir = TestDir::new(&[
+        ("main.wa", ".include \"util.wa\"\n.include_once \"util.wa\"\nsection .code\nmain:\n  ret\n"),
+        ("include/util.wa", ".include_once \"consts.wa\"\n"),
+        ("include/consts.wa", ".const SIZE 8\n"),
+    ]);
+
+    // Relative paths so that the rule matches the paths a Makefile would use
+    dir.assemble_ok(&["main.wa", "-I", "include", "-o", "main", "-M", "main.d"]);
+
+    assert_eq!(dir.read_to_string("main.d"), "\
+main: \\
+  main.wa \\
+  include/util.wa \\
+  include/consts.wa
+
+include/util.wa:
+
+include/consts.wa:
+");
+}
+
+#[test]
+fn json_diagnostics() {
+    let dir = TestDir::new(&[("errors.wa", "\
+section .code
+main:
+  mov $1
+.const A 1
+.const A 2
+")]);
+
+    let output = dir.assemble(&["errors.wa", "--check", "--message-format", "json"]);
+    assert!(!output.status.success());
+
+    // Every line is a separate JSON object
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let diagnostics: Vec<serde_json::Value> = stderr.lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|err| panic!("Invalid JSON '{}': {}", line, err)))
+        .collect();
+    assert_eq!(diagnostics.len(), 3, "{}", stderr);
+
+    let warning = &diagnostics[0];
+    assert_eq!(warning["level"], "warning");
+    assert_eq!(warning["code"], "constant-redefined");
+    assert_eq!(warning["file"], "errors.wa");
+    assert_eq!(warning["span"], serde_json::json!({"start_line": 5, "start_column": 1, "end_line": 5, "end_column": 10}));
+    assert_eq!(warning["notes"][0]["level"], "note");
+    assert_eq!(warning["notes"][0]["message"], "the previous declaration of this constant");
+    assert_eq!(warning["notes"][0]["span"]["start_line"], 4);
+
+    let error = &diagnostics[1];
+    assert_eq!(error["level"], "error");
+    assert_eq!(error["message"], "expected a source argument for `mov` instruction (takes 2 arguments) [E0002]");
+    assert_eq!(error["code"], "E0002");
+    assert_eq!(error["span"]["start_line"], 3);
+    assert_eq!(error["notes"], serde_json::json!([]));
+
+    // Diagnostics without a span have no file
+    assert_eq!(diagnostics[2]["message"], "aborting due to 1 previous error; 1 warning emitted");
+    assert_eq!(diagnostics[2]["code"], serde_json::Value::Null);
+    assert_eq!(diagnostics[2]["file"], serde_json::Value::Null);
+}
+
+#[test]
+fn warnings_summary() {
+    let dir = TestDir::new(&[("warnings.wa", "\
+.const A 1
+.const A 2
+.const B 1
+.const B 2
+section .code
+  ret
+")]);
+
+    let assemble = |quiet: bool| {
+        let quiet: &[&str] = if quiet { &["--quiet"] } else { &[] };
+        let output = dir.assemble_ok(&[&["warnings.wa", "--check"], quiet].concat());
+        String::from_utf8(output.stderr).unwrap()
+    };
+
+    let stderr = assemble(false);
+    assert_eq!(stderr.matches("was redefined").count(), 2, "{}", stderr);
+    assert!(stderr.ends_with("warning: 2 warnings emitted\n\n"), "{}", stderr);
+
+    // Only errors are reported with `--quiet`
+    assert_eq!(assemble(true), "");
+}
+
+#[test]
+fn error_limit() {
+    let dir = TestDir::new(&[("errors.wa", &format!("section .code\n{}", "  foo\n".repeat(30)))]);
+
+    let assemble = |limit: Option<&str>| {
+        let mut args = vec!["errors.wa", "--check"];
+        if let Some(limit) = limit {
+            args.extend(["--error-limit", limit]);
+        }
+        let output = dir.assemble(&args);
+        assert!(!output.status.success());
+        String::from_utf8(output.stderr).unwrap()
+    };
+
+    // Only the first 20 errors are reported by default, but every error is still counted
+    let stderr = assemble(None);
+    assert_eq!(stderr.matches("unknown instruction").count(), 20, "{}", stderr);
+    assert!(stderr.contains("error: too many errors, stopped reporting errors after the first 20\n"), "{}", stderr);
+    assert!(stderr.ends_with("error: aborting due to 30 previous errors\n\n"), "{}", stderr);
+
+    let stderr = assemble(Some("5"));
+    assert_eq!(stderr.matches("unknown instruction").count(), 5, "{}", stderr);
+
+    let stderr = assemble(Some("0"));
+    assert_eq!(stderr.matches("unknown instruction").count(), 30, "{}", stderr);
+    assert!(!stderr.contains("too many errors"), "{}", stderr);
+}
+
+#[test]
+fn explain() {
+    let output = Command::new(EXEC_PATH)
+        .args(["--explain", "E0003"])
+        .output()
+        .unwrap_or_else(|err| panic!("Failed to run assembler: {}", err));
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("A label was used that is not defined"), "{}", stdout);
+
+    let output = Command::new(EXEC_PATH)
+        .args(["--explain", "E9999"])
+        .output()
+        .unwrap_or_else(|err| panic!("Failed to run assembler: {}", err));
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("unknown error code `E9999`"), "{}", stderr);
+}
+
+/// Assembles a generated program with 100,000 labels and constants and reports how long it took
+///
+/// Run with `cargo test --release --test assembler-test -- --ignored --nocapture large_program`
+#[test]
+#[ignore]
+fn large_program() {
+    const LABELS: usize = 100_000;
+
+    let mut source = String::new();
+    for i in 0..LABELS {
+        source += &format!(".const c{} {}\n", i, i % 1000);
+    }
+    source += "section .code\n  jmp end\n";
+    for i in 0..LABELS {
+        source += &format!("label{}:\n  mov $8, label{}\n  add $8, c{}\n", i, i * 7919 % LABELS, i);
+    }
+    source += "end:\n  ret\n";
+    let dir = TestDir::new(&[("large.wa", &source)]);
+
+    let start = Instant::now();
+    dir.assemble_ok(&["large.wa"]);
+    let elapsed = start.elapsed();
+
+    println!("assembled {} labels in {:.3?}", LABELS, elapsed);
+}
+
+#[test]
+fn link_errors() {
+    let dir = TestDir::new(&[
+        ("a.wa", ".export shared\n.extern missing\nsection .code\nmain:\n  call missing\nshared:\n  ret\n"),
+        ("b.wa", ".export shared\nsection .code\nshared:\n  ret\n"),
+        ("c.wa", "section .code\nmain:\n  call missing\n"),
+    ]);
+    dir.assemble_ok(&["-c", "a.wa"]);
+    dir.assemble_ok(&["-c", "b.wa"]);
+
+    let output = Command::new(LINKER_EXEC_PATH)
+        .args(["a.o", "b.o", "--color=never", "-o", "a"])
+        .current_dir(dir.path("."))
+        .output()
+        .unwrap_or_else(|err| panic!("Failed to run linker: {}", err));
+    assert!(!output.status.success(), "Linker should have failed");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("undefined label `missing`"), "Unexpected error message:\n{}", stderr);
+    assert!(stderr.contains("label `shared` is exported by both"), "Unexpected error message:\n{}", stderr);
+
+    // Labels from other object files must be declared with `.extern`
+    let output = dir.assemble(&["-c", "c.wa"]);
+    assert!(!output.status.success(), "Assembler should have failed");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("must be declared with `.extern missing`"), "Unexpected error message:\n{}", stderr);
+}
+
+/// A temporary directory of source files written by a test, which the assembler is run in
+struct TestDir {
+    dir: TempDir,
+}
+
+impl TestDir {
+    /// Writes each of the given files (name and contents) to a new temporary directory
+    fn new(files: &[(&str, &str)]) -> Self {
+        let dir = tempfile::tempdir()
+            .unwrap_or_else(|err| panic!("Failed to create temporary directory: {}", err));
+        let test_dir = TestDir {dir};
+        for &(name, contents) in files {
+            test_dir.write(name, contents);
+        }
+
+        test_dir
+    }
+
+    /// Returns the path to the given file in the directory
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    /// Writes a file to the directory, creating any directories in its name
+    fn write(&self, name: &str, contents: &str) {
+        let path = self.path(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .unwrap_or_else(|err| panic!("Failed to create '{}': {}", parent.display(), err));
+        }
+        fs::write(&path, contents)
+            .unwrap_or_else(|err| panic!("Failed to write '{}': {}", path.display(), err));
+    }
+
+    fn read(&self, name: &str) -> Vec<u8> {
+        let path = self.path(name);
+        fs::read(&path).unwrap_or_else(|err| panic!("Failed to read '{}': {}", path.display(), err))
+    }
+
+    fn read_to_string(&self, name: &str) -> String {
+        let path = self.path(name);
+        fs::read_to_string(&path).unwrap_or_else(|err| panic!("Failed to read '{}': {}", path.display(), err))
+    }
+
+    /// Runs the assembler in the directory with the given arguments
+    fn assemble(&self, args: &[&str]) -> Output {
+        Command::new(EXEC_PATH)
+            .args(args)
+            .arg("--color=never")
+            .current_dir(self.dir.path())
+            .output()
+            .unwrap_or_else(|err| panic!("Failed to run assembler: {}", err))
+    }
+
+    /// Runs the assembler in the directory with the given arguments, panicking if it fails
+    fn assemble_ok(&self, args: &[&str]) -> Output {
+        let output = self.assemble(args);
+        assert!(output.status.success(), "Assembler failed for {:?}:\n{}", args, String::from_utf8_lossy(&output.stderr));
+        output
+    }
+}
+
+/// Writes the given source to a file called `name` in a new temporary directory and assembles it
+/// there with the given extra arguments, panicking if the assembler fails
+fn assemble_source(name: &str, source: &str, args: &[&str]) -> TestDir {
+    let dir = TestDir::new(&[(name, source)]);
+    dir.assemble_ok(&[&[name], args].concat());
+    dir
+}
+
 /// Runs the assembler on a single file, returning (path to the generated
 /// executable, stdout contents) if the assembler succeeded. Returns the
 /// assembler error message if the assembler failed.
@@ -100,7 +814,12 @@ fn run_assembler(source_path: &Path) -> Result<(TempPath, String), String> {
 
     let output = Command::new(EXEC_PATH)
         .arg(source_path)
+        // Enables the `.ifdef TEST` blocks in self-testing programs
+        .arg("-D")
+        .arg("TEST=1")
         .arg("--color=never")
+        // The UI tests check every error, no matter how many there are
+        .arg("--error-limit=0")
         .arg("-o")
         .arg(executable.path())
         .output()
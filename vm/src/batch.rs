@@ -0,0 +1,230 @@
+//! Batch mode (`wolf-vm --batch`), which runs a list of executables and reports the results of
+//! each one as JSON
+//!
+//! This is intended for graders and for differential testing against reference outputs.
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use wolf_asm::{executable::Executable, debug_info::DebugInfo};
+
+use crate::{
+    io::Stdio,
+    device::{Sleep, SLEEP_ADDR},
+    perf::PerfCounters,
+    engine::{Engine, Executor},
+    machine::{Machine, ProgramStatus, instr_context},
+};
+
+/// The number of steps between each check of whether a program has run out of time
+const TIMEOUT_CHECK_INTERVAL: u64 = 1024;
+
+/// The list of programs to run, read from a JSON file
+///
+/// All paths are relative to the directory containing the manifest.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// The default maximum number of instructions that each program may execute
+    #[serde(default)]
+    pub max_steps: Option<u64>,
+    /// The default maximum amount of time (in milliseconds) that each program may run for
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    pub programs: Vec<ManifestEntry>,
+}
+
+/// A single program to run in batch mode
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestEntry {
+    /// The name of the program in the report [default: the executable path]
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The executable file generated by the wolf-asm assembler
+    pub executable: PathBuf,
+    /// A file containing the input for the program [default: no input]
+    #[serde(default)]
+    pub stdin: Option<PathBuf>,
+    /// A file containing the output that the program is expected to produce
+    #[serde(default)]
+    pub expected_stdout: Option<PathBuf>,
+    /// Overrides the default maximum number of instructions from the manifest
+    #[serde(default)]
+    pub max_steps: Option<u64>,
+    /// Overrides the default timeout from the manifest
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+/// The results of running every program in a manifest, in the same order as the manifest
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BatchReport {
+    pub programs: Vec<ProgramReport>,
+}
+
+/// The result of running a single program
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProgramReport {
+    pub name: String,
+    pub status: ExitStatus,
+    /// The error that stopped the program, if any
+    pub error: Option<String>,
+    /// The SHA-256 digest of everything the program wrote to stdout, as a lowercase hex string
+    pub stdout_sha256: String,
+    /// The number of bytes the program wrote to stdout
+    pub stdout_len: usize,
+    /// Whether stdout matched the expected output, or `None` if no output was expected
+    pub stdout_matches: Option<bool>,
+    /// The performance counters at the time the program stopped
+    pub stats: PerfCounters,
+    /// The wall-clock time taken to run the program in milliseconds
+    pub elapsed_ms: u128,
+}
+
+/// How a program in batch mode stopped running
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitStatus {
+    /// The program returned from its entry point
+    Quit,
+    /// An error occurred while running the program
+    Error,
+    /// The program executed the maximum number of instructions without quitting
+    StepLimit,
+    /// The program ran for the maximum amount of time without quitting
+    Timeout,
+    /// The program could not be loaded (or its input or expected output could not be read)
+    LoadError,
+}
+
+/// Reads the manifest at the given path and runs every program in it with the given engine, each
+/// on a machine with `memory_size` bytes of memory
+pub fn run_batch(manifest_path: &Path, memory_size: usize, null_guard: bool, engine: Engine) -> anyhow::Result<BatchReport> {
+    let manifest_file = File::open(manifest_path)
+        .with_context(|| format!("Failed to read manifest: `{}`", manifest_path.display()))?;
+    let manifest: Manifest = serde_json::from_reader(manifest_file)
+        .with_context(|| format!("Invalid manifest: `{}`", manifest_path.display()))?;
+
+    let base_dir = manifest_path.parent().unwrap_or_else(|| Path::new(""));
+    let programs = manifest.programs.iter()
+        .map(|entry| run_entry(&manifest, entry, base_dir, memory_size, null_guard, engine))
+        .collect();
+
+    Ok(BatchReport {programs})
+}
+
+fn run_entry(manifest: &Manifest, entry: &ManifestEntry, base_dir: &Path, memory_size: usize, null_guard: bool, engine: Engine) -> ProgramReport {
+    let name = entry.name.clone().unwrap_or_else(|| entry.executable.display().to_string());
+    let max_steps = entry.max_steps.or(manifest.max_steps);
+    let timeout = entry.timeout_ms.or(manifest.timeout_ms).map(Duration::from_millis);
+
+    let start = Instant::now();
+    let mut report = ProgramReport {
+        name,
+        status: ExitStatus::LoadError,
+        error: None,
+        stdout_sha256: String::new(),
+        stdout_len: 0,
+        stdout_matches: None,
+        stats: PerfCounters::default(),
+        elapsed_ms: 0,
+    };
+
+    let LoadedEntry {mut vm, mut executor, debug_info, expected_stdout} = match load_entry(entry, base_dir, memory_size, null_guard, engine) {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            report.error = Some(format!("{:#}", err));
+            report.stdout_sha256 = sha256_hex(&[]);
+            return report;
+        },
+    };
+
+    let mut steps = 0;
+    let mut prev_pc = None;
+    report.status = loop {
+        if max_steps.map(|max_steps| steps >= max_steps).unwrap_or(false) {
+            break ExitStatus::StepLimit;
+        }
+        if steps % TIMEOUT_CHECK_INTERVAL == 0 && timeout.map(|timeout| start.elapsed() >= timeout).unwrap_or(false) {
+            break ExitStatus::Timeout;
+        }
+
+        let pc = vm.program_counter;
+        match executor.step(&mut vm) {
+            Ok(ProgramStatus::Continue) => {},
+            Ok(ProgramStatus::Quit) => break ExitStatus::Quit,
+            Err(err) => {
+                report.error = Some(format!("{}: {}", instr_context(debug_info.as_ref(), pc, prev_pc), err));
+                break ExitStatus::Error;
+            },
+        }
+        prev_pc = Some(pc);
+        steps += 1;
+    };
+
+    let stdout = vm.io.captured_output().expect("bug: batch mode output should be captured");
+    report.stdout_sha256 = sha256_hex(stdout);
+    report.stdout_len = stdout.len();
+    report.stdout_matches = expected_stdout.map(|expected| expected == stdout);
+    report.stats = vm.counters.clone();
+    report.elapsed_ms = start.elapsed().as_millis();
+
+    report
+}
+
+/// A program from the manifest, ready to run
+struct LoadedEntry {
+    vm: Machine,
+    executor: Executor,
+    debug_info: Option<DebugInfo>,
+    /// The output that the program is expected to produce, if any
+    expected_stdout: Option<Vec<u8>>,
+}
+
+/// Loads the executable for the given entry with its input
+fn load_entry(entry: &ManifestEntry, base_dir: &Path, memory_size: usize, null_guard: bool, engine: Engine) -> anyhow::Result<LoadedEntry> {
+    let executable_path = base_dir.join(&entry.executable);
+    let executable_file = File::open(&executable_path)
+        .with_context(|| format!("Failed to read executable: `{}`", executable_path.display()))?;
+    let exec = Executable::read_from(executable_file)
+        .with_context(|| format!("Failed to deserialize executable: `{}`", executable_path.display()))?;
+    exec.validate(memory_size as u64)
+        .with_context(|| format!("Invalid executable: `{}`", executable_path.display()))?;
+
+    let input = match &entry.stdin {
+        Some(stdin_path) => {
+            let stdin_path = base_dir.join(stdin_path);
+            fs::read(&stdin_path)
+                .with_context(|| format!("Failed to read stdin file: `{}`", stdin_path.display()))?
+        },
+        None => Vec::new(),
+    };
+
+    let expected_stdout = match &entry.expected_stdout {
+        Some(expected_path) => {
+            let expected_path = base_dir.join(expected_path);
+            let expected = fs::read(&expected_path)
+                .with_context(|| format!("Failed to read expected stdout file: `{}`", expected_path.display()))?;
+            Some(expected)
+        },
+        None => None,
+    };
+
+    let mut vm = Machine::load(&exec, memory_size, null_guard, Stdio::captured(input), &[], &[])
+        .context("Failed to load executable into memory")?;
+    // Programs are only checked for their output, so sleeping would just use up their time limit
+    vm.devices.register(SLEEP_ADDR..SLEEP_ADDR + 1, Sleep::new(true));
+    let executor = Executor::new(engine, &vm, &exec);
+
+    Ok(LoadedEntry {vm, executor, debug_info: exec.debug_info, expected_stdout})
+}
+
+/// Returns the SHA-256 digest of the given bytes as a lowercase hex string
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -4,67 +4,393 @@
 
 #![deny(unused_must_use)]
 
+use std::io::{self, Read, Write};
+use std::convert::TryFrom;
 use std::path::PathBuf;
-use std::fs::File;
+use std::ops::Range;
+use std::fs;
+use std::process;
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use anyhow::Context;
 use structopt::StructOpt;
-use wolf_asm::executable::Executable;
+use thiserror::Error;
+use wolf_asm::{
+    cli::{parse_addr, parse_size, parse_addr_range},
+    executable::{Executable, LegacyExecutable, SectionId, ValidationError, DEFAULT_BASE_ADDR},
+    demangle::{Demangler, DemangleRule},
+    elf,
+};
 use wolf_vm::{
-    memory::Memory,
-    write_memory::WriteMemory,
-    registers::Registers,
-    flags::Flags,
     io::Stdio,
-    machine::{Machine, ProgramStatus},
+    device::{Sleep, SLEEP_ADDR},
+    file_io::{FileIo, FILE_IO_ADDRS},
+    batch::run_batch,
+    engine::{Engine, Executor},
+    self_check::SelfCheck,
+    stats::ExecStats,
+    machine::{Machine, ProgramStatus, instr_context},
 };
-
-const MACHINE_MEMORY: usize = 4 * 1024; // 4 kb
-
-/// The address where program execution should start
-const START_ADDR: u64 = 0;
+#[cfg(feature = "net")]
+use wolf_vm::net::{NetIo, NET_IO_ADDRS};
+#[cfg(feature = "framebuffer")]
+use wolf_vm::framebuffer::{Framebuffer, FRAMEBUFFER_DEVICE_ADDRS};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "wolf-vm", about)]
 struct VMOptions {
     /// The executable file generated by the wolf-asm assembler
-    #[structopt(name = "input", parse(from_os_str))]
-    executable_path: PathBuf,
+    #[structopt(name = "input", parse(from_os_str), required_unless = "manifest")]
+    executable_path: Option<PathBuf>,
+
+    /// Run every executable listed in the JSON file <manifest> (each with its
+    /// own input and limits) and print a JSON report of the results
+    #[structopt(long = "batch", name = "manifest", parse(from_os_str),
+        conflicts_with_all = &["input", "legacy-executable", "addr"])]
+    batch_manifest: Option<PathBuf>,
+
+    /// Start executing at the instruction with the label <label> instead of
+    /// at the start of the program (the executable must be assembled with
+    /// `wolf-asm -g`). Like in the assembler, the label is not case-sensitive
+    /// unless the program was assembled with `--case-sensitive`.
+    #[structopt(long = "entry", name = "label", conflicts_with = "manifest")]
+    entry: Option<String>,
+
+    /// Show the labels that match <rule> demangled in errors and backtraces.
+    /// A rule is written as `PATTERN=REPLACEMENT`, where each `*` in the
+    /// pattern matches any text and `$1` to `$9` in the replacement are the
+    /// text matched by each `*` (e.g. `_W*_*=$1::$2`). The first rule that
+    /// matches a label is used.
+    #[structopt(long = "demangle", name = "rule", number_of_values = 1, conflicts_with = "manifest")]
+    demangle: Vec<DemangleRule>,
+
+    /// Do not reserve the start of memory, so that loading from or storing to
+    /// a null address is not an error
+    #[structopt(long)]
+    no_null_guard: bool,
+
+    /// Stop with an error if the program loads or stores 2, 4, or 8 bytes at
+    /// an address that is not a multiple of the number of bytes
+    #[structopt(long, conflicts_with = "manifest")]
+    strict_align: bool,
+
+    /// Stop with an error if the program loads from memory that has never
+    /// been written to (e.g. memory declared with `.uninit`)
+    #[structopt(long, conflicts_with = "manifest")]
+    check_uninit: bool,
+
+    /// Stop with an error if `ret` does not pop the return address pushed by
+    /// the matching `call` (e.g. because a value pushed by the function was
+    /// never popped or the return address was overwritten)
+    #[structopt(long, conflicts_with = "manifest")]
+    check_returns: bool,
+
+    /// Load a flat binary image (e.g. from `wolf-asm --format bin`) at the
+    /// address given by `--load-addr` (default: 0x1000) and start executing
+    /// its first byte. The whole image can be executed and written to since
+    /// its sections are not known
+    #[structopt(long, conflicts_with = "legacy-executable")]
+    raw: bool,
+
+    /// Load an executable generated by an older assembler that did not store
+    /// the address to load the executable at (these are loaded at address 0)
+    #[structopt(long)]
+    legacy_executable: bool,
+
+    /// Load the executable at <addr> instead of the address it was assembled
+    /// for (the executable must be assembled with `--relocatable`, unless it
+    /// is loaded with `--raw`)
+    #[structopt(long = "load-addr", name = "addr", parse(try_from_str = parse_addr))]
+    load_addr: Option<u64>,
+
+    /// The amount of memory available to the program, in bytes or with a
+    /// `K`, `M`, or `G` suffix (e.g. `16M`). The stack starts at the end of
+    /// memory
+    #[structopt(long = "memory-size", name = "size", parse(try_from_str = parse_size), default_value = "8K")]
+    memory_size: u64,
+
+    /// Reserve <guard-size> bytes after the end of the program so that the
+    /// stack growing into them is an error instead of overwriting static
+    /// data (use `--stack-guard 0` to disable)
+    #[structopt(long = "stack-guard", name = "guard-size", parse(try_from_str = parse_size), default_value = "256")]
+    stack_guard: u64,
+
+    /// Allow the program to store to its own code section (for programs that
+    /// intentionally modify their own code)
+    #[structopt(long, conflicts_with = "manifest")]
+    writable_code: bool,
+
+    /// Stop with an error if the program executes more than <steps>
+    /// instructions without quitting (in `--batch` mode, set `max_steps` in
+    /// the manifest instead)
+    #[structopt(long = "max-steps", name = "steps", conflicts_with = "manifest")]
+    max_steps: Option<u64>,
+
+    /// Print the value of every register, the flags, and the program counter
+    /// to stderr when the program quits or stops with an error
+    #[structopt(long, conflicts_with = "manifest")]
+    dump_registers: bool,
+
+    /// Write the contents of memory to the file <dump-file> when the program
+    /// quits or stops with an error
+    #[structopt(long = "dump-memory", name = "dump-file", parse(from_os_str), conflicts_with = "manifest")]
+    dump_memory: Option<PathBuf>,
+
+    /// Only write the addresses in <range> (e.g. `0x1000..0x1100`) with
+    /// `--dump-memory`, instead of all of memory
+    #[structopt(long = "dump-range", name = "range", parse(try_from_str = parse_addr_range), requires = "dump-file")]
+    dump_range: Option<Range<u64>>,
+
+    /// Print the number of instructions executed (in total and of each kind),
+    /// memory reads and writes, and the peak stack depth to stderr when the
+    /// program quits or stops with an error
+    #[structopt(long, conflicts_with = "manifest")]
+    stats: bool,
+
+    /// The engine used to execute instructions
+    #[structopt(long, parse(try_from_str), default_value = "interp",
+        possible_values = Engine::VARIANTS, case_insensitive = true)]
+    engine: Engine,
+
+    /// Run a copy of the program with the other engine in lockstep and stop
+    /// with an error if the two ever disagree (all input is read before the
+    /// program starts)
+    #[structopt(long, conflicts_with = "manifest")]
+    self_check: bool,
+
+    /// Let the program open, read, write, seek, and close the files in
+    /// <dir> (and its subdirectories) through the file device. Paths outside
+    /// of <dir> cannot be opened
+    #[structopt(long = "file-dir", name = "dir", parse(from_os_str), conflicts_with_all = &["manifest", "self-check"])]
+    file_dir: Option<PathBuf>,
+
+    /// Attach a 320x200 framebuffer and write each frame that the program
+    /// presents to <image-file> as a PPM image, replacing the previous frame.
+    /// Frames are not shown in a window. Requires the `framebuffer` feature.
+    #[structopt(long = "framebuffer", name = "image-file", parse(from_os_str), conflicts_with = "manifest")]
+    framebuffer: Option<PathBuf>,
+
+    /// Let the program connect to, listen on, read from, and write to TCP
+    /// sockets through the network device (requires the `net` feature)
+    #[structopt(long, conflicts_with_all = &["manifest", "self-check"])]
+    net: bool,
+
+    /// Do not pause when the program stores to the sleep address, e.g. to
+    /// run animations quickly in tests
+    #[structopt(long, conflicts_with = "manifest")]
+    no_sleep: bool,
+
+    /// Pass the environment variable <var> to the program if it is set, or
+    /// use `NAME=value` to pass a variable with the given value (may be given
+    /// more than once)
+    #[structopt(long = "env", name = "var", number_of_values = 1, conflicts_with = "manifest")]
+    env: Vec<String>,
+
+    /// The command-line arguments passed to the program (given after `--`)
+    #[structopt(name = "args", last = true, conflicts_with = "manifest")]
+    args: Vec<String>,
 }
 
 fn main() -> anyhow::Result<()> {
-    let VMOptions {executable_path} = VMOptions::from_args();
+    let VMOptions {executable_path, batch_manifest, entry, demangle, no_null_guard, strict_align, check_uninit, check_returns, raw, legacy_executable, load_addr, memory_size, stack_guard, writable_code, max_steps, dump_registers, dump_memory, dump_range, stats, engine, self_check, file_dir, framebuffer, net, no_sleep, env, args} = VMOptions::from_args();
+    let null_guard = !no_null_guard;
+    let memory_size = usize::try_from(memory_size)
+        .with_context(|| format!("Memory size is too large: `{}`", memory_size))?;
 
-    let executable_file = File::open(&executable_path)
+    if let Some(manifest_path) = batch_manifest {
+        let report = run_batch(&manifest_path, memory_size, null_guard, engine)?;
+        serde_json::to_writer_pretty(io::stdout(), &report)
+            .context("Failed to write batch report")?;
+        println!();
+        return Ok(());
+    }
+    let executable_path = executable_path.expect("bug: structopt should require an input path");
+
+    let bytes = fs::read(&executable_path)
         .with_context(|| format!("Failed to read executable: `{}`", executable_path.display()))?;
-    let exec: Executable = bincode::deserialize_from(executable_file)
-        .with_context(|| format!("Failed to deserialize executable: `{}`", executable_path.display()))?;
+    // The address to start executing at, if it is not the start of the code section
+    let mut entry_point = None;
+    let mut exec: Executable = if raw {
+        Executable::from_flat_binary(load_addr.unwrap_or(DEFAULT_BASE_ADDR), bytes)
+    } else if legacy_executable {
+        bincode::deserialize_from::<_, LegacyExecutable>(&bytes[..]).map(Into::into)
+            .with_context(|| format!("Failed to deserialize executable: `{}`", executable_path.display()))?
+    } else if elf::is_elf(&bytes) {
+        let (exec, entry) = elf::read_elf(&bytes)
+            .with_context(|| format!("Failed to read ELF file: `{}`", executable_path.display()))?;
+        entry_point = Some(entry);
+        exec
+    } else {
+        Executable::read_from(&bytes[..])
+            .with_context(|| format!("Failed to deserialize executable: `{}` (executables generated by older versions of the assembler require `--legacy-executable`)", executable_path.display()))?
+    };
+
+    if let Some(load_addr) = load_addr {
+        if load_addr != exec.base_addr {
+            exec.relocate(load_addr)
+                .with_context(|| format!("Failed to load executable at `0x{:x}`", load_addr))?;
+        }
+    }
+
+    if let Some(debug_info) = &mut exec.debug_info {
+        debug_info.set_demangler(Demangler::new(demangle));
+    }
+
+    exec.validate(memory_size as u64).map_err(|err| {
+        let hint = match err {
+            ValidationError::DoesNotFit {..} => " (use `--memory-size` to give the program more memory)",
+            _ => "",
+        };
+        anyhow::Error::new(err).context(format!("Failed to load executable into memory{}", hint))
+    })?;
 
-    let mut memory = Memory::new(MACHINE_MEMORY);
-    // Write the executable at the starting address
-    exec.write_into(&mut memory, START_ADDR)
+    let env = env_vars(&env)?;
+    let io = if self_check {
+        // Both machines must receive the same input, so all of it is read up front
+        let mut input = Vec::new();
+        io::stdin().read_to_end(&mut input)
+            .context("Failed to read input")?;
+        Stdio::captured(input)
+    } else {
+        Stdio::default()
+    };
+
+    // Write the executable at the address it was assembled for (or relocated to)
+    let mut vm = Machine::load(&exec, memory_size, null_guard, io, &args, &env)
         .context("Failed to load executable into memory")?;
+    vm.set_stack_guard_size(stack_guard);
+    vm.set_code_writable(writable_code || raw);
+    vm.strict_align = strict_align;
+    if check_uninit {
+        vm.check_uninit_reads(&exec);
+    }
+    if check_returns {
+        vm.check_returns();
+    }
+    if stats {
+        vm.stats = Some(ExecStats::new(memory_size as u64));
+    }
+    if let Some(file_dir) = file_dir {
+        let file_io = FileIo::new(&file_dir)
+            .with_context(|| format!("Failed to open file directory: `{}`", file_dir.display()))?;
+        vm.devices.register(FILE_IO_ADDRS, file_io);
+    }
+    if let Some(image_path) = framebuffer {
+        attach_framebuffer(&mut vm, image_path)?;
+    }
+    if net {
+        attach_net(&mut vm)?;
+    }
+    if no_sleep {
+        vm.devices.register(SLEEP_ADDR..SLEEP_ADDR + 1, Sleep::new(true));
+    }
+    if let Some(entry) = entry {
+        vm.program_counter = entry_addr(&exec, &entry)?;
+    } else if let Some(entry_point) = entry_point {
+        vm.program_counter = entry_point;
+    }
 
-    // Start with the stack pointer pointing just past the end of the stack
-    let registers = Registers::new(MACHINE_MEMORY);
-    let flags = Flags::default();
-    let io = Stdio::default();
-
-    let mut vm = Machine {
-        program_counter: START_ADDR,
-        memory,
-        registers,
-        flags,
-        io,
+    handle_interrupts();
+    let result = if self_check {
+        let mut check = SelfCheck::new(engine, &vm, &exec);
+        run_self_check(&mut vm, &mut check, &exec, max_steps)
+    } else {
+        let mut executor = Executor::new(engine, &vm, &exec);
+        run(&mut vm, &mut executor, &exec, max_steps)
     };
-    vm.push_quit_addr()
-        .expect("bug: should always be able to push quit address");
 
+    let interrupted = matches!(&result, Err(err) if err.is::<Interrupted>());
+    if interrupted {
+        write_interrupted(&vm, &exec)
+            .context("Failed to write machine state")?;
+    } else if dump_registers {
+        write_state(&vm)?;
+    }
+    if let Some(dump_path) = dump_memory {
+        let range = dump_range.unwrap_or(0..memory_size as u64);
+        let bytes = vm.memory.slice(range.clone())
+            .with_context(|| format!("Failed to dump memory in the range `0x{:x}..0x{:x}`", range.start, range.end))?;
+        fs::write(&dump_path, bytes)
+            .with_context(|| format!("Failed to write memory dump: `{}`", dump_path.display()))?;
+    }
+    if let Some(stats) = &vm.stats {
+        let mut stderr = io::stderr();
+        writeln!(stderr, "Execution statistics:")
+            .and_then(|()| stats.write_summary(&vm.counters, &mut stderr))
+            .context("Failed to write execution statistics")?;
+    }
+
+    if interrupted {
+        // The exit code used by shells for processes stopped by Ctrl-C
+        process::exit(130);
+    }
+    result?;
+    // The exit code of the program becomes the exit code of the VM
+    if vm.exit_code != 0 {
+        process::exit(vm.exit_code.into());
+    }
+
+    Ok(())
+}
+
+/// Runs the program until it quits
+fn run(vm: &mut Machine, executor: &mut Executor, exec: &Executable, max_steps: Option<u64>) -> anyhow::Result<()> {
+    let mut steps = 0;
+    let mut prev_pc = None;
+    loop {
+        check_interrupted()?;
+        check_step_limit(steps, max_steps)?;
+        steps += 1;
+
+        let pc = vm.program_counter;
+        let status = executor.step(vm)
+            .with_context(|| instr_context(exec.debug_info.as_ref(), pc, prev_pc))?;
+        prev_pc = Some(pc);
+
+        match status {
+            ProgramStatus::Continue => {},
+            ProgramStatus::Quit => break,
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the program with both engines in lockstep until it quits, writing the output and error
+/// output of the program as it runs
+fn run_self_check(vm: &mut Machine, check: &mut SelfCheck, exec: &Executable, max_steps: Option<u64>) -> anyhow::Result<()> {
+    let mut stdout = io::stdout();
+    let mut stderr = io::stderr();
+    let mut written = 0;
+    let mut error_written = 0;
+    let mut steps = 0;
+    let mut prev_pc = None;
     loop {
+        check_interrupted()?;
+        check_step_limit(steps, max_steps)?;
+        steps += 1;
+
         let pc = vm.program_counter;
-        let status = vm.step()
-            .with_context(|| format!("Failed to execute instruction at `0x{:x}`", pc))?;
+        let status = check.step(vm);
+
+        let output = vm.io.captured_output().expect("bug: self-check output should be captured");
+        if output.len() > written {
+            stdout.write_all(&output[written..])
+                .and_then(|()| stdout.flush())
+                .context("Failed to write output")?;
+            written = output.len();
+        }
+        let error_output = vm.io.captured_error_output().expect("bug: self-check output should be captured");
+        if error_output.len() > error_written {
+            stderr.write_all(&error_output[error_written..])
+                .context("Failed to write error output")?;
+            error_written = error_output.len();
+        }
 
+        let status = status
+            .with_context(|| instr_context(exec.debug_info.as_ref(), pc, prev_pc))?;
+        prev_pc = Some(pc);
         match status {
             ProgramStatus::Continue => {},
             ProgramStatus::Quit => break,
@@ -73,3 +399,142 @@ fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Registers the network device with the given machine
+#[cfg(feature = "net")]
+fn attach_net(vm: &mut Machine) -> anyhow::Result<()> {
+    vm.devices.register(NET_IO_ADDRS, NetIo::default());
+    Ok(())
+}
+
+#[cfg(not(feature = "net"))]
+fn attach_net(_vm: &mut Machine) -> anyhow::Result<()> {
+    anyhow::bail!("`--net` requires wolf-vm to be built with the `net` feature (e.g. `cargo run -p wolf-vm --features net`)")
+}
+
+/// Registers a framebuffer that writes each frame to the given image file with the given machine
+#[cfg(feature = "framebuffer")]
+fn attach_framebuffer(vm: &mut Machine, image_path: PathBuf) -> anyhow::Result<()> {
+    vm.devices.register(FRAMEBUFFER_DEVICE_ADDRS, Framebuffer::new(image_path));
+    Ok(())
+}
+
+#[cfg(not(feature = "framebuffer"))]
+fn attach_framebuffer(_vm: &mut Machine, _image_path: PathBuf) -> anyhow::Result<()> {
+    anyhow::bail!("`--framebuffer` requires wolf-vm to be built with the `framebuffer` feature (e.g. `cargo run -p wolf-vm --features framebuffer`)")
+}
+
+/// Returns each of the given environment variables in the form `NAME=value`, looking up the value
+/// of any variable given without one
+///
+/// Variables that are not set are skipped.
+fn env_vars(vars: &[String]) -> anyhow::Result<Vec<String>> {
+    let mut resolved = Vec::new();
+    for var in vars {
+        if var.contains('=') {
+            resolved.push(var.clone());
+            continue;
+        }
+
+        match env::var(var) {
+            Ok(value) => resolved.push(format!("{}={}", var, value)),
+            Err(env::VarError::NotPresent) => {},
+            Err(err) => return Err(err).with_context(|| format!("Failed to read environment variable `{}`", var)),
+        }
+    }
+
+    Ok(resolved)
+}
+
+/// Returns the address of the label that the program should start executing at
+fn entry_addr(exec: &Executable, label: &str) -> anyhow::Result<u64> {
+    let debug_info = exec.debug_info.as_ref()
+        .with_context(|| format!("Cannot start at `{}`: the executable has no labels (assemble it with `wolf-asm -g`)", label))?;
+    // Labels are lowercased by the assembler unless it was run with `--case-sensitive`, so the
+    // name is only lowercased if it doesn't match a label exactly
+    let symbol = debug_info.symbol(label)
+        .or_else(|| debug_info.symbol(&label.to_ascii_lowercase()))
+        .with_context(|| format!("Cannot start at `{}`: no label with that name was found", label))?;
+    if symbol.section != SectionId::Code {
+        anyhow::bail!("Cannot start at `{}`: the label is in the `{}` section, not the `.code` section", label, symbol.section);
+    }
+
+    Ok(symbol.addr)
+}
+
+/// Set when Ctrl-C is pressed so the program can be stopped before its next instruction
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// The maximum number of return addresses written when the program is interrupted
+const INTERRUPTED_BACKTRACE_FRAMES: usize = 16;
+
+#[derive(Debug, Error)]
+#[error("Interrupted")]
+struct Interrupted;
+
+/// Stops the program before its next instruction when Ctrl-C is pressed instead of killing the VM
+///
+/// Pressing Ctrl-C a second time exits immediately (e.g. if the program is waiting for input).
+#[cfg(unix)]
+fn handle_interrupts() {
+    extern "C" fn on_interrupt(_signal: libc::c_int) {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            // Safety: `_exit` is safe to call from a signal handler
+            unsafe { libc::_exit(130) };
+        }
+    }
+
+    let handler: extern "C" fn(libc::c_int) = on_interrupt;
+    // Safety: the handler only performs operations that are safe in a signal handler
+    unsafe { libc::signal(libc::SIGINT, handler as libc::sighandler_t) };
+}
+
+#[cfg(not(unix))]
+fn handle_interrupts() {}
+
+/// Returns an error if Ctrl-C was pressed
+fn check_interrupted() -> anyhow::Result<()> {
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        Err(Interrupted.into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Writes where the program was interrupted, a backtrace, and the state of the machine to stderr
+fn write_interrupted(vm: &Machine, exec: &Executable) -> io::Result<()> {
+    let describe = |addr| match exec.debug_info.as_ref().and_then(|debug_info| debug_info.describe(addr)) {
+        Some(location) => format!("`0x{:x}` ({})", addr, location),
+        None => format!("`0x{:x}`", addr),
+    };
+
+    let mut stderr = io::stderr();
+    writeln!(stderr, "Interrupted before executing the instruction at {}", describe(vm.program_counter))?;
+    writeln!(stderr, "Backtrace:")?;
+    writeln!(stderr, "  0: {}", describe(vm.program_counter))?;
+    for (i, return_addr) in vm.backtrace(INTERRUPTED_BACKTRACE_FRAMES).into_iter().enumerate() {
+        // Show the `call` instruction rather than the instruction after it
+        writeln!(stderr, "  {}: {}", i + 1, describe(return_addr.wrapping_sub(8)))?;
+    }
+    writeln!(stderr, "Machine state:")?;
+    vm.write_state(&mut stderr)
+}
+
+/// Returns an error if `steps` instructions have already been executed and that is the maximum
+/// number of instructions allowed
+fn check_step_limit(steps: u64, max_steps: Option<u64>) -> anyhow::Result<()> {
+    match max_steps {
+        Some(max_steps) if steps >= max_steps => {
+            anyhow::bail!("Instruction limit exceeded: the program executed {} instructions without quitting", max_steps)
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Writes the final state of the machine to stderr
+fn write_state(vm: &Machine) -> anyhow::Result<()> {
+    let mut stderr = io::stderr();
+    writeln!(stderr, "Machine state:")
+        .and_then(|()| vm.write_state(&mut stderr))
+        .context("Failed to write machine state")
+}
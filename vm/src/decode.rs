@@ -20,7 +20,7 @@ use wolf_asm::asm::{
 use thiserror::Error;
 
 use crate::machine::Machine;
-use crate::execute::{Execute, ExecuteError};
+use crate::execute::{Execute, ExecuteFn, ExecuteError};
 use crate::operands::{Source, Destination, Location};
 
 #[derive(Debug, Error, Clone)]
@@ -61,11 +61,29 @@ macro_rules! instr {
                 }
             }
 
+            /// Returns the kind of this instruction
+            pub fn kind(&self) -> InstrKind {
+                match self {
+                    $($instr_enum::$instr_variant(_) => InstrKind::$instr_variant),*
+                }
+            }
+
             /// Returns the size in bytes that this will have in the generated executable
             pub fn size_bytes(&self) -> u64 {
                 // All instructions are currently 8 bytes
                 8
             }
+
+            /// Converts this instruction into a closure that executes it
+            ///
+            /// The closure calls the `Execute` impl of the instruction directly, so the kind of
+            /// instruction is only matched on once.
+            pub fn into_closure(self) -> ExecuteFn {
+                use $instr_enum::*;
+                match self {
+                    $($instr_variant(instr) => Box::new(move |vm| instr.clone().execute(vm))),*
+                }
+            }
         }
 
         impl Execute for $instr_enum {
@@ -154,13 +172,13 @@ instr! {
         Jbe(struct Jbe {loc: Location}),
         Jo(struct Jo {loc: Location}),
         Jno(struct Jno {loc: Location}),
-        Jz(struct Jz {loc: Location}),
-        Jnz(struct Jnz {loc: Location}),
         Js(struct Js {loc: Location}),
         Jns(struct Jns {loc: Location}),
 
         Call(struct Call {loc: Location}),
         Ret(struct Ret {}),
+
+        Hcall(struct Hcall {source: Source}),
     }
 }
 
@@ -0,0 +1,402 @@
+use std::fmt;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::ops::Range;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::machine::Machine;
+use crate::memory::Protection;
+use crate::execute::ExecuteError;
+use crate::timer::{Timer, TIMER_COUNT_ADDR, TIMER_FLAG_ADDR};
+
+/// The address used for stdout
+pub const STDOUT_ADDR: u64 = 0xffff_000c;
+/// The address used for stdin
+pub const STDIN_ADDR: u64 = 0xffff_0004;
+/// The address used for stderr
+pub const STDERR_ADDR: u64 = 0xffff_001c;
+/// Loading from this address returns whether input is available and whether EOF has been reached
+/// without waiting for input (see `StdinStatus`)
+pub const STDIN_STATUS_ADDR: u64 = 0xffff_0074;
+/// Storing a value at this address writes its lowest byte to stdout as-is
+pub const STDOUT_BYTE_ADDR: u64 = 0xffff_0024;
+/// The address of the buffer that the next line of stdin is read into (see `LineReader`)
+pub const LINE_BUF_ADDR: u64 = 0xffff_002c;
+/// The length of the buffer that the next line of stdin is read into (see `LineReader`)
+pub const LINE_LEN_ADDR: u64 = 0xffff_0034;
+/// Loading from this address reads the next line of stdin into the buffer and returns the number
+/// of bytes read (see `LineReader`)
+pub const READ_LINE_ADDR: u64 = 0xffff_003c;
+/// Storing a value at this address prints it as a signed decimal number followed by a newline
+pub const DEBUG_INT_ADDR: u64 = 0xffff_0044;
+/// Storing a value at this address prints it as an unsigned decimal number followed by a newline
+pub const DEBUG_UINT_ADDR: u64 = 0xffff_004c;
+/// Storing a value at this address prints it as a hexadecimal number followed by a newline
+pub const DEBUG_HEX_ADDR: u64 = 0xffff_0054;
+/// Storing a value at this address prints it as a signed decimal number followed by a space
+pub const DEBUG_INT_SPACE_ADDR: u64 = 0xffff_005c;
+/// Storing a value at this address prints it as an unsigned decimal number followed by a space
+pub const DEBUG_UINT_SPACE_ADDR: u64 = 0xffff_0064;
+/// Storing a value at this address prints it as a hexadecimal number followed by a space
+pub const DEBUG_HEX_SPACE_ADDR: u64 = 0xffff_006c;
+/// Storing a value at this address quits the program with that value as its exit code
+pub const EXIT_ADDR: u64 = 0xffff_0014;
+/// Loading from this address returns the wall-clock time in nanoseconds since the Unix epoch
+pub const CLOCK_REALTIME_ADDR: u64 = 0xffff_0300;
+/// Loading from this address returns the number of nanoseconds since the machine was created,
+/// which never decreases even if the wall-clock time is changed
+pub const CLOCK_MONOTONIC_ADDR: u64 = 0xffff_0308;
+/// Storing N at this address pauses the program for N milliseconds (see `Sleep`)
+pub const SLEEP_ADDR: u64 = 0xffff_0500;
+/// The byte used to indicate EOF
+pub const EOF_BYTE: u8 = b'\0';
+
+/// A peripheral that programs access by loading from and storing to memory-mapped addresses
+///
+/// A device handles every load and store that starts at an address in the range it was
+/// registered for (see `Devices::register`), no matter how many bytes are accessed. Those
+/// accesses never reach memory and are never checked for alignment.
+pub trait Device {
+    /// Returns the value loaded from the given address by a load of `size` bytes
+    ///
+    /// Only the lowest `size` bytes of the value are kept by the load. Devices that cannot be
+    /// read from can leave this as the default, which returns an error.
+    fn read(&mut self, vm: &mut Machine, addr: u64, size: u64) -> Result<u64, ExecuteError> {
+        let _ = (vm, size);
+        Err(ExecuteError::UnsupportedDeviceAccess {addr, access: "read from"})
+    }
+
+    /// Handles a store of the given bytes (in little-endian order) to the given address
+    ///
+    /// Devices that cannot be written to can leave this as the default, which returns an error.
+    fn write(&mut self, vm: &mut Machine, addr: u64, bytes: &[u8]) -> Result<(), ExecuteError> {
+        let _ = (vm, bytes);
+        Err(ExecuteError::UnsupportedDeviceAccess {addr, access: "written to"})
+    }
+}
+
+/// A device shared between a machine and any copies of it
+pub type DeviceRef = Rc<RefCell<dyn Device>>;
+
+/// The devices attached to a machine, keyed by the range of addresses that each one handles
+///
+/// The default devices are `Stdin`, `StdinStatus`, `LineReader`, `Stdout`, `StdoutByte`, `Stderr`,
+/// `Exit`, `DebugPrint`, `Clock`, `Timer`, and `Sleep`.
+#[derive(Clone)]
+pub struct Devices {
+    devices: Vec<(Range<u64>, DeviceRef)>,
+}
+
+impl Default for Devices {
+    fn default() -> Self {
+        let mut devices = Self {devices: Vec::new()};
+        devices.register(STDIN_ADDR..STDIN_ADDR + 1, Stdin);
+        devices.register(STDIN_STATUS_ADDR..STDIN_STATUS_ADDR + 1, StdinStatus);
+        devices.register(LINE_BUF_ADDR..READ_LINE_ADDR + 1, LineReader::default());
+        devices.register(STDOUT_ADDR..STDOUT_ADDR + 1, Stdout);
+        devices.register(STDOUT_BYTE_ADDR..STDOUT_BYTE_ADDR + 1, StdoutByte);
+        devices.register(STDERR_ADDR..STDERR_ADDR + 1, Stderr);
+        devices.register(EXIT_ADDR..EXIT_ADDR + 1, Exit);
+        devices.register(DEBUG_INT_ADDR..DEBUG_HEX_SPACE_ADDR + 1, DebugPrint);
+        devices.register(CLOCK_REALTIME_ADDR..CLOCK_MONOTONIC_ADDR + 1, Clock::new());
+        devices.register(TIMER_COUNT_ADDR..TIMER_FLAG_ADDR + 1, Timer::default());
+        devices.register(SLEEP_ADDR..SLEEP_ADDR + 1, Sleep::default());
+        devices
+    }
+}
+
+impl fmt::Debug for Devices {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.devices.iter().map(|(range, _)| range)).finish()
+    }
+}
+
+impl PartialEq for Devices {
+    fn eq(&self, other: &Self) -> bool {
+        self.devices.len() == other.devices.len() && self.devices.iter().zip(&other.devices).all(|((range, device), (other_range, other_device))| {
+            range == other_range && Rc::ptr_eq(device, other_device)
+        })
+    }
+}
+
+impl Devices {
+    /// Registers a device to handle the given range of addresses, replacing any devices that were
+    /// previously registered for addresses in that range
+    pub fn register<D: Device + 'static>(&mut self, range: Range<u64>, device: D) {
+        self.devices.retain(|(other, _)| other.end <= range.start || other.start >= range.end);
+        self.devices.push((range, Rc::new(RefCell::new(device))));
+    }
+
+    /// Returns true if a device handles the given address
+    pub fn contains(&self, addr: u64) -> bool {
+        self.get(addr).is_some()
+    }
+
+    /// Returns the device that handles the given address, if any
+    pub fn get(&self, addr: u64) -> Option<DeviceRef> {
+        self.devices.iter()
+            .find(|(range, _)| range.contains(&addr))
+            .map(|(_, device)| device.clone())
+    }
+}
+
+/// Reads `size` bytes from the device at the given address, or returns `None` if no device
+/// handles that address
+pub fn read_device(vm: &mut Machine, addr: u64, size: u64) -> Result<Option<u64>, ExecuteError> {
+    match vm.devices.get(addr) {
+        Some(device) => device.borrow_mut().read(vm, addr, size).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Writes the given bytes to the device at the given address, or returns false if no device
+/// handles that address
+pub fn write_device(vm: &mut Machine, addr: u64, bytes: &[u8]) -> Result<bool, ExecuteError> {
+    match vm.devices.get(addr) {
+        Some(device) => device.borrow_mut().write(vm, addr, bytes).map(|()| true),
+        None => Ok(false),
+    }
+}
+
+/// Zero-extends or truncates the given little-endian bytes to an N byte array
+pub(crate) fn resize_le<const N: usize>(bytes: &[u8]) -> [u8; N] {
+    let mut buf = [0; N];
+    let len = bytes.len().min(N);
+    buf[..len].copy_from_slice(&bytes[..len]);
+    buf
+}
+
+/// Reads a single byte of input from the machine's `io` whenever it is loaded from, or
+/// `EOF_BYTE` if there is no more input
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stdin;
+
+impl Device for Stdin {
+    fn read(&mut self, vm: &mut Machine, _addr: u64, _size: u64) -> Result<u64, ExecuteError> {
+        Ok(vm.io.read_byte()?.unwrap_or(EOF_BYTE) as u64)
+    }
+}
+
+/// Returns the status of the input of the machine's `io` whenever it is loaded from, without
+/// waiting for input to arrive
+///
+/// Bit 0 (`INPUT_AVAILABLE`) is set if a byte can be loaded from `STDIN_ADDR` without waiting, and
+/// bit 1 (`INPUT_EOF`) is set if there is no more input. Since `EOF_BYTE` is also a valid byte of
+/// input, programs that read binary data can use this to tell the two apart. If neither bit is
+/// set, no input has arrived yet.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StdinStatus;
+
+impl Device for StdinStatus {
+    fn read(&mut self, vm: &mut Machine, _addr: u64, _size: u64) -> Result<u64, ExecuteError> {
+        Ok(vm.io.status()?)
+    }
+}
+
+/// Reads a whole line of input from the machine's `io` into a buffer in memory whenever
+/// `READ_LINE_ADDR` is loaded from, which is much faster than loading each byte from `STDIN_ADDR`
+///
+/// The address and length of the buffer are stored at `LINE_BUF_ADDR` and `LINE_LEN_ADDR` first.
+/// Each line read includes its newline, unless it does not fit in the buffer, in which case the
+/// rest of it is read next time. The value loaded is the number of bytes read, which is 0 at EOF.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LineReader {
+    buf: u64,
+    len: u64,
+}
+
+impl Device for LineReader {
+    fn read(&mut self, vm: &mut Machine, addr: u64, _size: u64) -> Result<u64, ExecuteError> {
+        match addr {
+            LINE_BUF_ADDR => return Ok(self.buf),
+            LINE_LEN_ADDR => return Ok(self.len),
+            READ_LINE_ADDR => {},
+            _ => return Err(ExecuteError::UnsupportedDeviceAccess {addr, access: "read from"}),
+        }
+
+        let buf = self.buf..self.buf.saturating_add(self.len);
+        if let Some((region, Protection::ReadOnly)) = vm.memory.protection(buf.clone()) {
+            return Err(ExecuteError::ProtectedWrite {
+                instr: "read line",
+                addr: self.buf,
+                region_start: region.start,
+                region_end: region.end,
+            });
+        }
+        let Machine {io, memory, ..} = vm;
+        let len = io.read_line(memory.slice_mut(buf)?)?;
+        Ok(len as u64)
+    }
+
+    fn write(&mut self, _vm: &mut Machine, addr: u64, bytes: &[u8]) -> Result<(), ExecuteError> {
+        let value = u64::from_le_bytes(resize_le(bytes));
+        match addr {
+            LINE_BUF_ADDR => self.buf = value,
+            LINE_LEN_ADDR => self.len = value,
+            _ => return Err(ExecuteError::UnsupportedDeviceAccess {addr, access: "written to"}),
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes the lowest 4 bytes of every value stored to it to the machine's `io` as a `char`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stdout;
+
+impl Device for Stdout {
+    fn write(&mut self, vm: &mut Machine, _addr: u64, bytes: &[u8]) -> Result<(), ExecuteError> {
+        vm.io.write_bytes(u32::from_le_bytes(resize_le(bytes)))?;
+        Ok(())
+    }
+}
+
+/// Writes the lowest byte of every value stored to it to the machine's `io` without converting it
+/// to a `char`, so programs can write binary data or UTF-8 that they encoded themselves
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StdoutByte;
+
+impl Device for StdoutByte {
+    fn write(&mut self, vm: &mut Machine, _addr: u64, bytes: &[u8]) -> Result<(), ExecuteError> {
+        vm.io.write_raw_byte(bytes.first().copied().unwrap_or(0))?;
+        Ok(())
+    }
+}
+
+/// Writes the lowest 4 bytes of every value stored to it to the machine's `io` as a `char` on
+/// stderr, so diagnostics can be kept separate from the output of the program
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stderr;
+
+impl Device for Stderr {
+    fn write(&mut self, vm: &mut Machine, _addr: u64, bytes: &[u8]) -> Result<(), ExecuteError> {
+        vm.io.write_err_bytes(u32::from_le_bytes(resize_le(bytes)))?;
+        Ok(())
+    }
+}
+
+/// Prints every value stored to it as a number on stdout so that programs can show results without
+/// having to convert numbers to text themselves (see `DEBUG_INT_ADDR` and the addresses after it)
+///
+/// Values only have as many bytes as the store that wrote them, so storing -1 with `store1` prints `-1` as a signed number,
+/// `255` as an unsigned number, and `0xff` as a hexadecimal number.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DebugPrint;
+
+impl Device for DebugPrint {
+    fn write(&mut self, vm: &mut Machine, addr: u64, bytes: &[u8]) -> Result<(), ExecuteError> {
+        let value = u64::from_le_bytes(resize_le(bytes));
+        // Sign-extends the value from the number of bytes that were stored
+        let unused_bits = 64 - 8 * bytes.len().clamp(1, 8) as u32;
+        let signed = ((value << unused_bits) as i64) >> unused_bits;
+
+        let text = match addr {
+            DEBUG_INT_ADDR => format!("{}\n", signed),
+            DEBUG_UINT_ADDR => format!("{}\n", value),
+            DEBUG_HEX_ADDR => format!("0x{:x}\n", value),
+            DEBUG_INT_SPACE_ADDR => format!("{} ", signed),
+            DEBUG_UINT_SPACE_ADDR => format!("{} ", value),
+            DEBUG_HEX_SPACE_ADDR => format!("0x{:x} ", value),
+            _ => return Err(ExecuteError::UnsupportedDeviceAccess {addr, access: "written to"}),
+        };
+        vm.io.write_str(&text)?;
+
+        Ok(())
+    }
+}
+
+/// Quits the program when a value is stored to `EXIT_ADDR`, using the value as its exit code
+/// (see `Machine::exit`)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Exit;
+
+impl Device for Exit {
+    fn write(&mut self, vm: &mut Machine, _addr: u64, bytes: &[u8]) -> Result<(), ExecuteError> {
+        vm.exit(u64::from_le_bytes(resize_le(bytes)));
+        Ok(())
+    }
+}
+
+/// Returns the current time when it is loaded from (see `CLOCK_REALTIME_ADDR` and
+/// `CLOCK_MONOTONIC_ADDR`)
+///
+/// The time is read from the host, so programs that use it do not produce the same results every
+/// time they run. Loads performed after the same number of instructions return the same time, so
+/// a machine and its copy running in lockstep (see `SelfCheck`) see the same time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Clock {
+    start: Instant,
+    /// The address and instruction count of the last load, and the time it returned
+    last_read: Option<(u64, u64, u64)>,
+}
+
+impl Clock {
+    /// Creates a clock whose monotonic time starts at zero now
+    pub fn new() -> Self {
+        Self {start: Instant::now(), last_read: None}
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for Clock {
+    fn read(&mut self, vm: &mut Machine, addr: u64, _size: u64) -> Result<u64, ExecuteError> {
+        let instructions = vm.counters.instructions;
+        if let Some((last_addr, last_instructions, time)) = self.last_read {
+            if last_addr == addr && last_instructions == instructions {
+                return Ok(time);
+            }
+        }
+
+        let nanos = match addr {
+            // A host clock set before 1970 is treated as the epoch
+            CLOCK_REALTIME_ADDR => SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos(),
+            CLOCK_MONOTONIC_ADDR => self.start.elapsed().as_nanos(),
+            _ => return Err(ExecuteError::UnsupportedDeviceAccess {addr, access: "read from"}),
+        };
+
+        // Wraps around after 584 years
+        let time = nanos as u64;
+        self.last_read = Some((addr, instructions, time));
+        Ok(time)
+    }
+}
+
+/// Pauses the program for the number of milliseconds stored to `SLEEP_ADDR`, e.g. to show each
+/// frame of an animation for a while
+///
+/// A machine and its copy running in lockstep (see `SelfCheck`) only pause once for each store.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Sleep {
+    /// If true, stores return immediately without pausing
+    skip: bool,
+    /// The instruction count of the last store
+    last_write: Option<u64>,
+}
+
+impl Sleep {
+    /// Creates a device that pauses the program, or that returns immediately if `skip` is true so
+    /// that programs can be run quickly in tests
+    pub fn new(skip: bool) -> Self {
+        Self {skip, last_write: None}
+    }
+}
+
+impl Device for Sleep {
+    fn write(&mut self, vm: &mut Machine, _addr: u64, bytes: &[u8]) -> Result<(), ExecuteError> {
+        let instructions = vm.counters.instructions;
+        if self.skip || self.last_write == Some(instructions) {
+            return Ok(());
+        }
+        self.last_write = Some(instructions);
+
+        let millis = u64::from_le_bytes(resize_le(bytes));
+        thread::sleep(Duration::from_millis(millis));
+        Ok(())
+    }
+}
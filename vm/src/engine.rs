@@ -0,0 +1,166 @@
+//! The execution engines that can be used to run a machine (`wolf-vm --engine`)
+
+use std::fmt;
+use std::str::FromStr;
+
+use wolf_asm::{asm::InstrKind, executable::Executable};
+
+use crate::{
+    decode::Instr,
+    perf::PerfCounters,
+    execute::{ExecuteFn, NULL_GUARD_SIZE},
+    machine::{Machine, ProgramStatus, ExecutionError},
+};
+
+/// Selects how instructions are executed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// Decode each instruction from memory every time it is executed
+    #[default]
+    Interp,
+    /// Translate every instruction in the code section into a closure when the program is loaded
+    Closure,
+}
+
+impl Engine {
+    /// Allowed values the argument
+    pub const VARIANTS: &'static [&'static str] = &["interp", "closure"];
+
+    /// Returns the engine that the given engine is checked against by `--self-check`
+    pub fn other(self) -> Self {
+        match self {
+            Engine::Interp => Engine::Closure,
+            Engine::Closure => Engine::Interp,
+        }
+    }
+}
+
+impl FromStr for Engine {
+    type Err = &'static str;
+
+    fn from_str(src: &str) -> Result<Engine, &'static str> {
+        match src {
+            _ if src.eq_ignore_ascii_case("interp") => Ok(Engine::Interp),
+            _ if src.eq_ignore_ascii_case("closure") => Ok(Engine::Closure),
+            _ => Err("valid values: interp, closure"),
+        }
+    }
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Engine::Interp => write!(f, "interp"),
+            Engine::Closure => write!(f, "closure"),
+        }
+    }
+}
+
+/// Runs a machine with one of the execution engines
+pub enum Executor {
+    Interp,
+    Closure(ClosureEngine),
+}
+
+impl Executor {
+    /// Prepares the given engine to run the executable that was loaded into the given machine
+    pub fn new(engine: Engine, vm: &Machine, exec: &Executable) -> Self {
+        match engine {
+            Engine::Interp => Executor::Interp,
+            Engine::Closure => Executor::Closure(ClosureEngine::new(vm, exec)),
+        }
+    }
+
+    /// Runs the instruction at the program counter
+    pub fn step(&mut self, vm: &mut Machine) -> Result<ProgramStatus, ExecutionError> {
+        match self {
+            Executor::Interp => vm.step(),
+            Executor::Closure(engine) => engine.step(vm),
+        }
+    }
+}
+
+/// A single instruction translated into a closure
+struct CompiledInstr {
+    /// The encoded instruction that the closure was created from
+    ///
+    /// If the instruction in memory no longer matches this (because the program modified its own
+    /// code), the instruction is translated again.
+    word: u64,
+    /// The kind of instruction, recorded in the execution statistics
+    kind: InstrKind,
+    /// The number of cycles taken by the instruction (see `PerfCounters::instr_cycles`)
+    cycles: u64,
+    execute: ExecuteFn,
+}
+
+impl CompiledInstr {
+    fn compile(word: u64) -> Option<Self> {
+        // Instructions that fail to decode are left to the interpreter so it can report the error
+        let instr = Instr::decode(word).ok()?;
+        let kind = instr.kind();
+        let cycles = PerfCounters::instr_cycles(&instr);
+        Some(Self {word, kind, cycles, execute: instr.into_closure()})
+    }
+}
+
+/// Executes the instructions in the code section using closures created when the program was
+/// loaded, avoiding the cost of decoding every instruction each time it runs
+///
+/// Instructions outside of the code section are executed by the interpreter.
+pub struct ClosureEngine {
+    /// The address of the start of the code section
+    code_addr: u64,
+    /// The translated instruction at each byte offset from the start of the code section
+    instrs: Vec<Option<CompiledInstr>>,
+}
+
+impl ClosureEngine {
+    pub fn new(vm: &Machine, exec: &Executable) -> Self {
+        let code_addr = exec.base_addr;
+        let code_len: u64 = exec.code_section.iter().map(|stmt| stmt.size_bytes()).sum();
+
+        let mut instrs: Vec<_> = (0..code_len).map(|_| None).collect();
+        let mut offset = 0;
+        for stmt in &exec.code_section {
+            if let Ok(word) = vm.memory.read_u64(code_addr + offset) {
+                instrs[offset as usize] = CompiledInstr::compile(word);
+            }
+            offset += stmt.size_bytes();
+        }
+
+        Self {code_addr, instrs}
+    }
+
+    pub fn step(&mut self, vm: &mut Machine) -> Result<ProgramStatus, ExecutionError> {
+        let pc = vm.program_counter;
+        let offset = match pc.checked_sub(self.code_addr) {
+            Some(offset) if offset < self.instrs.len() as u64 => offset as usize,
+            _ => return vm.step(),
+        };
+        // The interpreter reports null guard violations and any reads past the end of memory
+        if vm.null_guard && pc < NULL_GUARD_SIZE {
+            return vm.step();
+        }
+        let word = match vm.memory.read_u64(pc) {
+            Ok(word) => word,
+            Err(_) => return vm.step(),
+        };
+
+        let slot = &mut self.instrs[offset];
+        if slot.as_ref().map(|instr| instr.word != word).unwrap_or(true) {
+            *slot = CompiledInstr::compile(word);
+        }
+        let instr = match slot {
+            Some(instr) => instr,
+            None => return vm.step(),
+        };
+
+        // All instructions are currently 8 bytes
+        vm.program_counter += 8;
+        (instr.execute)(vm)?;
+        vm.record_instr(instr.kind, instr.cycles);
+
+        Ok(vm.status())
+    }
+}
@@ -3,20 +3,20 @@ use std::io;
 use thiserror::Error;
 
 use crate::reinterpret::Reinterpret;
-use crate::machine::Machine;
-use crate::memory::OutOfBounds;
+use crate::machine::{Machine, ReturnAddr};
+use crate::memory::{OutOfBounds, Protection};
 use crate::flags::{Flags, CF, ZF, SF, OF};
 use crate::operands::{StoreDestination, Operand};
 use crate::decode::*;
+use crate::host_call::HostCallError;
+use crate::perf::PerfCounters;
+use crate::device::{read_device, write_device};
 
 /// The address used to indicate that the program should quit
 pub const QUIT_ADDR: u64 = u64::MAX;
-/// The address used for stdout
-pub const STDOUT_ADDR: u64 = 0xffff_000c;
-/// The address used for stdin
-pub const STDIN_ADDR: u64 = 0xffff_0004;
-/// The byte used to indicate EOF
-pub const EOF_BYTE: u8 = b'\0';
+/// The number of bytes starting at address zero that cannot be accessed when the null guard is
+/// enabled (the first page of memory)
+pub const NULL_GUARD_SIZE: u64 = 0x1000;
 
 fn size_bytes_of<T>() -> u64 {
     std::mem::size_of::<T>() as u64
@@ -30,12 +30,187 @@ pub enum ExecuteError {
     OutOfBounds(#[from] OutOfBounds),
     #[error("Divided a number by zero")]
     DivideByZero,
+    #[error("Null address access: `{instr}` attempted to access `0x{addr:x}` (the first {} bytes of memory are reserved by the null guard)", NULL_GUARD_SIZE)]
+    NullAddressAccess {
+        instr: &'static str,
+        addr: u64,
+    },
+    #[error("Null address access: attempted to execute an instruction at `0x{0:x}` (the first {} bytes of memory are reserved by the null guard)", NULL_GUARD_SIZE)]
+    NullAddressExecute(u64),
+    #[error("Data execution: attempted to execute data at `0x{0:x}` (only instructions in the code section can be executed)")]
+    DataExecute(u64),
+    #[error("Uninitialized memory: `{instr}` attempted to read `0x{addr:x}`, which has never been written to")]
+    UninitializedRead {
+        instr: &'static str,
+        addr: u64,
+    },
+    #[error("Misaligned address: `{instr}` attempted to access `0x{addr:x}` (the address must be a multiple of {align})")]
+    MisalignedAccess {
+        instr: &'static str,
+        addr: u64,
+        align: u64,
+    },
+    #[error("Read-only address: `{instr}` attempted to write to the performance counter at `0x{addr:x}`")]
+    ReadOnlyAddress {
+        instr: &'static str,
+        addr: u64,
+    },
+    #[error("Read-only address: `{instr}` attempted to write to `0x{addr:x}` (the addresses from `0x{region_start:x}` to `0x{region_end:x}` are read-only)")]
+    ProtectedWrite {
+        instr: &'static str,
+        addr: u64,
+        region_start: u64,
+        region_end: u64,
+    },
+    #[error("Stack imbalance: `ret` popped the return address from `0x{sp:x}`, but the matching `call` pushed it to `0x{slot:x}` (everything pushed since the `call` must be popped before `ret`)")]
+    StackImbalance {
+        sp: u64,
+        slot: u64,
+    },
+    #[error("Return address corrupted: `ret` popped `0x{found:x}` from `0x{slot:x}`, but the matching `call` pushed `0x{expected:x}` (the return address was overwritten)")]
+    ReturnAddressCorrupted {
+        slot: u64,
+        found: u64,
+        expected: u64,
+    },
+    #[error("Stack imbalance: `ret` popped the return address from `0x{0:x}`, but there is no matching `call`")]
+    UnmatchedReturn(u64),
+    #[error("Stack overflow: `{instr}` attempted to write to `0x{addr:x}` (the addresses from `0x{guard_start:x}` to `0x{guard_end:x}` are reserved by the stack guard)")]
+    StackOverflow {
+        instr: &'static str,
+        addr: u64,
+        guard_start: u64,
+        guard_end: u64,
+    },
+    #[error("Invalid device access: the device at `0x{addr:x}` cannot be {access}")]
+    UnsupportedDeviceAccess {
+        addr: u64,
+        access: &'static str,
+    },
+    #[error("Invalid device access: the device at `0x{addr:x}` has no command {command}")]
+    UnknownDeviceCommand {
+        addr: u64,
+        command: u64,
+    },
+    #[error("No host function registered for `hcall {0}`")]
+    UnknownHostCall(u64),
+    #[error("Host function for `hcall {sub_opcode}` failed: {source}")]
+    HostCallFailed {
+        sub_opcode: u64,
+        source: HostCallError,
+    },
+}
+
+/// Returns an error if the null guard is enabled and the given address is reserved by it
+fn check_null_guard(vm: &Machine, instr: &'static str, addr: u64) -> Result<(), ExecuteError> {
+    if vm.null_guard && addr < NULL_GUARD_SIZE {
+        return Err(ExecuteError::NullAddressAccess {instr, addr});
+    }
+
+    Ok(())
+}
+
+/// Returns an error if any of the `size` bytes at the given address have never been written to
+///
+/// This is only checked if the memory is keeping track of writes (see `Memory::track_writes`).
+fn check_written(vm: &Machine, instr: &'static str, addr: u64, size: u64) -> Result<(), ExecuteError> {
+    match vm.memory.first_unwritten(addr..addr.saturating_add(size)) {
+        Some(addr) => Err(ExecuteError::UninitializedRead {instr, addr}),
+        None => Ok(()),
+    }
+}
+
+/// Returns an error if strict alignment is enabled and the given address is not a multiple of
+/// `size`
+///
+/// The addresses handled by devices can be accessed with any size of load or store.
+fn check_alignment(vm: &Machine, instr: &'static str, addr: u64, size: u64) -> Result<(), ExecuteError> {
+    if vm.strict_align && !addr.is_multiple_of(size) && !vm.devices.contains(addr) {
+        return Err(ExecuteError::MisalignedAccess {instr, addr, align: size});
+    }
+
+    Ok(())
+}
+
+/// Returns an error if the given address cannot be written to
+fn check_read_only(instr: &'static str, addr: u64) -> Result<(), ExecuteError> {
+    if PerfCounters::is_counter_addr(addr) {
+        return Err(ExecuteError::ReadOnlyAddress {instr, addr});
+    }
+
+    Ok(())
+}
+
+/// Returns an error if writing `size` bytes at the given address would write to an address that
+/// is protected from being written to (see `Memory::protect`)
+fn check_protection(vm: &Machine, instr: &'static str, addr: u64, size: u64) -> Result<(), ExecuteError> {
+    match vm.memory.protection(addr..addr.saturating_add(size)) {
+        Some((region, Protection::ReadOnly)) => Err(ExecuteError::ProtectedWrite {
+            instr,
+            addr,
+            region_start: region.start,
+            region_end: region.end,
+        }),
+        Some((_, Protection::ReadWrite)) | None => Ok(()),
+    }
+}
+
+/// Returns an error if writing `size` bytes at the given address would write to an address
+/// reserved by the stack guard
+fn check_stack_guard(vm: &Machine, instr: &'static str, addr: u64, size: u64) -> Result<(), ExecuteError> {
+    let guard = &vm.stack_guard;
+    if !guard.is_empty() && addr < guard.end && addr.saturating_add(size) > guard.start {
+        return Err(ExecuteError::StackOverflow {
+            instr,
+            addr,
+            guard_start: guard.start,
+            guard_end: guard.end,
+        });
+    }
+
+    Ok(())
+}
+
+/// Returns an error if writing `size` bytes at the given address would fail any of the checks
+/// run by a store
+///
+/// Devices that write directly into memory use this so they cannot write anywhere a store can't.
+pub(crate) fn check_store(vm: &Machine, instr: &'static str, addr: u64, size: u64) -> Result<(), ExecuteError> {
+    check_null_guard(vm, instr, addr)?;
+    check_read_only(instr, addr)?;
+    check_stack_guard(vm, instr, addr, size)?;
+    check_protection(vm, instr, addr, size)
+}
+
+/// Returns an error if return addresses are being checked and `ret` would pop `value` from `sp`
+/// instead of the return address pushed by the matching `call` (see `Machine::check_returns`)
+fn check_return(vm: &mut Machine, sp: u64, value: u64) -> Result<(), ExecuteError> {
+    let call_stack = match &mut vm.call_stack {
+        Some(call_stack) => call_stack,
+        None => return Ok(()),
+    };
+
+    let ReturnAddr {addr, slot} = match call_stack.pop() {
+        Some(return_addr) => return_addr,
+        None => return Err(ExecuteError::UnmatchedReturn(sp)),
+    };
+    if sp != slot {
+        return Err(ExecuteError::StackImbalance {sp, slot});
+    }
+    if value != addr {
+        return Err(ExecuteError::ReturnAddressCorrupted {slot, found: value, expected: addr});
+    }
+
+    Ok(())
 }
 
 pub trait Execute {
     fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError>;
 }
 
+/// A function that executes a single decoded instruction (see `Instr::into_closure`)
+pub type ExecuteFn = Box<dyn Fn(&mut Machine) -> Result<(), ExecuteError>>;
+
 impl Execute for Nop {
     fn execute(self, _vm: &mut Machine) -> Result<(), ExecuteError> {
         let Nop {} = self;
@@ -542,12 +717,17 @@ impl Execute for Load1 {
         let Load1 {dest, loc} = self;
 
         let addr: u64 = loc.into_value(vm);
+        check_null_guard(vm, "load1", addr)?;
         // load1 loads only 1 byte
-        let value = if addr == STDIN_ADDR {
-            u8::reinterpret(vm.io.read_byte()?.unwrap_or(EOF_BYTE))
+        let value = if let Some(value) = read_device(vm, addr, size_bytes_of::<u8>())? {
+            u8::reinterpret(value)
+        } else if let Some(counter) = vm.counters.read(addr) {
+            u8::reinterpret(counter)
         } else {
+            check_written(vm, "load1", addr, size_bytes_of::<u8>())?;
             vm.memory.get(addr)?
         };
+        vm.counters.record_load();
         // load (unlike loadu) must sign-extend (hence i8)
         let value = i8::reinterpret(value);
         vm.store_dest(dest, value);
@@ -561,12 +741,17 @@ impl Execute for Loadu1 {
         let Loadu1 {dest, loc} = self;
 
         let addr: u64 = loc.into_value(vm);
+        check_null_guard(vm, "loadu1", addr)?;
         // loadu1 loads only 1 byte
-        let value = if addr == STDIN_ADDR {
-            u8::reinterpret(vm.io.read_byte()?.unwrap_or(EOF_BYTE))
+        let value = if let Some(value) = read_device(vm, addr, size_bytes_of::<u8>())? {
+            u8::reinterpret(value)
+        } else if let Some(counter) = vm.counters.read(addr) {
+            u8::reinterpret(counter)
         } else {
+            check_written(vm, "loadu1", addr, size_bytes_of::<u8>())?;
             vm.memory.get(addr)?
         };
+        vm.counters.record_load();
         // loadu (unlike load) must NOT sign-extend (hence u8 is fine)
         vm.store_dest(dest, value);
 
@@ -579,12 +764,18 @@ impl Execute for Load2 {
         let Load2 {dest, loc} = self;
 
         let addr: u64 = loc.into_value(vm);
+        check_null_guard(vm, "load2", addr)?;
+        check_alignment(vm, "load2", addr, size_bytes_of::<u16>())?;
         // load2 loads 2 bytes
-        let value = if addr == STDIN_ADDR {
-            u16::reinterpret(vm.io.read_byte()?.unwrap_or(EOF_BYTE))
+        let value = if let Some(value) = read_device(vm, addr, size_bytes_of::<u16>())? {
+            u16::reinterpret(value)
+        } else if let Some(counter) = vm.counters.read(addr) {
+            u16::reinterpret(counter)
         } else {
+            check_written(vm, "load2", addr, size_bytes_of::<u16>())?;
             vm.memory.read_u16(addr)?
         };
+        vm.counters.record_load();
         // load (unlike loadu) must sign-extend (hence i16)
         let value = i16::reinterpret(value);
         vm.store_dest(dest, value);
@@ -598,12 +789,18 @@ impl Execute for Loadu2 {
         let Loadu2 {dest, loc} = self;
 
         let addr: u64 = loc.into_value(vm);
+        check_null_guard(vm, "loadu2", addr)?;
+        check_alignment(vm, "loadu2", addr, size_bytes_of::<u16>())?;
         // load2 loads 2 bytes
-        let value = if addr == STDIN_ADDR {
-            u16::reinterpret(vm.io.read_byte()?.unwrap_or(EOF_BYTE))
+        let value = if let Some(value) = read_device(vm, addr, size_bytes_of::<u16>())? {
+            u16::reinterpret(value)
+        } else if let Some(counter) = vm.counters.read(addr) {
+            u16::reinterpret(counter)
         } else {
+            check_written(vm, "loadu2", addr, size_bytes_of::<u16>())?;
             vm.memory.read_u16(addr)?
         };
+        vm.counters.record_load();
         // loadu (unlike load) must NOT sign-extend (hence u16 is fine)
         vm.store_dest(dest, value);
 
@@ -616,12 +813,18 @@ impl Execute for Load4 {
         let Load4 {dest, loc} = self;
 
         let addr: u64 = loc.into_value(vm);
+        check_null_guard(vm, "load4", addr)?;
+        check_alignment(vm, "load4", addr, size_bytes_of::<u32>())?;
         // load4 loads 4 bytes
-        let value = if addr == STDIN_ADDR {
-            u32::reinterpret(vm.io.read_byte()?.unwrap_or(EOF_BYTE))
+        let value = if let Some(value) = read_device(vm, addr, size_bytes_of::<u32>())? {
+            u32::reinterpret(value)
+        } else if let Some(counter) = vm.counters.read(addr) {
+            u32::reinterpret(counter)
         } else {
+            check_written(vm, "load4", addr, size_bytes_of::<u32>())?;
             vm.memory.read_u32(addr)?
         };
+        vm.counters.record_load();
         // load (unlike loadu) must sign-extend (hence i32)
         let value = i32::reinterpret(value);
         vm.store_dest(dest, value);
@@ -635,12 +838,18 @@ impl Execute for Loadu4 {
         let Loadu4 {dest, loc} = self;
 
         let addr: u64 = loc.into_value(vm);
+        check_null_guard(vm, "loadu4", addr)?;
+        check_alignment(vm, "loadu4", addr, size_bytes_of::<u32>())?;
         // load4 loads 4 bytes
-        let value = if addr == STDIN_ADDR {
-            u32::reinterpret(vm.io.read_byte()?.unwrap_or(EOF_BYTE))
+        let value = if let Some(value) = read_device(vm, addr, size_bytes_of::<u32>())? {
+            u32::reinterpret(value)
+        } else if let Some(counter) = vm.counters.read(addr) {
+            u32::reinterpret(counter)
         } else {
+            check_written(vm, "loadu4", addr, size_bytes_of::<u32>())?;
             vm.memory.read_u32(addr)?
         };
+        vm.counters.record_load();
         // loadu (unlike load) must NOT sign-extend (hence u32 is fine)
         vm.store_dest(dest, value);
 
@@ -654,13 +863,19 @@ impl Execute for Load8 {
         let Load8 {dest, loc} = self;
 
         let addr: u64 = loc.into_value(vm);
-        let value = if addr == STDIN_ADDR {
-            u64::reinterpret(vm.io.read_byte()?.unwrap_or(EOF_BYTE))
+        check_null_guard(vm, "load8", addr)?;
+        check_alignment(vm, "load8", addr, size_bytes_of::<u64>())?;
+        let value = if let Some(value) = read_device(vm, addr, size_bytes_of::<u64>())? {
+            u64::reinterpret(value)
+        } else if let Some(counter) = vm.counters.read(addr) {
+            u64::reinterpret(counter)
         } else {
+            check_written(vm, "load8", addr, size_bytes_of::<u64>())?;
             // Since the value is already 8 bytes, we don't need to worry about
             // sign-extension
             vm.memory.read_u64(addr)?
         };
+        vm.counters.record_load();
         vm.store_dest(dest, value);
 
         Ok(())
@@ -673,13 +888,19 @@ impl Execute for Loadu8 {
         let Loadu8 {dest, loc} = self;
 
         let addr: u64 = loc.into_value(vm);
-        let value = if addr == STDIN_ADDR {
-            u64::reinterpret(vm.io.read_byte()?.unwrap_or(EOF_BYTE))
+        check_null_guard(vm, "loadu8", addr)?;
+        check_alignment(vm, "loadu8", addr, size_bytes_of::<u64>())?;
+        let value = if let Some(value) = read_device(vm, addr, size_bytes_of::<u64>())? {
+            u64::reinterpret(value)
+        } else if let Some(counter) = vm.counters.read(addr) {
+            u64::reinterpret(counter)
         } else {
+            check_written(vm, "loadu8", addr, size_bytes_of::<u64>())?;
             // Since the value is already 8 bytes, we don't need to worry about
             // zero-extension
             vm.memory.read_u64(addr)?
         };
+        vm.counters.record_load();
         vm.store_dest(dest, value);
 
         Ok(())
@@ -690,14 +911,17 @@ impl Execute for Store1 {
     fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
         let Store1 {loc, source} = self;
         let addr: u64 = loc.into_value(vm);
+        check_null_guard(vm, "store1", addr)?;
+        check_read_only("store1", addr)?;
+        check_stack_guard(vm, "store1", addr, size_bytes_of::<u8>())?;
+        check_protection(vm, "store1", addr, size_bytes_of::<u8>())?;
 
         let value: u8 = source.into_value(vm);
 
-        if addr == STDOUT_ADDR {
-            vm.io.write_bytes(u32::reinterpret(value))?;
-        } else {
+        if !write_device(vm, addr, &value.to_le_bytes())? {
             vm.memory.set(addr, value)?;
         }
+        vm.counters.record_store();
 
         Ok(())
     }
@@ -707,14 +931,18 @@ impl Execute for Store2 {
     fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
         let Store2 {loc, source} = self;
         let addr: u64 = loc.into_value(vm);
+        check_null_guard(vm, "store2", addr)?;
+        check_alignment(vm, "store2", addr, size_bytes_of::<u16>())?;
+        check_read_only("store2", addr)?;
+        check_stack_guard(vm, "store2", addr, size_bytes_of::<u16>())?;
+        check_protection(vm, "store2", addr, size_bytes_of::<u16>())?;
 
         let value: u16 = source.into_value(vm);
 
-        if addr == STDOUT_ADDR {
-            vm.io.write_bytes(u32::reinterpret(value))?;
-        } else {
+        if !write_device(vm, addr, &value.to_le_bytes())? {
             vm.memory.write_u16(addr, value)?;
         }
+        vm.counters.record_store();
 
         Ok(())
     }
@@ -724,14 +952,18 @@ impl Execute for Store4 {
     fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
         let Store4 {loc, source} = self;
         let addr: u64 = loc.into_value(vm);
+        check_null_guard(vm, "store4", addr)?;
+        check_alignment(vm, "store4", addr, size_bytes_of::<u32>())?;
+        check_read_only("store4", addr)?;
+        check_stack_guard(vm, "store4", addr, size_bytes_of::<u32>())?;
+        check_protection(vm, "store4", addr, size_bytes_of::<u32>())?;
 
         let value: u32 = source.into_value(vm);
 
-        if addr == STDOUT_ADDR {
-            vm.io.write_bytes(u32::reinterpret(value))?;
-        } else {
+        if !write_device(vm, addr, &value.to_le_bytes())? {
             vm.memory.write_u32(addr, value)?;
         }
+        vm.counters.record_store();
 
         Ok(())
     }
@@ -741,14 +973,18 @@ impl Execute for Store8 {
     fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
         let Store8 {loc, source} = self;
         let addr: u64 = loc.into_value(vm);
+        check_null_guard(vm, "store8", addr)?;
+        check_alignment(vm, "store8", addr, size_bytes_of::<u64>())?;
+        check_read_only("store8", addr)?;
+        check_stack_guard(vm, "store8", addr, size_bytes_of::<u64>())?;
+        check_protection(vm, "store8", addr, size_bytes_of::<u64>())?;
 
         let value: u64 = source.into_value(vm);
 
-        if addr == STDOUT_ADDR {
-            vm.io.write_bytes(u32::reinterpret(value))?;
-        } else {
+        if !write_device(vm, addr, &value.to_le_bytes())? {
             vm.memory.write_u64(addr, value)?;
         }
+        vm.counters.record_store();
 
         Ok(())
     }
@@ -760,12 +996,16 @@ impl Execute for Push {
 
         // Decrement the stack pointer
         let sp: u64 = vm.registers.load_sp();
-        let stack_top = sp - size_bytes_of::<u64>();
+        // Running out of stack space wraps around to an address that is out of bounds
+        let stack_top = sp.wrapping_sub(size_bytes_of::<u64>());
+        check_stack_guard(vm, "push", stack_top, size_bytes_of::<u64>())?;
+        check_protection(vm, "push", stack_top, size_bytes_of::<u64>())?;
         vm.registers.store_sp(stack_top);
 
         // Store the value at the top of the stack
         let value: u64 = source.into_value(vm);
         vm.memory.write_u64(stack_top, value)?;
+        vm.counters.record_store();
 
         Ok(())
     }
@@ -778,6 +1018,7 @@ impl Execute for Pop {
         // Load the top of the stack into the destination
         let stack_top: u64 = vm.registers.load_sp();
         let value = vm.memory.read_u64(stack_top)?;
+        vm.counters.record_load();
         vm.store_dest(dest, value);
 
         // Increment the stack pointer
@@ -1010,36 +1251,6 @@ impl Execute for Jno {
     }
 }
 
-impl Execute for Jz {
-    fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
-        let Jz {loc} = self;
-        let addr: u64 = loc.into_value(vm);
-
-        // Zero if ZF = 1
-        // See: https://en.wikibooks.org/wiki/X86_Assembly/Control_Flow#Jump_if_Zero
-        if vm.flags.zero == ZF::Zero {
-            vm.program_counter = addr;
-        }
-
-        Ok(())
-    }
-}
-
-impl Execute for Jnz {
-    fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
-        let Jnz {loc} = self;
-        let addr: u64 = loc.into_value(vm);
-
-        // Not zero if ZF = 0
-        // See: https://en.wikibooks.org/wiki/X86_Assembly/Control_Flow#Jump_if_Not_Zero
-        if vm.flags.zero == ZF::NonZero {
-            vm.program_counter = addr;
-        }
-
-        Ok(())
-    }
-}
-
 impl Execute for Js {
     fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
         let Js {loc} = self;
@@ -1076,11 +1287,18 @@ impl Execute for Call {
 
         // Decrement the stack pointer
         let sp: u64 = vm.registers.load_sp();
-        let stack_top = sp - size_bytes_of::<u64>();
+        // Running out of stack space wraps around to an address that is out of bounds
+        let stack_top = sp.wrapping_sub(size_bytes_of::<u64>());
+        check_stack_guard(vm, "call", stack_top, size_bytes_of::<u64>())?;
+        check_protection(vm, "call", stack_top, size_bytes_of::<u64>())?;
         vm.registers.store_sp(stack_top);
 
         // Store the program counter at the top of the stack
         vm.memory.write_u64(stack_top, vm.program_counter)?;
+        vm.counters.record_store();
+        if let Some(call_stack) = &mut vm.call_stack {
+            call_stack.push(ReturnAddr {addr: vm.program_counter, slot: stack_top});
+        }
 
         // Jump to the given location
         let addr: u64 = loc.into_value(vm);
@@ -1097,6 +1315,8 @@ impl Execute for Ret {
         // Load the top of the stack into the program counter
         let stack_top: u64 = vm.registers.load_sp();
         let value = vm.memory.read_u64(stack_top)?;
+        check_return(vm, stack_top, value)?;
+        vm.counters.record_load();
         vm.program_counter = value;
 
         // Increment the stack pointer
@@ -1106,3 +1326,17 @@ impl Execute for Ret {
         Ok(())
     }
 }
+
+impl Execute for Hcall {
+    fn execute(self, vm: &mut Machine) -> Result<(), ExecuteError> {
+        let Hcall {source} = self;
+
+        let sub_opcode: u64 = source.into_value(vm);
+        // Clone the function out of the registry so that it can borrow the machine mutably
+        let host_fn = vm.host_calls.get(sub_opcode)
+            .ok_or(ExecuteError::UnknownHostCall(sub_opcode))?;
+        host_fn(vm).map_err(|source| ExecuteError::HostCallFailed {sub_opcode, source})?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf, Component};
+use std::ops::Range;
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+use crate::machine::Machine;
+use crate::device::{Device, resize_le};
+use crate::execute::{ExecuteError, check_store};
+
+/// Storing a command number at this address runs that command and loading from it returns the
+/// result of the last command
+pub const FILE_CMD_ADDR: u64 = 0xffff_0200;
+/// The file descriptor used by the next command
+pub const FILE_FD_ADDR: u64 = 0xffff_0208;
+/// The address of the buffer (or path) used by the next command
+pub const FILE_BUF_ADDR: u64 = 0xffff_0210;
+/// The length of the buffer (or path) used by the next command, or the offset used by a seek
+pub const FILE_LEN_ADDR: u64 = 0xffff_0218;
+/// The addresses handled by the file device
+pub const FILE_IO_ADDRS: Range<u64> = FILE_CMD_ADDR..FILE_LEN_ADDR + 8;
+
+/// Opens the file at the path in the buffer for reading
+pub const FILE_OPEN_READ: u64 = 1;
+/// Opens the file at the path in the buffer for writing, creating it if it does not exist and
+/// truncating it if it does
+pub const FILE_OPEN_WRITE: u64 = 2;
+/// Opens the file at the path in the buffer for writing at its end, creating it if it does not
+/// exist
+pub const FILE_OPEN_APPEND: u64 = 3;
+/// Reads up to the length of the buffer from the file into the buffer
+pub const FILE_READ: u64 = 4;
+/// Writes the buffer to the file
+pub const FILE_WRITE: u64 = 5;
+/// Moves to the offset from the start of the file
+pub const FILE_SEEK_START: u64 = 6;
+/// Moves to the offset from the current position in the file
+pub const FILE_SEEK_CURRENT: u64 = 7;
+/// Moves to the offset from the end of the file
+pub const FILE_SEEK_END: u64 = 8;
+/// Closes the file
+pub const FILE_CLOSE: u64 = 9;
+
+/// The first file descriptor returned when a file is opened (0 to 2 are left for stdio)
+const FIRST_FD: u64 = 3;
+
+/// Gives programs access to the files in a single host directory (the sandbox)
+///
+/// A command is run by storing its number at `FILE_CMD_ADDR` after storing its operands at
+/// `FILE_FD_ADDR`, `FILE_BUF_ADDR`, and `FILE_LEN_ADDR`. Loading from `FILE_CMD_ADDR` then returns
+/// the result of the command: the file descriptor of an opened file, the number of bytes read or
+/// written, the new position in the file after a seek, or 0 after a close. The result is -1 if
+/// the command failed for any reason, including a path outside of the sandbox.
+///
+/// Paths are relative to the sandbox and may not contain `..`. Symbolic links may not lead
+/// outside of it either, and a symbolic link to a file that does not exist cannot be opened at
+/// all. Reading into memory runs the same checks as a store, so it fails the same way a store to
+/// the buffer would.
+#[derive(Debug)]
+pub struct FileIo {
+    /// The canonical path of the sandbox directory
+    root: PathBuf,
+    files: HashMap<u64, File>,
+    next_fd: u64,
+    fd: u64,
+    buf: u64,
+    len: u64,
+    result: i64,
+}
+
+impl FileIo {
+    /// Creates a device that can only access the files in the given directory
+    pub fn new(root: &Path) -> io::Result<Self> {
+        let root = fs::canonicalize(root)?;
+        if !root.is_dir() {
+            return Err(io::Error::other(format!("'{}' is not a directory", root.display())));
+        }
+
+        Ok(Self {
+            root,
+            files: HashMap::new(),
+            next_fd: FIRST_FD,
+            fd: 0,
+            buf: 0,
+            len: 0,
+            result: 0,
+        })
+    }
+
+    /// Returns the path to the given file in the sandbox, or None if the path is invalid or leads
+    /// outside of it
+    fn resolve(&self, path: &[u8]) -> Option<PathBuf> {
+        let path = Path::new(std::str::from_utf8(path).ok()?);
+        let is_relative = path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if !is_relative || path.file_name().is_none() {
+            return None;
+        }
+
+        let path = self.root.join(path);
+        // A file being created does not exist yet, so only its directory can be canonicalized
+        let path = match fs::canonicalize(&path) {
+            Ok(path) => path,
+            // Opening a dangling symbolic link for writing would create the file it points to,
+            // which may be outside of the sandbox
+            Err(_) if fs::symlink_metadata(&path).is_ok() => return None,
+            Err(_) => fs::canonicalize(path.parent()?).ok()?.join(path.file_name()?),
+        };
+        if path.starts_with(&self.root) {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    /// Runs the given command, returning its result or None if it failed
+    fn run(&mut self, vm: &mut Machine, command: u64) -> Result<Option<u64>, ExecuteError> {
+        let buf = self.buf..self.buf.saturating_add(self.len);
+        let result = match command {
+            FILE_OPEN_READ | FILE_OPEN_WRITE | FILE_OPEN_APPEND => {
+                let path = match self.resolve(vm.memory.slice(buf)?) {
+                    Some(path) => path,
+                    None => return Ok(None),
+                };
+                let mut options = OpenOptions::new();
+                match command {
+                    FILE_OPEN_READ => options.read(true),
+                    FILE_OPEN_WRITE => options.write(true).create(true).truncate(true),
+                    _ => options.append(true).create(true),
+                };
+                // The path has no symbolic links in it now, but one could be created before the
+                // file is opened
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::OpenOptionsExt;
+                    options.custom_flags(libc::O_NOFOLLOW);
+                }
+                options.open(path).ok().map(|file| {
+                    let fd = self.next_fd;
+                    self.next_fd += 1;
+                    self.files.insert(fd, file);
+                    fd
+                })
+            },
+
+            FILE_READ => match self.files.get_mut(&self.fd) {
+                // Reading into memory is a store, so it cannot bypass the checks a store runs
+                Some(file) => {
+                    check_store(vm, "file read", self.buf, self.len)?;
+                    file.read(vm.memory.slice_mut(buf)?).ok().map(|len| len as u64)
+                },
+                None => None,
+            },
+
+            FILE_WRITE => match self.files.get_mut(&self.fd) {
+                Some(file) => file.write(vm.memory.slice(buf)?).ok().map(|len| len as u64),
+                None => None,
+            },
+
+            FILE_SEEK_START | FILE_SEEK_CURRENT | FILE_SEEK_END => {
+                let offset = self.len as i64;
+                let pos = match command {
+                    FILE_SEEK_START => offset.try_into().ok().map(SeekFrom::Start),
+                    FILE_SEEK_CURRENT => Some(SeekFrom::Current(offset)),
+                    _ => Some(SeekFrom::End(offset)),
+                };
+                match (self.files.get_mut(&self.fd), pos) {
+                    (Some(file), Some(pos)) => file.seek(pos).ok(),
+                    _ => None,
+                }
+            },
+
+            FILE_CLOSE => self.files.remove(&self.fd).map(|_| 0),
+
+            _ => return Err(ExecuteError::UnknownDeviceCommand {addr: FILE_CMD_ADDR, command}),
+        };
+
+        Ok(result)
+    }
+}
+
+impl Device for FileIo {
+    fn read(&mut self, _vm: &mut Machine, addr: u64, _size: u64) -> Result<u64, ExecuteError> {
+        Ok(match addr {
+            FILE_FD_ADDR => self.fd,
+            FILE_BUF_ADDR => self.buf,
+            FILE_LEN_ADDR => self.len,
+            FILE_CMD_ADDR => self.result as u64,
+            _ => return Err(ExecuteError::UnsupportedDeviceAccess {addr, access: "read from"}),
+        })
+    }
+
+    fn write(&mut self, vm: &mut Machine, addr: u64, bytes: &[u8]) -> Result<(), ExecuteError> {
+        let value = u64::from_le_bytes(resize_le(bytes));
+
+        match addr {
+            FILE_FD_ADDR => self.fd = value,
+            FILE_BUF_ADDR => self.buf = value,
+            FILE_LEN_ADDR => self.len = value,
+            FILE_CMD_ADDR => {
+                self.result = self.run(vm, value)?.map(|result| result as i64).unwrap_or(-1);
+            },
+            _ => return Err(ExecuteError::UnsupportedDeviceAccess {addr, access: "written to"}),
+        }
+
+        Ok(())
+    }
+}
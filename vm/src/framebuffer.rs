@@ -0,0 +1,121 @@
+use std::io::{self, Write};
+use std::fs::File;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use crate::machine::Machine;
+use crate::device::Device;
+use crate::execute::ExecuteError;
+
+/// The width of the framebuffer in pixels
+pub const FRAMEBUFFER_WIDTH: u64 = 320;
+/// The height of the framebuffer in pixels
+pub const FRAMEBUFFER_HEIGHT: u64 = 200;
+/// The addresses of the pixels of the framebuffer
+///
+/// Each pixel is a single byte that selects a color from the palette. Pixels are stored row by
+/// row, so the pixel at (x, y) is at `FRAMEBUFFER_ADDRS.start + y * FRAMEBUFFER_WIDTH + x`.
+pub const FRAMEBUFFER_ADDRS: Range<u64> = 0xfffe_0000..0xfffe_0000 + FRAMEBUFFER_WIDTH * FRAMEBUFFER_HEIGHT;
+/// Storing any value at this address presents the current frame (see `Framebuffer::new`)
+pub const PRESENT_ADDR: u64 = 0xfffe_fb00;
+/// The addresses of the palette, which holds 256 colors of 4 bytes each (`0x00RRGGBB`)
+pub const PALETTE_ADDRS: Range<u64> = 0xfffe_fc00..0xffff_0000;
+/// The addresses handled by the framebuffer, including the pixels, the palette, and
+/// `PRESENT_ADDR`
+pub const FRAMEBUFFER_DEVICE_ADDRS: Range<u64> = FRAMEBUFFER_ADDRS.start..PALETTE_ADDRS.end;
+
+/// A 320x200 framebuffer with one byte per pixel, where each byte selects a color from a palette
+/// of 256 colors
+///
+/// The palette starts out with every combination of 3 bits of red, 3 bits of green, and 2 bits
+/// of blue (`0bRRRGGGBB`), so pixels can be used as colors directly. Every pixel starts out as 0
+/// (black).
+///
+/// This only dumps each frame to an image file. Frames are not rendered to a window, since the VM
+/// has no window backend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    pixels: Vec<u8>,
+    /// The bytes of each color in the palette
+    palette: Vec<u8>,
+    /// The file that each frame is written to when it is presented
+    output: PathBuf,
+}
+
+impl Framebuffer {
+    /// Creates a framebuffer that writes each frame to the given file (as a binary PPM image)
+    /// when it is presented, replacing the previous frame
+    pub fn new(output: PathBuf) -> Self {
+        let palette = (0..256u32).flat_map(|color| {
+            // Scale each component so that its maximum value is 0xff
+            let red = (color >> 5) * 0xff / 0b111;
+            let green = ((color >> 2) & 0b111) * 0xff / 0b111;
+            let blue = (color & 0b11) * 0xff / 0b11;
+            ((red << 16) | (green << 8) | blue).to_le_bytes()
+        }).collect();
+
+        Self {
+            pixels: vec![0; (FRAMEBUFFER_WIDTH * FRAMEBUFFER_HEIGHT) as usize],
+            palette,
+            output,
+        }
+    }
+
+    /// Writes the current frame as a binary PPM image
+    pub fn write_ppm<W: Write>(&self, mut out: W) -> io::Result<()> {
+        write!(out, "P6\n{} {}\n255\n", FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT)?;
+        for &pixel in &self.pixels {
+            let color = &self.palette[pixel as usize * 4..][..4];
+            // Colors are stored as `0x00RRGGBB` in little-endian order
+            out.write_all(&[color[2], color[1], color[0]])?;
+        }
+        out.flush()
+    }
+
+    /// Returns the bytes at the given address and the address of the first of them, or None if
+    /// the address is not a pixel or in the palette
+    fn bytes_mut(&mut self, addr: u64) -> Option<(&mut [u8], u64)> {
+        if FRAMEBUFFER_ADDRS.contains(&addr) {
+            Some((&mut self.pixels, FRAMEBUFFER_ADDRS.start))
+        } else if PALETTE_ADDRS.contains(&addr) {
+            Some((&mut self.palette, PALETTE_ADDRS.start))
+        } else {
+            None
+        }
+    }
+}
+
+impl Device for Framebuffer {
+    fn read(&mut self, _vm: &mut Machine, addr: u64, size: u64) -> Result<u64, ExecuteError> {
+        let (bytes, start) = match self.bytes_mut(addr) {
+            Some(bytes) => bytes,
+            None => return Err(ExecuteError::UnsupportedDeviceAccess {addr, access: "read from"}),
+        };
+
+        // Loads past the end only return the bytes that exist
+        let bytes = &bytes[(addr - start) as usize..];
+        let len = bytes.len().min(size as usize).min(8);
+        let mut value = [0; 8];
+        value[..len].copy_from_slice(&bytes[..len]);
+        Ok(u64::from_le_bytes(value))
+    }
+
+    fn write(&mut self, _vm: &mut Machine, addr: u64, value: &[u8]) -> Result<(), ExecuteError> {
+        if addr == PRESENT_ADDR {
+            let file = File::create(&self.output)?;
+            self.write_ppm(io::BufWriter::new(file))?;
+            return Ok(());
+        }
+
+        let (bytes, start) = match self.bytes_mut(addr) {
+            Some(bytes) => bytes,
+            None => return Err(ExecuteError::UnsupportedDeviceAccess {addr, access: "written to"}),
+        };
+
+        // Stores past the end only change the bytes that exist
+        let bytes = &mut bytes[(addr - start) as usize..];
+        let len = bytes.len().min(value.len());
+        bytes[..len].copy_from_slice(&value[..len]);
+        Ok(())
+    }
+}
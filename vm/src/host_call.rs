@@ -0,0 +1,81 @@
+use std::fmt;
+use std::rc::Rc;
+use std::collections::HashMap;
+
+use crate::machine::Machine;
+
+/// The error type returned by host functions
+pub type HostCallError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A function provided by the embedder that runs when the `hcall` instruction is executed
+///
+/// Host functions can read their arguments from and write their results to the machine registers
+/// and memory using the same calling convention as any other function.
+pub type HostFn = Rc<dyn Fn(&mut Machine) -> Result<(), HostCallError>>;
+
+/// The host functions available to the `hcall` instruction, keyed by sub-opcode
+#[derive(Default, Clone)]
+pub struct HostCalls {
+    fns: HashMap<u64, HostFn>,
+}
+
+impl fmt::Debug for HostCalls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut sub_opcodes: Vec<_> = self.fns.keys().collect();
+        sub_opcodes.sort_unstable();
+        f.debug_set().entries(sub_opcodes).finish()
+    }
+}
+
+impl PartialEq for HostCalls {
+    fn eq(&self, other: &Self) -> bool {
+        self.fns.len() == other.fns.len() && self.fns.iter().all(|(sub_opcode, f)| {
+            other.fns.get(sub_opcode).map(|other_f| Rc::ptr_eq(f, other_f)).unwrap_or(false)
+        })
+    }
+}
+
+impl HostCalls {
+    /// Registers a host function for the given sub-opcode, replacing any function that was
+    /// previously registered for it
+    pub fn register<F>(&mut self, sub_opcode: u64, f: F)
+        where F: Fn(&mut Machine) -> Result<(), HostCallError> + 'static,
+    {
+        self.fns.insert(sub_opcode, Rc::new(f));
+    }
+
+    /// Returns true if no host functions have been registered
+    pub fn is_empty(&self) -> bool {
+        self.fns.is_empty()
+    }
+
+    /// Returns the host function registered for the given sub-opcode, if any
+    pub fn get(&self, sub_opcode: u64) -> Option<HostFn> {
+        self.fns.get(&sub_opcode).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wolf_asm::asm::{InstrKind, layout::{Opcode, Layout, BitPattern}};
+
+    /// The first opcode reserved for host calls
+    const HOST_CALL_OPCODES_START: u16 = 0xf00;
+
+    fn opcode(value: u16) -> Opcode {
+        Opcode::read((value as u64) << (64 - Opcode::size_bits()), 0)
+    }
+
+    #[test]
+    fn host_call_opcodes_reserved() {
+        for value in HOST_CALL_OPCODES_START..1 << Opcode::size_bits() {
+            let (kind, _) = InstrKind::from_opcode(opcode(value));
+            assert_eq!(kind, InstrKind::Hcall, "opcode 0x{:x} is reserved for host calls, but it is used by `{}`", value, kind.name());
+        }
+
+        // Each instruction uses one opcode for each layout, so the instruction just before the
+        // reserved range must not have any opcodes in it
+        let (kind, offset) = InstrKind::from_opcode(opcode(HOST_CALL_OPCODES_START - 1));
+        assert!(Layout::from_binary(0, offset + 1).is_none(), "the opcodes of `{}` overlap the opcodes reserved for host calls", kind.name());
+    }
+}
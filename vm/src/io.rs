@@ -4,32 +4,71 @@ use std::io::{BufRead, Write};
 #[cfg(not(test))]
 use std::char;
 
-#[derive(Debug, Default, PartialEq)]
+/// Set in the value returned by `Stdio::status` if a byte of input can be read without waiting
+pub const INPUT_AVAILABLE: u64 = 1;
+/// Set in the value returned by `Stdio::status` if there is no more input
+pub const INPUT_EOF: u64 = 2;
+
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Stdio {
+    /// The input read from stdin that has not been returned yet
     line: Vec<u8>,
     /// The current index into the line
     current: usize,
+    /// If not `None`, input and output use these buffers instead of stdin and stdout
+    captured: Option<CapturedIo>,
+}
+
+/// The buffers used in place of stdin, stdout, and stderr by `Stdio::captured`
+#[derive(Debug, Default, Clone, PartialEq)]
+struct CapturedIo {
+    input: Vec<u8>,
+    /// The current index into the input
+    input_pos: usize,
+    output: Vec<u8>,
+    error_output: Vec<u8>,
 }
 
 impl Stdio {
-    /// Reads the next line of input from stdin
+    /// Creates an `Stdio` that reads from the given input instead of stdin and collects its output
+    /// instead of writing it to stdout and stderr
+    pub fn captured(input: Vec<u8>) -> Self {
+        Self {
+            captured: Some(CapturedIo {input, input_pos: 0, output: Vec::new(), error_output: Vec::new()}),
+            ..Self::default()
+        }
+    }
+
+    /// Returns the output collected so far if this was created with `captured`
+    pub fn captured_output(&self) -> Option<&[u8]> {
+        self.captured.as_ref().map(|captured| &*captured.output)
+    }
+
+    /// Returns the error output collected so far if this was created with `captured`
+    pub fn captured_error_output(&self) -> Option<&[u8]> {
+        self.captured.as_ref().map(|captured| &*captured.error_output)
+    }
+
+    /// Reads the next byte of input from stdin
     ///
     /// Returns Ok(None) if EOF has been reached
     #[cfg(not(test))]
     pub fn read_byte(&mut self) -> io::Result<Option<u8>> {
-        if self.current >= self.line.len() {
-            self.line.clear();
-            let stdin = io::stdin();
-            stdin.lock().read_until(b'\n', &mut self.line)?;
-            self.current = 0;
+        if let Some(captured) = &mut self.captured {
+            let byte = captured.input.get(captured.input_pos).copied();
+            if byte.is_some() {
+                captured.input_pos += 1;
+            }
+            return Ok(byte);
         }
 
-        Ok(self.line.get(self.current).copied().map(|byte| {
-            // Found a character, advance the current index
-            // This avoids `current` being incremented after EOF
-            self.current += 1;
-            byte
-        }))
+        if self.current >= self.line.len() && !self.fill()? {
+            return Ok(None);
+        }
+
+        let byte = self.line[self.current];
+        self.current += 1;
+        Ok(Some(byte))
     }
 
     #[cfg(test)]
@@ -37,13 +76,93 @@ impl Stdio {
         Ok(None)
     }
 
+    /// Returns whether input can be read without waiting (`INPUT_AVAILABLE`) and whether there is
+    /// no more input (`INPUT_EOF`), or 0 if neither is known yet because no input has arrived
+    ///
+    /// Unlike `read_byte`, this never waits for input, except on platforms where stdin cannot be
+    /// polled.
+    #[cfg(not(test))]
+    pub fn status(&mut self) -> io::Result<u64> {
+        if let Some(captured) = &self.captured {
+            return Ok(if captured.input_pos < captured.input.len() { INPUT_AVAILABLE } else { INPUT_EOF });
+        }
+
+        if self.current < self.line.len() {
+            return Ok(INPUT_AVAILABLE);
+        }
+        if !stdin_ready()? {
+            return Ok(0);
+        }
+        Ok(if self.fill()? { INPUT_AVAILABLE } else { INPUT_EOF })
+    }
+
+    #[cfg(test)]
+    pub fn status(&mut self) -> io::Result<u64> {
+        Ok(INPUT_EOF)
+    }
+
+    /// Replaces the line with whatever input stdin has (waiting for some if it has none), returning
+    /// false if EOF has been reached
+    ///
+    /// All of the input is taken out of the buffer in stdin so that `stdin_ready` can tell whether
+    /// there is more.
+    #[cfg(not(test))]
+    fn fill(&mut self) -> io::Result<bool> {
+        let stdin = io::stdin();
+        let mut stdin = stdin.lock();
+        let input = loop {
+            match stdin.fill_buf() {
+                Ok(input) => break input,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        };
+
+        self.line.clear();
+        self.line.extend_from_slice(input);
+        self.current = 0;
+        let len = input.len();
+        stdin.consume(len);
+
+        Ok(len > 0)
+    }
+
+    /// Reads bytes from stdin into the given buffer until a newline has been read, the buffer is
+    /// full, or EOF is reached
+    ///
+    /// The newline is included in the buffer. Any part of a line that does not fit in the buffer
+    /// is left to be read next. Returns the number of bytes read, which is only 0 at EOF (or if the
+    /// buffer is empty).
+    pub fn read_line(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut len = 0;
+        while len < buf.len() {
+            let byte = match self.read_byte()? {
+                Some(byte) => byte,
+                None => break,
+            };
+            buf[len] = byte;
+            len += 1;
+            if byte == b'\n' {
+                break;
+            }
+        }
+
+        Ok(len)
+    }
+
     /// Writes the given 4 bytes to stdout, printing the unicode replacement
     /// character if the bytes are not a valid `char`
     #[cfg(not(test))]
-    pub fn write_bytes(&self, value: u32) -> io::Result<()> {
+    pub fn write_bytes(&mut self, value: u32) -> io::Result<()> {
         let ch = char::from_u32(value)
             .unwrap_or(char::REPLACEMENT_CHARACTER);
 
+        if let Some(captured) = &mut self.captured {
+            let mut buf = [0; 4];
+            captured.output.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+            return Ok(());
+        }
+
         let mut stdout = io::stdout();
         write!(stdout, "{}", ch)?;
         stdout.flush()?;
@@ -52,7 +171,90 @@ impl Stdio {
     }
 
     #[cfg(test)]
-    pub fn write_bytes(&self, _value: u32) -> io::Result<()> {
+    pub fn write_bytes(&mut self, _value: u32) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Writes the given text to stdout
+    #[cfg(not(test))]
+    pub fn write_str(&mut self, text: &str) -> io::Result<()> {
+        if let Some(captured) = &mut self.captured {
+            captured.output.extend_from_slice(text.as_bytes());
+            return Ok(());
+        }
+
+        let mut stdout = io::stdout();
+        stdout.write_all(text.as_bytes())?;
+        stdout.flush()?;
+
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub fn write_str(&mut self, _text: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Writes the given byte to stdout as-is, even if it is not valid UTF-8 on its own
+    #[cfg(not(test))]
+    pub fn write_raw_byte(&mut self, byte: u8) -> io::Result<()> {
+        if let Some(captured) = &mut self.captured {
+            captured.output.push(byte);
+            return Ok(());
+        }
+
+        let mut stdout = io::stdout();
+        stdout.write_all(&[byte])?;
+        stdout.flush()?;
+
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub fn write_raw_byte(&mut self, _byte: u8) -> io::Result<()> {
         Ok(())
     }
+
+    /// Writes the given 4 bytes to stderr, printing the unicode replacement
+    /// character if the bytes are not a valid `char`
+    #[cfg(not(test))]
+    pub fn write_err_bytes(&mut self, value: u32) -> io::Result<()> {
+        let ch = char::from_u32(value)
+            .unwrap_or(char::REPLACEMENT_CHARACTER);
+
+        if let Some(captured) = &mut self.captured {
+            let mut buf = [0; 4];
+            captured.error_output.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+            return Ok(());
+        }
+
+        // stderr is unbuffered
+        write!(io::stderr(), "{}", ch)?;
+
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub fn write_err_bytes(&mut self, _value: u32) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns true if reading from stdin would not wait, either because there is input or because
+/// EOF has been reached
+#[cfg(all(unix, not(test)))]
+fn stdin_ready() -> io::Result<bool> {
+    let mut fd = libc::pollfd {fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0};
+    // Safety: exactly one valid pollfd is passed, and a timeout of 0 returns immediately
+    let ready = unsafe { libc::poll(&mut fd, 1, 0) };
+    if ready < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ready > 0)
+}
+
+/// stdin cannot be polled on this platform, so checking it waits for input
+#[cfg(all(not(unix), not(test)))]
+fn stdin_ready() -> io::Result<bool> {
+    Ok(true)
 }
@@ -8,5 +8,18 @@ pub mod flags;
 pub mod operands;
 pub mod decode;
 pub mod io;
+pub mod device;
+pub mod file_io;
+#[cfg(feature = "net")]
+pub mod net;
 pub mod machine;
 pub mod execute;
+pub mod host_call;
+pub mod perf;
+pub mod timer;
+#[cfg(feature = "framebuffer")]
+pub mod framebuffer;
+pub mod stats;
+pub mod batch;
+pub mod engine;
+pub mod self_check;
@@ -1,15 +1,49 @@
+use std::io;
+use std::ops::Range;
+
 use thiserror::Error;
+use wolf_asm::{asm::{self, InstrKind}, executable::{Executable, Stmt, StaticData}, debug_info::DebugInfo};
 
 use crate::{
-    memory::{Memory, OutOfBounds},
+    write_memory::WriteMemory,
+    memory::{Memory, OutOfBounds, Protection},
     registers::Registers,
     flags::Flags,
     io::Stdio,
+    device::Devices,
+    host_call::HostCalls,
+    perf::PerfCounters,
+    stats::ExecStats,
     decode::{Instr, DecodeError, Push},
     operands::Source,
-    execute::{QUIT_ADDR, Execute, ExecuteError},
+    execute::{QUIT_ADDR, NULL_GUARD_SIZE, Execute, ExecuteError},
 };
 
+/// The amount of memory (in bytes) that a machine has by default (see `wolf-vm --memory-size`)
+pub const MACHINE_MEMORY: usize = 8 * 1024; // 8 kb
+
+/// The number of bytes after the end of the program reserved by the stack guard by default (see
+/// `wolf-vm --stack-guard`)
+pub const STACK_GUARD_SIZE: u64 = 256;
+
+/// Returns the context added to an error that occurred while executing the instruction at the
+/// given address, including its label and source line if debug info is available
+///
+/// `prev_pc` is the address of the instruction executed before it, if any. An address without a
+/// source line is usually reached by a bad jump, so the source line of the previous instruction
+/// is given instead to show where that happened.
+pub fn instr_context(debug_info: Option<&DebugInfo>, pc: u64, prev_pc: Option<u64>) -> String {
+    let describe = |addr| debug_info.and_then(|debug_info| debug_info.describe(addr));
+    if let Some(location) = describe(pc) {
+        return format!("Failed to execute instruction at `0x{:x}` ({})", pc, location);
+    }
+
+    match prev_pc.and_then(|prev_pc| describe(prev_pc).map(|location| (prev_pc, location))) {
+        Some((prev_pc, prev_location)) => format!("Failed to execute instruction at `0x{:x}` after executing the instruction at `0x{:x}` ({})", pc, prev_pc, prev_location),
+        None => format!("Failed to execute instruction at `0x{:x}`", pc),
+    }
+}
+
 /// Whether the program should continue running
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ProgramStatus {
@@ -25,7 +59,16 @@ pub enum ExecutionError {
     ExecuteError(#[from] ExecuteError),
 }
 
-#[derive(Debug, PartialEq)]
+/// A return address pushed onto the stack by a `call` instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReturnAddr {
+    /// The address that the matching `ret` should return to
+    pub addr: u64,
+    /// The address on the stack where the return address was stored
+    pub slot: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Machine {
     /// Holds the address of the next instruction to execute
     pub program_counter: u64,
@@ -37,24 +80,296 @@ pub struct Machine {
     pub flags: Flags,
     /// Access to input and output
     pub io: Stdio,
+    /// The devices that handle loads and stores to memory-mapped addresses instead of memory
+    pub devices: Devices,
+    /// If true, accessing or executing an address reserved by the null guard is an error
+    ///
+    /// This should only be enabled if nothing was loaded into the reserved region of memory.
+    pub null_guard: bool,
+    /// If true, loading or storing 2, 4, or 8 bytes at an address that is not a multiple of the
+    /// number of bytes is an error
+    pub strict_align: bool,
+    /// The host functions that can be called using the `hcall` instruction
+    pub host_calls: HostCalls,
+    /// The performance counters that programs can read to measure themselves
+    pub counters: PerfCounters,
+    /// Detailed statistics about the execution of the program (see `wolf-vm --stats`)
+    ///
+    /// These are only collected if this is set since collecting them slows down every instruction.
+    pub stats: Option<ExecStats>,
+    /// The exit code that the program quit with (see `Machine::exit`)
+    pub exit_code: u8,
+    /// The addresses between the end of the program and the stack that may not be written to
+    ///
+    /// Writing to these addresses means that the stack has grown so large that it is about to
+    /// overwrite the static data of the program. An empty range disables the stack guard.
+    pub stack_guard: Range<u64>,
+    /// The addresses of the code section of the program
+    ///
+    /// Executing an instruction at any other address is an error since it would decode data as if
+    /// it were an instruction.
+    pub code: Range<u64>,
+    /// The return address of every `call` that has not returned yet, used to check that `ret`
+    /// returns to the right address, or `None` if this is not being checked (see
+    /// `Machine::check_returns`)
+    pub call_stack: Option<Vec<ReturnAddr>>,
 }
 
 impl Machine {
+    /// Creates a machine with the given executable loaded at its base address, ready to start
+    /// executing it
+    ///
+    /// The machine has `memory_size` bytes of memory. The null guard is only enabled if
+    /// `null_guard` is true and nothing is loaded into the memory it reserves. The given
+    /// command-line arguments and environment variables are copied onto the stack (see
+    /// `push_args`). The stack guard reserves `STACK_GUARD_SIZE` bytes after the end of the
+    /// program (see `set_stack_guard_size`). The code section is read-only (see
+    /// `set_code_writable`).
+    pub fn load(exec: &Executable, memory_size: usize, null_guard: bool, io: Stdio, args: &[String], env: &[String]) -> Result<Self, OutOfBounds> {
+        let mut memory = Memory::new(memory_size);
+        let image_end = exec.write_into(&mut memory, exec.base_addr)?;
+        let code_len: u64 = exec.code_section.iter().map(|stmt| stmt.size_bytes()).sum();
+        let code = exec.base_addr..exec.base_addr + code_len;
+        memory.protect(code.clone(), Protection::ReadOnly);
+
+        let mut vm = Machine {
+            program_counter: exec.base_addr,
+            memory,
+            // Start with the stack pointer pointing just past the end of the stack
+            registers: Registers::new(memory_size),
+            flags: Flags::default(),
+            io,
+            devices: Devices::default(),
+            null_guard: null_guard && exec.base_addr >= NULL_GUARD_SIZE,
+            strict_align: false,
+            // No host functions are provided by default
+            host_calls: HostCalls::default(),
+            counters: PerfCounters::default(),
+            stats: None,
+            exit_code: 0,
+            stack_guard: image_end..image_end,
+            code,
+            call_stack: None,
+        };
+        // Pushing can only fail if there is no room for the stack
+        match vm.push_args(args, env).and_then(|()| vm.push_quit_addr()) {
+            Ok(()) => {
+                vm.set_stack_guard_size(STACK_GUARD_SIZE);
+                Ok(vm)
+            },
+            Err(ExecutionError::OutOfBounds(err)) => Err(err),
+            Err(err) => unreachable!("bug: should always be able to push quit address: {}", err),
+        }
+    }
+
+    /// Reserves `size` bytes after the end of the program for the stack guard, or disables the
+    /// stack guard if `size` is zero
+    ///
+    /// The stack guard never reserves any of the stack that is already in use, so it may end up
+    /// smaller than `size` if there is not enough memory.
+    pub fn set_stack_guard_size(&mut self, size: u64) {
+        let start = self.stack_guard.start;
+        let end = start.saturating_add(size).min(self.registers.load_sp()).max(start);
+        self.stack_guard = start..end;
+    }
+
+    /// Allows or prevents the program from storing to its own code section
+    ///
+    /// Only programs that intentionally modify their own code should need this. Otherwise, storing
+    /// to the code section is almost always a bug.
+    pub fn set_code_writable(&mut self, writable: bool) {
+        let protection = if writable { Protection::ReadWrite } else { Protection::ReadOnly };
+        self.memory.protect(self.code.clone(), protection);
+    }
+
+    /// Makes loading from memory that has never been written to an error (see
+    /// `wolf-vm --check-uninit`)
+    ///
+    /// Everything loaded from the given executable counts as written to, except for data declared
+    /// with `.uninit`. Everything that was pushed onto the stack before the program started also
+    /// counts as written to.
+    pub fn check_uninit_reads(&mut self, exec: &Executable) {
+        self.memory.track_writes();
+
+        let sections = exec.code_section.iter()
+            .chain(&exec.rodata_section)
+            .chain(&exec.static_section)
+            .chain(&exec.bss_section);
+        let mut addr = self.code.start;
+        for stmt in sections {
+            let size = stmt.size_bytes();
+            if !matches!(stmt, Stmt::StaticData(StaticData::StaticUninit(_))) {
+                self.memory.mark_written(addr..addr + size);
+            }
+            addr += size;
+        }
+
+        // Everything from the top of the stack to the end of memory
+        let sp = self.registers.load_sp();
+        self.memory.mark_written(sp..u64::MAX);
+    }
+
+    /// Makes `ret` an error unless it pops the return address pushed by the matching `call` from
+    /// the same place on the stack (see `wolf-vm --check-returns`)
+    ///
+    /// The program itself returns to the quit address that was pushed when it was loaded.
+    pub fn check_returns(&mut self) {
+        let quit = ReturnAddr {addr: QUIT_ADDR, slot: self.registers.load_sp()};
+        self.call_stack = Some(vec![quit]);
+    }
+
+    /// Writes a table of the value of every register, the flags, and the program counter
+    pub fn write_state<W: io::Write>(&self, mut out: W) -> io::Result<()> {
+        /// The number of registers written on each line
+        const COLUMNS: usize = 4;
+
+        let registers: Vec<_> = self.registers.iter().collect();
+        for row in registers.chunks(COLUMNS) {
+            let cells: Vec<_> = row.iter()
+                .map(|(name, value)| format!("{:<3} = 0x{:016x}", name, value))
+                .collect();
+            writeln!(out, "{}", cells.join("   "))?;
+        }
+
+        let Flags {carry, zero, sign, overflow} = self.flags;
+        writeln!(out, "CF = {}   ZF = {}   SF = {}   OF = {}", carry as u8, zero as u8, sign as u8, overflow as u8)?;
+        writeln!(out, "PC = 0x{:x}", self.program_counter)
+    }
+
     /// Decode and run the instruction at the program counter
     pub fn step(&mut self) -> Result<ProgramStatus, ExecutionError> {
+        if self.null_guard && self.program_counter < NULL_GUARD_SIZE {
+            return Err(ExecuteError::NullAddressExecute(self.program_counter).into());
+        }
+
         let instr = self.memory.read_u64(self.program_counter)?;
+        if !self.code.contains(&self.program_counter) {
+            return Err(ExecuteError::DataExecute(self.program_counter).into());
+        }
         let instr = Instr::decode(instr)?;
         self.program_counter += instr.size_bytes();
 
+        let kind = instr.kind();
+        let cycles = PerfCounters::instr_cycles(&instr);
         instr.execute(self)?;
+        self.record_instr(kind, cycles);
 
+        Ok(self.status())
+    }
+
+    /// Records that an instruction of the given kind taking the given number of cycles has
+    /// finished executing
+    pub fn record_instr(&mut self, kind: InstrKind, cycles: u64) {
+        self.counters.record_instr(cycles);
+        if let Some(stats) = &mut self.stats {
+            stats.record_instr(kind, self.registers.load_sp());
+        }
+    }
+
+    /// Quits the program with the given exit code
+    ///
+    /// Only the lowest byte of the exit code is kept, since that is all that most operating systems
+    /// allow a process to exit with.
+    pub fn exit(&mut self, exit_code: u64) {
+        self.exit_code = exit_code as u8;
+        self.program_counter = QUIT_ADDR;
+    }
+
+    /// Returns whether the program should continue running after the instruction that was just
+    /// executed
+    pub fn status(&self) -> ProgramStatus {
         if self.program_counter == QUIT_ADDR {
-            Ok(ProgramStatus::Quit)
+            ProgramStatus::Quit
         } else {
-            Ok(ProgramStatus::Continue)
+            ProgramStatus::Continue
         }
     }
 
+    /// Copies the given command-line arguments and environment variables to the top of the stack
+    ///
+    /// Each argument and environment variable (in the form `NAME=value`) is stored as a string of
+    /// UTF-8 bytes followed by a zero byte. Below those is an array of the addresses of each
+    /// environment variable and then an array of the addresses of each argument. Each array ends
+    /// with a zero address. The number of arguments is stored in `$a0`, the address of the array
+    /// of arguments is stored in `$a1`, and the address of the array of environment variables is
+    /// stored in `$a2`. The frame pointer is moved below everything that was copied so the stack
+    /// starts after it.
+    ///
+    /// Nothing is copied if there are no arguments or environment variables, so `$a0`, `$a1`, and
+    /// `$a2` are all left as zero.
+    pub fn push_args(&mut self, args: &[String], env: &[String]) -> Result<(), ExecutionError> {
+        if args.is_empty() && env.is_empty() {
+            return Ok(());
+        }
+
+        let mut sp: u64 = self.registers.load_sp();
+        let mut addrs = Vec::with_capacity(args.len() + env.len());
+        for string in args.iter().chain(env) {
+            // Running out of stack space wraps around to an address that is out of bounds
+            sp = sp.wrapping_sub(string.len() as u64 + 1);
+            for (addr, &byte) in (sp..).zip(string.as_bytes().iter().chain(&[0])) {
+                self.memory.set(addr, byte)?;
+            }
+            addrs.push(sp);
+        }
+        let (arg_addrs, env_addrs) = addrs.split_at(args.len());
+
+        // Keep the rest of the stack aligned to 8 bytes
+        sp -= sp % 8;
+        self.registers.store_sp(sp);
+        let envp = self.push_addr_array(env_addrs)?;
+        let argv = self.push_addr_array(arg_addrs)?;
+
+        self.registers.store(asm::RegisterKind::Numbered(1).into(), args.len() as u64);
+        self.registers.store(asm::RegisterKind::Numbered(2).into(), argv);
+        self.registers.store(asm::RegisterKind::Numbered(3).into(), envp);
+        self.registers.store_fp(argv);
+
+        Ok(())
+    }
+
+    /// Pushes an array containing the given addresses followed by a zero address, returning the
+    /// address of the start of the array
+    fn push_addr_array(&mut self, addrs: &[u64]) -> Result<u64, ExecutionError> {
+        self.push_immediate(0)?;
+        for &addr in addrs.iter().rev() {
+            self.push_immediate(addr.into())?;
+        }
+
+        Ok(self.registers.load_sp())
+    }
+
+    /// Returns the return address of each function that is currently running, starting with the
+    /// most recent call
+    ///
+    /// This follows the frame pointers saved by functions that start with `push $fp` and
+    /// `mov $fp, $sp`, so functions that do not save the frame pointer are skipped. At most
+    /// `max_frames` addresses are returned.
+    pub fn backtrace(&self, max_frames: usize) -> Vec<u64> {
+        let mut return_addrs = Vec::new();
+        let mut fp: u64 = self.registers.load_fp();
+        // The frame pointer may hold any value, so stop if reading the frame would overflow
+        while return_addrs.len() < max_frames && fp.checked_add(16).is_some() {
+            let (saved_fp, return_addr) = match (self.memory.read_u64(fp), self.memory.read_u64(fp + 8)) {
+                (Ok(saved_fp), Ok(return_addr)) => (saved_fp, return_addr),
+                _ => break,
+            };
+            if return_addr == QUIT_ADDR {
+                break;
+            }
+            return_addrs.push(return_addr);
+
+            // Frames are always further up the stack, so anything else means the frame pointer
+            // was not saved as expected
+            if saved_fp <= fp {
+                break;
+            }
+            fp = saved_fp;
+        }
+
+        return_addrs
+    }
+
     pub fn push_quit_addr(&mut self) -> Result<(), ExecutionError> {
         self.push_immediate(QUIT_ADDR as i128)
     }
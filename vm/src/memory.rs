@@ -9,9 +9,26 @@ pub struct OutOfBounds {
     capacity: usize,
 }
 
+/// The ways that a program may access a region of memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Protection {
+    /// The program may load from and store to the region
+    ReadWrite,
+    /// The program may only load from the region
+    ReadOnly,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Memory {
     bytes: Vec<u8>,
+    /// The protection of each region of memory that is not `Protection::ReadWrite`
+    ///
+    /// Protection is only checked when the program stores to memory (see `Memory::protection`),
+    /// so the VM itself can still write anywhere, e.g. to load an executable.
+    regions: Vec<(Range<u64>, Protection)>,
+    /// Whether each byte of memory has been written to, or `None` if this is not being tracked
+    /// (see `Memory::track_writes`)
+    written: Option<Vec<bool>>,
 }
 
 impl Memory {
@@ -21,7 +38,62 @@ impl Memory {
         // Fill with zeros
         bytes.resize_with(size_bytes, Default::default);
 
-        Self {bytes}
+        Self {bytes, regions: Vec::new(), written: None}
+    }
+
+    /// Sets the protection of every address in the given range, replacing any protection that was
+    /// previously set for those addresses
+    pub fn protect(&mut self, addr_range: Range<u64>, protection: Protection) {
+        let mut regions = Vec::with_capacity(self.regions.len() + 2);
+        for (range, prev) in self.regions.drain(..) {
+            // Keep the parts of the previous region that are outside of the new one
+            if range.start < addr_range.start {
+                regions.push((range.start..range.end.min(addr_range.start), prev));
+            }
+            if range.end > addr_range.end {
+                regions.push((range.start.max(addr_range.end)..range.end, prev));
+            }
+        }
+        if protection != Protection::ReadWrite && !addr_range.is_empty() {
+            regions.push((addr_range, protection));
+        }
+        regions.retain(|(range, _)| !range.is_empty());
+
+        self.regions = regions;
+    }
+
+    /// Returns a protected region that overlaps the given range and its protection, or `None` if
+    /// every address in the range can be both loaded from and stored to
+    pub fn protection(&self, addr_range: Range<u64>) -> Option<(Range<u64>, Protection)> {
+        self.regions.iter()
+            .find(|(range, _)| range.start < addr_range.end && addr_range.start < range.end)
+            .cloned()
+    }
+
+    /// Starts keeping track of which bytes have been written to, treating every byte as never
+    /// written until it is written to or marked with `mark_written`
+    ///
+    /// This slows down every write, so it should only be enabled when it is needed.
+    pub fn track_writes(&mut self) {
+        self.written = Some(vec![false; self.bytes.len()]);
+    }
+
+    /// Marks the bytes in the given address range as written to, if writes are being tracked
+    pub fn mark_written(&mut self, addr_range: Range<u64>) {
+        if let Some(written) = &mut self.written {
+            let end = (addr_range.end as usize).min(written.len());
+            let start = (addr_range.start as usize).min(end);
+            written[start..end].iter_mut().for_each(|byte| *byte = true);
+        }
+    }
+
+    /// Returns the address of the first byte in the given range that has never been written to,
+    /// or `None` if every byte has been written to or writes are not being tracked
+    pub fn first_unwritten(&self, addr_range: Range<u64>) -> Option<u64> {
+        let written = self.written.as_ref()?;
+        let end = (addr_range.end as usize).min(written.len());
+        let start = (addr_range.start as usize).min(end);
+        written[start..end].iter().position(|&byte| !byte).map(|offset| (start + offset) as u64)
     }
 
     /// Retrieves a single byte at the given memory address
@@ -39,10 +111,24 @@ impl Memory {
 
         let cell = self.bytes.get_mut(addr).ok_or_else(|| OutOfBounds {addr, capacity})?;
         *cell = value;
+        if let Some(written) = &mut self.written {
+            written[addr] = true;
+        }
 
         Ok(())
     }
 
+    /// Returns the address of the first byte that is different in the given memory, or None if
+    /// both have the same contents
+    pub fn first_difference(&self, other: &Memory) -> Option<u64> {
+        let differing = self.bytes.iter().zip(&other.bytes).position(|(byte, other_byte)| byte != other_byte);
+        match differing {
+            Some(addr) => Some(addr as u64),
+            None if self.bytes.len() != other.bytes.len() => Some(self.bytes.len().min(other.bytes.len()) as u64),
+            None => None,
+        }
+    }
+
     /// Retrieves a slice of bytes in the given address range
     pub fn slice(&self, addr_range: Range<u64>) -> Result<&[u8], OutOfBounds> {
         let addr_range = addr_range.start as usize .. addr_range.end as usize;
@@ -62,11 +148,14 @@ impl Memory {
     }
 
     /// Retrieves a mutable slice of bytes in the given address range
+    ///
+    /// The bytes are assumed to be written to (see `track_writes`).
     pub fn slice_mut(&mut self, addr_range: Range<u64>) -> Result<&mut [u8], OutOfBounds> {
         let addr_range = addr_range.start as usize .. addr_range.end as usize;
-        let capacity = self.bytes.len();
+        let Self {bytes, regions: _, written} = self;
+        let capacity = bytes.len();
 
-        self.bytes.get_mut(addr_range.clone()).ok_or_else(|| {
+        let slice = bytes.get_mut(addr_range.clone()).ok_or_else(|| {
             if addr_range.start >= capacity {
                 OutOfBounds {addr: addr_range.start, capacity}
 
@@ -76,7 +165,12 @@ impl Memory {
             } else {
                 unreachable!("bug: one of the above conditions should have been met")
             }
-        })
+        })?;
+        if let Some(written) = written {
+            written[addr_range].iter_mut().for_each(|byte| *byte = true);
+        }
+
+        Ok(slice)
     }
 
     /// Writes the given value at the given address
@@ -0,0 +1,157 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::ops::Range;
+use std::collections::HashMap;
+
+use crate::machine::Machine;
+use crate::device::{Device, resize_le};
+use crate::memory::Protection;
+use crate::execute::ExecuteError;
+
+/// Storing a command number at this address runs that command and loading from it returns the
+/// result of the last command
+pub const NET_CMD_ADDR: u64 = 0xffff_0600;
+/// The socket used by the next command
+pub const NET_SOCKET_ADDR: u64 = 0xffff_0608;
+/// The address of the buffer (or socket address) used by the next command
+pub const NET_BUF_ADDR: u64 = 0xffff_0610;
+/// The length of the buffer (or socket address) used by the next command
+pub const NET_LEN_ADDR: u64 = 0xffff_0618;
+/// The addresses handled by the network device
+pub const NET_IO_ADDRS: Range<u64> = NET_CMD_ADDR..NET_LEN_ADDR + 8;
+
+/// Connects to the TCP socket address in the buffer (e.g. `127.0.0.1:8080` or `example.com:80`)
+pub const NET_CONNECT: u64 = 1;
+/// Listens for TCP connections on the socket address in the buffer
+pub const NET_LISTEN: u64 = 2;
+/// Waits for a connection to the listening socket and accepts it
+pub const NET_ACCEPT: u64 = 3;
+/// Reads up to the length of the buffer from the socket into the buffer
+pub const NET_READ: u64 = 4;
+/// Writes the buffer to the socket
+pub const NET_WRITE: u64 = 5;
+/// Closes the socket
+pub const NET_CLOSE: u64 = 6;
+
+/// The first socket returned when a socket is opened
+const FIRST_SOCKET: u64 = 1;
+
+#[derive(Debug)]
+enum Socket {
+    Stream(TcpStream),
+    Listener(TcpListener),
+}
+
+/// Gives programs access to TCP sockets on the host
+///
+/// A command is run by storing its number at `NET_CMD_ADDR` after storing its operands at
+/// `NET_SOCKET_ADDR`, `NET_BUF_ADDR`, and `NET_LEN_ADDR`. Loading from `NET_CMD_ADDR` then returns
+/// the result of the command: the socket of a new connection or listener, the number of bytes read
+/// or written, or 0 after a close. The result is -1 if the command failed for any reason.
+///
+/// Every command waits until it is done, so a program reading from a socket stops until data
+/// arrives.
+#[derive(Debug)]
+pub struct NetIo {
+    sockets: HashMap<u64, Socket>,
+    next_socket: u64,
+    socket: u64,
+    buf: u64,
+    len: u64,
+    result: i64,
+}
+
+impl Default for NetIo {
+    fn default() -> Self {
+        Self {
+            sockets: HashMap::new(),
+            next_socket: FIRST_SOCKET,
+            socket: 0,
+            buf: 0,
+            len: 0,
+            result: 0,
+        }
+    }
+}
+
+impl NetIo {
+    /// Adds the given socket, returning the number used to refer to it
+    fn insert(&mut self, socket: Socket) -> u64 {
+        let id = self.next_socket;
+        self.next_socket += 1;
+        self.sockets.insert(id, socket);
+        id
+    }
+
+    /// Runs the given command, returning its result or None if it failed
+    fn run(&mut self, vm: &mut Machine, command: u64) -> Result<Option<u64>, ExecuteError> {
+        let buf = self.buf..self.buf.saturating_add(self.len);
+        let result = match command {
+            NET_CONNECT | NET_LISTEN => {
+                let addr = match std::str::from_utf8(vm.memory.slice(buf)?) {
+                    Ok(addr) => addr,
+                    Err(_) => return Ok(None),
+                };
+                let socket = match command {
+                    NET_CONNECT => TcpStream::connect(addr).map(Socket::Stream),
+                    _ => TcpListener::bind(addr).map(Socket::Listener),
+                };
+                socket.ok().map(|socket| self.insert(socket))
+            },
+
+            NET_ACCEPT => match self.sockets.get(&self.socket) {
+                Some(Socket::Listener(listener)) => match listener.accept() {
+                    Ok((stream, _)) => Some(self.insert(Socket::Stream(stream))),
+                    Err(_) => None,
+                },
+                _ => None,
+            },
+
+            NET_READ => match self.sockets.get_mut(&self.socket) {
+                // Reading into memory is a store, so it cannot bypass the protection of memory
+                Some(Socket::Stream(_)) if matches!(vm.memory.protection(buf.clone()), Some((_, Protection::ReadOnly))) => None,
+                Some(Socket::Stream(stream)) => stream.read(vm.memory.slice_mut(buf)?).ok().map(|len| len as u64),
+                _ => None,
+            },
+
+            NET_WRITE => match self.sockets.get_mut(&self.socket) {
+                Some(Socket::Stream(stream)) => stream.write(vm.memory.slice(buf)?).ok().map(|len| len as u64),
+                _ => None,
+            },
+
+            NET_CLOSE => self.sockets.remove(&self.socket).map(|_| 0),
+
+            _ => return Err(ExecuteError::UnknownDeviceCommand {addr: NET_CMD_ADDR, command}),
+        };
+
+        Ok(result)
+    }
+}
+
+impl Device for NetIo {
+    fn read(&mut self, _vm: &mut Machine, addr: u64, _size: u64) -> Result<u64, ExecuteError> {
+        Ok(match addr {
+            NET_SOCKET_ADDR => self.socket,
+            NET_BUF_ADDR => self.buf,
+            NET_LEN_ADDR => self.len,
+            NET_CMD_ADDR => self.result as u64,
+            _ => return Err(ExecuteError::UnsupportedDeviceAccess {addr, access: "read from"}),
+        })
+    }
+
+    fn write(&mut self, vm: &mut Machine, addr: u64, bytes: &[u8]) -> Result<(), ExecuteError> {
+        let value = u64::from_le_bytes(resize_le(bytes));
+
+        match addr {
+            NET_SOCKET_ADDR => self.socket = value,
+            NET_BUF_ADDR => self.buf = value,
+            NET_LEN_ADDR => self.len = value,
+            NET_CMD_ADDR => {
+                self.result = self.run(vm, value)?.map(|result| result as i64).unwrap_or(-1);
+            },
+            _ => return Err(ExecuteError::UnsupportedDeviceAccess {addr, access: "written to"}),
+        }
+
+        Ok(())
+    }
+}
@@ -150,9 +150,9 @@ impl Operand for Location {
     fn into_value<R: Reinterpret<u64>>(self, vm: &Machine) -> R {
         match self {
             Location::Register(reg, offset) => {
-                let value = vm.registers.load(reg);
+                let value: u64 = vm.registers.load(reg);
                 R::reinterpret(match offset {
-                    Some(offset) => value + u64::reinterpret(offset),
+                    Some(offset) => value.wrapping_add(u64::reinterpret(offset)),
                     None => value,
                 })
             },
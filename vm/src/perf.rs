@@ -0,0 +1,80 @@
+use serde::Serialize;
+
+use crate::decode::Instr;
+
+/// The address of the number of instructions executed so far
+pub const INSTRUCTIONS_ADDR: u64 = 0xffff_0100;
+/// The address of the number of cycles taken so far
+pub const CYCLES_ADDR: u64 = 0xffff_0108;
+/// The address of the number of memory loads performed so far
+pub const LOADS_ADDR: u64 = 0xffff_0110;
+/// The address of the number of memory stores performed so far
+pub const STORES_ADDR: u64 = 0xffff_0118;
+
+/// The additional number of cycles taken by every memory access
+const MEMORY_ACCESS_CYCLES: u64 = 2;
+
+/// Counters that programs can read (but not write) through memory-mapped addresses in order to
+/// measure their own performance
+///
+/// Each counter only includes instructions that completed before the instruction reading it.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct PerfCounters {
+    /// The number of instructions executed
+    pub instructions: u64,
+    /// The number of cycles taken, according to a simple cost model (see `instr_cycles`)
+    pub cycles: u64,
+    /// The number of memory loads, including the stack accesses performed by `pop` and `ret`
+    pub loads: u64,
+    /// The number of memory stores, including the stack accesses performed by `push` and `call`
+    pub stores: u64,
+}
+
+impl PerfCounters {
+    /// Returns true if the given address is the address of a counter
+    pub fn is_counter_addr(addr: u64) -> bool {
+        matches!(addr, INSTRUCTIONS_ADDR | CYCLES_ADDR | LOADS_ADDR | STORES_ADDR)
+    }
+
+    /// Returns the value of the counter at the given address or None if there is no counter there
+    pub fn read(&self, addr: u64) -> Option<u64> {
+        match addr {
+            INSTRUCTIONS_ADDR => Some(self.instructions),
+            CYCLES_ADDR => Some(self.cycles),
+            LOADS_ADDR => Some(self.loads),
+            STORES_ADDR => Some(self.stores),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of cycles taken by the given instruction, not including the cycles taken
+    /// by any memory accesses it performs
+    ///
+    /// Most instructions take a single cycle. Multiplication takes 4 cycles and division takes 20.
+    pub fn instr_cycles(instr: &Instr) -> u64 {
+        use Instr::*;
+        match instr {
+            Mul(_) | Mull(_) | Mulu(_) | Mullu(_) => 4,
+            Div(_) | Divr(_) | Divu(_) | Divru(_) | Rem(_) | Remu(_) => 20,
+            _ => 1,
+        }
+    }
+
+    /// Records that an instruction taking the given number of cycles has finished executing
+    pub fn record_instr(&mut self, cycles: u64) {
+        self.instructions += 1;
+        self.cycles += cycles;
+    }
+
+    /// Records that a value was loaded from memory
+    pub fn record_load(&mut self) {
+        self.loads += 1;
+        self.cycles += MEMORY_ACCESS_CYCLES;
+    }
+
+    /// Records that a value was stored in memory
+    pub fn record_store(&mut self) {
+        self.stores += 1;
+        self.cycles += MEMORY_ACCESS_CYCLES;
+    }
+}
@@ -34,7 +34,7 @@ impl PartialEq for Registers {
         if registers.len() != other_registers.len() {
             unreachable!("bug: should always have the same number of registers");
         } else {
-            registers.iter().zip(&other_registers[..]).all(|(x, y)| x.eq(y))
+            registers[..] == other_registers[..]
         }
     }
 }
@@ -53,6 +53,23 @@ impl Registers {
         regs
     }
 
+    /// Returns the name (e.g. `$8` or `$sp`) and value of every register in order
+    pub fn iter(&self) -> impl Iterator<Item=(String, u64)> + '_ {
+        let sp = Reg::from(asm::RegisterKind::StackPointer).into_value() as usize;
+        let fp = Reg::from(asm::RegisterKind::FramePointer).into_value() as usize;
+
+        self.registers.iter().enumerate().map(move |(index, &value)| {
+            let name = if index == sp {
+                "$sp".to_string()
+            } else if index == fp {
+                "$fp".to_string()
+            } else {
+                format!("${}", index)
+            };
+            (name, value)
+        })
+    }
+
     /// Loads the given register value
     pub fn load<R: Reinterpret<u64>>(&self, reg: Reg) -> R {
         let index = reg.into_value() as usize;
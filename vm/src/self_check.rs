@@ -0,0 +1,127 @@
+//! Differential self-check mode (`wolf-vm --self-check`), which runs a second machine with a
+//! different execution engine in lockstep and reports the first instruction where they disagree
+
+use thiserror::Error;
+use wolf_asm::executable::Executable;
+
+use crate::{
+    engine::{Engine, Executor},
+    machine::{Machine, ProgramStatus, ExecutionError},
+};
+
+#[derive(Debug, Error)]
+pub enum SelfCheckError {
+    #[error(transparent)]
+    ExecutionError(#[from] ExecutionError),
+    #[error("Self-check failed: the `{engine}` and `{shadow_engine}` engines disagree after executing the instruction at `0x{pc:x}`: {difference}")]
+    Diverged {
+        engine: Engine,
+        shadow_engine: Engine,
+        pc: u64,
+        difference: String,
+    },
+}
+
+/// A copy of the machine that is run with a different engine and compared with the original
+/// machine after every instruction
+pub struct SelfCheck {
+    engine: Engine,
+    executor: Executor,
+    shadow: Machine,
+    shadow_engine: Engine,
+    shadow_executor: Executor,
+    /// The number of stores performed when the memory of both machines was last compared
+    compared_stores: u64,
+}
+
+impl SelfCheck {
+    /// Creates a self-check that runs the given machine with `engine` and a copy of it with the
+    /// other engine
+    ///
+    /// The machine should use captured input and output (see `Stdio::captured`) so that both
+    /// machines receive the same input.
+    pub fn new(engine: Engine, vm: &Machine, exec: &Executable) -> Self {
+        let shadow_engine = engine.other();
+        Self {
+            engine,
+            executor: Executor::new(engine, vm, exec),
+            shadow: vm.clone(),
+            shadow_engine,
+            shadow_executor: Executor::new(shadow_engine, vm, exec),
+            compared_stores: vm.counters.stores,
+        }
+    }
+
+    /// Runs the instruction at the program counter on both machines, returning an error if the
+    /// machines end up in different states
+    pub fn step(&mut self, vm: &mut Machine) -> Result<ProgramStatus, SelfCheckError> {
+        let pc = vm.program_counter;
+        let status = self.executor.step(vm);
+        let shadow_status = self.shadow_executor.step(&mut self.shadow);
+
+        let difference = match (&status, &shadow_status) {
+            (Ok(_), Ok(_)) => {
+                // Memory only changes when something is stored, unless a host function changes it
+                let compare_memory = vm.counters.stores != self.compared_stores || !vm.host_calls.is_empty();
+                self.compared_stores = vm.counters.stores;
+                difference(vm, &self.shadow, compare_memory)
+            },
+            (Err(err), Err(shadow_err)) => {
+                let (err, shadow_err) = (err.to_string(), shadow_err.to_string());
+                if err == shadow_err {
+                    None
+                } else {
+                    Some(format!("`{}` produced the error \"{}\" but `{}` produced the error \"{}\"", self.engine, err, self.shadow_engine, shadow_err))
+                }
+            },
+            (Err(err), Ok(_)) => Some(format!("`{}` produced the error \"{}\" but `{}` did not", self.engine, err, self.shadow_engine)),
+            (Ok(_), Err(err)) => Some(format!("`{}` produced the error \"{}\" but `{}` did not", self.shadow_engine, err, self.engine)),
+        };
+
+        match difference {
+            Some(difference) => Err(SelfCheckError::Diverged {
+                engine: self.engine,
+                shadow_engine: self.shadow_engine,
+                pc,
+                difference,
+            }),
+            None => Ok(status?),
+        }
+    }
+}
+
+/// Describes the first difference found between the two machines, or returns None if they are
+/// in the same state
+///
+/// Comparing memory is by far the slowest part of this, so it is skipped if `compare_memory` is
+/// false.
+fn difference(vm: &Machine, shadow: &Machine, compare_memory: bool) -> Option<String> {
+    if vm.program_counter != shadow.program_counter {
+        return Some(format!("program counter is `0x{:x}` vs `0x{:x}`", vm.program_counter, shadow.program_counter));
+    }
+    if vm.registers != shadow.registers {
+        return Some(format!("registers are {:?} vs {:?}", vm.registers, shadow.registers));
+    }
+    if vm.flags != shadow.flags {
+        return Some(format!("flags are {:?} vs {:?}", vm.flags, shadow.flags));
+    }
+    // Comparing the entire memory is much faster than searching for the first difference
+    if compare_memory && vm.memory != shadow.memory {
+        let addr = vm.memory.first_difference(&shadow.memory)
+            .expect("bug: memory should have a difference if it is not equal");
+        let byte = vm.memory.get(addr).ok();
+        let shadow_byte = shadow.memory.get(addr).ok();
+        return Some(format!("memory at `0x{:x}` is {:?} vs {:?}", addr, byte, shadow_byte));
+    }
+    if vm.counters != shadow.counters {
+        return Some(format!("performance counters are {:?} vs {:?}", vm.counters, shadow.counters));
+    }
+    if vm.io != shadow.io {
+        return Some("input or output differs".to_string());
+    }
+    if vm.exit_code != shadow.exit_code {
+        return Some(format!("exit code is {} vs {}", vm.exit_code, shadow.exit_code));
+    }
+
+    None
+}
@@ -0,0 +1,59 @@
+//! Execution statistics reported by `wolf-vm --stats`
+
+use std::io;
+use std::collections::HashMap;
+
+use wolf_asm::asm::InstrKind;
+
+use crate::perf::PerfCounters;
+
+/// Statistics collected while a program runs, used to find out where a program spends its time
+///
+/// The total number of instructions and memory accesses are already tracked by `PerfCounters`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecStats {
+    /// The number of times each kind of instruction was executed
+    pub instrs: HashMap<InstrKind, u64>,
+    /// The address just past the end of the stack
+    pub stack_end: u64,
+    /// The largest number of bytes that the stack has used
+    pub peak_stack_depth: u64,
+}
+
+impl ExecStats {
+    /// Creates statistics for a machine whose stack ends just before `stack_end`
+    pub fn new(stack_end: u64) -> Self {
+        Self {
+            instrs: HashMap::new(),
+            stack_end,
+            peak_stack_depth: 0,
+        }
+    }
+
+    /// Records that an instruction of the given kind has finished executing, leaving the stack
+    /// pointer at `sp`
+    pub fn record_instr(&mut self, kind: InstrKind, sp: u64) {
+        *self.instrs.entry(kind).or_default() += 1;
+        // A stack pointer past the end of the stack does not count as using any of it
+        let depth = self.stack_end.saturating_sub(sp);
+        self.peak_stack_depth = self.peak_stack_depth.max(depth);
+    }
+
+    /// Writes a summary of these statistics and the given counters
+    pub fn write_summary<W: io::Write>(&self, counters: &PerfCounters, mut out: W) -> io::Result<()> {
+        writeln!(out, "instructions executed: {}", counters.instructions)?;
+        writeln!(out, "memory reads: {}", counters.loads)?;
+        writeln!(out, "memory writes: {}", counters.stores)?;
+        writeln!(out, "peak stack depth: {} bytes", self.peak_stack_depth)?;
+
+        writeln!(out, "instructions executed by kind:")?;
+        // Most frequent first, with ties broken by name so the output is always the same
+        let mut instrs: Vec<_> = self.instrs.iter().collect();
+        instrs.sort_by_key(|&(kind, &count)| (std::cmp::Reverse(count), kind.name()));
+        for (kind, count) in instrs {
+            writeln!(out, "  {:<8} {}", kind.name(), count)?;
+        }
+
+        Ok(())
+    }
+}
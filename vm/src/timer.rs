@@ -0,0 +1,74 @@
+use crate::machine::Machine;
+use crate::device::{Device, resize_le};
+use crate::execute::ExecuteError;
+
+/// Storing N at this address starts the timer so that it expires after N more cycles (0 stops
+/// it). Loading from it returns the number of cycles left until it expires, or 0 if it is stopped.
+pub const TIMER_COUNT_ADDR: u64 = 0xffff_0400;
+/// Storing P at this address makes the timer start again with a count of P every time it expires
+/// (0 makes it stop once it expires)
+pub const TIMER_PERIOD_ADDR: u64 = 0xffff_0408;
+/// Loading from this address returns 1 if the timer has expired since the flag was last cleared
+/// and 0 otherwise. Storing any value to it clears the flag.
+pub const TIMER_FLAG_ADDR: u64 = 0xffff_0410;
+
+/// A timer that counts the cycles taken by the program (see `PerfCounters::cycles`) and sets a
+/// flag that the program can poll when a given number of them have passed
+///
+/// The timer only uses the cycle count, so a program always sees it expire at the same point.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Timer {
+    /// The cycle count at which the timer expires, or None if it is stopped
+    deadline: Option<u64>,
+    period: u64,
+    expired: bool,
+}
+
+impl Timer {
+    /// Updates the timer to the given cycle count, setting the flag if it has expired
+    fn update(&mut self, cycles: u64) {
+        let deadline = match self.deadline {
+            Some(deadline) if deadline <= cycles => deadline,
+            _ => return,
+        };
+
+        self.expired = true;
+        // A period of zero stops the timer. Otherwise, every period that has already passed is
+        // skipped since the flag cannot be set twice.
+        self.deadline = (cycles - deadline).checked_div(self.period).and_then(|passed| {
+            deadline.checked_add((passed + 1).saturating_mul(self.period))
+        });
+    }
+}
+
+impl Device for Timer {
+    fn read(&mut self, vm: &mut Machine, addr: u64, _size: u64) -> Result<u64, ExecuteError> {
+        let cycles = vm.counters.cycles;
+        self.update(cycles);
+
+        Ok(match addr {
+            TIMER_COUNT_ADDR => self.deadline.map(|deadline| deadline - cycles).unwrap_or(0),
+            TIMER_PERIOD_ADDR => self.period,
+            TIMER_FLAG_ADDR => self.expired as u64,
+            _ => return Err(ExecuteError::UnsupportedDeviceAccess {addr, access: "read from"}),
+        })
+    }
+
+    fn write(&mut self, vm: &mut Machine, addr: u64, bytes: &[u8]) -> Result<(), ExecuteError> {
+        let cycles = vm.counters.cycles;
+        self.update(cycles);
+
+        let value = u64::from_le_bytes(resize_le(bytes));
+        match addr {
+            TIMER_COUNT_ADDR => self.deadline = match value {
+                0 => None,
+                count => Some(cycles.saturating_add(count)),
+            },
+            TIMER_PERIOD_ADDR => self.period = value,
+            TIMER_FLAG_ADDR => self.expired = false,
+            _ => return Err(ExecuteError::UnsupportedDeviceAccess {addr, access: "written to"}),
+        }
+
+        Ok(())
+    }
+}
@@ -35,10 +35,12 @@ impl WriteMemory for u64 {
 
 impl WriteMemory for exec::Executable {
     fn write_into(&self, mem: &mut Memory, addr: u64) -> Result<u64, OutOfBounds> {
-        let exec::Executable {code_section, static_section} = self;
+        let exec::Executable {base_addr: _, code_section, rodata_section, static_section, bss_section, relocations: _, debug_info: _} = self;
 
         let addr = code_section.write_into(mem, addr)?;
-        static_section.write_into(mem, addr)
+        let addr = rodata_section.write_into(mem, addr)?;
+        let addr = static_section.write_into(mem, addr)?;
+        bss_section.write_into(mem, addr)
     }
 }
 
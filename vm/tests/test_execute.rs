@@ -5,7 +5,10 @@ use wolf_vm::{
     machine::{Machine, ExecutionError},
     flags::{Flags, CF::*, ZF::*, SF::*, OF::*},
     io::Stdio,
-    execute::Execute,
+    device::{Device, Devices, STDOUT_ADDR},
+    host_call::HostCalls,
+    perf::PerfCounters,
+    execute::{Execute, ExecuteError},
 };
 use wolf_asm::{
     asm::{self, layout::Reg},
@@ -64,6 +67,16 @@ macro_rules! execute {
             registers: Registers::new(TEST_MEMORY),
             flags: Flags::default(),
             io: Stdio::default(),
+            devices: Devices::default(),
+            null_guard: false,
+            strict_align: false,
+            host_calls: HostCalls::default(),
+            counters: PerfCounters::default(),
+            stats: None,
+            exit_code: 0,
+            stack_guard: 0..0,
+            code: 0..0,
+            call_stack: None,
         };
 
         $(
@@ -329,3 +342,112 @@ fn mullu_flags() -> Result<(), ExecutionError> {
 
     Ok(())
 }
+
+#[test]
+fn host_calls() -> Result<(), ExecutionError> {
+    let mut vm = Machine {
+        program_counter: 0,
+        memory: Memory::new(TEST_MEMORY),
+        registers: Registers::new(TEST_MEMORY),
+        flags: Flags::default(),
+        io: Stdio::default(),
+        devices: Devices::default(),
+        null_guard: false,
+        strict_align: false,
+        host_calls: HostCalls::default(),
+        counters: PerfCounters::default(),
+        stats: None,
+        exit_code: 0,
+        stack_guard: 0..0,
+        code: 0..0,
+        call_stack: None,
+    };
+
+    // Adds the first two argument registers and stores the result in the return value register
+    vm.host_calls.register(3, |vm| {
+        let a: u64 = vm.registers.load(r(1));
+        let b: u64 = vm.registers.load(r(2));
+        vm.registers.store(r(0), a + b);
+        Ok(())
+    });
+    vm.host_calls.register(4, |_| Err("unsupported".into()));
+
+    Mov {dest: r(1).into(), source: 17u64.into()}.execute(&mut vm)?;
+    Mov {dest: r(2).into(), source: 25u64.into()}.execute(&mut vm)?;
+    Mov {dest: r(3).into(), source: 3u64.into()}.execute(&mut vm)?;
+    Hcall {source: r(3).into()}.execute(&mut vm)?;
+    postconditions!(vm, reg r(0) => (u64) 42);
+
+    let instr = Hcall {source: 4u64.into()};
+    match instr.execute(&mut vm) {
+        Err(ExecuteError::HostCallFailed {sub_opcode: 4, ..}) => {},
+        res => panic!("expected the host call to fail, got {:?}", res),
+    }
+
+    let instr = Hcall {source: 5u64.into()};
+    match instr.execute(&mut vm) {
+        Err(ExecuteError::UnknownHostCall(5)) => {},
+        res => panic!("expected an unknown host call error, got {:?}", res),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn devices() -> Result<(), ExecutionError> {
+    /// A device that remembers the last value stored to it and loads it back incremented
+    struct Counter(u64);
+
+    impl Device for Counter {
+        fn read(&mut self, _vm: &mut Machine, _addr: u64, _size: u64) -> Result<u64, ExecuteError> {
+            self.0 += 1;
+            Ok(self.0)
+        }
+
+        fn write(&mut self, _vm: &mut Machine, _addr: u64, bytes: &[u8]) -> Result<(), ExecuteError> {
+            let mut value = [0; 8];
+            value[..bytes.len()].copy_from_slice(bytes);
+            self.0 = u64::from_le_bytes(value);
+            Ok(())
+        }
+    }
+
+    let mut vm = Machine {
+        program_counter: 0,
+        memory: Memory::new(TEST_MEMORY),
+        registers: Registers::new(TEST_MEMORY),
+        flags: Flags::default(),
+        io: Stdio::default(),
+        devices: Devices::default(),
+        null_guard: false,
+        strict_align: true,
+        host_calls: HostCalls::default(),
+        counters: PerfCounters::default(),
+        stats: None,
+        exit_code: 0,
+        stack_guard: 0..0,
+        code: 0..0,
+        call_stack: None,
+    };
+
+    // Devices can be mapped over memory and are accessed at any alignment
+    vm.devices.register(0x100..0x110, Counter(0));
+    Mov {dest: r(1).into(), source: 0x103u64.into()}.execute(&mut vm)?;
+    Store2 {loc: r(1).into(), source: 0x1234u64.into()}.execute(&mut vm)?;
+    Loadu8 {dest: r(0).into(), loc: r(1).into()}.execute(&mut vm)?;
+    postconditions!(vm, reg r(0) => (u64) 0x1235);
+    // Only the loaded bytes are kept
+    Loadu1 {dest: r(0).into(), loc: r(1).into()}.execute(&mut vm)?;
+    postconditions!(vm, reg r(0) => (u64) 0x36);
+    // The memory underneath the device is untouched
+    assert_eq!(vm.memory.read_u16(0x103)?, 0);
+
+    Mov {dest: r(2).into(), source: STDOUT_ADDR.into()}.execute(&mut vm)?;
+    let instr = Load8 {dest: r(0).into(), loc: r(2).into()};
+    match instr.execute(&mut vm) {
+        Err(ExecuteError::UnsupportedDeviceAccess {addr: STDOUT_ADDR, access: "read from"}) => {},
+        res => panic!("expected an unsupported device access error, got {:?}", res),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,392 @@
+//! Generates and checks the conformance test vectors in `tests/vectors`
+//!
+//! Each vector assembles a single instruction, decodes it, and executes it once on a known machine
+//! state. The vectors are generated from this implementation so that other implementations can
+//! check themselves against the same results. See `tests/vectors/README.md` for the format.
+
+use std::fs::{self, File};
+use std::env;
+use std::path::Path;
+use std::process::Command;
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Deserialize};
+use tempfile::NamedTempFile;
+use wolf_asm::{
+    asm::{InstrKind, layout::{Opcode, BitPattern, InstrLayout}},
+    executable::{Executable, Stmt},
+};
+use wolf_vm::{
+    memory::Memory,
+    write_memory::WriteMemory,
+    registers::Registers,
+    flags::{Flags, CF, ZF, SF, OF},
+    io::Stdio,
+    device::Devices,
+    host_call::HostCalls,
+    perf::PerfCounters,
+    machine::{Machine, MACHINE_MEMORY},
+};
+
+const VM_EXEC_PATH: &str = env!("CARGO_BIN_EXE_wolf-vm");
+const VECTORS_DIR: &str = "../tests/vectors";
+
+/// The address that every instruction is loaded at
+const PC: u64 = 0x1000;
+/// The address of the data used by loads and stores
+const DATA_ADDR: u64 = 0x1800;
+/// The bytes stored at `DATA_ADDR` before loads are executed
+const DATA: [u8; 8] = [0x80, 0x01, 0xff, 0x7f, 0x00, 0x10, 0x20, 0xfe];
+
+/// Values that exercise the edge cases of arithmetic and comparisons
+const PAIRS: &[(u64, u64)] = &[
+    (0, 0),
+    (1, 2),
+    (2, 1),
+    (12345, -7i64 as u64),
+    (0x7fff_ffff_ffff_ffff, 1),
+    (0x8000_0000_0000_0000, -1i64 as u64),
+    (u64::MAX, 1),
+    (u64::MAX, u64::MAX),
+    (0xdead_beef, 0),
+];
+
+/// A single conformance test vector
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Vector {
+    /// The instruction, as written in assembly language
+    asm: String,
+    /// The encoded instruction (64 bits, as hex)
+    word: String,
+    /// The opcode in the first 12 bits of the instruction
+    opcode: u16,
+    /// The name of the instruction that the opcode decodes to
+    name: String,
+    /// The layout of the instruction's arguments (the offset from the instruction's base opcode)
+    layout: u16,
+    /// The machine state before the instruction is executed
+    pre: State,
+    /// The machine state after the instruction is executed, or the error it produced
+    post: Outcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Outcome {
+    State(State),
+    Error {error: String},
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct State {
+    /// The program counter (as hex)
+    pc: String,
+    /// The value of every non-zero register (as hex), keyed by register number (e.g. `$63`)
+    registers: BTreeMap<String, String>,
+    flags: FlagsState,
+    /// Every run of consecutive 8-byte aligned words in memory that are not entirely zero
+    memory: Vec<MemoryRun>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct FlagsState {
+    carry: u8,
+    zero: u8,
+    sign: u8,
+    overflow: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct MemoryRun {
+    /// The address of the first byte (as hex)
+    addr: String,
+    /// The bytes in the run (as hex)
+    bytes: String,
+}
+
+/// The instruction and initial state used to generate a vector
+#[derive(Debug, Clone, Default)]
+struct Case {
+    asm: String,
+    registers: Vec<(u8, u64)>,
+    flags: Option<Flags>,
+    memory: Vec<(u64, Vec<u8>)>,
+}
+
+impl Case {
+    fn new(asm: impl Into<String>) -> Self {
+        Self {asm: asm.into(), ..Self::default()}
+    }
+
+    fn reg(mut self, reg: u8, value: u64) -> Self {
+        self.registers.push((reg, value));
+        self
+    }
+
+    fn flags(mut self, flags: Flags) -> Self {
+        self.flags = Some(flags);
+        self
+    }
+
+    fn memory(mut self, addr: u64, bytes: &[u8]) -> Self {
+        self.memory.push((addr, bytes.to_vec()));
+        self
+    }
+}
+
+#[test]
+fn vectors() {
+    // Pass the environment variable TESTVM=overwrite to regenerate the vectors
+    let overwrite_vectors = env::var("TESTVM")
+        .map(|val| val == "overwrite")
+        .unwrap_or(false);
+
+    let cases = cases();
+    let words = assemble(&cases);
+
+    let mut files: BTreeMap<String, Vec<Vector>> = BTreeMap::new();
+    for ((file, case), word) in cases.iter().zip(words) {
+        files.entry(file.clone()).or_default().push(vector(case, word));
+    }
+
+    let vectors_dir = Path::new(VECTORS_DIR);
+    for (file, vectors) in &files {
+        let path = vectors_dir.join(file).with_extension("json");
+
+        if overwrite_vectors {
+            let mut contents = serde_json::to_string_pretty(vectors)
+                .unwrap_or_else(|err| panic!("Failed to serialize vectors for '{}': {}", path.display(), err));
+            contents.push('\n');
+            fs::write(&path, contents)
+                .unwrap_or_else(|err| panic!("Failed to write '{}': {}", path.display(), err));
+            continue;
+        }
+
+        let expected_file = File::open(&path)
+            .unwrap_or_else(|err| panic!("Failed to open '{}': {}", path.display(), err));
+        let expected: Vec<Vector> = serde_json::from_reader(expected_file)
+            .unwrap_or_else(|err| panic!("Failed to read '{}': {}", path.display(), err));
+        assert_eq!(expected.len(), vectors.len(), "Wrong number of vectors in '{}'", path.display());
+        for (expected, actual) in expected.iter().zip(vectors) {
+            assert_eq!(expected, actual, "Vector for `{}` did not match '{}'", actual.asm, path.display());
+        }
+    }
+}
+
+/// Returns every case, along with the name of the file its vector will be stored in
+fn cases() -> Vec<(String, Case)> {
+    let mut cases = Vec::new();
+    let mut add = |file: &str, case: Case| cases.push((file.to_string(), case));
+
+    add("nop", Case::new("nop"));
+
+    for &op in &["add", "sub", "mul", "mulu", "div", "divu", "rem", "remu", "and", "or", "xor", "test", "cmp"] {
+        for &(a, b) in PAIRS {
+            add(op, Case::new(format!("{} $1, $2", op)).reg(1, a).reg(2, b));
+        }
+        for &a in &[0, 5, -5i64 as u64] {
+            add(op, Case::new(format!("{} $1, -3", op)).reg(1, a));
+        }
+    }
+
+    for &op in &["mull", "mullu", "divr", "divru"] {
+        for &(a, b) in PAIRS {
+            add(op, Case::new(format!("{} $3, $1, $2", op)).reg(1, a).reg(2, b));
+        }
+        add(op, Case::new(format!("{} $3, $1, 7", op)).reg(1, -100i64 as u64));
+    }
+
+    for &a in &[0, 1, u64::MAX, 0x8000_0000_0000_0000] {
+        add("not", Case::new("not $1").reg(1, a));
+    }
+
+    add("mov", Case::new("mov $1, $2").reg(1, 3).reg(2, 0x1122_3344_5566_7788));
+    add("mov", Case::new("mov $1, -1"));
+    add("mov", Case::new("mov $1, 0x7fffffff"));
+
+    for &op in &["load1", "loadu1", "load2", "loadu2", "load4", "loadu4", "load8", "loadu8"] {
+        add(op, Case::new(format!("{} $1, $2", op)).reg(2, DATA_ADDR).memory(DATA_ADDR, &DATA));
+        add(op, Case::new(format!("{} $1, 4($2)", op)).reg(2, DATA_ADDR - 2).memory(DATA_ADDR, &DATA));
+        add(op, Case::new(format!("{} $1, {}", op, DATA_ADDR)).memory(DATA_ADDR, &DATA));
+        add(op, Case::new(format!("{} $1, 0x3000", op)));
+    }
+
+    for &op in &["store1", "store2", "store4", "store8"] {
+        add(op, Case::new(format!("{} $2, $1", op)).reg(1, 0x1122_3344_5566_7788).reg(2, DATA_ADDR));
+        add(op, Case::new(format!("{} -8($2), -1", op)).reg(2, DATA_ADDR + 8));
+        add(op, Case::new(format!("{} {}, $1", op, DATA_ADDR)).reg(1, 0xfedc_ba98_7654_3210));
+        add(op, Case::new(format!("{} 0x3000, $1", op)).reg(1, 1));
+    }
+
+    add("push", Case::new("push $1").reg(1, 0x1122_3344_5566_7788));
+    add("push", Case::new("push -5"));
+    add("pop", Case::new("pop $1").memory(MACHINE_MEMORY as u64 - 8, &[0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11])
+        .reg(63, MACHINE_MEMORY as u64 - 8));
+    add("pop", Case::new("pop $1"));
+
+    add("jmp", Case::new(format!("jmp {}", DATA_ADDR)));
+    add("jmp", Case::new("jmp $1").reg(1, DATA_ADDR));
+    for &op in &["je", "jne", "jg", "jge", "ja", "jae", "jl", "jle", "jb", "jbe", "jo", "jno", "js", "jns"] {
+        for flags in flag_states() {
+            add(op, Case::new(format!("{} {}", op, DATA_ADDR)).flags(flags));
+        }
+    }
+
+    add("call", Case::new(format!("call {}", DATA_ADDR)));
+    add("call", Case::new("call $1").reg(1, DATA_ADDR));
+    add("ret", Case::new("ret").memory(MACHINE_MEMORY as u64 - 8, &[0x00, 0x18]).reg(63, MACHINE_MEMORY as u64 - 8));
+
+    cases
+}
+
+/// Returns a variety of flag states for testing conditional jumps
+fn flag_states() -> Vec<Flags> {
+    let flags = |carry, zero, sign, overflow| Flags {
+        carry: if carry { CF::Carry } else { CF::NoCarry },
+        zero: if zero { ZF::Zero } else { ZF::NonZero },
+        sign: if sign { SF::NegativeSign } else { SF::PositiveSign },
+        overflow: if overflow { OF::Overflow } else { OF::NoOverflow },
+    };
+
+    vec![
+        flags(false, false, false, false),
+        flags(false, true, false, false),
+        flags(true, false, false, false),
+        flags(false, false, true, false),
+        flags(false, false, false, true),
+        flags(false, false, true, true),
+        flags(true, true, false, false),
+    ]
+}
+
+/// Assembles the instruction of every case, returning each one's layout
+fn assemble(cases: &[(String, Case)]) -> Vec<InstrLayout> {
+    let mut source = String::from("section .code\n");
+    for (_, case) in cases {
+        source += &case.asm;
+        source.push('\n');
+    }
+
+    let source_file = tempfile::Builder::new().suffix(".wa").tempfile()
+        .unwrap_or_else(|err| panic!("Failed to create temporary file: {}", err));
+    fs::write(source_file.path(), source)
+        .unwrap_or_else(|err| panic!("Failed to write '{}': {}", source_file.path().display(), err));
+    let exec_file = NamedTempFile::new()
+        .unwrap_or_else(|err| panic!("Failed to create temporary file: {}", err));
+
+    let asm_exec_path = Path::new(VM_EXEC_PATH).parent().unwrap().join("wolf-asm");
+    let output = Command::new(asm_exec_path)
+        .arg(source_file.path())
+        .arg("--color=never")
+        .arg("-o")
+        .arg(exec_file.path())
+        .output()
+        .unwrap_or_else(|err| panic!("Failed to run assembler: {}", err));
+    assert!(output.status.success(), "Assembler failed:\n{}", String::from_utf8_lossy(&output.stderr));
+
+    let exec = Executable::read_from(exec_file.as_file())
+        .unwrap_or_else(|err| panic!("Failed to read executable: {}", err));
+    exec.code_section.into_iter().map(|stmt| match stmt {
+        Stmt::Instr(instr) => instr,
+        Stmt::StaticData(_) => unreachable!("bug: only instructions were assembled"),
+    }).collect()
+}
+
+/// Runs a single instruction on the initial state of the given case
+fn vector(case: &Case, instr: InstrLayout) -> Vector {
+    let word = instr.to_binary();
+    let opcode = Opcode::read(word, 0);
+    let (kind, layout) = InstrKind::from_opcode(opcode);
+
+    let mut memory = Memory::new(MACHINE_MEMORY);
+    let code_end = instr.write_into(&mut memory, PC).expect("bug: instruction should fit in memory");
+    for (addr, bytes) in &case.memory {
+        bytes[..].write_into(&mut memory, *addr).expect("bug: data should fit in memory");
+    }
+
+    let mut registers = Registers::new(MACHINE_MEMORY);
+    for &(reg, value) in &case.registers {
+        registers.store(reg_from_index(reg), value);
+    }
+
+    let mut vm = Machine {
+        program_counter: PC,
+        memory,
+        registers,
+        flags: case.flags.clone().unwrap_or_default(),
+        io: Stdio::captured(Vec::new()),
+        devices: Devices::default(),
+        null_guard: false,
+        strict_align: false,
+        host_calls: HostCalls::default(),
+        counters: PerfCounters::default(),
+        stats: None,
+        exit_code: 0,
+        stack_guard: 0..0,
+        code: PC..code_end,
+        call_stack: None,
+    };
+
+    let pre = state(&vm);
+    let post = match vm.step() {
+        Ok(_) => Outcome::State(state(&vm)),
+        Err(err) => Outcome::Error {error: err.to_string()},
+    };
+
+    Vector {
+        asm: case.asm.clone(),
+        word: format!("{:016x}", word),
+        opcode: opcode.into_value(),
+        name: kind.name().to_string(),
+        layout,
+        pre,
+        post,
+    }
+}
+
+fn reg_from_index(index: u8) -> wolf_asm::asm::layout::Reg {
+    wolf_asm::asm::RegisterKind::Numbered(index).into()
+}
+
+fn state(vm: &Machine) -> State {
+    let registers = (0..64).filter_map(|index| {
+        let value: u64 = vm.registers.load(reg_from_index(index));
+        if value == 0 {
+            None
+        } else {
+            Some((format!("${}", index), format!("{:x}", value)))
+        }
+    }).collect();
+
+    let flags = FlagsState {
+        carry: vm.flags.carry as u8,
+        zero: vm.flags.zero as u8,
+        sign: vm.flags.sign as u8,
+        overflow: vm.flags.overflow as u8,
+    };
+
+    let bytes = vm.memory.slice(0..MACHINE_MEMORY as u64).expect("bug: memory should have the expected size");
+    // Memory is compared in aligned 8-byte words so that runs are not split up by zero bytes
+    let mut memory: Vec<MemoryRun> = Vec::new();
+    let mut run_end = None;
+    for (index, word) in bytes.chunks(8).enumerate() {
+        if word.iter().all(|&byte| byte == 0) {
+            continue;
+        }
+
+        let addr = index * 8;
+        let hex: String = word.iter().map(|byte| format!("{:02x}", byte)).collect();
+        match memory.last_mut() {
+            Some(run) if run_end == Some(addr) => run.bytes += &hex,
+            _ => memory.push(MemoryRun {addr: format!("{:x}", addr), bytes: hex}),
+        }
+        run_end = Some(addr + 8);
+    }
+
+    State {
+        pc: format!("{:x}", vm.program_counter),
+        registers,
+        flags,
+        memory,
+    }
+}
@@ -1,13 +1,46 @@
 use std::fs;
 use std::env;
+use std::thread;
 use std::ffi::OsStr;
-use std::path::Path;
-use std::process::{Command, Stdio};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio, ChildStdin};
 
 use rayon::prelude::*;
-use tempfile::{NamedTempFile, TempPath};
+use tempfile::{NamedTempFile, TempDir, TempPath};
 
 const VM_EXEC_PATH: &str = env!("CARGO_BIN_EXE_wolf-vm");
+/// The maximum amount of time that an `expect` command in a `.stdin-script` file will wait for
+const EXPECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The input that will be provided to a test program
+enum TestInput {
+    /// No input (stdin is immediately at EOF)
+    None,
+    /// The contents of a `.stdin` file, piped all at once
+    File(fs::File),
+    /// The commands from a `.stdin-script` file, run as the program produces output
+    Script(PathBuf, Vec<ScriptCommand>),
+}
+
+/// A single line of a `.stdin-script` file
+///
+/// Each line is a command followed by a single space and then its argument. Empty lines and lines
+/// starting with `#` are ignored.
+///
+/// * `expect TEXT` - wait until the program prints `TEXT`
+/// * `send TEXT` - write `TEXT` to the program's stdin (`\n`, `\t`, and `\\` are unescaped)
+/// * `wait MS` - wait for the given number of milliseconds
+/// * `close` - close stdin so the program sees EOF (done automatically at the end of the script)
+#[derive(Debug)]
+enum ScriptCommand {
+    Expect(String),
+    Send(String),
+    Wait(Duration),
+    Close,
+}
 
 #[test]
 fn run_fail() {
@@ -28,18 +61,12 @@ fn run_fail() {
         }
 
         println!("[run-fail] Running assembler on {}", entry_path.display());
-        let exec_path = run_assembler(&entry_path);
-
-        let input_path = entry_path.with_extension("stdin");
-        let stdin = if input_path.exists() {
-            Some(fs::File::open(&input_path)
-                .unwrap_or_else(|err| panic!("Failed to read '{}': {}", input_path.display(), err)))
-        } else {
-            None
-        };
+        let exec_path = run_assembler(&entry_path, &[]);
+
+        let stdin = test_input(&entry_path);
 
         println!("[run-fail] Running VM on {} ({})", entry_path.display(), exec_path.display());
-        match run_vm(&exec_path, stdin) {
+        match run_vm(&exec_path, &[], stdin) {
             Ok(_) => {
                 panic!("VM should have failed to run '{}'", entry_path.display());
             },
@@ -85,13 +112,839 @@ fn run_fail() {
 
 #[test]
 fn run_pass() {
+    run_pass_dir(Path::new("../tests/run-pass"), "run-pass", &[], &[], &[]);
+}
+
+#[test]
+fn examples() {
+    run_pass_dir(Path::new("../examples"), "examples", &[], &[], &[]);
+}
+
+#[test]
+fn self_check() {
+    // Runs every program with both execution engines and checks that they always agree
+    run_pass_dir(Path::new("../tests/run-pass"), "self-check", &[], &["--self-check"], &[]);
+}
+
+#[test]
+fn optimized() {
+    // Runs every program assembled with `-O` and checks that the output does not change
+    // Programs that print their own performance counters are expected to change
+    run_pass_dir(Path::new("../tests/run-pass"), "optimized", &["-O"], &[], &["perf-counters.wa"]);
+}
+
+#[test]
+fn link() {
+    let tests_dir = Path::new("../tests/link");
+    let bin_dir = Path::new(VM_EXEC_PATH).parent().unwrap();
+    let objects_dir = tempfile::tempdir()
+        .unwrap_or_else(|err| panic!("Failed to create temporary directory: {}", err));
+
+    // The object containing the entry point must be linked first
+    let object_paths: Vec<_> = ["main.wa", "counter.wa"].iter().map(|name| {
+        let source_path = tests_dir.join(name);
+        let object_path = objects_dir.path().join(name).with_extension("o");
+        let status = Command::new(bin_dir.join("wolf-asm"))
+            .arg("-c")
+            .arg(&source_path)
+            .arg("-o")
+            .arg(&object_path)
+            .status()
+            .unwrap_or_else(|err| panic!("Failed to run assembler: {}", err));
+        assert!(status.success(), "Assembler failed for '{}'", source_path.display());
+
+        object_path
+    }).collect();
+
+    let exec_path = objects_dir.path().join("main");
+    let status = Command::new(bin_dir.join("wolf-ld"))
+        .args(&object_paths)
+        .arg("-o")
+        .arg(&exec_path)
+        .status()
+        .unwrap_or_else(|err| panic!("Failed to run linker: {}", err));
+    assert!(status.success(), "Linker failed for '{}'", tests_dir.display());
+
+    let stdout_file = tests_dir.join("main.stdout");
+    let expected_stdout = fs::read_to_string(&stdout_file)
+        .unwrap_or_else(|err| panic!("Failed to open '{}': {}", stdout_file.display(), err));
+    match run_vm(&exec_path, &[], TestInput::None) {
+        Ok((stdout, _)) => assert_eq!(stdout, expected_stdout, "Output did not match '{}'", stdout_file.display()),
+        Err(_) => panic!("VM failed to run linked executable for '{}'", tests_dir.display()),
+    }
+}
+
+#[test]
+fn relocation() {
+    let source_path = Path::new("../examples/bubble-sort.wa");
+    let exec_path = NamedTempFile::new()
+        .unwrap_or_else(|err| panic!("Failed to created temporary file: {}", err))
+        .into_temp_path();
+
+    let asm_exec_path = Path::new(VM_EXEC_PATH).parent().unwrap().join("wolf-asm");
+    let status = Command::new(asm_exec_path)
+        .arg(source_path)
+        .arg("--relocatable")
+        .arg("-D")
+        .arg("TEST=1")
+        .arg("-o")
+        .arg(&exec_path)
+        .status()
+        .unwrap_or_else(|err| panic!("Failed to run assembler: {}", err));
+    assert!(status.success(), "Assembler failed for '{}'", source_path.display());
+
+    let stdout_file = source_path.with_extension("stdout");
+    let expected_stdout = fs::read_to_string(&stdout_file)
+        .unwrap_or_else(|err| panic!("Failed to open '{}': {}", stdout_file.display(), err));
+    // Both the address the program was assembled for and a different address should work
+    for &load_addr in &["0x1000", "0x1400"] {
+        match run_vm(&exec_path, &["--load-addr", load_addr], TestInput::None) {
+            Ok((stdout, _)) => assert_eq!(stdout, expected_stdout, "Output did not match '{}'", stdout_file.display()),
+            Err((_, stderr)) => panic!("VM failed to run '{}' at {}:\n{}", source_path.display(), load_addr, stderr),
+        }
+    }
+}
+
+#[test]
+fn debug_info() {
+    let source_path = Path::new("../tests/run-fail/divide-by-zero.wa");
+    let exec_path = NamedTempFile::new()
+        .unwrap_or_else(|err| panic!("Failed to created temporary file: {}", err))
+        .into_temp_path();
+
+    let asm_exec_path = Path::new(VM_EXEC_PATH).parent().unwrap().join("wolf-asm");
+    let status = Command::new(asm_exec_path)
+        .arg(source_path)
+        .arg("-g")
+        .arg("--relocatable")
+        .arg("-o")
+        .arg(&exec_path)
+        .status()
+        .unwrap_or_else(|err| panic!("Failed to run assembler: {}", err));
+    assert!(status.success(), "Assembler failed for '{}'", source_path.display());
+
+    // The source line should still be found after the executable is relocated
+    for &(load_addr, pc) in &[("0x1000", "0x1020"), ("0x1400", "0x1420")] {
+        match run_vm(&exec_path, &["--load-addr", load_addr], TestInput::None) {
+            Ok(_) => panic!("VM should have failed to run '{}'", source_path.display()),
+            Err((_, stderr)) => {
+                let expected = format!("Failed to execute instruction at `{}` (at {}:11)", pc, source_path.display());
+                assert!(stderr.contains(&expected), "stderr did not contain `{}`:\n{}", expected, stderr);
+            },
+        }
+    }
+
+    // A jump to an address outside of the program points to the jump that caused the error
+    let source_path = Path::new("../tests/run-fail/null-address-jump.wa");
+    let exec_path = run_assembler(source_path, &["-g"]);
+    for &vm_args in &[&[][..], &["--engine", "closure"], &["--self-check"]] {
+        match run_vm(&exec_path, vm_args, TestInput::None) {
+            Ok(_) => panic!("VM should have failed to run '{}'", source_path.display()),
+            Err((_, stderr)) => {
+                let expected = format!("Failed to execute instruction at `0x0` after executing the instruction at `0x1008` (main+0x8 at {}:8)", source_path.display());
+                assert!(stderr.contains(&expected), "stderr did not contain `{}`:\n{}", expected, stderr);
+            },
+        }
+    }
+}
+
+#[test]
+fn null_guard() {
+    let source_path = Path::new("../tests/run-fail/null-address-access.wa");
+    let exec_path = run_assembler(source_path, &[]);
+    // The store succeeds once the start of memory is no longer reserved
+    if let Err((_, stderr)) = run_vm(&exec_path, &["--no-null-guard"], TestInput::None) {
+        panic!("VM failed to run '{}' with --no-null-guard:\n{}", source_path.display(), stderr);
+    }
+}
+
+#[test]
+fn memory_size() {
+    // The buffer alone does not fit in the default amount of memory
+    let program = assemble_source("large.wa", "section .code\nmain:\n  mov $8, buffer\n  add $8, 16383\n  store1 $8, 1\n  ret\n\
+        section .bss\nbuffer:\n  .uninit 16384\n", &[]);
+
+    assert_contains(&program.run_fail(&[]), "past the end of memory at `0x2000`");
+    for &size in &["32K", "32768", "1m"] {
+        program.run(&["--memory-size", size]);
+    }
+    assert_contains(&program.run_fail(&["--memory-size", "16T"]), "--memory-size");
+}
+
+#[test]
+fn max_steps() {
+    let program = assemble_source("loop.wa", "section .code\nmain:\n  jmp main\n", &[]);
+
+    let expected = "Instruction limit exceeded: the program executed 100 instructions without quitting";
+    for &vm_args in &[&["--max-steps", "100"][..], &["--max-steps", "100", "--self-check"]] {
+        assert_contains(&program.run_fail(vm_args), expected);
+    }
+
+    // Programs that quit before reaching the limit are unaffected
+    let hello_path = Path::new("../tests/run-pass/hello.wa");
+    let hello_exec = run_assembler(hello_path, &[]);
+    if let Err((_, stderr)) = run_vm(&hello_exec, &["--max-steps", "1000"], TestInput::None) {
+        panic!("VM failed to run '{}' with --max-steps:\n{}", hello_path.display(), stderr);
+    }
+}
+
+#[test]
+fn dump_registers() {
+    let program = assemble_source("regs.wa", "section .code\nmain:\n  mov $5, 42\n  cmp $5, 43\n  ret\n", &[]);
+
+    for &engine in &["interp", "closure"] {
+        let (_, stderr) = program.run(&["--dump-registers", "--engine", engine]);
+        for expected in &["$5  = 0x000000000000002a", "$sp = 0x0000000000002000", "CF = 1   ZF = 0   SF = 1   OF = 0"] {
+            assert_contains(&stderr, expected);
+        }
+    }
+
+    // The state is also written when the program stops with an error
+    let divide_by_zero_path = Path::new("../tests/run-fail/divide-by-zero.wa");
+    let exec_path = run_assembler(divide_by_zero_path, &[]);
+    match run_vm(&exec_path, &["--dump-registers"], TestInput::None) {
+        Ok(_) => panic!("VM should have failed to run '{}'", divide_by_zero_path.display()),
+        Err((_, stderr)) => assert_contains(&stderr, "$1  = 0x000000000000000a"),
+    }
+}
+
+#[test]
+fn stats() {
+    let program = assemble_source("stack.wa", "section .code\nmain:\n  push 1\n  push 2\n  pop $1\n  pop $1\n  mov $2, 3\n  ret\n", &[]);
+
+    for &vm_args in &[&["--stats"][..], &["--stats", "--engine", "closure"], &["--stats", "--self-check"]] {
+        let (_, stderr) = program.run(vm_args);
+        // The address pushed when the program starts (used by the last `ret`) counts as a write
+        // and is also on the stack
+        let expected = "instructions executed: 6\nmemory reads: 3\nmemory writes: 3\npeak stack depth: 24 bytes\n\
+            instructions executed by kind:\n  pop      2\n  push     2\n  mov      1\n  ret      1\n";
+        assert!(stderr.contains(expected), "stderr for {:?} did not contain:\n{}\n\nstderr:\n{}", vm_args, expected, stderr);
+    }
+}
+
+#[test]
+fn exit_code() {
+    let program = assemble_source("exit.wa", "section .code\nmain:\n  mov $1, 97\n  store1 0xffff_000c, $1\n  mov $1, 3\n  store8 0xffff_0014, $1\n  store1 0xffff_000c, $1\n  ret\n", &[]);
+
+    for &vm_args in &[&[][..], &["--engine", "closure"], &["--self-check"]] {
+        let output = Command::new(VM_EXEC_PATH)
+            .arg(&*program.exec_path)
+            .args(vm_args)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap_or_else(|err| panic!("Failed to run VM: {}", err));
+        assert_eq!(output.status.code(), Some(3), "VM exited with the wrong code for {:?}:\n{}", vm_args, String::from_utf8_lossy(&output.stderr));
+        // The program quits as soon as the exit code is stored
+        assert_eq!(output.stdout, b"a");
+    }
+}
+
+#[test]
+fn args() {
+    // Prints each argument on its own line
+    let program = assemble_source("args.wa", "section .code\n\
+        main:\n  cmp $a0, 0\n  je end\n  load8 $t0, $a1\n\
+        print:\n  loadu1 $t1, $t0\n  cmp $t1, 0\n  je next\n  store1 0xffff_000c, $t1\n  add $t0, 1\n  jmp print\n\
+        next:\n  mov $t1, 10\n  store1 0xffff_000c, $t1\n  add $a1, 8\n  sub $a0, 1\n  jmp main\n\
+        end:\n  ret\n", &[]);
+
+    for &vm_args in &[&["--", "one", "two three", "", "four"][..], &["--engine", "closure", "--", "one", "two three", "", "four"]] {
+        let (stdout, _) = program.run(vm_args);
+        assert_eq!(stdout, "one\ntwo three\n\nfour\n");
+    }
+    let (stdout, _) = program.run(&[]);
+    assert_eq!(stdout, "");
+}
+
+#[test]
+fn env() {
+    // Prints each environment variable on its own line
+    let program = assemble_source("env.wa", "section .code\n\
+        main:\n  load8 $t0, $a2\n  cmp $t0, 0\n  je end\n\
+        print:\n  loadu1 $t1, $t0\n  cmp $t1, 0\n  je next\n  store1 0xffff_000c, $t1\n  add $t0, 1\n  jmp print\n\
+        next:\n  mov $t1, 10\n  store1 0xffff_000c, $t1\n  add $a2, 8\n  jmp main\n\
+        end:\n  ret\n", &[]);
+
+    let output = Command::new(VM_EXEC_PATH)
+        .arg(&*program.exec_path)
+        .args(["--env", "WOLF_TEST_VAR", "--env", "WOLF_UNSET_VAR", "--env", "GREETING=hello world", "--", "arg"])
+        .env("WOLF_TEST_VAR", "set")
+        .env_remove("WOLF_UNSET_VAR")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap_or_else(|err| panic!("Failed to run VM: {}", err));
+    assert!(output.status.success(), "VM failed:\n{}", String::from_utf8_lossy(&output.stderr));
+    // Variables that are not set are skipped
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "WOLF_TEST_VAR=set\nGREETING=hello world\n");
+}
+
+#[test]
+fn dump_memory() {
+    // `buffer` is at 0x1020, just after the four instructions
+    let program = assemble_source("store.wa", "section .code\nmain:\n  mov $1, 0x123456\n  mov $2, buffer\n  store4 $2, $1\n  ret\n\
+        section .bss\nbuffer:\n  .uninit 4\n", &[]);
+    let dump_path = program.dir.path().join("memory.bin");
+    let dump_path_str = dump_path.to_str().unwrap();
+
+    program.run(&["--dump-memory", dump_path_str]);
+    let memory = fs::read(&dump_path)
+        .unwrap_or_else(|err| panic!("Failed to read '{}': {}", dump_path.display(), err));
+    assert_eq!(memory.len(), 8 * 1024);
+    assert_eq!(&memory[0x1020..0x1024], &[0x56, 0x34, 0x12, 0x00]);
+
+    program.run(&["--dump-memory", dump_path_str, "--dump-range", "0x1020..0x1024"]);
+    let memory = fs::read(&dump_path)
+        .unwrap_or_else(|err| panic!("Failed to read '{}': {}", dump_path.display(), err));
+    assert_eq!(memory, &[0x56, 0x34, 0x12, 0x00]);
+
+    let stderr = program.run_fail(&["--dump-memory", dump_path_str, "--dump-range", "0x1000..0x3000"]);
+    assert_contains(&stderr, "Failed to dump memory in the range `0x1000..0x3000`");
+}
+
+#[cfg(unix)]
+#[test]
+fn interrupt() {
+    let program = assemble_source("spin.wa", "section .code\nmain:\n  push $fp\n  mov $fp, $sp\n  call spin\n  pop $fp\n  ret\n\
+        spin:\n  push $fp\n  mov $fp, $sp\nloop:\n  jmp loop\n", &["-g"]);
+
+    let child = Command::new(VM_EXEC_PATH)
+        .arg(&*program.exec_path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap_or_else(|err| panic!("Failed to spawn VM process: {}", err));
+    // Give the VM time to start running the program
+    thread::sleep(Duration::from_millis(500));
+    let status = Command::new("kill")
+        .arg("-INT")
+        .arg(child.id().to_string())
+        .status()
+        .unwrap_or_else(|err| panic!("Failed to run kill: {}", err));
+    assert!(status.success(), "Failed to send SIGINT to the VM");
+
+    let output = child.wait_with_output()
+        .unwrap_or_else(|err| panic!("Failed to wait for VM: {}", err));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(130), "VM exited with the wrong code:\n{}", stderr);
+    for expected in &[
+        format!("Interrupted before executing the instruction at `0x1038` (loop at {}:12)", program.source_path.display()),
+        format!("  1: `0x1010` (main+0x10 at {}:5)", program.source_path.display()),
+        "$sp = ".to_string(),
+    ] {
+        assert_contains(&stderr, expected);
+    }
+}
+
+#[test]
+fn stack_guard() {
+    let recurse = assemble_source("recurse.wa", "section .code\nmain:\n  call main\n", &[]);
+    for &engine in &["interp", "closure"] {
+        assert_contains(&recurse.run_fail(&["--engine", engine]), "Stack overflow: `call`");
+    }
+
+    // Without the guard, the stack silently overwrites the program
+    let stderr = recurse.run_fail(&["--stack-guard", "0", "--max-steps", "10000"]);
+    assert!(!stderr.contains("Stack overflow"), "stderr should not report a stack overflow:\n{}", stderr);
+
+    // Explicit stores into the guard are also reported
+    let store = assemble_source("store.wa", "section .code\nmain:\n  mov $5, data\n  store8 8($5), 1\n  ret\nsection .static\ndata:\n  .b8 0\n", &[]);
+    assert_contains(&store.run_fail(&[]), "Stack overflow: `store8`");
+    store.run(&["--stack-guard", "0"]);
+}
+
+#[test]
+fn writable_code() {
+    // Overwrites the first instruction of the program after it has already been executed
+    let program = assemble_source("overwrite.wa", "section .code\nmain:\n  mov $5, main\n  store8 0($5), 0\n  ret\n", &[]);
+
+    for &engine in &["interp", "closure"] {
+        assert_contains(&program.run_fail(&["--engine", engine]), "Read-only address: `store8` attempted to write to `0x1000`");
+        program.run(&["--engine", engine, "--writable-code"]);
+    }
+}
+
+#[test]
+fn strict_align() {
+    let program = assemble_source("misaligned.wa", "section .code\nmain:\n  mov $5, data\n  load4 $6, 4($5)\n  load8 $6, 4($5)\n  ret\nsection .static\ndata:\n  .b8 0\n  .b8 0\n", &[]);
+
+    for &engine in &["interp", "closure"] {
+        assert_contains(&program.run_fail(&["--strict-align", "--engine", engine]), "Misaligned address: `load8`");
+    }
+
+    // Misaligned accesses are allowed by default
+    program.run(&[]);
+}
+
+#[test]
+fn check_uninit() {
+    // Reads zeroed and written memory before reading memory that was never written to
+    let uninit = assemble_source("uninit.wa", "section .code\nmain:\n  load8 $5, zeroed\n  mov $6, buf\n  store8 0($6), 1\n  load8 $5, 0($6)\n  load8 $5, 8($6)\n  ret\nsection .bss\nzeroed:\n  .zero 8\nbuf:\n  .uninit 16\n", &[]);
+    for &engine in &["interp", "closure"] {
+        assert_contains(&uninit.run_fail(&["--check-uninit", "--engine", engine]), "Uninitialized memory: `load8` attempted to read `0x1040`");
+    }
+    uninit.run(&[]);
+
+    // Stack slots that were reserved but never written to are also uninitialized
+    let stack = assemble_source("stack.wa", "section .code\nmain:\n  sub $sp, 8\n  load8 $5, 0($sp)\n  add $sp, 8\n  ret\n", &[]);
+    assert_contains(&stack.run_fail(&["--check-uninit"]), "Uninitialized memory: `load8`");
+}
+
+#[test]
+fn check_returns() {
+    let cases = &[
+        ("balanced.wa", "section .code\nmain:\n  call f\n  ret\nf:\n  push 1\n  pop $5\n  ret\n", None),
+        ("imbalance.wa", "section .code\nmain:\n  call f\n  ret\nf:\n  push 1\n  ret\n", Some("Stack imbalance: `ret` popped the return address from `0x1fe8`, but the matching `call` pushed it to `0x1ff0`")),
+        ("corrupted.wa", "section .code\nmain:\n  call f\n  ret\nf:\n  store8 0($sp), 0\n  ret\n", Some("Return address corrupted: `ret` popped `0x0` from `0x1ff0`, but the matching `call` pushed `0x1008`")),
+    ];
+
+    for &(name, source, expected) in cases {
+        let program = assemble_source(name, source, &[]);
+        for &engine in &["interp", "closure"] {
+            let vm_args = &["--check-returns", "--engine", engine];
+            match expected {
+                Some(expected) => assert_contains(&program.run_fail(vm_args), expected),
+                None => { program.run(vm_args); },
+            }
+        }
+    }
+}
+
+#[test]
+fn entry() {
+    // Starting at `main` jumps to the null address, but starting at `other` quits successfully
+    let program = assemble_source("entry.wa", "section .code\nmain:\n  mov $1, 0\n  jmp $1\nother:\n  ret\nsection .static\ndata:\n  .b8 0\n", &["-g"]);
+    program.run(&["--entry", "other"]);
+    program.run(&["--entry", "OTHER"]);
+    program.run_fail(&[]);
+
+    // Labels keep their case when the program is assembled with `--case-sensitive`
+    let case_sensitive = assemble_source("entry.wa", "section .code\nmain:\n  mov $1, 0\n  jmp $1\nOther:\n  ret\n", &["-g", "--case-sensitive"]);
+    case_sensitive.run(&["--entry", "Other"]);
+
+    let no_debug_info_exec = run_assembler(&program.source_path, &[]);
+    let cases: &[(&TempPath, &str, &str)] = &[
+        (&program.exec_path, "missing", "Cannot start at `missing`: no label with that name was found"),
+        (&program.exec_path, "data", "Cannot start at `data`: the label is in the `.static` section"),
+        (&no_debug_info_exec, "other", "Cannot start at `other`: the executable has no labels"),
+    ];
+    for &(exec, label, expected) in cases {
+        match run_vm(exec, &["--entry", label], TestInput::None) {
+            Ok(_) => panic!("VM should have failed to start '{}' at `{}`", program.source_path.display(), label),
+            Err((_, stderr)) => assert_contains(&stderr, expected),
+        }
+    }
+}
+
+#[test]
+fn demangle() {
+    let program = assemble_source("demangle.wa", "section .code\nmain:\n  call _Wmath_fail\n  ret\n_Wmath_fail:\n  mov $1, 0\n  jmp $1\n", &["-g", "--case-sensitive"]);
+    assert_contains(&program.run_fail(&[]), "(_Wmath_fail+0x8 at ");
+    assert_contains(&program.run_fail(&["--demangle", "_W*_*=$1::$2"]), "(math::fail+0x8 at ");
+    // Labels that don't match any rule are shown as they are
+    assert_contains(&program.run_fail(&["--demangle", "_Z*=$1"]), "(_Wmath_fail+0x8 at ");
+    // Labels can be given to `--entry` by their demangled name
+    assert_contains(&program.run_fail(&["--demangle", "_W*_*=$1::$2", "--entry", "math::fail"]), "(math::fail+0x8 at ");
+
+    assert_contains(&program.run_fail(&["--demangle", "_W*=$2"]), "`$2` in `_W*=$2` does not refer to a `*` in the pattern");
+}
+
+#[test]
+fn invalid_executable() {
+    let exec_path = run_assembler(Path::new("../tests/run-pass/hello.wa"), &[]);
+    let exec = fs::read(&exec_path)
+        .unwrap_or_else(|err| panic!("Failed to read '{}': {}", exec_path.display(), err));
+
+    let empty = assemble_source("empty.wa", "", &[]);
+    let empty_exec = fs::read(&empty.exec_path)
+        .unwrap_or_else(|err| panic!("Failed to read executable for '{}': {}", empty.source_path.display(), err));
+
+    let cases: &[(&str, &[u8], &str)] = &[
+        ("empty", &[], "the file is empty"),
+        ("truncated", &exec[..exec.len() / 2], "before the end of the executable (it may be truncated)"),
+        ("text", b"this is not an executable", "the file is not an executable generated by wolf-asm or it is corrupted"),
+        ("no-code", &empty_exec, "the executable has no instructions"),
+    ];
+    for &(name, bytes, expected) in cases {
+        let path = empty.dir.path().join(name);
+        fs::write(&path, bytes)
+            .unwrap_or_else(|err| panic!("Failed to write '{}': {}", path.display(), err));
+        match run_vm(&path, &[], TestInput::None) {
+            Ok(_) => panic!("VM should have failed to load '{}'", path.display()),
+            Err((_, stderr)) => assert_contains(&stderr, expected),
+        }
+    }
+}
+
+#[test]
+fn raw() {
+    let hello_path = Path::new("../tests/run-pass/hello.wa");
+    let expected = fs::read_to_string(hello_path.with_extension("stdout"))
+        .unwrap_or_else(|err| panic!("Failed to read expected output for '{}': {}", hello_path.display(), err));
+
+    let cases: &[(&[&str], &[&str])] = &[
+        (&["--format", "bin"], &["--raw"]),
+        (&["--format", "bin", "--base-addr", "0"], &["--raw", "--load-addr", "0"]),
+        (&["--format", "bin", "--base-addr", "0"], &["--raw", "--load-addr", "0", "--engine", "closure"]),
+    ];
+    for &(asm_args, vm_args) in cases {
+        let image_path = run_assembler(hello_path, asm_args);
+        match run_vm(&image_path, vm_args, TestInput::None) {
+            Ok((stdout, _)) => assert_eq!(stdout, expected, "unexpected output with {:?}", vm_args),
+            Err((_, stderr)) => panic!("VM failed to run '{}' with {:?}:\n{}", hello_path.display(), vm_args, stderr),
+        }
+    }
+}
+
+#[test]
+fn elf() {
+    for name in &["hello", "sections"] {
+        let source_path = Path::new("../tests/run-pass").join(name).with_extension("wa");
+        let expected = fs::read_to_string(source_path.with_extension("stdout"))
+            .unwrap_or_else(|err| panic!("Failed to read expected output for '{}': {}", source_path.display(), err));
+
+        let elf_path = run_assembler(&source_path, &["--format", "elf"]);
+        for &vm_args in &[&[][..], &["--engine", "closure"][..]] {
+            match run_vm(&elf_path, vm_args, TestInput::None) {
+                Ok((stdout, _)) => assert_eq!(stdout, expected, "unexpected output for '{}' with {:?}", name, vm_args),
+                Err((_, stderr)) => panic!("VM failed to run '{}' with {:?}:\n{}", source_path.display(), vm_args, stderr),
+            }
+        }
+    }
+
+    // ELF files for a different machine or with headers past the end of the file must be rejected
+    // rather than run
+    let hello_path = Path::new("../tests/run-pass/hello.wa");
+    let elf_path = run_assembler(hello_path, &["--format", "elf"]);
+    let elf = fs::read(&elf_path)
+        .unwrap_or_else(|err| panic!("Failed to read '{}': {}", elf_path.display(), err));
+    let cases: &[(&str, usize, &[u8], &str)] = &[
+        // e_machine = EM_X86_64
+        ("machine", 18, &62u16.to_le_bytes(), "machine type"),
+        // e_phoff close enough to the maximum offset that the end of the program headers overflows
+        ("phoff", 32, &0xffff_ffff_ffff_fff0u64.to_le_bytes(), "the end of the program headers"),
+    ];
+    for &(name, offset, value, expected) in cases {
+        let mut bytes = elf.clone();
+        bytes[offset..offset+value.len()].copy_from_slice(value);
+        fs::write(&elf_path, &bytes)
+            .unwrap_or_else(|err| panic!("Failed to write '{}': {}", elf_path.display(), err));
+        match run_vm(&elf_path, &[], TestInput::None) {
+            Ok(_) => panic!("VM should have rejected the ELF file with an invalid {}", name),
+            Err((_, stderr)) => assert_contains(&stderr, expected),
+        }
+    }
+}
+
+#[test]
+fn file_io() {
+    let source_path = Path::new("../tests/file-io/copy.wa");
+    let exec_path = run_assembler(source_path, &[]);
+
+    let source_dir = tempfile::tempdir()
+        .unwrap_or_else(|err| panic!("Failed to create temporary directory: {}", err));
+    let file_dir = source_dir.path().join("files");
+    fs::create_dir(&file_dir)
+        .unwrap_or_else(|err| panic!("Failed to create '{}': {}", file_dir.display(), err));
+    fs::write(file_dir.join("in.txt"), "abc\n")
+        .unwrap_or_else(|err| panic!("Failed to write input file: {}", err));
+    // The file outside of the directory exists, so only the sandbox stops it from being opened
+    fs::write(source_dir.path().join("in.txt"), "secret\n")
+        .unwrap_or_else(|err| panic!("Failed to write input file: {}", err));
+
+    let file_dir_arg = file_dir.to_str().expect("bug: temporary directory should be valid UTF-8");
+    for &engine in &["interp", "closure"] {
+        if let Err((_, stderr)) = run_vm(&exec_path, &["--file-dir", file_dir_arg, "--engine", engine], TestInput::None) {
+            panic!("VM failed to run '{}' with --file-dir:\n{}", source_path.display(), stderr);
+        }
+        let output = fs::read_to_string(file_dir.join("out.txt"))
+            .unwrap_or_else(|err| panic!("Failed to read output file: {}", err));
+        assert_eq!(output, "abc\n");
+        fs::remove_file(file_dir.join("out.txt"))
+            .unwrap_or_else(|err| panic!("Failed to remove output file: {}", err));
+    }
+
+    // Without a directory, there is no file device
+    match run_vm(&exec_path, &[], TestInput::None) {
+        Ok(_) => panic!("VM should have failed to run '{}' without --file-dir", source_path.display()),
+        Err((_, stderr)) => assert!(stderr.contains("Invalid memory access"), "unexpected error:\n{}", stderr),
+    }
+}
+
+#[test]
+fn file_io_sandbox() {
+    let source = |set_buf: &str| format!("\
+.const FILE_CMD 0xffff_0200
+.const FILE_FD 0xffff_0208
+.const FILE_BUF 0xffff_0210
+.const FILE_LEN 0xffff_0218
+.const READ 4
+
+section .code
+main:
+  mov $5, path
+  store8 FILE_BUF, $5
+  mov $5, PATH_LEN
+  store8 FILE_LEN, $5
+  mov $5, OPEN
+  store8 FILE_CMD, $5
+  load8 $6, FILE_CMD
+  store8 FILE_FD, $6
+  {}
+  store8 FILE_BUF, $5
+  mov $5, 4
+  store8 FILE_LEN, $5
+  mov $5, READ
+  store8 FILE_CMD, $5
+  # Exit with 0 if the file could not be opened
+  add $6, 1
+  store8 0xffff_0014, $6
+  ret
+
+section .static
+path:
+  .bytes 'file.txt'
+.const PATH_LEN $here - path
+", set_buf);
+
+    let sandbox = tempfile::tempdir()
+        .unwrap_or_else(|err| panic!("Failed to create temporary directory: {}", err));
+    let file_dir = sandbox.path().join("files");
+    fs::create_dir(&file_dir)
+        .unwrap_or_else(|err| panic!("Failed to create '{}': {}", file_dir.display(), err));
+    let file_dir_arg = file_dir.to_str().expect("bug: temporary directory should be valid UTF-8");
+
+    // A dangling symbolic link must not be followed to create a file outside of the sandbox
+    #[cfg(unix)]
+    {
+        let outside_path = sandbox.path().join("outside.txt");
+        std::os::unix::fs::symlink(&outside_path, file_dir.join("file.txt"))
+            .unwrap_or_else(|err| panic!("Failed to create symbolic link: {}", err));
+        // Opens the file for writing
+        let program = assemble_source("symlink.wa", &source("mov $5, path").replace("OPEN", "2"), &[]);
+        program.run(&["--file-dir", file_dir_arg]);
+        assert!(!outside_path.exists(), "file was created outside of the sandbox through a symbolic link");
+        fs::remove_file(file_dir.join("file.txt"))
+            .unwrap_or_else(|err| panic!("Failed to remove symbolic link: {}", err));
+    }
+
+    // Reading into memory fails wherever a store would
+    fs::write(file_dir.join("file.txt"), "abcd")
+        .unwrap_or_else(|err| panic!("Failed to write input file: {}", err));
+    let cases = &[
+        ("mov $5, 0", "Null address access: `file read`"),
+        ("mov $5, main", "Read-only address: `file read`"),
+        // The stack guard starts right after the path at the end of the program
+        ("mov $5, path\n  add $5, PATH_LEN", "Stack overflow: `file read`"),
+    ];
+    for &(set_buf, expected) in cases {
+        let program = assemble_source("read.wa", &source(set_buf).replace("OPEN", "1"), &[]);
+        for &engine in &["interp", "closure"] {
+            assert_contains(&program.run_fail(&["--file-dir", file_dir_arg, "--engine", engine]), expected);
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "net")]
+fn net() {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    let source_path = Path::new("../tests/net/client.wa");
+    let exec_path = run_assembler(source_path, &[]);
+
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .unwrap_or_else(|err| panic!("Failed to listen on a local port: {}", err));
+    let addr = listener.local_addr()
+        .unwrap_or_else(|err| panic!("Failed to get the local address: {}", err)).to_string();
+    // Replies to a single `ping` from each engine
+    let server = thread::spawn(move || {
+        for _ in 0..2 {
+            let (stream, _) = listener.accept()
+                .unwrap_or_else(|err| panic!("Failed to accept a connection: {}", err));
+            let mut stream = BufReader::new(stream);
+            let mut line = String::new();
+            stream.read_line(&mut line)
+                .unwrap_or_else(|err| panic!("Failed to read from the connection: {}", err));
+            assert_eq!(line, "ping\n");
+            stream.get_mut().write_all(b"pong\n")
+                .unwrap_or_else(|err| panic!("Failed to write to the connection: {}", err));
+        }
+    });
+
+    for &engine in &["interp", "closure"] {
+        match run_vm(&exec_path, &["--net", "--engine", engine, "--", &addr], TestInput::None) {
+            Ok((stdout, _)) => assert_eq!(stdout, "pong\n"),
+            Err((_, stderr)) => panic!("VM failed to run '{}' with --net:\n{}", source_path.display(), stderr),
+        }
+    }
+    server.join().expect("server thread panicked");
+
+    // Without --net, there is no network device
+    match run_vm(&exec_path, &["--", &addr], TestInput::None) {
+        Ok(_) => panic!("VM should have failed to run '{}' without --net", source_path.display()),
+        Err((_, stderr)) => assert!(stderr.contains("Invalid memory access"), "unexpected error:\n{}", stderr),
+    }
+}
+
+#[test]
+#[cfg(not(feature = "net"))]
+fn net_disabled() {
+    let source_path = Path::new("../tests/net/client.wa");
+    let exec_path = run_assembler(source_path, &[]);
+
+    match run_vm(&exec_path, &["--net", "--", "127.0.0.1:1"], TestInput::None) {
+        Ok(_) => panic!("VM should not support --net without the `net` feature"),
+        Err((_, stderr)) => assert!(stderr.contains("`net` feature"), "unexpected error:\n{}", stderr),
+    }
+}
+
+#[test]
+fn clock() {
+    // Exits with 1 if the monotonic time goes backwards or the wall-clock time is not after 1970
+    let program = assemble_source("clock.wa", "section .code\nmain:\n  load8 $6, 0xffff_0308\n  load8 $7, 0xffff_0308\n  cmp $7, $6\n  jl fail\n  load8 $8, 0xffff_0300\n  cmp $8, 0\n  jle fail\n  ret\nfail:\n  mov $5, 1\n  store8 0xffff_0014, $5\n  ret\n", &[]);
+
+    // Both machines must see the same time for the self-check to pass
+    for &vm_args in &[&["--engine", "interp"][..], &["--engine", "closure"][..], &["--self-check"][..]] {
+        program.run(vm_args);
+    }
+}
+
+#[test]
+fn timer() {
+    // Polls a timer that expires every 100 cycles until it has expired 5 times
+    let program = assemble_source("timer.wa", "section .code\nmain:\n  mov $5, 100\n  store8 0xffff_0408, $5\n  store8 0xffff_0400, $5\n  mov $6, 0\nloop:\n  load8 $7, 0xffff_0410\n  cmp $7, 0\n  je loop\n  store8 0xffff_0410, $7\n  add $6, 1\n  cmp $6, 5\n  jl loop\n  ret\n", &[]);
+
+    for &vm_args in &[&["--engine", "interp"][..], &["--engine", "closure"][..], &["--self-check"][..]] {
+        let vm_args: Vec<_> = vm_args.iter().copied().chain(["--max-steps", "10000"]).collect();
+        program.run(&vm_args);
+    }
+
+    // The timer never expires if it is not started
+    let stopped = assemble_source("stopped.wa", "section .code\nmain:\n  load8 $7, 0xffff_0410\n  cmp $7, 0\n  je main\n  ret\n", &[]);
+    assert_contains(&stopped.run_fail(&["--max-steps", "10000"]), "Instruction limit exceeded");
+}
+
+#[test]
+#[cfg(feature = "framebuffer")]
+fn framebuffer() {
+    let program = assemble_source("line.wa", "section .code\nmain:\n  # $5 = the address of the first pixel\n  mov $5, 0xfffe\n  mul $5, 0x10000\n  # Draw a red diagonal line\n  mov $6, 0\nloop:\n  cmp $6, 200\n  jge present\n  mov $7, $6\n  mul $7, 321\n  add $7, $5\n  mov $8, 224\n  store1 0($7), $8\n  add $6, 1\n  jmp loop\npresent:\n  # Change the background color to dark blue\n  mov $9, 0x000040\n  store4 0xfffe_fc00, $9\n  store1 0xfffe_fb00, $9\n  ret\n", &[]);
+
+    let image_path = program.dir.path().join("frame.ppm");
+    let image_arg = image_path.to_str().expect("bug: temporary directory should be valid UTF-8");
+    for &engine in &["interp", "closure"] {
+        program.run(&["--framebuffer", image_arg, "--engine", engine]);
+
+        let image = fs::read(&image_path)
+            .unwrap_or_else(|err| panic!("Failed to read '{}': {}", image_path.display(), err));
+        let header = b"P6\n320 200\n255\n";
+        assert!(image.starts_with(header), "unexpected image header");
+        let pixels = &image[header.len()..];
+        assert_eq!(pixels.len(), 320 * 200 * 3);
+        let pixel = |x: usize, y: usize| &pixels[(y * 320 + x) * 3..][..3];
+        // The line uses the default palette and the background uses the color that was changed
+        assert_eq!(pixel(0, 0), [0xff, 0, 0]);
+        assert_eq!(pixel(199, 199), [0xff, 0, 0]);
+        assert_eq!(pixel(1, 0), [0, 0, 0x40]);
+        fs::remove_file(&image_path)
+            .unwrap_or_else(|err| panic!("Failed to remove '{}': {}", image_path.display(), err));
+    }
+}
+
+#[test]
+#[cfg(not(feature = "framebuffer"))]
+fn framebuffer_disabled() {
+    let program = assemble_source("present.wa", "section .code\nmain:\n  store1 0xfffe_fb00, $0\n  ret\n", &[]);
+    let image_path = program.dir.path().join("frame.ppm");
+    let image_arg = image_path.to_str().expect("bug: temporary directory should be valid UTF-8");
+    assert_contains(&program.run_fail(&["--framebuffer", image_arg]), "`framebuffer` feature");
+}
+
+#[test]
+fn sleep() {
+    // Sleeps for 20ms and prints 1 if at least that much monotonic time passed, or 0 otherwise
+    let program = assemble_source("sleep.wa", "section .code\nmain:\n  load8 $6, 0xffff_0308\n  mov $5, 20\n  store8 0xffff_0500, $5\n  load8 $7, 0xffff_0308\n  sub $7, $6\n  mov $8, 0\n  cmp $7, 20000000\n  jl done\n  mov $8, 1\ndone:\n  store8 0xffff_004c, $8\n  ret\n", &[]);
+
+    let cases: &[(&[&str], &str)] = &[
+        (&["--engine", "interp"], "1\n"),
+        (&["--engine", "closure"], "1\n"),
+        (&["--self-check"], "1\n"),
+        (&["--no-sleep"], "0\n"),
+        (&["--no-sleep", "--self-check"], "0\n"),
+    ];
+    for &(vm_args, expected) in cases {
+        let (stdout, _) = program.run(vm_args);
+        assert_eq!(stdout, expected, "wrong output for {:?}", vm_args);
+    }
+}
+
+#[test]
+fn batch() {
+    let looping = assemble_source("loop.wa", "section .code\nmain:\n  jmp main\n", &[]);
+    let batch_dir = &looping.dir;
+
+    let run_pass_dir = Path::new("../tests/run-pass").canonicalize()
+        .unwrap_or_else(|err| panic!("Failed to find run-pass directory: {}", err));
+    let divide_by_zero_path = Path::new("../tests/run-fail/divide-by-zero.wa").to_path_buf();
+    let exec_paths: Vec<_> = [run_pass_dir.join("hello.wa"), run_pass_dir.join("cat.wa"), divide_by_zero_path].iter()
+        .map(|source_path| run_assembler(source_path, &[]))
+        .collect();
+
+    let manifest = serde_json::json!({
+        "max_steps": 1_000_000,
+        "programs": [
+            // Deliberately compared against the wrong output
+            {"name": "hello", "executable": &*exec_paths[0], "expected_stdout": run_pass_dir.join("cat.stdout")},
+            {"name": "cat", "executable": &*exec_paths[1], "stdin": run_pass_dir.join("cat.stdin"),
+                "expected_stdout": run_pass_dir.join("cat.stdout")},
+            {"name": "loop", "executable": &*looping.exec_path, "max_steps": 100},
+            {"name": "divide-by-zero", "executable": &*exec_paths[2]},
+            {"name": "missing", "executable": batch_dir.path().join("does-not-exist")},
+        ],
+    });
+    let manifest_path = batch_dir.path().join("manifest.json");
+    fs::write(&manifest_path, manifest.to_string())
+        .unwrap_or_else(|err| panic!("Failed to write '{}': {}", manifest_path.display(), err));
+
+    let output = Command::new(VM_EXEC_PATH)
+        .arg("--batch")
+        .arg(&manifest_path)
+        .output()
+        .unwrap_or_else(|err| panic!("Failed to run VM: {}", err));
+    assert!(output.status.success(), "VM failed:\n{}", String::from_utf8_lossy(&output.stderr));
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .unwrap_or_else(|err| panic!("Batch report was not valid JSON: {}", err));
+    let programs = report["programs"].as_array().expect("report should contain a list of programs");
+    let summary: Vec<_> = programs.iter()
+        .map(|program| (program["name"].as_str().unwrap(), program["status"].as_str().unwrap(), program["stdout_matches"].as_bool()))
+        .collect();
+    assert_eq!(summary, &[
+        ("hello", "quit", Some(false)),
+        ("cat", "quit", Some(true)),
+        ("loop", "step_limit", None),
+        ("divide-by-zero", "error", None),
+        ("missing", "load_error", None),
+    ]);
+
+    let cat_stdout = fs::read(run_pass_dir.join("cat.stdout"))
+        .unwrap_or_else(|err| panic!("Failed to read expected output: {}", err));
+    assert_eq!(programs[1]["stdout_len"], cat_stdout.len());
+    assert_eq!(programs[2]["stats"]["instructions"], 100);
+}
+
+/// Assembles and runs every program in the given directory with the given extra assembler and VM
+/// arguments, checking that each one succeeds and produces the expected output
+///
+/// Files whose names are in `skip` are not run.
+fn run_pass_dir(tests_dir: &Path, label: &str, asm_args: &[&str], vm_args: &[&str], skip: &[&str]) {
     // Pass the environment variable TESTVM=overwrite to overwrite the stdout and stderr files
-    let overwrite_expected_output = env::var("TESTVM")
+    // Output is only overwritten by the run without any extra arguments
+    let overwrite_expected_output = asm_args.is_empty() && vm_args.is_empty() && env::var("TESTVM")
         .map(|val| val == "overwrite")
         .unwrap_or(false);
 
-    let tests_dir = Path::new("../tests/run-pass");
-
     let test_files = tests_dir.read_dir()
         .unwrap_or_else(|err| panic!("Failed to read test files directory '{}': {}", tests_dir.display(), err));
     test_files.par_bridge().panic_fuse().for_each(|entry| {
@@ -100,27 +953,30 @@ fn run_pass() {
         if entry_path.is_dir() || entry_path.extension() != Some(OsStr::new("wa")) {
             return;
         }
+        if entry_path.file_name().map(|name| skip.iter().any(|&skip| name == skip)).unwrap_or(false) {
+            return;
+        }
 
-        println!("[run-pass] Running assembler on {}", entry_path.display());
-        let exec_path = run_assembler(&entry_path);
+        println!("[{}] Running assembler on {}", label, entry_path.display());
+        let exec_path = run_assembler(&entry_path, asm_args);
 
-        let input_path = entry_path.with_extension("stdin");
-        let stdin = if input_path.exists() {
-            Some(fs::File::open(&input_path)
-                .unwrap_or_else(|err| panic!("Failed to read '{}': {}", input_path.display(), err)))
-        } else {
-            None
-        };
+        let stdin = test_input(&entry_path);
 
-        println!("[run-pass] Running VM on {} ({})", entry_path.display(), exec_path.display());
-        match run_vm(&exec_path, stdin) {
+        println!("[{}] Running VM on {} ({})", label, entry_path.display(), exec_path.display());
+        match run_vm(&exec_path, vm_args, stdin) {
             Ok((stdout, stderr)) => {
                 // Check the stdout and stderr output against what's expected
+                // The stderr file is optional
                 let stdout_file = entry_path.with_extension("stdout");
+                let stderr_file = entry_path.with_extension("stderr");
 
                 if overwrite_expected_output {
                     fs::write(&stdout_file, &stdout)
                         .unwrap_or_else(|err| panic!("Failed to write expected output to '{}': {}", stdout_file.display(), err));
+                    if !stderr.is_empty() {
+                        fs::write(&stderr_file, &stderr)
+                            .unwrap_or_else(|err| panic!("Failed to write expected error output to '{}': {}", stderr_file.display(), err));
+                    }
                     return;
                 }
 
@@ -130,11 +986,17 @@ fn run_pass() {
                 if stdout != expected_stdout {
                     panic!("Output for '{}' did not match '{}'", entry_path.display(), stdout_file.display());
                 }
-                if !stderr.is_empty() {
+                if stderr_file.exists() {
+                    let expected_stderr = fs::read_to_string(&stderr_file)
+                        .unwrap_or_else(|err| panic!("Failed to open '{}': {}", stderr_file.display(), err));
+                    if stderr != expected_stderr {
+                        panic!("Error output for '{}' did not match '{}'", entry_path.display(), stderr_file.display());
+                    }
+                } else if !stderr.is_empty() {
                     panic!("stderr for '{}' was not empty", entry_path.display());
                 }
 
-                println!("[run-pass] Finished running VM on {}", entry_path.display());
+                println!("[{}] Finished running VM on {}", label, entry_path.display());
             },
             Err(_) => {
                 panic!("VM failed to run '{}'", entry_path.display());
@@ -143,9 +1005,127 @@ fn run_pass() {
     });
 }
 
-/// Runs the assembler on a single file, returning the path to the generated
-/// executable or panicking if an error occurs.
-fn run_assembler(source_path: &Path) -> TempPath {
+/// Finds the input for the test program at the given path
+///
+/// A test program may have either a `.stdin` file or a `.stdin-script` file, but not both.
+fn test_input(source_path: &Path) -> TestInput {
+    let input_path = source_path.with_extension("stdin");
+    let script_path = source_path.with_extension("stdin-script");
+
+    match (input_path.exists(), script_path.exists()) {
+        (true, true) => panic!("'{}' cannot have both a '.stdin' file and a '.stdin-script' file", source_path.display()),
+
+        (true, false) => TestInput::File(fs::File::open(&input_path)
+            .unwrap_or_else(|err| panic!("Failed to read '{}': {}", input_path.display(), err))),
+
+        (false, true) => {
+            let commands = parse_script(&script_path);
+            TestInput::Script(script_path, commands)
+        },
+
+        (false, false) => TestInput::None,
+    }
+}
+
+/// Parses a `.stdin-script` file, panicking if it is not valid
+fn parse_script(script_path: &Path) -> Vec<ScriptCommand> {
+    let script = fs::read_to_string(script_path)
+        .unwrap_or_else(|err| panic!("Failed to read '{}': {}", script_path.display(), err));
+
+    script.lines().enumerate().filter_map(|(i, line)| {
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (command, arg) = match line.find(' ') {
+            Some(index) => (&line[..index], Some(&line[index+1..])),
+            None => (line, None),
+        };
+
+        let command = match (command, arg) {
+            ("expect", Some(text)) => ScriptCommand::Expect(unescape(text)),
+            ("send", Some(text)) => ScriptCommand::Send(unescape(text)),
+            ("wait", Some(millis)) => ScriptCommand::Wait(Duration::from_millis(millis.parse()
+                .unwrap_or_else(|err| panic!("{}:{}: invalid number of milliseconds: {}", script_path.display(), i+1, err)))),
+            ("close", None) => ScriptCommand::Close,
+            _ => panic!("{}:{}: invalid command `{}`", script_path.display(), i+1, line),
+        };
+
+        Some(command)
+    }).collect()
+}
+
+/// Replaces the escape sequences `\n`, `\t`, and `\\` with the characters they represent
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            unescaped.push(ch);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('\\') => unescaped.push('\\'),
+            Some(ch) => panic!("Unknown escape sequence `\\{}` in `{}`", ch, text),
+            None => panic!("Incomplete escape sequence in `{}`", text),
+        }
+    }
+
+    unescaped
+}
+
+/// Runs the commands in a `.stdin-script` file as the program produces output
+///
+/// Output chunks are received from the program's stdout through `stdout_chunks` and accumulated
+/// into `stdout`.
+fn run_script(
+    script_path: &Path,
+    commands: Vec<ScriptCommand>,
+    stdin: ChildStdin,
+    stdout_chunks: &mpsc::Receiver<Vec<u8>>,
+    stdout: &mut Vec<u8>,
+) {
+    let mut stdin = Some(stdin);
+    // The index into stdout after the last expected text that was found
+    let mut search_start = 0;
+
+    for command in commands {
+        match command {
+            ScriptCommand::Expect(text) => loop {
+                let searched = String::from_utf8_lossy(&stdout[search_start..]);
+                if let Some(index) = searched.find(&text) {
+                    search_start += index + text.len();
+                    break;
+                }
+
+                match stdout_chunks.recv_timeout(EXPECT_TIMEOUT) {
+                    Ok(chunk) => stdout.extend(chunk),
+                    Err(mpsc::RecvTimeoutError::Timeout) => panic!("{}: timed out waiting for the VM to print `{}`", script_path.display(), text),
+                    Err(mpsc::RecvTimeoutError::Disconnected) => panic!("{}: VM exited before printing `{}`", script_path.display(), text),
+                }
+            },
+
+            ScriptCommand::Send(text) => {
+                let stdin = stdin.as_mut().unwrap_or_else(|| panic!("{}: cannot send `{}` after stdin was closed", script_path.display(), text));
+                stdin.write_all(text.as_bytes())
+                    .unwrap_or_else(|err| panic!("{}: failed to write to VM stdin: {}", script_path.display(), err));
+            },
+
+            ScriptCommand::Wait(duration) => thread::sleep(duration),
+
+            // Dropping stdin closes it
+            ScriptCommand::Close => stdin = None,
+        }
+    }
+}
+
+/// Runs the assembler on a single file with the given extra arguments, returning the path to the
+/// generated executable or panicking if an error occurs.
+fn run_assembler(source_path: &Path, asm_args: &[&str]) -> TempPath {
     // The path to the executable that will be generated
     // Using temp file so this is reliably cleaned up
     let executable = NamedTempFile::new()
@@ -154,6 +1134,10 @@ fn run_assembler(source_path: &Path) -> TempPath {
     let asm_exec_path = Path::new(VM_EXEC_PATH).parent().unwrap().join("wolf-asm");
     let status = Command::new(asm_exec_path)
         .arg(source_path)
+        // Enables the `.ifdef TEST` blocks in self-testing programs
+        .arg("-D")
+        .arg("TEST=1")
+        .args(asm_args)
         .arg("-o")
         .arg(executable.path())
         .status()
@@ -167,24 +1151,111 @@ fn run_assembler(source_path: &Path) -> TempPath {
     executable.into_temp_path()
 }
 
-/// Runs the given executable using the virtual machine
+/// A program written to a temporary directory by a test and assembled
+struct TestProgram {
+    /// The directory containing the source file, which tests may also write other files to
+    dir: TempDir,
+    source_path: PathBuf,
+    exec_path: TempPath,
+}
+
+impl TestProgram {
+    /// Runs the program with the given extra VM arguments, returning (stdout, stderr) or panicking
+    /// if the VM fails
+    fn run(&self, vm_args: &[&str]) -> (String, String) {
+        run_vm(&self.exec_path, vm_args, TestInput::None)
+            .unwrap_or_else(|(_, stderr)| panic!("VM failed to run '{}' with {:?}:\n{}", self.source_path.display(), vm_args, stderr))
+    }
+
+    /// Runs the program with the given extra VM arguments, returning stderr or panicking if the VM
+    /// succeeds
+    fn run_fail(&self, vm_args: &[&str]) -> String {
+        match run_vm(&self.exec_path, vm_args, TestInput::None) {
+            Ok(_) => panic!("VM should have failed to run '{}' with {:?}", self.source_path.display(), vm_args),
+            Err((_, stderr)) => stderr,
+        }
+    }
+}
+
+/// Writes the given source to a file called `name` in a new temporary directory and assembles it
+/// with the given extra arguments, panicking if an error occurs
+fn assemble_source(name: &str, source: &str, asm_args: &[&str]) -> TestProgram {
+    let dir = tempfile::tempdir()
+        .unwrap_or_else(|err| panic!("Failed to create temporary directory: {}", err));
+    let source_path = dir.path().join(name);
+    fs::write(&source_path, source)
+        .unwrap_or_else(|err| panic!("Failed to write '{}': {}", source_path.display(), err));
+    let exec_path = run_assembler(&source_path, asm_args);
+
+    TestProgram {dir, source_path, exec_path}
+}
+
+/// Panics if the output of the VM does not contain the expected text
+fn assert_contains(output: &str, expected: &str) {
+    assert!(output.contains(expected), "output did not contain `{}`:\n{}", expected, output);
+}
+
+/// Runs the given executable using the virtual machine, passing it the given extra arguments
 ///
 /// Returns (stdout, stderr)
-fn run_vm(exec_path: &Path, stdin: Option<fs::File>) -> Result<(String, String), (String, String)> {
-    let stdin = stdin.map(Stdio::from).unwrap_or_else(Stdio::null);
+fn run_vm(exec_path: &Path, vm_args: &[&str], input: TestInput) -> Result<(String, String), (String, String)> {
+    let (stdin, script) = match input {
+        TestInput::None => (Stdio::null(), None),
+        TestInput::File(file) => (Stdio::from(file), None),
+        TestInput::Script(script_path, commands) => (Stdio::piped(), Some((script_path, commands))),
+    };
 
-    let output = Command::new(VM_EXEC_PATH)
+    let mut child = Command::new(VM_EXEC_PATH)
         .arg(exec_path)
+        .args(vm_args)
         .stdin(stdin)
-        .output()
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
         .unwrap_or_else(|err| panic!("Failed to spawn VM process: {}", err));
 
-    let stdout = String::from_utf8(output.stdout)
+    // Read stdout and stderr on separate threads so the VM never blocks on a full pipe
+    let (stdout_sender, stdout_chunks) = mpsc::channel();
+    let mut child_stdout = child.stdout.take().expect("bug: stdout should be piped");
+    let stdout_thread = thread::spawn(move || {
+        let mut buffer = [0; 1024];
+        loop {
+            match child_stdout.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => if stdout_sender.send(buffer[..n].to_vec()).is_err() {
+                    break;
+                },
+                Err(err) => panic!("Failed to read VM stdout: {}", err),
+            }
+        }
+    });
+    let mut child_stderr = child.stderr.take().expect("bug: stderr should be piped");
+    let stderr_thread = thread::spawn(move || {
+        let mut stderr = Vec::new();
+        child_stderr.read_to_end(&mut stderr)
+            .unwrap_or_else(|err| panic!("Failed to read VM stderr: {}", err));
+        stderr
+    });
+
+    let mut stdout = Vec::new();
+    if let Some((script_path, commands)) = script {
+        let stdin = child.stdin.take().expect("bug: stdin should be piped");
+        run_script(&script_path, commands, stdin, &stdout_chunks, &mut stdout);
+    }
+
+    // Collect the rest of the output once the VM exits
+    stdout.extend(stdout_chunks.iter().flatten());
+    stdout_thread.join().expect("stdout thread panicked");
+    let stderr = stderr_thread.join().expect("stderr thread panicked");
+    let status = child.wait()
+        .unwrap_or_else(|err| panic!("Failed to wait for VM process: {}", err));
+
+    let stdout = String::from_utf8(stdout)
         .unwrap_or_else(|err| panic!("VM stdout was not valid UTF-8: {}", err));
-    let stderr = String::from_utf8(output.stderr)
+    let stderr = String::from_utf8(stderr)
         .unwrap_or_else(|err| panic!("VM stderr was not valid UTF-8: {}", err));
 
-    if output.status.success() {
+    if status.success() {
         Ok((stdout, stderr))
     } else {
         Err((stdout, stderr))